@@ -4,6 +4,7 @@ set -ex
 
 cargo build --target ${TARGET}
 cargo test --target ${TARGET}
+cargo test --target ${TARGET} --features zstd
 
 # On Rust 1.31.0, we only care about passing tests.
 if [ "$TRAVIS_RUST_VERSION" = "stable" ] && [ "$TARGET" = "x86_64-unknown-linux-gnu" ] ; then
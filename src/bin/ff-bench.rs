@@ -0,0 +1,200 @@
+use std::time::Instant;
+
+use clap::{App, AppSettings, Arg};
+use failure::Error;
+use finalfrontier::source::build_vocab;
+use finalfrontier::{
+    BucketConfig, CommonConfig, LossType, LrAnchor, ModelType, SamplerType, SentenceSource,
+    SkipGramConfig, SkipgramTrainer, SubwordHash, SubwordVocab, SubwordVocabConfig, TextCorpus,
+    SGD,
+};
+use finalfusion::subword::FinalfusionHashIndexer;
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use stdinout::OrExit;
+use zipf::ZipfDistribution;
+
+static CORPUS: &str = "corpus";
+static SENTENCES: &str = "sentences";
+
+/// Seed of the synthetic corpus and the trainer.
+const SEED: u64 = 42;
+
+/// Number of word types in the synthetic corpus.
+const N_WORD_TYPES: usize = 10_000;
+
+/// Sentence length in the synthetic corpus.
+const SENTENCE_LEN: usize = 20;
+
+const BENCH_COMMON_CONFIG: CommonConfig = CommonConfig {
+    dims: 100,
+    dropout: 0.,
+    epochs: 1,
+    freq_lr_scaling: false,
+    init_scale: None,
+    loss: LossType::LogisticNegativeSampling,
+    lr: 0.05,
+    lr_anchor: LrAnchor::Tokens,
+    max_examples_per_token: 0,
+    max_ngrams_per_token: 0,
+    max_token_len: 100,
+    min_lr: 0.,
+    negative_samples: 5,
+    normalize_digits: false,
+    output_dims: 100,
+    positive_weight: 1.,
+    sampler: SamplerType::Zipf,
+    senses: 1,
+    warmup_tokens: 0,
+    zipf_exponent: 0.5,
+};
+
+const BENCH_SKIPGRAM_CONFIG: SkipGramConfig = SkipGramConfig {
+    context_size: 5,
+    context_subsampling_threshold: 0.,
+    discard_boundary_tokens: false,
+    discard_oov_contexts: false,
+    exclude_focus_negatives: false,
+    min_context: 0,
+    model: ModelType::SkipGram,
+    tied_weights: false,
+};
+
+const BENCH_VOCAB_CONFIG: SubwordVocabConfig<BucketConfig> = SubwordVocabConfig {
+    discard_threshold: 1e-4,
+    min_count: 5,
+    max_n: 6,
+    min_n: 3,
+    indexer: BucketConfig {
+        buckets_exp: 16,
+        hash: SubwordHash::FinalFusion,
+    },
+};
+
+/// Benchmark results.
+struct BenchResult {
+    n_tokens: usize,
+    elapsed_secs: f64,
+    loss: f32,
+}
+
+impl BenchResult {
+    fn tokens_per_sec(&self) -> f64 {
+        self.n_tokens as f64 / self.elapsed_secs
+    }
+
+    fn report(&self) -> String {
+        format!(
+            "tokens: {}\ntokens/s: {:.0}\nloss: {:.5}",
+            self.n_tokens,
+            self.tokens_per_sec(),
+            self.loss
+        )
+    }
+}
+
+/// Generate a corpus with Zipf-distributed words.
+fn synthetic_corpus(n_sentences: usize) -> Vec<Vec<String>> {
+    let mut rng = XorShiftRng::seed_from_u64(SEED);
+    let dist = ZipfDistribution::new(N_WORD_TYPES, 1.0).unwrap();
+
+    (0..n_sentences)
+        .map(|_| {
+            (0..SENTENCE_LEN)
+                .map(|_| format!("w{}", rng.sample(dist)))
+                .collect()
+        })
+        .collect()
+}
+
+/// Train a skipgram model on `source` and measure the training time.
+///
+/// Constructing the vocabulary is not part of the measured time.
+fn bench<S>(source: &S, common_config: CommonConfig) -> Result<BenchResult, Error>
+where
+    S: SentenceSource + ?Sized,
+{
+    let vocab: SubwordVocab<_, FinalfusionHashIndexer> = build_vocab(source, BENCH_VOCAB_CONFIG)?;
+    let trainer = SkipgramTrainer::new(
+        vocab,
+        XorShiftRng::seed_from_u64(SEED),
+        common_config,
+        BENCH_SKIPGRAM_CONFIG,
+    );
+    let mut sgd = SGD::new(trainer.into());
+
+    let start = Instant::now();
+    sgd.train_source(source)?;
+    let elapsed = start.elapsed();
+
+    Ok(BenchResult {
+        n_tokens: sgd.n_tokens_processed(),
+        elapsed_secs: elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9,
+        loss: sgd.train_loss(),
+    })
+}
+
+fn main() {
+    let matches = App::new("ff-bench")
+        .settings(&[
+            AppSettings::DontCollapseArgsInUsage,
+            AppSettings::UnifiedHelpMessage,
+        ])
+        .about("Measure finalfrontier training throughput")
+        .arg(
+            Arg::with_name(SENTENCES)
+                .long("sentences")
+                .value_name("N")
+                .help("Number of sentences in the synthetic corpus")
+                .takes_value(true)
+                .default_value("50000"),
+        )
+        .arg(
+            Arg::with_name(CORPUS)
+                .help("Tokenized corpus (default: synthetic corpus)")
+                .index(1),
+        )
+        .get_matches();
+
+    let result = match matches.value_of(CORPUS) {
+        Some(corpus) => bench(&TextCorpus::new(corpus), BENCH_COMMON_CONFIG),
+        None => {
+            let n_sentences = matches
+                .value_of(SENTENCES)
+                .map(|v| v.parse().or_exit("Cannot parse number of sentences", 1))
+                .unwrap();
+            bench(&synthetic_corpus(n_sentences), BENCH_COMMON_CONFIG)
+        }
+    }
+    .or_exit("Cannot run benchmark", 1);
+
+    println!("{}", result.report());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bench, synthetic_corpus, BENCH_COMMON_CONFIG};
+
+    #[test]
+    fn bench_reports_throughput() {
+        let mut common_config = BENCH_COMMON_CONFIG;
+        common_config.dims = 10;
+
+        let corpus = synthetic_corpus(200);
+        assert_eq!(corpus, synthetic_corpus(200), "Corpus is not fixed");
+
+        let result = bench(&corpus, common_config).unwrap();
+        // At most 20 tokens and an end-of-sentence marker per sentence,
+        // infrequent and subsampled tokens are not used as focus tokens.
+        assert!(result.n_tokens > 0 && result.n_tokens <= 200 * 21);
+        assert!(result.loss.is_finite());
+
+        let report = result.report();
+        let tokens_per_sec = report
+            .lines()
+            .find(|line| line.starts_with("tokens/s: "))
+            .map(|line| line["tokens/s: ".len()..].parse::<f64>().unwrap())
+            .unwrap();
+        assert!(tokens_per_sec.is_finite() && tokens_per_sec > 0.);
+    }
+}
@@ -1,8 +1,10 @@
-use failure::{err_msg, Error};
+use std::mem;
+
+use failure::Error;
 use serde::Serialize;
 
 /// Model types.
-#[derive(Copy, Clone, Debug, Serialize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
 pub enum ModelType {
     // The skip-gram model (Mikolov, 2013).
     SkipGram,
@@ -20,7 +22,7 @@ impl ModelType {
             0 => Ok(ModelType::SkipGram),
             1 => Ok(ModelType::StructuredSkipGram),
             2 => Ok(ModelType::DirectionalSkipgram),
-            _ => Err(err_msg(format!("Unknown model type: {}", model))),
+            _ => Err(crate::Error::Config(format!("Unknown model type: {}", model)).into()),
         }
     }
 
@@ -29,7 +31,7 @@ impl ModelType {
             "skipgram" => Ok(ModelType::SkipGram),
             "structgram" => Ok(ModelType::StructuredSkipGram),
             "dirgram" => Ok(ModelType::DirectionalSkipgram),
-            _ => Err(err_msg(format!("Unknown model type: {}", model))),
+            _ => Err(crate::Error::Config(format!("Unknown model type: {}", model)).into()),
         }
     }
 }
@@ -45,7 +47,86 @@ impl LossType {
     pub fn try_from(model: u8) -> Result<LossType, Error> {
         match model {
             0 => Ok(LossType::LogisticNegativeSampling),
-            _ => Err(err_msg(format!("Unknown model type: {}", model))),
+            _ => Err(crate::Error::Config(format!("Unknown loss type: {}", model)).into()),
+        }
+    }
+}
+
+/// Unit in which the learning rate schedule progresses.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum LrAnchor {
+    /// Decay the learning rate after every processed token.
+    Tokens,
+
+    /// Decay the learning rate in discrete steps at epoch boundaries.
+    ///
+    /// The learning rate is constant within an epoch. This is useful when
+    /// the number of tokens in the corpus is not known precisely.
+    Epochs,
+}
+
+impl LrAnchor {
+    pub fn try_from_str(anchor: &str) -> Result<LrAnchor, Error> {
+        match anchor {
+            "epochs" => Ok(LrAnchor::Epochs),
+            "tokens" => Ok(LrAnchor::Tokens),
+            _ => Err(
+                crate::Error::Config(format!("Unknown learning rate anchor: {}", anchor)).into(),
+            ),
+        }
+    }
+}
+
+/// Negative samplers.
+///
+/// All samplers draw negatives with the same distribution, see
+/// `CommonConfig::zipf_exponent`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum SamplerType {
+    /// Sample from the Zipf distribution directly.
+    ///
+    /// This sampler uses constant memory.
+    Zipf,
+
+    /// Sample with Walker's alias method.
+    ///
+    /// This sampler draws in constant time, but uses a table that is
+    /// linear in the vocabulary size.
+    Alias,
+}
+
+impl SamplerType {
+    pub fn try_from_str(sampler: &str) -> Result<SamplerType, Error> {
+        match sampler {
+            "alias" => Ok(SamplerType::Alias),
+            "zipf" => Ok(SamplerType::Zipf),
+            _ => Err(crate::Error::Config(format!("Unknown sampler: {}", sampler)).into()),
+        }
+    }
+}
+
+/// Composition of the embedding of a token from its subwords.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum SubwordComposition {
+    /// The mean of the n-gram embeddings.
+    ///
+    /// The embedding of a long token has the same scale as the embedding
+    /// of a short token.
+    Mean,
+
+    /// The sum of the n-gram embeddings, as in fastText.
+    Sum,
+}
+
+impl SubwordComposition {
+    pub fn try_from_str(composition: &str) -> Result<SubwordComposition, Error> {
+        match composition {
+            "mean" => Ok(SubwordComposition::Mean),
+            "sum" => Ok(SubwordComposition::Sum),
+            _ => Err(
+                crate::Error::Config(format!("Unknown subword composition: {}", composition))
+                    .into(),
+            ),
         }
     }
 }
@@ -59,21 +140,188 @@ pub struct CommonConfig {
     /// Word embedding dimensionality.
     pub dims: u32,
 
+    /// Fraction of embedding dimensions that is dropped in an update.
+    ///
+    /// In every update, each dimension of the input embedding is dropped
+    /// with this probability and the remaining dimensions are scaled by
+    /// *1 / (1 - dropout)*. Dropped dimensions are not updated. Dropout
+    /// is only applied during training, the embeddings of the trained
+    /// model are not masked. A value of zero disables dropout.
+    pub dropout: f32,
+
     /// The number of training epochs.
     pub epochs: u32,
 
+    /// Scale the learning rate of focus tokens by their frequency.
+    ///
+    /// If `true`, the learning rate of a focus token is multiplied by
+    /// *sqrt(mean_count / count)*, bounded to *[0.1, 10]*, where *count*
+    /// is the token's count and *mean_count* the mean count of the words
+    /// in the vocabulary. Rare tokens, which receive few updates, then
+    /// learn faster than frequent tokens. This is a simple alternative to
+    /// per-parameter adaptive learning rates, such as Adagrad.
+    pub freq_lr_scaling: bool,
+
+    /// Scale of the random initialization of the embeddings.
+    ///
+    /// The initial weights of the embedding matrices are drawn uniformly
+    /// from *[-init_scale, init_scale]*. If `None`, the scale is
+    /// *1 / dims*.
+    pub init_scale: Option<f32>,
+
     /// Number of negative samples to use for each context word.
     pub negative_samples: u32,
 
+    /// Weight of the positive example in the loss.
+    ///
+    /// The gradient of the positive output is multiplied by this weight,
+    /// the gradients of negative samples are not weighted. A weight
+    /// larger than *1* emphasizes the positive examples relative to the
+    /// negatives.
+    pub positive_weight: f32,
+
+    /// Output embedding dimensionality.
+    ///
+    /// If this differs from `dims`, input embeddings are mapped to the
+    /// dimensionality of the output embeddings by a projection matrix
+    /// that is learned jointly with the embeddings. The projection is
+    /// discarded after training.
+    pub output_dims: u32,
+
+    /// Replace runs of digits in tokens by a single `0`.
+    ///
+    /// The normalization is applied when the vocabulary is constructed
+    /// and during training, so that e.g. *1999* and *2020* are the same
+    /// token.
+    pub normalize_digits: bool,
+
     /// The initial learning rate.
     pub lr: f32,
 
+    /// The unit in which the learning rate decays.
+    ///
+    /// With `LrAnchor::Tokens`, the learning rate decays after every
+    /// token. With `LrAnchor::Epochs`, the learning rate decays in steps
+    /// of *(lr - min_lr) / epochs* at the start of every epoch.
+    pub lr_anchor: LrAnchor,
+
+    /// Maximum number of training examples per focus token per epoch.
+    ///
+    /// Very frequent tokens generate a disproportionate number of training
+    /// examples. If this is non-zero, further examples of a focus token
+    /// are skipped once the token was the focus of `max_examples_per_token`
+    /// examples in the current epoch. A value of zero disables the cap.
+    pub max_examples_per_token: u32,
+
+    /// Maximum number of subword n-grams that are updated per token.
+    ///
+    /// Long tokens have many n-grams, which would otherwise dominate the
+    /// updates of the subword embeddings. If this is non-zero, only the
+    /// first `max_ngrams_per_token` n-grams of a focus token (in the order
+    /// of the vocabulary's subword indices) are updated. The prediction
+    /// still uses all n-grams. This biases training: n-grams beyond the
+    /// cap are only trained through other, shorter tokens, while the
+    /// embedding of a long token is still composed from all of its
+    /// n-grams. A value of zero disables the cap.
+    pub max_ngrams_per_token: u32,
+
+    /// Maximum token length in bytes.
+    ///
+    /// Longer tokens are skipped, both when counting types and during
+    /// training.
+    pub max_token_len: u32,
+
+    /// The learning rate at the end of training.
+    ///
+    /// The learning rate decays linearly from `lr` to `min_lr`, rather
+    /// than to zero, so that the last part of training still updates the
+    /// embeddings.
+    pub min_lr: f32,
+
+    /// The sampler that draws negatives.
+    pub sampler: SamplerType,
+
+    /// The number of sense vectors per word.
+    ///
+    /// With more than one sense, every word has additional input vectors
+    /// for its other senses. In every training example, the sense whose
+    /// embedding is closest to the averaged output embeddings of the
+    /// context words is used and updated (multi-prototype embeddings,
+    /// Neelakantan et al., 2014). Subword embeddings are shared between
+    /// the senses of a word. The first sense of a word is written as the
+    /// word, sense *k > 0* is written as *word#k* after all words. A
+    /// value of one trains a single vector per word.
+    pub senses: u32,
+
+    /// The number of tokens over which the learning rate is warmed up.
+    ///
+    /// The learning rate increases linearly from zero to `lr` over the
+    /// first `warmup_tokens` tokens. A value of zero disables warmup.
+    pub warmup_tokens: u64,
+
     /// Exponent in zipfian distribution.
     ///
     /// This is s in *f(k) = 1 / (k^s H_{N, s})*.
     pub zipf_exponent: f64,
 }
 
+impl CommonConfig {
+    /// Get the estimated memory use of a model's parameters in bytes.
+    ///
+    /// This is the size of the input and output matrices of a model with
+    /// `vocab_len` words and `bucket_count` subword embeddings, which can
+    /// be computed before the matrices are allocated. `n_outputs` is the
+    /// number of output embeddings, which is zero if the outputs are tied
+    /// to the inputs.
+    pub fn estimated_bytes(
+        &self,
+        vocab_len: usize,
+        bucket_count: usize,
+        n_outputs: usize,
+    ) -> usize {
+        let dims = self.dims as usize;
+        let output_dims = self.output_dims as usize;
+
+        let n_input_params = (vocab_len + bucket_count) * dims;
+        let n_projection_params = if dims != output_dims {
+            dims * output_dims
+        } else {
+            0
+        };
+        let n_sense_params = vocab_len * (self.senses as usize - 1) * dims;
+
+        (n_input_params + n_outputs * output_dims + n_projection_params + n_sense_params)
+            * mem::size_of::<f32>()
+    }
+}
+
+/// Common hyperparameters of unit tests.
+///
+/// Tests that need other values change them with struct update syntax.
+#[cfg(test)]
+pub(crate) const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+    dims: 3,
+    dropout: 0.,
+    epochs: 1,
+    freq_lr_scaling: false,
+    init_scale: None,
+    loss: LossType::LogisticNegativeSampling,
+    lr: 0.05,
+    lr_anchor: LrAnchor::Tokens,
+    max_examples_per_token: 0,
+    max_ngrams_per_token: 0,
+    max_token_len: 100,
+    min_lr: 0.,
+    negative_samples: 5,
+    normalize_digits: false,
+    output_dims: 3,
+    positive_weight: 1.,
+    sampler: SamplerType::Zipf,
+    senses: 1,
+    warmup_tokens: 0,
+    zipf_exponent: 0.5,
+};
+
 /// Hyperparameters for Dependency Embeddings.
 #[derive(Clone, Copy, Debug, Serialize)]
 #[serde(tag = "type")]
@@ -135,6 +383,36 @@ pub struct BucketConfig {
     /// A typical value for this parameter is 21, which gives roughly 2M
     /// buckets.
     pub buckets_exp: u32,
+
+    /// The hash function used to map n-grams to buckets.
+    pub hash: SubwordHash,
+}
+
+/// Subword hash functions.
+///
+/// Both hash functions operate on fixed-width, little-endian encodings of
+/// the n-grams, so that n-grams are mapped to the same buckets on every
+/// platform, regardless of the pointer width or endianness.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum SubwordHash {
+    /// The finalfusion hash function (64-bit FNV-1a).
+    FinalFusion,
+
+    /// The fastText hash function.
+    ///
+    /// This is fastText's variant of FNV-1a, including its sign extension
+    /// of bytes. Use this hash for compatibility with fastText models.
+    FastText,
+}
+
+impl SubwordHash {
+    pub fn try_from_str(hash: &str) -> Result<SubwordHash, Error> {
+        match hash {
+            "finalfusion" => Ok(SubwordHash::FinalFusion),
+            "fasttext" => Ok(SubwordHash::FastText),
+            _ => Err(crate::Error::Config(format!("Unknown subword hash: {}", hash)).into()),
+        }
+    }
 }
 
 /// Hyperparameters for ngram-vocabs.
@@ -182,4 +460,64 @@ pub struct SkipGramConfig {
     /// For example, a context size of 5 will consider the 5 tokens preceding
     /// and the 5 tokens succeeding the focus token.
     pub context_size: u32,
+
+    /// Subsample frequent context words.
+    ///
+    /// If non-zero, every context word in a window is kept with a
+    /// probability that decreases with its frequency, with the same
+    /// formula as the subsampling of focus words (Mikolov et al., 2013),
+    /// but with this threshold. Focus words are subsampled from the
+    /// sentence before windowing, so that they are neither focus nor
+    /// context words, context words are subsampled per window. A value
+    /// of zero disables context subsampling.
+    pub context_subsampling_threshold: f32,
+
+    /// Remove out-of-vocabulary tokens before extracting contexts.
+    ///
+    /// If `true`, out-of-vocabulary tokens are removed from a sentence
+    /// before windowing, so that the context window closes up over them.
+    /// If `false`, out-of-vocabulary tokens keep their position in the
+    /// sentence, but are not used as focus or context tokens.
+    pub discard_oov_contexts: bool,
+
+    /// Remove boundary tokens before extracting contexts.
+    ///
+    /// Boundary tokens always terminate context windows. If `true`,
+    /// boundary tokens are additionally removed from the sentence, so that
+    /// they are not used as focus or context tokens.
+    pub discard_boundary_tokens: bool,
+
+    /// Do not sample the focus word as a negative.
+    ///
+    /// If `false`, the focus word itself can be sampled as a negative,
+    /// as in fastText. The focus word's input embedding and its subword
+    /// embeddings are then also updated to predict the absence of the
+    /// focus word's own output embedding. If `true`, such negatives are
+    /// rejected, so that the focus word's subword embeddings only receive
+    /// gradients from the outputs of other words.
+    pub exclude_focus_negatives: bool,
+
+    /// Minimum number of context words of a training example.
+    ///
+    /// Focus tokens with fewer context words are skipped. Context words
+    /// are counted after subsampling and after removing unknown tokens,
+    /// so this skips focus tokens with near-empty context windows. A value
+    /// of zero disables this check.
+    pub min_context: u32,
+
+    /// Use the input matrix as the output matrix.
+    ///
+    /// If `true`, the output embedding of a word is the input embedding
+    /// of the word, gradients of both roles are accumulated in the same
+    /// rows. This halves the memory use of the embedding matrices. Tied
+    /// weights are only supported by `ModelType::SkipGram`, since the
+    /// other models have more outputs than words.
+    ///
+    /// Hogwild training does not synchronize updates of the embedding
+    /// matrices. With tied weights, updates of a word's input and output
+    /// embeddings also write to the same row without synchronization,
+    /// both within and across training threads. For instance, the update
+    /// of a focus word's embedding can partially overwrite the update of
+    /// the same embedding as a context word.
+    pub tied_weights: bool,
 }
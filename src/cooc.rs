@@ -0,0 +1,243 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::Write;
+
+use failure::{err_msg, Error};
+
+use crate::idx::WordIdx;
+use crate::skipgram_trainer::fixed_context_windows;
+use crate::source::SentenceSource;
+use crate::util::EOS;
+use crate::Vocab;
+
+/// Weighting of co-occurrences by the distance between two words.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CoocWeighting {
+    /// Every co-occurrence has the weight *1*.
+    Uniform,
+
+    /// A co-occurrence at distance *d* has the weight *1 / d*.
+    ///
+    /// This is the weighting that is used by GloVe (Pennington et al., 2014).
+    Harmonic,
+}
+
+impl CoocWeighting {
+    pub fn try_from_str(weighting: &str) -> Result<CoocWeighting, Error> {
+        match weighting {
+            "harmonic" => Ok(CoocWeighting::Harmonic),
+            "uniform" => Ok(CoocWeighting::Uniform),
+            _ => Err(err_msg(format!(
+                "Unknown co-occurrence weighting: {}",
+                weighting
+            ))),
+        }
+    }
+
+    /// Get the weight of a co-occurrence at `distance`.
+    pub fn weight(self, distance: usize) -> f32 {
+        match self {
+            CoocWeighting::Uniform => 1.,
+            CoocWeighting::Harmonic => 1. / distance as f32,
+        }
+    }
+}
+
+/// Sparse matrix of weighted word co-occurrences.
+///
+/// Two words co-occur when they are at most `context_size` tokens apart
+/// in a sentence. Co-occurrences are symmetric, every co-occurrence of
+/// two words is counted for both orders. As during training, context
+/// windows never cross the end of a sentence and unknown tokens hold
+/// their position, but do not co-occur with other words.
+///
+/// In contrast to training, the window size is fixed and tokens are not
+/// subsampled, so that the counts are deterministic.
+#[derive(Clone, Debug)]
+pub struct Cooccurrences {
+    context_size: usize,
+    weighting: CoocWeighting,
+    counts: HashMap<(usize, usize), f32>,
+}
+
+impl Cooccurrences {
+    /// Construct an empty co-occurrence matrix.
+    pub fn new(context_size: usize, weighting: CoocWeighting) -> Self {
+        Cooccurrences {
+            context_size,
+            weighting,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Count the co-occurrences in a sentence.
+    ///
+    /// The sentence should include the end-of-sentence marker, if it
+    /// should co-occur with the last words of the sentence.
+    pub fn count_sentence<S, V>(&mut self, vocab: &V, sentence: &[S])
+    where
+        S: Hash + Eq,
+        V: Vocab,
+        V::VocabType: Borrow<S>,
+    {
+        let ids = sentence
+            .iter()
+            .map(|token| vocab.idx(token).map(|idx| idx.word_idx() as usize))
+            .collect::<Vec<_>>();
+
+        // Windows are symmetric, so every co-occurrence is counted for
+        // both orders.
+        for (i, contexts) in fixed_context_windows(&ids, &[], self.context_size) {
+            let focus = ids[i].unwrap();
+            for j in contexts {
+                let weight = self.weighting.weight(i.max(j) - i.min(j));
+                *self.counts.entry((focus, ids[j].unwrap())).or_insert(0.) += weight;
+            }
+        }
+    }
+
+    /// Count the co-occurrences in all sentences of a source.
+    ///
+    /// An end-of-sentence marker is added to each sentence.
+    pub fn count_source<S, V>(&mut self, vocab: &V, source: &S) -> Result<(), crate::Error>
+    where
+        S: SentenceSource + ?Sized,
+        V: Vocab,
+        V::VocabType: Borrow<String>,
+    {
+        for sentence in source.sentences()? {
+            let mut sentence = sentence?;
+            sentence.push(EOS.to_string());
+            self.count_sentence(vocab, &sentence);
+        }
+
+        Ok(())
+    }
+
+    /// Get the weight of the co-occurrences of two words.
+    pub fn get(&self, word_i: usize, word_j: usize) -> f32 {
+        self.counts.get(&(word_i, word_j)).cloned().unwrap_or(0.)
+    }
+
+    /// Returns `true` if no words co-occur.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    /// Get the number of co-occurring word pairs.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Get the weighted co-occurrences as `(word_i, word_j, weight)` triples.
+    ///
+    /// The triples are sorted by the word indices.
+    pub fn triples(&self) -> Vec<(usize, usize, f32)> {
+        let mut triples = self
+            .counts
+            .iter()
+            .map(|(&(word_i, word_j), &weight)| (word_i, word_j, weight))
+            .collect::<Vec<_>>();
+        triples.sort_unstable_by_key(|&(word_i, word_j, _)| (word_i, word_j));
+        triples
+    }
+
+    /// Write the co-occurrences as text.
+    ///
+    /// Every line contains a `(word_i, word_j, weight)` triple, separated
+    /// by tabs.
+    pub fn write_triples<V, W>(&self, write: &mut W, vocab: &V) -> Result<(), Error>
+    where
+        V: Vocab,
+        V::VocabType: ToString,
+        W: Write,
+    {
+        let types = vocab.types();
+        for (word_i, word_j, weight) in self.triples() {
+            writeln!(
+                write,
+                "{}\t{}\t{}",
+                types[word_i].label().to_string(),
+                types[word_j].label().to_string(),
+                weight
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CoocWeighting, Cooccurrences};
+    use crate::idx::WordIdx;
+    use crate::util::numeric::close;
+    use crate::util::EOS;
+    use crate::{SimpleVocab, SimpleVocabConfig, Vocab, VocabBuilder};
+
+    fn test_vocab() -> SimpleVocab<String> {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 2,
+            discard_threshold: 1.,
+        });
+        for token in &["a", "a", "b", "b", "c", "c", "d", EOS, EOS] {
+            builder.count(token.to_string());
+        }
+        builder.into()
+    }
+
+    fn sentence(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn cooccurrences_match_hand_counts() {
+        let vocab = test_vocab();
+        let idx = |word: &str| vocab.idx(word).unwrap().word_idx() as usize;
+
+        // "d" is unknown, it holds its position.
+        let mut cooc = Cooccurrences::new(2, CoocWeighting::Harmonic);
+        cooc.count_sentence(&vocab, &sentence(&["a", "b", "d", "a", EOS]));
+        cooc.count_sentence(&vocab, &sentence(&["c", "a", EOS]));
+
+        // a-b: 1 (distance 1) + 1/2 (distance 2).
+        assert!(close(cooc.get(idx("a"), idx("b")), 1.5, 1e-6));
+        assert!(close(cooc.get(idx("b"), idx("a")), 1.5, 1e-6));
+        // The two occurrences of "a" in the first sentence are too far apart.
+        assert_eq!(cooc.get(idx("a"), idx("a")), 0.);
+        // a-</s>: 1 + 1.
+        assert!(close(cooc.get(idx("a"), idx(EOS)), 2., 1e-6));
+        // c-a and c-</s>.
+        assert!(close(cooc.get(idx("c"), idx("a")), 1., 1e-6));
+        assert!(close(cooc.get(idx("c"), idx(EOS)), 0.5, 1e-6));
+        assert_eq!(cooc.get(idx("b"), idx(EOS)), 0.);
+        assert_eq!(cooc.get(idx("b"), idx("c")), 0.);
+        assert_eq!(cooc.len(), 8);
+
+        let mut uniform = Cooccurrences::new(2, CoocWeighting::Uniform);
+        uniform.count_sentence(&vocab, &sentence(&["a", "b", "d", "a", EOS]));
+        assert_eq!(uniform.get(idx("a"), idx("b")), 2.);
+    }
+
+    #[test]
+    fn cooccurrence_triples_are_sorted() {
+        let vocab = test_vocab();
+        let mut cooc = Cooccurrences::new(1, CoocWeighting::Uniform);
+        cooc.count_sentence(&vocab, &sentence(&["b", "a"]));
+
+        let mut data = Vec::new();
+        cooc.write_triples(&mut data, &vocab).unwrap();
+        let mut lines = String::from_utf8(data)
+            .unwrap()
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        lines.sort();
+        assert_eq!(lines, vec!["a\tb\t1", "b\ta\t1"]);
+
+        let triples = cooc.triples();
+        assert!(triples[0].0 < triples[1].0);
+    }
+}
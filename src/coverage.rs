@@ -0,0 +1,215 @@
+use std::borrow::Borrow;
+use std::collections::HashSet;
+
+use finalfusion::subword::Indexer;
+
+use crate::{SubwordVocab, Vocab};
+
+/// Coverage of a reference word list by a vocabulary.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Coverage {
+    /// Number of words in the reference list.
+    pub n_words: usize,
+
+    /// Number of reference words that are in the vocabulary.
+    pub n_in_vocab: usize,
+
+    /// Number of out-of-vocabulary reference words with trained subword
+    /// indices.
+    ///
+    /// This is `None` for vocabularies without subword units.
+    pub n_oov_with_subwords: Option<usize>,
+}
+
+impl Coverage {
+    /// Compute the coverage of `words` by a vocabulary.
+    ///
+    /// This does not take subword units into account, see `Coverage::subwords`
+    /// for subword vocabularies.
+    pub fn new<V, S>(vocab: &V, words: impl IntoIterator<Item = S>) -> Self
+    where
+        V: Vocab,
+        V::VocabType: Borrow<str>,
+        S: AsRef<str>,
+    {
+        let mut coverage = Coverage {
+            n_words: 0,
+            n_in_vocab: 0,
+            n_oov_with_subwords: None,
+        };
+
+        for word in words {
+            coverage.n_words += 1;
+            if vocab.idx(word.as_ref()).is_some() {
+                coverage.n_in_vocab += 1;
+            }
+        }
+
+        coverage
+    }
+
+    /// Compute the coverage of `words` by a subword vocabulary.
+    ///
+    /// Out-of-vocabulary words are counted as having a subword
+    /// representation when at least one of their n-grams has an index that
+    /// is used by the n-grams of a vocabulary word. The embeddings of other
+    /// indices, such as buckets that no vocabulary word maps to, are never
+    /// trained.
+    pub fn subwords<C, I, S>(vocab: &SubwordVocab<C, I>, words: impl IntoIterator<Item = S>) -> Self
+    where
+        C: Copy + Clone,
+        I: Indexer,
+        S: AsRef<str>,
+    {
+        let used_indices = vocab
+            .types()
+            .iter()
+            .flat_map(|word| vocab.ngram_indices(word.label()))
+            .map(|(_, idx)| idx)
+            .collect::<HashSet<_>>();
+
+        let mut coverage = Coverage {
+            n_words: 0,
+            n_in_vocab: 0,
+            n_oov_with_subwords: Some(0),
+        };
+
+        for word in words {
+            let word = word.as_ref();
+            coverage.n_words += 1;
+            if vocab.idx(word).is_some() {
+                coverage.n_in_vocab += 1;
+            } else if vocab
+                .ngram_indices(word)
+                .iter()
+                .any(|(_, idx)| used_indices.contains(idx))
+            {
+                coverage.n_oov_with_subwords = coverage.n_oov_with_subwords.map(|n| n + 1);
+            }
+        }
+
+        coverage
+    }
+
+    /// Number of out-of-vocabulary reference words.
+    pub fn n_oov(&self) -> usize {
+        self.n_words - self.n_in_vocab
+    }
+
+    /// Fraction of the reference words that are in the vocabulary.
+    pub fn in_vocab_fraction(&self) -> f64 {
+        fraction(self.n_in_vocab, self.n_words)
+    }
+
+    /// Fraction of the out-of-vocabulary words with trained subword indices.
+    pub fn oov_subword_fraction(&self) -> Option<f64> {
+        self.n_oov_with_subwords
+            .map(|n_subwords| fraction(n_subwords, self.n_oov()))
+    }
+}
+
+fn fraction(n: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.
+    } else {
+        n as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+
+    use super::Coverage;
+    use crate::util::numeric::close;
+    use crate::{
+        BucketConfig, NGramConfig, SimpleVocab, SimpleVocabConfig, SubwordHash, SubwordVocab,
+        SubwordVocabConfig, VocabBuilder,
+    };
+
+    static CORPUS: &[&str] = &["the", "the", "cat", "cat", "sat", "sat", "dog"];
+
+    static REFERENCE: &[&str] = &["the", "cat", "dog", "cats", "zz"];
+
+    #[test]
+    fn simple_vocab_coverage() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 2,
+            discard_threshold: 1e-4,
+        });
+        CORPUS.iter().for_each(|&token| builder.count(token));
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let coverage = Coverage::new(&vocab, REFERENCE);
+        assert_eq!(coverage.n_words, 5);
+        assert_eq!(coverage.n_in_vocab, 2);
+        assert!(close(coverage.in_vocab_fraction() as f32, 0.4, 1e-6));
+        assert_eq!(coverage.oov_subword_fraction(), None);
+    }
+
+    fn bucket_vocab(buckets_exp: u32) -> SubwordVocab<BucketConfig, FinalfusionHashIndexer> {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SubwordVocabConfig {
+            discard_threshold: 1e-4,
+            min_count: 2,
+            max_n: 6,
+            min_n: 3,
+            indexer: BucketConfig {
+                buckets_exp,
+                hash: SubwordHash::FinalFusion,
+            },
+        });
+        CORPUS.iter().for_each(|&token| builder.count(token));
+        builder.into()
+    }
+
+    #[test]
+    fn bucket_vocab_coverage() {
+        // With two buckets, the n-grams of the vocabulary use every bucket.
+        let coverage = Coverage::subwords(&bucket_vocab(1), REFERENCE);
+        assert!(close(coverage.in_vocab_fraction() as f32, 0.4, 1e-6));
+        assert_eq!(coverage.n_oov_with_subwords, Some(3));
+        assert!(close(
+            coverage.oov_subword_fraction().unwrap() as f32,
+            1.0,
+            1e-6
+        ));
+    }
+
+    #[test]
+    fn unused_buckets_are_not_covered() {
+        // Most buckets are unused, the n-grams of "dog" and "zz" only map
+        // to buckets that no vocabulary word uses.
+        let coverage = Coverage::subwords(&bucket_vocab(21), REFERENCE);
+        assert_eq!(coverage.n_oov(), 3);
+        assert_eq!(coverage.n_oov_with_subwords, Some(1));
+        assert!(close(
+            coverage.oov_subword_fraction().unwrap() as f32,
+            1. / 3.,
+            1e-6
+        ));
+    }
+
+    #[test]
+    fn ngram_vocab_coverage() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SubwordVocabConfig {
+            discard_threshold: 1e-4,
+            min_count: 2,
+            max_n: 6,
+            min_n: 3,
+            indexer: NGramConfig { min_ngram_count: 2 },
+        });
+        CORPUS.iter().for_each(|&token| builder.count(token));
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+
+        // Only "cats" shares n-grams with the vocabulary ("<ca", "cat", ...),
+        // neither "dog" nor "zz" do.
+        let coverage = Coverage::subwords(&vocab, REFERENCE);
+        assert_eq!(coverage.n_oov(), 3);
+        assert_eq!(coverage.n_oov_with_subwords, Some(1));
+        assert!(close(
+            coverage.oov_subword_fraction().unwrap() as f32,
+            1. / 3.,
+            1e-6
+        ));
+    }
+}
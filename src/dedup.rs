@@ -0,0 +1,162 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+/// Default number of counters of a deduplication filter (as a power of two).
+pub const DEFAULT_DEDUP_COUNTERS_EXP: u32 = 22;
+
+/// Number of counters that a sentence is hashed to.
+const N_HASHES: u64 = 4;
+
+/// Approximate filter of repeated sentences.
+///
+/// The filter counts sentences in a counting Bloom filter (a count-min
+/// sketch): every sentence is hashed to a small number of counters and
+/// the number of occurrences of a sentence is estimated by the smallest
+/// of its counters. A sentence is rejected when it was already seen
+/// `max_repeats` times. The filter uses a fixed amount of memory,
+/// regardless of the number of sentences.
+///
+/// Since different sentences can share counters, the number of
+/// occurrences can be overestimated, so that a small number of unique
+/// sentences may be rejected. The number of occurrences is never
+/// underestimated, repeats beyond the cap are always rejected.
+///
+/// The counters are shared between clones of a filter, so that threads
+/// that read different parts of a corpus detect repeats across the
+/// corpus. Counters are updated without locking, so that concurrent
+/// insertions of the same sentence may exceed the cap by a few repeats.
+///
+/// Deduplication applies per pass over a corpus: in pass *p* (see
+/// `start_pass`), a sentence is accepted until it was accepted
+/// *(p + 1) max_repeats* times, so that the same number of repeats is
+/// kept in every epoch.
+#[derive(Clone, Debug)]
+pub struct SentenceDedup {
+    counters: Arc<Vec<AtomicU32>>,
+    max_repeats: u32,
+    pass: Arc<AtomicU32>,
+}
+
+impl SentenceDedup {
+    /// Construct a filter that accepts `max_repeats` occurrences of a sentence.
+    ///
+    /// The filter has *2^counters_exp* counters.
+    pub fn new(max_repeats: u32, counters_exp: u32) -> Self {
+        assert!(
+            max_repeats > 0,
+            "The maximum number of repeats must be positive"
+        );
+
+        SentenceDedup {
+            counters: Arc::new((0..1 << counters_exp).map(|_| AtomicU32::new(0)).collect()),
+            max_repeats,
+            pass: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Get the current pass over the corpus.
+    pub fn pass(&self) -> u32 {
+        self.pass.load(Ordering::Relaxed)
+    }
+
+    /// Start counting the repeats of a pass over the corpus.
+    ///
+    /// The pass of the filter is only moved forward, so that every
+    /// clone of the filter can start a pass when it notices one.
+    pub fn start_pass(&self, pass: u32) {
+        self.pass.fetch_max(pass, Ordering::Relaxed);
+    }
+
+    /// Count a sentence.
+    ///
+    /// Returns `true` if the sentence should be kept, `false` if it
+    /// occurred `max_repeats` times before in the current pass. Rejected
+    /// sentences are not counted.
+    pub fn insert<S>(&self, sentence: &[S]) -> bool
+    where
+        S: Hash,
+    {
+        let mut hasher = DefaultHasher::new();
+        sentence.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        // Derive the counter indices from two halves of the hash
+        // (Kirsch & Mitzenmacher, 2006).
+        let h1 = hash & 0xffff_ffff;
+        let h2 = (hash >> 32) | 1;
+        let n_counters = self.counters.len() as u64;
+        let mut indices = [0usize; N_HASHES as usize];
+        for (i, index) in indices.iter_mut().enumerate() {
+            *index = (h1.wrapping_add(i as u64 * h2) % n_counters) as usize;
+        }
+
+        let count = indices
+            .iter()
+            .map(|&index| self.counters[index].load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0);
+        if count
+            >= self
+                .max_repeats
+                .saturating_mul(self.pass().saturating_add(1))
+        {
+            return false;
+        }
+
+        // Conservative update: only increment the counters that hold the
+        // estimate, to reduce overestimation.
+        for &index in &indices {
+            let _ = self.counters[index].compare_exchange(
+                count,
+                count + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SentenceDedup;
+
+    #[test]
+    fn repeats_beyond_cap_are_rejected() {
+        let dedup = SentenceDedup::new(2, 10);
+        let first = ["the", "cat"];
+        let second = ["a", "dog"];
+
+        assert!(dedup.insert(&first));
+        assert!(dedup.insert(&second));
+        assert!(dedup.insert(&first));
+        assert!(!dedup.insert(&first));
+        assert!(!dedup.insert(&first));
+        assert!(dedup.insert(&second));
+        assert!(!dedup.insert(&second));
+    }
+
+    #[test]
+    fn repeats_are_counted_per_pass_across_clones() {
+        let dedup = SentenceDedup::new(1, 10);
+        let other = dedup.clone();
+        let sentence = ["the", "cat"];
+
+        assert!(dedup.insert(&sentence));
+        assert!(!other.insert(&sentence));
+
+        // Starting a pass in one clone starts the pass for all clones.
+        other.start_pass(1);
+        assert_eq!(dedup.pass(), 1);
+        assert!(dedup.insert(&sentence));
+        assert!(!other.insert(&sentence));
+
+        // The pass is not moved backward.
+        dedup.start_pass(0);
+        assert_eq!(dedup.pass(), 1);
+        assert!(!dedup.insert(&sentence));
+    }
+}
@@ -7,9 +7,9 @@ use rand::{Rng, SeedableRng};
 use serde::Serialize;
 
 use crate::idx::WordIdx;
-use crate::sampling::ZipfRangeGenerator;
-use crate::train_model::{NegativeSamples, TrainIterFrom};
-use crate::util::ReseedOnCloneRng;
+use crate::sampling::{NegativeRangeGenerator, MAX_NEGATIVE_DRAWS};
+use crate::train_model::{NegativeSamples, SkippedTokens, TrainIterFrom};
+use crate::util::{dropout_mask, ReseedOnCloneRng};
 use crate::{
     CommonConfig, DepembedsConfig, Dependency, DependencyIterator, SimpleVocab, SimpleVocabConfig,
     Trainer, Vocab,
@@ -26,8 +26,9 @@ pub struct DepembedsTrainer<R, V> {
     common_config: CommonConfig,
     input_vocab: Arc<V>,
     output_vocab: Arc<SimpleVocab<Dependency>>,
-    range_gen: ZipfRangeGenerator<R>,
+    range_gen: NegativeRangeGenerator<R>,
     rng: R,
+    skipped: SkippedTokens,
 }
 
 impl<R, V> DepembedsTrainer<R, V> {
@@ -49,10 +50,11 @@ where
         rng: R,
     ) -> Self {
         let rng = ReseedOnCloneRng(rng);
-        let range_gen = ZipfRangeGenerator::new_with_exponent(
+        let range_gen = NegativeRangeGenerator::new(
             rng.clone(),
             output_vocab.len(),
             common_config.zipf_exponent,
+            common_config.sampler,
         );
         DepembedsTrainer {
             common_config,
@@ -61,6 +63,7 @@ where
             output_vocab: Arc::new(output_vocab),
             range_gen,
             rng,
+            skipped: SkippedTokens::default(),
         }
     }
 }
@@ -69,13 +72,20 @@ impl<R, V> NegativeSamples for DepembedsTrainer<R, V>
 where
     R: Rng,
 {
+    fn dropout_mask(&mut self, keep_prob: f32, mask: &mut [bool]) {
+        dropout_mask(&mut self.rng, keep_prob, mask);
+    }
+
     fn negative_sample(&mut self, output: usize) -> usize {
-        loop {
-            let negative = self.range_gen.next().unwrap();
+        let mut negative = self.range_gen.next().unwrap();
+        for _ in 1..MAX_NEGATIVE_DRAWS {
             if negative != output {
-                return negative;
+                break;
             }
+            negative = self.range_gen.next().unwrap();
         }
+
+        negative
     }
 }
 
@@ -93,15 +103,22 @@ where
     fn train_iter_from(&mut self, sentence: &Sentence) -> Self::Iter {
         let invalid_idx = self.input_vocab.len() as u64;
         let mut tokens = vec![WordIdx::from_word_idx(invalid_idx); sentence.len() - 1];
+        let mut skipped = SkippedTokens::default();
         for (idx, token) in sentence.iter().filter_map(|node| node.token()).enumerate() {
-            if let Some(vocab_idx) = self.input_vocab.idx(token.form()) {
-                if self.rng.gen_range(0f32, 1f32)
-                    < self.input_vocab.discard(vocab_idx.word_idx() as usize)
-                {
-                    tokens[idx] = vocab_idx
+            match self.input_vocab.idx(token.form()) {
+                Some(vocab_idx) => {
+                    if self.rng.gen_range(0f32, 1f32)
+                        < self.input_vocab.discard(vocab_idx.word_idx() as usize)
+                    {
+                        tokens[idx] = vocab_idx
+                    } else {
+                        skipped.n_discarded += 1;
+                    }
                 }
+                None => skipped.n_oov += 1,
             }
         }
+        self.skipped = skipped;
 
         let mut contexts = vec![Vec::new(); sentence.len() - 1];
         let graph = sentence.dep_graph();
@@ -122,6 +139,10 @@ where
                 .filter(move |(focus, _)| focus.word_idx() != invalid_idx),
         )
     }
+
+    fn skipped_tokens(&self) -> SkippedTokens {
+        self.skipped
+    }
 }
 
 impl<R, V> Trainer for DepembedsTrainer<R, V>
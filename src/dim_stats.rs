@@ -0,0 +1,104 @@
+use std::fmt;
+
+use ndarray::ArrayView2;
+
+/// Per-dimension statistics of an embedding matrix.
+///
+/// The statistics are the mean and the (population) variance of every
+/// column of the matrix. Dimensions with a variance close to zero or a
+/// very large variance point to degenerate dimensions, e.g. dimensions
+/// that were never trained or whose values exploded.
+///
+/// The statistics are computed in a single pass over the rows, using
+/// Welford's online algorithm.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DimensionStats {
+    n_rows: usize,
+    means: Vec<f32>,
+    variances: Vec<f32>,
+}
+
+impl DimensionStats {
+    /// Compute the statistics of the columns of `matrix`.
+    pub fn from_matrix(matrix: ArrayView2<f32>) -> Self {
+        let mut means = vec![0f64; matrix.ncols()];
+        let mut sq_diffs = vec![0f64; matrix.ncols()];
+
+        for (idx, row) in matrix.outer_iter().enumerate() {
+            let n = (idx + 1) as f64;
+            for ((&v, mean), sq_diff) in row.iter().zip(&mut means).zip(&mut sq_diffs) {
+                let v = v as f64;
+                let delta = v - *mean;
+                *mean += delta / n;
+                *sq_diff += delta * (v - *mean);
+            }
+        }
+
+        let n_rows = matrix.nrows();
+        let variances = sq_diffs
+            .iter()
+            .map(|&sq_diff| (sq_diff / n_rows.max(1) as f64) as f32)
+            .collect();
+
+        DimensionStats {
+            n_rows,
+            means: means.into_iter().map(|mean| mean as f32).collect(),
+            variances,
+        }
+    }
+
+    /// Get the mean of every dimension.
+    pub fn means(&self) -> &[f32] {
+        &self.means
+    }
+
+    /// Get the number of rows that the statistics were computed from.
+    pub fn n_rows(&self) -> usize {
+        self.n_rows
+    }
+
+    /// Get the variance of every dimension.
+    pub fn variances(&self) -> &[f32] {
+        &self.variances
+    }
+}
+
+impl fmt::Display for DimensionStats {
+    /// Format the statistics as a table with a dimension per line.
+    ///
+    /// Every line contains the dimension, its mean and its variance,
+    /// separated by tabs.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (dim, (mean, variance)) in self.means.iter().zip(&self.variances).enumerate() {
+            writeln!(f, "{}\t{:e}\t{:e}", dim, mean, variance)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::arr2;
+
+    use super::DimensionStats;
+    use crate::util::numeric::close;
+
+    #[test]
+    fn dimension_stats_of_hand_matrix() {
+        let matrix = arr2(&[[1., 0., -2.], [3., 0., 2.], [5., 0., 0.]]);
+        let stats = DimensionStats::from_matrix(matrix.view());
+
+        assert_eq!(stats.n_rows(), 3);
+        assert!(close(stats.means()[0], 3., 1e-6));
+        assert!(close(stats.variances()[0], 8. / 3., 1e-6));
+        assert_eq!(stats.means()[1], 0.);
+        assert_eq!(stats.variances()[1], 0.);
+        assert!(close(stats.means()[2], 0., 1e-6));
+        assert!(close(stats.variances()[2], 8. / 3., 1e-6));
+
+        let report = stats.to_string();
+        assert_eq!(report.lines().count(), 3);
+        assert_eq!(report.lines().nth(1), Some("1\t0e0\t0e0"));
+    }
+}
@@ -0,0 +1,147 @@
+use std::cmp::Ordering;
+
+use ndarray::ArrayView1;
+
+use crate::vec_simd::dot;
+
+/// Displacement of the embedding of a token during training.
+///
+/// The displacement is the Euclidean distance between the embedding
+/// after training and the embedding before training.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenDisplacement {
+    token: String,
+    init_norm: f32,
+    norm: f32,
+    displacement: f32,
+}
+
+impl TokenDisplacement {
+    /// Construct the displacement of a token's embedding.
+    ///
+    /// `init_embed` and `embed` are the embeddings of the token before
+    /// and after training.
+    pub fn new(
+        token: impl Into<String>,
+        init_embed: ArrayView1<f32>,
+        embed: ArrayView1<f32>,
+    ) -> Self {
+        let diff = &embed - &init_embed;
+        TokenDisplacement {
+            token: token.into(),
+            init_norm: dot(init_embed, init_embed).sqrt(),
+            norm: dot(embed, embed).sqrt(),
+            displacement: dot(diff.view(), diff.view()).sqrt(),
+        }
+    }
+
+    /// Get the displacement.
+    pub fn displacement(&self) -> f32 {
+        self.displacement
+    }
+
+    /// Get the norm of the embedding before training.
+    pub fn init_norm(&self) -> f32 {
+        self.init_norm
+    }
+
+    /// Get the norm of the embedding after training.
+    pub fn norm(&self) -> f32 {
+        self.norm
+    }
+
+    /// Get the token.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+/// Displacements of token embeddings, ranked from most to least moved.
+///
+/// Tokens that did not move were never updated, e.g. because they were
+/// discarded by subsampling. Computing the displacements requires a copy
+/// of the word embeddings before training.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Displacements {
+    ranked: Vec<TokenDisplacement>,
+}
+
+impl Displacements {
+    /// Rank the displacements of tokens.
+    ///
+    /// Ties are kept in the order of `displacements`.
+    pub fn new(mut displacements: Vec<TokenDisplacement>) -> Self {
+        displacements.sort_by(|d1, d2| {
+            d2.displacement()
+                .partial_cmp(&d1.displacement())
+                .unwrap_or(Ordering::Equal)
+        });
+
+        Displacements {
+            ranked: displacements,
+        }
+    }
+
+    /// Get the `k` tokens that moved the least, least moved first.
+    pub fn least_moved(&self, k: usize) -> impl Iterator<Item = &TokenDisplacement> {
+        self.ranked.iter().rev().take(k)
+    }
+
+    /// Get the `k` tokens that moved the most, most moved first.
+    pub fn most_moved(&self, k: usize) -> &[TokenDisplacement] {
+        &self.ranked[..k.min(self.ranked.len())]
+    }
+
+    /// Get the displacements of all tokens, most moved first.
+    pub fn ranked(&self) -> &[TokenDisplacement] {
+        &self.ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr1, Array1};
+
+    use super::{Displacements, TokenDisplacement};
+    use crate::util::numeric::close;
+
+    fn displacement(token: &str, init_embed: &[f32], embed: &[f32]) -> TokenDisplacement {
+        TokenDisplacement::new(token, arr1(init_embed).view(), arr1(embed).view())
+    }
+
+    #[test]
+    fn displacement_is_distance_between_embeddings() {
+        // The norm does not change when the embedding rotates.
+        let rotated = displacement("a", &[1., 0.], &[0., 1.]);
+        assert_eq!(rotated.init_norm(), 1.);
+        assert_eq!(rotated.norm(), 1.);
+        assert!(close(rotated.displacement(), 2f32.sqrt(), 1e-6));
+
+        let unchanged = Array1::from(vec![3., 4.]);
+        let unchanged = TokenDisplacement::new("b", unchanged.view(), unchanged.view());
+        assert_eq!(unchanged.norm(), 5.);
+        assert_eq!(unchanged.displacement(), 0.);
+    }
+
+    #[test]
+    fn displacements_are_ranked() {
+        let displacements = Displacements::new(vec![
+            displacement("a", &[1., 0.], &[1.5, 0.]),
+            displacement("b", &[1., 0.], &[1., 0.]),
+            displacement("c", &[2., 0.], &[0.5, 0.]),
+        ]);
+
+        let most_moved = displacements
+            .most_moved(2)
+            .iter()
+            .map(TokenDisplacement::token)
+            .collect::<Vec<_>>();
+        assert_eq!(most_moved, ["c", "a"]);
+        let least_moved = displacements
+            .least_moved(5)
+            .map(TokenDisplacement::token)
+            .collect::<Vec<_>>();
+        assert_eq!(least_moved, ["b", "a", "c"]);
+        assert_eq!(displacements.most_moved(5).len(), 3);
+    }
+}
@@ -0,0 +1,75 @@
+/// Early stopping on a validation loss.
+///
+/// Training is stopped when the validation loss did not improve by at
+/// least `min_delta` for `patience` consecutive epochs. The improvement
+/// is relative to the best validation loss so far, where only losses that
+/// improved by at least `min_delta` count as the best loss. So, slow
+/// improvements that add up to `min_delta` reset the patience.
+#[derive(Clone, Copy, Debug)]
+pub struct EarlyStopping {
+    min_delta: f32,
+    patience: usize,
+    best_loss: f32,
+    n_epochs_without_improvement: usize,
+}
+
+impl EarlyStopping {
+    /// Construct an early stopping guard.
+    pub fn new(min_delta: f32, patience: usize) -> Self {
+        assert!(patience > 0, "Patience should be at least one epoch");
+
+        EarlyStopping {
+            min_delta,
+            patience,
+            best_loss: f32::INFINITY,
+            n_epochs_without_improvement: 0,
+        }
+    }
+
+    /// Get the best validation loss so far.
+    ///
+    /// This is the last loss that improved by at least `min_delta`.
+    pub fn best_loss(&self) -> f32 {
+        self.best_loss
+    }
+
+    /// Record the validation loss after an epoch.
+    ///
+    /// Returns `true` when training should be stopped.
+    pub fn update(&mut self, validation_loss: f32) -> bool {
+        if validation_loss < self.best_loss - self.min_delta {
+            self.best_loss = validation_loss;
+            self.n_epochs_without_improvement = 0;
+        } else {
+            self.n_epochs_without_improvement += 1;
+        }
+
+        self.n_epochs_without_improvement >= self.patience
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EarlyStopping;
+
+    #[test]
+    fn stops_after_patience_epochs() {
+        let mut early_stopping = EarlyStopping::new(0.01, 2);
+        assert!(!early_stopping.update(1.0));
+        assert!(!early_stopping.update(0.5));
+        // Improvements smaller than min_delta do not count.
+        assert!(!early_stopping.update(0.495));
+        assert!(early_stopping.update(0.492));
+        assert_eq!(early_stopping.best_loss(), 0.5);
+    }
+
+    #[test]
+    fn improvement_resets_patience() {
+        let mut early_stopping = EarlyStopping::new(0.01, 2);
+        assert!(!early_stopping.update(1.0));
+        assert!(!early_stopping.update(1.0));
+        assert!(!early_stopping.update(0.8));
+        assert!(!early_stopping.update(0.8));
+        assert!(early_stopping.update(0.9));
+    }
+}
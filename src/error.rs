@@ -0,0 +1,112 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use failure::Fail;
+
+/// finalfrontier errors.
+///
+/// The entry points for reading inputs and training return this error:
+/// sentence sources (`SentenceSource`), the `read_*` functions of inputs
+/// such as token weights, vocabulary construction from a source
+/// (`build_vocab`), and training from a source (`SGD::train_source` and
+/// its variants). Other functions of this crate return `failure::Error`.
+/// Errors of this type that are wrapped in a `failure::Error` can be
+/// recovered with `failure::Error::downcast_ref`.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading a file failed.
+    Io {
+        /// Path of the file.
+        path: PathBuf,
+
+        /// Line number (1-based), if the error occurred on a specific line.
+        line: Option<usize>,
+
+        /// The underlying I/O error.
+        error: io::Error,
+    },
+
+    /// Reading an input that is not a file failed.
+    Read(io::Error),
+
+    /// Invalid configuration value.
+    Config(String),
+
+    /// An input is malformed, e.g. a line of a token weights file.
+    Format(String),
+
+    /// A word is not in the vocabulary.
+    Vocab(String),
+
+    /// Training was cancelled.
+    Cancelled,
+}
+
+impl Error {
+    /// Construct an I/O error for the file at `path`.
+    pub(crate) fn io(path: impl Into<PathBuf>, error: io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            line: None,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io {
+                path,
+                line: Some(line),
+                error,
+            } => write!(
+                f,
+                "Cannot read {}, line {}: {}",
+                path.display(),
+                line,
+                error
+            ),
+            Error::Io {
+                path,
+                line: None,
+                error,
+            } => write!(f, "Cannot read {}: {}", path.display(), error),
+            Error::Read(error) => write!(f, "Cannot read input: {}", error),
+            Error::Config(msg) => write!(f, "{}", msg),
+            Error::Format(msg) => write!(f, "{}", msg),
+            Error::Vocab(word) => write!(f, "Unknown word: {}", word),
+            Error::Cancelled => write!(f, "Training was cancelled"),
+        }
+    }
+}
+
+impl Fail for Error {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match self {
+            Error::Io { error, .. } | Error::Read(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::Error;
+
+    #[test]
+    fn io_error_display_includes_path_and_line() {
+        let err = Error::Io {
+            path: "corpus.txt".into(),
+            line: Some(3),
+            error: io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-8"),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Cannot read corpus.txt, line 3: invalid UTF-8"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+
+/// Upper bounds (exclusive) of all but the last histogram bucket.
+static BUCKET_BOUNDS: [f32; 7] = [1e-5, 1e-4, 1e-3, 1e-2, 1e-1, 1e0, 1e1];
+
+/// Number of histogram buckets.
+const N_BUCKETS: usize = 8;
+
+/// Histogram of gradient norms.
+///
+/// The histogram has fixed buckets, one per order of magnitude between
+/// *1e-5* and *1e1*. Smaller norms are counted in the first bucket;
+/// larger norms and non-finite norms are counted in the last bucket.
+///
+/// A histogram is not shared between threads. Every training thread
+/// collects its own histogram. The histograms are combined with `merge`
+/// after training.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GradientHistogram {
+    counts: [u64; N_BUCKETS],
+}
+
+impl GradientHistogram {
+    /// Construct an empty histogram.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a gradient norm.
+    pub fn add(&mut self, norm: f32) {
+        let bucket = if norm.is_finite() {
+            BUCKET_BOUNDS
+                .iter()
+                .take_while(|&&bound| norm >= bound)
+                .count()
+        } else {
+            N_BUCKETS - 1
+        };
+
+        self.counts[bucket] += 1;
+    }
+
+    /// Get the lower and upper bound of a bucket.
+    pub fn bucket_bounds(bucket: usize) -> (f32, f32) {
+        assert!(bucket < N_BUCKETS, "Bucket index out of bounds");
+
+        let lower = if bucket == 0 {
+            0.
+        } else {
+            BUCKET_BOUNDS[bucket - 1]
+        };
+        let upper = BUCKET_BOUNDS.get(bucket).cloned().unwrap_or(f32::INFINITY);
+
+        (lower, upper)
+    }
+
+    /// Get the number of gradients in each bucket.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Add the counts of another histogram.
+    pub fn merge(&mut self, other: &GradientHistogram) {
+        for (count, &other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+    }
+
+    /// Get the total number of gradients in the histogram.
+    pub fn n_gradients(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+impl fmt::Display for GradientHistogram {
+    /// Format the histogram as a table with a bucket per line.
+    ///
+    /// Every line contains the bucket range, the number of gradients in
+    /// the bucket and the percentage of all gradients, separated by tabs.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.n_gradients().max(1) as f64;
+        for (bucket, &count) in self.counts.iter().enumerate() {
+            let (lower, upper) = Self::bucket_bounds(bucket);
+            let lower = if lower == 0. {
+                "0".to_string()
+            } else {
+                format!("{:e}", lower)
+            };
+            writeln!(
+                f,
+                "[{}, {:e})\t{}\t{:.2}%",
+                lower,
+                upper,
+                count,
+                count as f64 / total * 100.
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GradientHistogram;
+
+    #[test]
+    fn gradients_land_in_magnitude_buckets() {
+        let mut histogram = GradientHistogram::new();
+        for &norm in &[0., 5e-6, 1e-5, 0.003, 0.005, 0.5, 1., 42., f32::NAN] {
+            histogram.add(norm);
+        }
+
+        assert_eq!(histogram.counts(), &[2, 1, 0, 2, 0, 1, 1, 2]);
+        assert_eq!(histogram.n_gradients(), 9);
+        assert_eq!(GradientHistogram::bucket_bounds(3), (1e-3, 1e-2));
+        assert_eq!(GradientHistogram::bucket_bounds(0), (0., 1e-5));
+        assert_eq!(GradientHistogram::bucket_bounds(7), (1e1, f32::INFINITY));
+    }
+
+    #[test]
+    fn merged_histogram_sums_counts() {
+        let mut histogram = GradientHistogram::new();
+        histogram.add(0.5);
+        let mut other = GradientHistogram::new();
+        other.add(0.5);
+        other.add(2.);
+
+        histogram.merge(&other);
+        assert_eq!(histogram.counts(), &[0, 0, 0, 0, 0, 2, 1, 0]);
+
+        let report = histogram.to_string();
+        assert_eq!(report.lines().count(), 8);
+        assert_eq!(report.lines().next(), Some("[0, 1e-5)\t0\t0.00%"));
+        assert_eq!(report.lines().nth(5), Some("[1e-1, 1e0)\t2\t66.67%"));
+    }
+}
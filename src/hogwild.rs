@@ -1,9 +1,54 @@
 use std::cell::UnsafeCell;
+use std::fs::File;
+use std::io;
+use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
+use memmap::{MmapMut, MmapOptions};
 use ndarray::{Array, ArrayView, ArrayViewMut, Axis, Dimension, Ix, Ix2, RemoveAxis};
 
+/// Storage of a Hogwild array.
+///
+/// The array is either stored in anonymous memory or in a memory-mapped
+/// file. Views of the storage do not copy the data of a memory-mapped
+/// array.
+pub struct HogwildStorage<A, D>(StorageInner<A, D>);
+
+enum StorageInner<A, D> {
+    /// Array in anonymous memory.
+    Array(Array<A, D>),
+
+    /// Array in a memory-mapped file, with the given shape.
+    Mmap(MmapMut, D),
+}
+
+impl<A, D> HogwildStorage<A, D>
+where
+    D: Dimension,
+{
+    /// Get an immutable view of the storage.
+    pub fn view(&self) -> ArrayView<'_, A, D> {
+        match &self.0 {
+            StorageInner::Array(array) => array.view(),
+            StorageInner::Mmap(mmap, dim) => unsafe {
+                ArrayView::from_shape_ptr(dim.clone(), mmap.as_ptr() as *const A)
+            },
+        }
+    }
+
+    /// Get a mutable view of the storage.
+    pub fn view_mut(&mut self) -> ArrayViewMut<'_, A, D> {
+        match &mut self.0 {
+            StorageInner::Array(array) => array.view_mut(),
+            StorageInner::Mmap(mmap, dim) => unsafe {
+                ArrayViewMut::from_shape_ptr(dim.clone(), mmap.as_mut_ptr() as *mut A)
+            },
+        }
+    }
+}
+
 /// Array for Hogwild parallel optimization.
 ///
 /// This array type can be used for the Hogwild (Niu, et al. 2011) method
@@ -19,25 +64,42 @@ use ndarray::{Array, ArrayView, ArrayViewMut, Axis, Dimension, Ix, Ix2, RemoveAx
 /// `HogwildArray` instances. Views of the underling `Array` can be borrowed
 /// mutably from each instance, without mutual exclusion between mutable
 /// borrows in different `HogwildArray` instances.
+///
+/// The array is normally stored in anonymous memory. An array can also be
+/// stored in a memory-mapped file (see `HogwildArray::mmap_zeros`), so
+/// that the operating system can page out parts of the array.
 #[derive(Clone)]
-pub struct HogwildArray<A, D>(Arc<UnsafeCell<Array<A, D>>>);
+pub struct HogwildArray<A, D>(Arc<UnsafeCell<HogwildStorage<A, D>>>);
 
-impl<A, D> HogwildArray<A, D> {
+impl<A, D> HogwildArray<A, D>
+where
+    D: Dimension,
+{
     #[inline]
-    fn as_mut(&mut self) -> &mut Array<A, D> {
+    fn as_mut(&mut self) -> ArrayViewMut<'_, A, D> {
         let ptr = self.0.as_ref().get();
-        unsafe { &mut *ptr }
+        unsafe { (*ptr).view_mut() }
     }
 
     #[inline]
-    fn as_ref(&self) -> &Array<A, D> {
+    fn as_ref(&self) -> ArrayView<'_, A, D> {
         let ptr = self.0.as_ref().get();
-        unsafe { &*ptr }
+        unsafe { (*ptr).view() }
     }
+}
 
-    pub fn into_inner(self) -> Arc<UnsafeCell<Array<A, D>>> {
+impl<A, D> HogwildArray<A, D> {
+    pub fn into_inner(self) -> Arc<UnsafeCell<HogwildStorage<A, D>>> {
         self.0
     }
+
+    /// Get a read-only handle to the Hogwild array.
+    ///
+    /// The handle shares the array with this instance, see
+    /// `ReadOnlyHogwildArray`.
+    pub fn freeze(&self) -> ReadOnlyHogwildArray<A, D> {
+        ReadOnlyHogwildArray(HogwildArray(self.0.clone()))
+    }
 }
 
 impl<A, D> HogwildArray<A, D>
@@ -47,13 +109,13 @@ where
     /// Get an immutable subview of the Hogwild array.
     #[inline]
     pub fn subview(&self, axis: Axis, index: Ix) -> ArrayView<A, D::Smaller> {
-        self.as_ref().index_axis(axis, index)
+        self.as_ref().index_axis_move(axis, index)
     }
 
     /// Get a mutable subview of the Hogwild array.
     #[inline]
     pub fn subview_mut(&mut self, axis: Axis, index: Ix) -> ArrayViewMut<A, D::Smaller> {
-        self.as_mut().index_axis_mut(axis, index)
+        self.as_mut().index_axis_move(axis, index)
     }
 }
 
@@ -64,13 +126,53 @@ where
     /// Get an immutable view of the Hogwild array.
     #[inline]
     pub fn view(&self) -> ArrayView<A, D> {
-        self.as_ref().view()
+        self.as_ref()
+    }
+
+    /// Get a mutable view of the Hogwild array.
+    #[inline]
+    pub fn view_mut(&mut self) -> ArrayViewMut<'_, A, D> {
+        self.as_mut()
+    }
+}
+
+impl<D> HogwildArray<f32, D>
+where
+    D: Dimension,
+{
+    /// Construct a zero-initialized array in a memory-mapped file.
+    ///
+    /// The file must be opened for reading and writing. Its data is
+    /// overwritten and its size is set to the size of the array. The array
+    /// is shared between clones like an array in anonymous memory. The file
+    /// is only used as backing storage: it holds the raw array data without
+    /// a header and it is not removed when the array is dropped.
+    ///
+    /// Since the operating system can page out parts of the array, the
+    /// array can be larger than the available memory. However, updates of
+    /// rows that are paged out require disk reads and writes, so training
+    /// is much slower when the array does not fit in the page cache.
+    /// Preferably, the file is on fast local storage.
+    pub fn mmap_zeros(file: &File, dim: D) -> io::Result<Self> {
+        // Memory maps cannot be empty.
+        let n_bytes = (dim.size() * mem::size_of::<f32>()).max(1);
+
+        // The file is filled with zero bytes, which encode 0.0 in IEEE 754.
+        file.set_len(0)?;
+        file.set_len(n_bytes as u64)?;
+        let mmap = unsafe { MmapOptions::new().len(n_bytes).map_mut(file)? };
+
+        Ok(HogwildArray(Arc::new(UnsafeCell::new(HogwildStorage(
+            StorageInner::Mmap(mmap, dim),
+        )))))
     }
 }
 
 impl<A, D> From<Array<A, D>> for HogwildArray<A, D> {
     fn from(a: Array<A, D>) -> Self {
-        HogwildArray(Arc::new(UnsafeCell::new(a)))
+        HogwildArray(Arc::new(UnsafeCell::new(HogwildStorage(
+            StorageInner::Array(a),
+        ))))
     }
 }
 
@@ -81,6 +183,67 @@ unsafe impl<A, D> Sync for HogwildArray<A, D> {}
 /// Two-dimensional Hogwild array.
 pub type HogwildArray2<A> = HogwildArray<A, Ix2>;
 
+/// Read-only handle to a Hogwild array.
+///
+/// This handle shares the array with the `HogwildArray` that it was
+/// obtained from (see `HogwildArray::freeze`), but only provides
+/// immutable views. It can be handed to consumers that should not modify
+/// the parameters, e.g. for evaluation or checkpointing while training
+/// continues in other threads. Note that the handle does not stop
+/// training threads from modifying the array, so values can still change
+/// while they are read.
+///
+/// Read operations are available:
+///
+/// ```
+/// use finalfrontier::ReadOnlyHogwildArray;
+/// use ndarray::Array2;
+///
+/// let frozen: ReadOnlyHogwildArray<f32, _> = Array2::<f32>::zeros((2, 3)).into();
+/// assert_eq!(frozen.view().shape(), &[2, 3]);
+/// assert_eq!(frozen.clone().view()[(1, 2)], 0.);
+/// ```
+///
+/// But the array cannot be modified:
+///
+/// ```compile_fail
+/// use finalfrontier::ReadOnlyHogwildArray;
+/// use ndarray::Array2;
+///
+/// let mut frozen: ReadOnlyHogwildArray<f32, _> = Array2::<f32>::zeros((2, 3)).into();
+/// frozen.view_mut()[(1, 2)] = 1.;
+/// ```
+#[derive(Clone)]
+pub struct ReadOnlyHogwildArray<A, D>(HogwildArray<A, D>);
+
+impl<A, D> ReadOnlyHogwildArray<A, D>
+where
+    D: Dimension + RemoveAxis,
+{
+    /// Get an immutable subview of the array.
+    #[inline]
+    pub fn subview(&self, axis: Axis, index: Ix) -> ArrayView<'_, A, D::Smaller> {
+        self.0.subview(axis, index)
+    }
+}
+
+impl<A, D> ReadOnlyHogwildArray<A, D>
+where
+    D: Dimension,
+{
+    /// Get an immutable view of the array.
+    #[inline]
+    pub fn view(&self) -> ArrayView<'_, A, D> {
+        self.0.view()
+    }
+}
+
+impl<A, D> From<Array<A, D>> for ReadOnlyHogwildArray<A, D> {
+    fn from(a: Array<A, D>) -> Self {
+        ReadOnlyHogwildArray(a.into())
+    }
+}
+
 /// Hogwild for arbitrary data types.
 ///
 /// `Hogwild` subverts Rust's type system by allowing concurrent modification
@@ -119,11 +282,84 @@ unsafe impl<T> Send for Hogwild<T> {}
 
 unsafe impl<T> Sync for Hogwild<T> {}
 
+/// Counters that are shared between threads.
+///
+/// `HogwildCounters` is a fixed-size vector of counters, e.g. to count the
+/// number of updates of each embedding during training. Clones share the
+/// same counters. In contrast to `Hogwild`, increments are atomic, so no
+/// increments are lost due to data races. Since the counters use relaxed
+/// memory ordering, a snapshot that is taken during training is not
+/// necessarily consistent across counters.
+#[derive(Clone)]
+pub struct HogwildCounters(Arc<Vec<AtomicU64>>);
+
+impl HogwildCounters {
+    /// Construct `len` counters that are initialized to zero.
+    pub fn new(len: usize) -> Self {
+        HogwildCounters(Arc::new((0..len).map(|_| AtomicU64::new(0)).collect()))
+    }
+
+    /// Increment the counter at `index`.
+    ///
+    /// Panics when `index` is out of bounds.
+    #[inline]
+    pub fn increment(&self, index: usize) {
+        self.0[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Increment the counter at `index` and return its previous value.
+    ///
+    /// Since the increment is atomic, every concurrent caller observes a
+    /// different previous value. This can be used to enforce a cap on the
+    /// counter across threads.
+    ///
+    /// Panics when `index` is out of bounds.
+    #[inline]
+    pub fn fetch_increment(&self, index: usize) -> u64 {
+        self.0[index].fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Get the value of the counter at `index`.
+    ///
+    /// Panics when `index` is out of bounds.
+    #[inline]
+    pub fn get(&self, index: usize) -> u64 {
+        self.0[index].load(Ordering::Relaxed)
+    }
+
+    /// Reset all counters to zero.
+    pub fn reset(&self) {
+        for counter in self.0.iter() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the number of counters.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if there are no counters.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the current values of the counters.
+    pub fn snapshot(&self) -> Vec<u64> {
+        self.0
+            .iter()
+            .map(|counter| counter.load(Ordering::Relaxed))
+            .collect()
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use ndarray::Array2;
+    use std::thread;
 
-    use super::{Hogwild, HogwildArray2};
+    use ndarray::{Array2, Axis};
+
+    use super::{Hogwild, HogwildArray2, HogwildCounters, ReadOnlyHogwildArray};
 
     #[test]
     pub fn hogwild_test() {
@@ -141,7 +377,7 @@ mod test {
         let mut a1: HogwildArray2<f32> = Array2::zeros((2, 2)).into();
         let mut a2 = a1.clone();
 
-        let mut a1_view = a1.as_mut().view_mut();
+        let mut a1_view = a1.view_mut();
 
         let c00 = &mut a1_view[(0, 0)];
         *c00 = 1.0;
@@ -151,4 +387,62 @@ mod test {
 
         assert_eq!(&[1.0, 0.0, 0.0, 2.0], a2.as_ref().as_slice().unwrap());
     }
+
+    #[test]
+    pub fn read_only_hogwild_array_test() {
+        let mut a: HogwildArray2<f32> = Array2::zeros((2, 2)).into();
+        let frozen = a.freeze();
+
+        // The read-only handle can be shared between threads.
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+        assert_send_sync(&frozen);
+
+        // Updates through the Hogwild array are visible in the handle.
+        a.view_mut()[(0, 1)] = 1.0;
+        let reader = {
+            let frozen = frozen.clone();
+            thread::spawn(move || frozen.subview(Axis(0), 0).to_owned())
+        };
+        assert_eq!(reader.join().unwrap().as_slice().unwrap(), &[0.0, 1.0]);
+        assert_eq!(frozen.view(), a.view());
+
+        let from_array: ReadOnlyHogwildArray<f32, _> = Array2::ones((1, 2)).into();
+        assert_eq!(from_array.view().sum(), 2.0);
+    }
+
+    #[test]
+    pub fn hogwild_counters_test() {
+        let counters = HogwildCounters::new(4);
+        assert_eq!(counters.len(), 4);
+
+        // Every thread increments counters 0 and 1, odd threads also 2.
+        let children = (0..8)
+            .map(|thread| {
+                let counters = counters.clone();
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        counters.increment(0);
+                        counters.increment(1);
+                        if thread % 2 == 1 {
+                            counters.increment(2);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for child in children {
+            child.join().unwrap();
+        }
+
+        assert_eq!(counters.snapshot(), vec![80_000, 80_000, 40_000, 0]);
+        assert_eq!(counters.get(2), 40_000);
+
+        counters.reset();
+        assert_eq!(counters.snapshot(), vec![0; 4]);
+
+        assert_eq!(counters.fetch_increment(3), 0);
+        assert_eq!(counters.fetch_increment(3), 1);
+        assert_eq!(counters.get(3), 2);
+    }
 }
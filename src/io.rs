@@ -1,13 +1,25 @@
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::{self, BufRead, Lines, Read, Seek, SeekFrom, Write};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::ops::Range;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use chrono::{DateTime, Local};
-use failure::{Error, ResultExt};
+use failure::{err_msg, Error, ResultExt};
 use indicatif::{ProgressBar, ProgressStyle};
 use memmap::{Mmap, MmapOptions};
+use ndarray::Array2;
 use serde::Serialize;
 
+use crate::dedup::SentenceDedup;
+use crate::idx::WordIdx;
 use crate::util::EOS;
+use crate::Vocab;
 
 pub struct FileProgress {
     inner: File,
@@ -49,6 +61,25 @@ impl Drop for FileProgress {
     }
 }
 
+/// Corpus input formats.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InputFormat {
+    /// One sentence per line, tokens are separated by whitespace.
+    Text,
+
+    /// One JSON object per line.
+    ///
+    /// The tokens of a sentence are stored as an array of strings in
+    /// the given field of the object.
+    Jsonl { field: String },
+
+    /// One focus-context pair per line.
+    ///
+    /// The focus and context word are separated by a tab. No
+    /// end-of-sentence marker is added to pairs.
+    Pairs,
+}
+
 /// Sentence iterator.
 ///
 /// This iterator consumes a reader with tokenized sentences:
@@ -56,11 +87,80 @@ impl Drop for FileProgress {
 /// - One sentence per line.
 /// - Tokens separated by a space.
 ///
+/// Lines can be split with another tokenizer, which is set with
+/// `tokenizer`.
+///
 /// It produces `Vec`s with the tokens, adding an end-of-sentence marker
 /// to the end of the sentence. Lines that are empty or only consist of
-/// whitespace are discarded.
+/// whitespace are discarded: they do not produce a sentence, so no
+/// end-of-sentence marker is added for them. Blank lines (e.g. between
+/// paragraphs) therefore do not influence token or type counts.
+///
+/// If the iterator is constructed with `with_max_token_len`, tokens that
+/// are longer than the maximum length (in bytes) are skipped. The number
+/// of skipped tokens can be retrieved with `n_skipped`.
+///
+/// Lines that start with a comment prefix, which can be set with
+/// `comment_prefix`, are discarded like empty lines. This can be used to
+/// skip headers with metadata.
+///
+/// Runs of digits in tokens are replaced by a single `0` when digit
+/// normalization is enabled with `normalize_digits`, see
+/// `normalize_digits`. Tokens are normalized before their length is
+/// checked. Tokens are lowercased when `lowercase` is enabled.
+///
+/// Other input formats can be read by setting the format with
+/// `input_format`. In the JSONL format, lines that are not a JSON object
+/// with an array of strings in the token field are skipped. The number of
+/// skipped lines can be retrieved with `n_malformed`. In the pairs format,
+/// lines that do not consist of two tab-separated words are skipped as
+/// malformed and pairs with a word that is too long are skipped.
+///
+/// Sentences that are longer than a maximum length, which can be set with
+/// `max_sentence_len`, are split into sentences of at most that length.
+/// Every part gets its own end-of-sentence marker, so that context windows
+/// do not cross the split. The number of split lines can be retrieved with
+/// `n_split`. Splitting does not apply to the pairs format.
+///
+/// When a document marker is set with `document_marker`, the lines of a
+/// document are joined into a single sentence, so that context windows
+/// cross line breaks within a document. Every line still ends with an
+/// end-of-sentence marker. Documents are separated by lines that consist
+/// of the document marker, context windows never cross a document marker.
+/// Splitting applies to the joined document.
+///
+/// Reading a line that is not valid UTF-8 is an error. When lossy decoding
+/// is enabled with `lossy_utf8`, invalid sequences are replaced by U+FFFD
+/// instead. The number of lines with replacements can be retrieved with
+/// `n_lossy`.
+///
+/// Repeated sentences can be skipped by enabling deduplication with
+/// `dedup`. Deduplication is approximate, see `SentenceDedup`. It applies
+/// to the sentences (or documents) read by this iterator, before they are
+/// split. Iterators that share a filter skip repeats across the
+/// iterators. The number of skipped sentences can be retrieved with
+/// `n_duplicates`.
 pub struct SentenceIterator<R> {
-    lines: Lines<R>,
+    read: R,
+    buf: Vec<u8>,
+    format: InputFormat,
+    comment_prefix: Option<String>,
+    document_marker: Option<String>,
+    max_token_len: Option<usize>,
+    max_sentence_len: Option<usize>,
+    normalize_digits: bool,
+    lowercase: bool,
+    lossy_utf8: bool,
+    tokenizer: Arc<dyn Tokenizer>,
+    dedup: Option<SentenceDedup>,
+    line_tokens: Vec<String>,
+    pending: Vec<Vec<String>>,
+    n_duplicates: usize,
+    n_lines: usize,
+    n_lossy: usize,
+    n_malformed: usize,
+    n_skipped: usize,
+    n_split: usize,
 }
 
 impl<R> SentenceIterator<R>
@@ -69,9 +169,193 @@ where
 {
     pub fn new(read: R) -> Self {
         SentenceIterator {
-            lines: read.lines(),
+            read,
+            buf: Vec::new(),
+            format: InputFormat::Text,
+            comment_prefix: None,
+            document_marker: None,
+            max_token_len: None,
+            max_sentence_len: None,
+            normalize_digits: false,
+            lowercase: false,
+            lossy_utf8: false,
+            tokenizer: Arc::new(WhitespaceTokenizer),
+            dedup: None,
+            line_tokens: Vec::new(),
+            pending: Vec::new(),
+            n_duplicates: 0,
+            n_lines: 0,
+            n_lossy: 0,
+            n_malformed: 0,
+            n_skipped: 0,
+            n_split: 0,
         }
     }
+
+    /// Construct an iterator that skips tokens longer than `max_token_len` bytes.
+    pub fn with_max_token_len(read: R, max_token_len: usize) -> Self {
+        SentenceIterator {
+            max_token_len: Some(max_token_len),
+            ..Self::new(read)
+        }
+    }
+
+    /// Set the input format.
+    pub fn input_format(mut self, format: InputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Skip lines that start with `prefix`.
+    ///
+    /// Leading whitespace is ignored when matching the prefix.
+    pub fn comment_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.comment_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Join the lines of documents that are separated by `marker` lines.
+    ///
+    /// Leading and trailing whitespace is ignored when matching the marker.
+    /// The document marker is not supported by the pairs format.
+    pub fn document_marker(mut self, marker: impl Into<String>) -> Self {
+        self.document_marker = Some(marker.into());
+        self
+    }
+
+    /// Split sentences that are longer than `max_sentence_len` tokens.
+    ///
+    /// The length does not include the end-of-sentence marker.
+    pub fn max_sentence_len(mut self, max_sentence_len: usize) -> Self {
+        assert!(
+            max_sentence_len > 0,
+            "The maximum sentence length should be positive"
+        );
+        self.max_sentence_len = Some(max_sentence_len);
+        self
+    }
+
+    /// Replace runs of digits in tokens by a single `0`.
+    pub fn normalize_digits(mut self, normalize_digits: bool) -> Self {
+        self.normalize_digits = normalize_digits;
+        self
+    }
+
+    /// Lowercase tokens.
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    /// Replace invalid UTF-8 sequences by U+FFFD.
+    pub fn lossy_utf8(mut self, lossy_utf8: bool) -> Self {
+        self.lossy_utf8 = lossy_utf8;
+        self
+    }
+
+    /// Set the tokenizer of plain text lines.
+    ///
+    /// The tokenizer is not used by the JSONL and pairs formats.
+    pub fn tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Skip sentences that are rejected by the filter of repeats.
+    ///
+    /// The counts of the filter are shared with its clones, so that a
+    /// clone of the filter can be used to deduplicate sentences across
+    /// iterators, e.g. over the parts of a corpus that are read by
+    /// different threads.
+    pub fn dedup(mut self, dedup: SentenceDedup) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// Get the number of sentences that were skipped as repeats.
+    pub fn n_duplicates(&self) -> usize {
+        self.n_duplicates
+    }
+
+    /// Get the number of lines that were read.
+    ///
+    /// After a read error, this is the number of the line that could not
+    /// be read.
+    pub fn n_lines(&self) -> usize {
+        self.n_lines
+    }
+
+    /// Get the number of lines in which invalid UTF-8 was replaced.
+    pub fn n_lossy(&self) -> usize {
+        self.n_lossy
+    }
+
+    /// Get the number of lines that were skipped because they were malformed.
+    pub fn n_malformed(&self) -> usize {
+        self.n_malformed
+    }
+
+    /// Get the number of tokens that were skipped because of their length.
+    pub fn n_skipped(&self) -> usize {
+        self.n_skipped
+    }
+
+    /// Get the number of lines that were split because of their length.
+    pub fn n_split(&self) -> usize {
+        self.n_split
+    }
+
+    /// Split a sentence with an end-of-sentence marker into parts of at
+    /// most `max_sentence_len` tokens.
+    ///
+    /// The first part is returned, the remaining parts are queued. The
+    /// tokens are moved into the parts, they are not copied.
+    fn split_sentence(&mut self, mut tokens: Vec<String>, max_sentence_len: usize) -> Vec<String> {
+        let eos = tokens
+            .pop()
+            .expect("Sentence without end-of-sentence marker");
+        self.n_split += 1;
+
+        // Split off the parts from the end, so that they are queued in
+        // reverse order and can be popped.
+        let mut parts = Vec::new();
+        while !tokens.is_empty() {
+            let start = (tokens.len() - 1) / max_sentence_len * max_sentence_len;
+            let mut part = tokens.split_off(start);
+            part.push(eos.clone());
+            parts.push(part);
+        }
+
+        let first = parts.pop().unwrap();
+        self.pending = parts;
+
+        first
+    }
+
+    /// Check whether a sentence is a repeat that should be skipped.
+    fn is_duplicate(&mut self, tokens: &[String]) -> bool {
+        let duplicate = match self.dedup {
+            Some(ref dedup) => !dedup.insert(tokens),
+            None => false,
+        };
+        if duplicate {
+            self.n_duplicates += 1;
+        }
+
+        duplicate
+    }
+
+    /// Finish a sentence, splitting it when it is too long.
+    fn finish_sentence(&mut self, tokens: Vec<String>) -> Vec<String> {
+        // Split long sentences. The end-of-sentence marker is not counted.
+        if let Some(max_sentence_len) = self.max_sentence_len {
+            if self.format != InputFormat::Pairs && tokens.len() > max_sentence_len + 1 {
+                return self.split_sentence(tokens, max_sentence_len);
+            }
+        }
+
+        tokens
+    }
 }
 
 impl<R> Iterator for SentenceIterator<R>
@@ -81,19 +365,125 @@ where
     type Item = Result<Vec<String>, io::Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        for line in &mut self.lines {
-            let line = match line {
-                Ok(ref line) => line.trim(),
-                Err(err) => return Some(Err(err)),
+        if let Some(part) = self.pending.pop() {
+            return Some(Ok(part));
+        }
+
+        // The lines of the current document, when a document marker is set.
+        let mut document = Vec::new();
+
+        loop {
+            self.buf.clear();
+            let read = self.read.read_until(b'\n', &mut self.buf);
+            match read {
+                Ok(0) if document.is_empty() || self.is_duplicate(&document) => return None,
+                Ok(0) => return Some(Ok(self.finish_sentence(document))),
+                Ok(_) => self.n_lines += 1,
+                Err(err) => {
+                    self.n_lines += 1;
+                    return Some(Err(err));
+                }
+            }
+
+            let line = match str::from_utf8(&self.buf) {
+                Ok(line) => Cow::Borrowed(line),
+                Err(_) if self.lossy_utf8 => {
+                    self.n_lossy += 1;
+                    String::from_utf8_lossy(&self.buf)
+                }
+                Err(_) => {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "stream did not contain valid UTF-8",
+                    )))
+                }
             };
+            let line = line.trim();
 
             // Skip empty lines.
-            if !line.is_empty() {
-                return Some(Ok(whitespace_tokenize(line)));
+            if line.is_empty() {
+                continue;
+            }
+
+            // Skip comments.
+            if let Some(ref prefix) = self.comment_prefix {
+                if line.starts_with(prefix.as_str()) {
+                    continue;
+                }
             }
-        }
 
-        None
+            // A document marker ends the current document.
+            if self.document_marker.as_deref() == Some(line) {
+                if document.is_empty() {
+                    continue;
+                }
+                if self.is_duplicate(&document) {
+                    document.clear();
+                    continue;
+                }
+                return Some(Ok(self.finish_sentence(document)));
+            }
+
+            let mut tokens = match self.format {
+                InputFormat::Text => text_tokenize(
+                    line,
+                    self.tokenizer.as_ref(),
+                    self.max_token_len,
+                    self.normalize_digits,
+                    &mut self.n_skipped,
+                    mem::take(&mut self.line_tokens),
+                ),
+                InputFormat::Jsonl { ref field } => {
+                    match jsonl_tokenize(
+                        line,
+                        field,
+                        self.max_token_len,
+                        self.normalize_digits,
+                        &mut self.n_skipped,
+                    ) {
+                        Some(tokens) => tokens,
+                        None => {
+                            self.n_malformed += 1;
+                            continue;
+                        }
+                    }
+                }
+                InputFormat::Pairs => {
+                    match pairs_tokenize(
+                        line,
+                        self.max_token_len,
+                        self.normalize_digits,
+                        &mut self.n_skipped,
+                    ) {
+                        Some(tokens) if tokens.len() == 2 => tokens,
+                        Some(_) => continue,
+                        None => {
+                            self.n_malformed += 1;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            if self.lowercase {
+                for token in &mut tokens {
+                    *token = token.to_lowercase();
+                }
+            }
+
+            if self.document_marker.is_some() && self.format != InputFormat::Pairs {
+                // Reuse the buffer of the line for the next line.
+                document.append(&mut tokens);
+                self.line_tokens = tokens;
+                continue;
+            }
+
+            if self.is_duplicate(&tokens) {
+                continue;
+            }
+
+            return Some(Ok(self.finish_sentence(tokens)));
+        }
     }
 }
 
@@ -110,25 +500,131 @@ pub fn thread_data_text(f: &File, thread: usize, n_threads: usize) -> Result<(Mm
         n_threads
     );
 
-    let size = f.metadata().context("Cannot get file metadata")?.len();
-    let chunk_size = size as usize / n_threads;
-
     let mmap = unsafe { MmapOptions::new().map(&f)? };
+    let start = thread_start(&mmap, thread, n_threads);
+
+    Ok((mmap, start))
+}
 
+/// Get shard-specific data.
+///
+/// This function splits the corpus into `n_shards` shards of complete
+/// lines, with roughly the same size. It returns a memory map of the corpus
+/// data and the byte range of the given shard. The shards of a corpus do
+/// not overlap and cover the complete corpus.
+pub fn shard_data_text(
+    f: &File,
+    shard: usize,
+    n_shards: usize,
+) -> Result<(Mmap, Range<usize>), Error> {
+    assert!(
+        shard < n_shards,
+        "Shard {} out of index [0, {})",
+        shard,
+        n_shards
+    );
+
+    let mmap = unsafe { MmapOptions::new().map(f)? };
+    let start = thread_start(&mmap, shard, n_shards);
+    let end = if shard + 1 == n_shards {
+        mmap.len()
+    } else {
+        thread_start(&mmap, shard + 1, n_shards)
+    };
+
+    Ok((mmap, start..end))
+}
+
+/// Get the starting position of a thread in text data.
+///
+/// The data is split in `n_threads` chunks of equal size. The starting
+/// position of a thread is the beginning of the first line after the start
+/// of its chunk.
+pub fn thread_start(data: &[u8], thread: usize, n_threads: usize) -> usize {
     if thread == 0 {
-        return Ok((mmap, 0));
+        return 0;
     }
 
+    let chunk_size = data.len() / n_threads;
     let mut start = thread * chunk_size;
-    while start < mmap.len() {
-        let next = mmap[start];
+    while start < data.len() {
+        let next = data[start];
         start += 1;
         if next == b'\n' {
             break;
         }
     }
 
-    Ok((mmap, start))
+    start
+}
+
+/// Queue of corpus chunks that is shared between threads.
+///
+/// The corpus is split into chunks of roughly `chunk_size` bytes, where
+/// each chunk consists of complete lines. Threads take the next available
+/// chunk from the queue when they are done with their previous chunk,
+/// rather than processing a fixed part of the corpus. This avoids idle
+/// threads when some parts of the corpus take longer to process.
+///
+/// The queue cycles through the chunks: after all chunks were handed out,
+/// the next pass over the chunks starts.
+pub struct ChunkQueue {
+    data: Mmap,
+    starts: Vec<usize>,
+    next: AtomicUsize,
+}
+
+impl ChunkQueue {
+    /// Construct a chunk queue for a text corpus.
+    pub fn new(f: &File, chunk_size: usize) -> Result<Self, Error> {
+        assert!(chunk_size > 0, "Chunk size should be larger than zero");
+
+        let data = unsafe { MmapOptions::new().map(f)? };
+
+        let mut starts = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            starts.push(start);
+
+            // Move the start of the next chunk to the beginning of a line.
+            start += chunk_size;
+            while start < data.len() && data[start - 1] != b'\n' {
+                start += 1;
+            }
+        }
+
+        Ok(ChunkQueue {
+            data,
+            starts,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Get the number of chunks.
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Returns `true` if the queue has no chunks.
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// Take the next chunk from the queue.
+    ///
+    /// Returns the pass over the chunks and the chunk data. `None` is
+    /// returned if the corpus is empty.
+    pub fn next_chunk(&self) -> Option<(usize, &[u8])> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        let (pass, idx) = (n / self.len(), n % self.len());
+        let end = self.starts.get(idx + 1).cloned().unwrap_or(self.data.len());
+
+        Some((pass, &self.data[self.starts[idx]..end]))
+    }
 }
 
 /// Get thread-specific data for a CONLLX-Corpus.
@@ -170,6 +666,338 @@ pub fn thread_data_conllx(
     Ok((mmap, start))
 }
 
+/// Read focus token weights.
+///
+/// This function reads lines of the form `token<TAB>weight` and returns
+/// a weight for each word in the vocabulary, indexed by word index.
+/// Words that are not listed get the weight 1.0, listed tokens that are
+/// not in the vocabulary are ignored. Weights must be finite and
+/// non-negative.
+pub fn read_token_weights<R, V>(read: R, vocab: &V) -> Result<Vec<f32>, crate::Error>
+where
+    R: BufRead,
+    V: Vocab,
+    V::VocabType: Borrow<str>,
+{
+    let mut weights = vec![1f32; vocab.len()];
+
+    for (line_idx, line) in read.lines().enumerate() {
+        let line = line.map_err(crate::Error::Read)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split('\t');
+        let (token, weight) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(token), Some(weight), None) => (token, weight),
+            _ => {
+                return Err(crate::Error::Format(format!(
+                    "Line {} is not of the form token<TAB>weight",
+                    line_idx + 1
+                )))
+            }
+        };
+        let weight = weight.trim().parse::<f32>().map_err(|err| {
+            crate::Error::Format(format!(
+                "Cannot parse weight of '{}' on line {}: {}",
+                token,
+                line_idx + 1,
+                err
+            ))
+        })?;
+        if !weight.is_finite() || weight < 0. {
+            return Err(crate::Error::Format(format!(
+                "Invalid weight of '{}' on line {}: {}",
+                token,
+                line_idx + 1,
+                weight
+            )));
+        }
+
+        if let Some(idx) = vocab.idx(token) {
+            weights[idx.word_idx() as usize] = weight;
+        }
+    }
+
+    Ok(weights)
+}
+
+/// Read a negative sampling distribution.
+///
+/// This function reads lines of the form `token<TAB>probability` and
+/// returns the probability of each word in the vocabulary, indexed by
+/// word index. The probabilities are normalized to sum to one, words
+/// that are not listed get the probability zero. Listed tokens must be
+/// in the vocabulary and may only be listed once. At least two tokens
+/// must have a non-zero probability, since a negative is never the
+/// positive output.
+pub fn read_negative_distribution<R, V>(read: R, vocab: &V) -> Result<Vec<f64>, crate::Error>
+where
+    R: BufRead,
+    V: Vocab,
+    V::VocabType: Borrow<str>,
+{
+    let mut probs = vec![0f64; vocab.len()];
+    let mut listed = vec![false; vocab.len()];
+
+    for (line_idx, line) in read.lines().enumerate() {
+        let line = line.map_err(crate::Error::Read)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split('\t');
+        let (token, prob) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(token), Some(prob), None) => (token, prob),
+            _ => {
+                return Err(crate::Error::Format(format!(
+                    "Line {} is not of the form token<TAB>probability",
+                    line_idx + 1
+                )))
+            }
+        };
+        let prob = prob.trim().parse::<f64>().map_err(|err| {
+            crate::Error::Format(format!(
+                "Cannot parse probability of '{}' on line {}: {}",
+                token,
+                line_idx + 1,
+                err
+            ))
+        })?;
+        if !prob.is_finite() || prob < 0. {
+            return Err(crate::Error::Format(format!(
+                "Invalid probability of '{}' on line {}: {}",
+                token,
+                line_idx + 1,
+                prob
+            )));
+        }
+
+        let idx = match vocab.idx(token) {
+            Some(idx) => idx.word_idx() as usize,
+            None => {
+                return Err(crate::Error::Format(format!(
+                    "Token '{}' on line {} is not in the vocabulary",
+                    token,
+                    line_idx + 1
+                )))
+            }
+        };
+        if listed[idx] {
+            return Err(crate::Error::Format(format!(
+                "Token '{}' on line {} is listed more than once",
+                token,
+                line_idx + 1
+            )));
+        }
+        listed[idx] = true;
+        probs[idx] = prob;
+    }
+
+    if probs.iter().filter(|&&prob| prob > 0.).count() < 2 {
+        return Err(crate::Error::Format(
+            "The negative sampling distribution must assign probability to at least two tokens"
+                .to_string(),
+        ));
+    }
+    let norm: f64 = probs.iter().sum();
+    for prob in &mut probs {
+        *prob /= norm;
+    }
+
+    Ok(probs)
+}
+
+/// Read tokens that should be frozen.
+///
+/// This function reads one token per line and returns a flag for each
+/// word in the vocabulary, indexed by word index. Listed tokens that are
+/// not in the vocabulary are ignored.
+pub fn read_frozen_tokens<R, V>(read: R, vocab: &V) -> Result<Vec<bool>, crate::Error>
+where
+    R: BufRead,
+    V: Vocab,
+    V::VocabType: Borrow<str>,
+{
+    let mut frozen = vec![false; vocab.len()];
+
+    for line in read.lines() {
+        let line = line.map_err(crate::Error::Read)?;
+        let token = line.trim();
+        if token.is_empty() {
+            continue;
+        }
+
+        if let Some(idx) = vocab.idx(token) {
+            frozen[idx.word_idx() as usize] = true;
+        }
+    }
+
+    Ok(frozen)
+}
+
+/// Read sentence weights.
+///
+/// This function reads one weight per line. The weight on line *n* is
+/// the weight of the sentence on line *n* of the corpus, so the file
+/// must have as many lines as the corpus. Weights must be finite and
+/// non-negative.
+pub fn read_sentence_weights<R>(read: R) -> Result<Vec<f32>, crate::Error>
+where
+    R: BufRead,
+{
+    let mut weights = Vec::new();
+
+    for (line_idx, line) in read.lines().enumerate() {
+        let line = line.map_err(crate::Error::Read)?;
+        let weight = line.trim().parse::<f32>().map_err(|err| {
+            crate::Error::Format(format!(
+                "Cannot parse sentence weight on line {}: {}",
+                line_idx + 1,
+                err
+            ))
+        })?;
+        if !weight.is_finite() || weight < 0. {
+            return Err(crate::Error::Format(format!(
+                "Sentence weight on line {} is not a non-negative number: {}",
+                line_idx + 1,
+                weight
+            )));
+        }
+
+        weights.push(weight);
+    }
+
+    Ok(weights)
+}
+
+/// Read side features of tokens.
+///
+/// This function reads lines of the form `token<TAB>f1 f2 ...`, where the
+/// features are separated by spaces. It returns a matrix with the features
+/// of each word in the vocabulary as rows, indexed by word index. All
+/// tokens must have the same number of features. Words that are not
+/// listed get zero features, listed tokens that are not in the vocabulary
+/// are ignored.
+pub fn read_side_features<R, V>(read: R, vocab: &V) -> Result<Array2<f32>, crate::Error>
+where
+    R: BufRead,
+    V: Vocab,
+    V::VocabType: Borrow<str>,
+{
+    let mut features: Option<Array2<f32>> = None;
+
+    for (line_idx, line) in read.lines().enumerate() {
+        let line = line.map_err(crate::Error::Read)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split('\t');
+        let (token, token_features) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(token), Some(token_features), None) => (token, token_features),
+            _ => {
+                return Err(crate::Error::Format(format!(
+                    "Line {} is not of the form token<TAB>features",
+                    line_idx + 1
+                )))
+            }
+        };
+        let token_features = token_features
+            .split_whitespace()
+            .map(|feature| feature.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                crate::Error::Format(format!(
+                    "Cannot parse features of '{}' on line {}: {}",
+                    token,
+                    line_idx + 1,
+                    err
+                ))
+            })?;
+        if token_features.iter().any(|feature| !feature.is_finite()) {
+            return Err(crate::Error::Format(format!(
+                "Features of '{}' on line {} are not finite",
+                token,
+                line_idx + 1
+            )));
+        }
+
+        let features =
+            features.get_or_insert_with(|| Array2::zeros((vocab.len(), token_features.len())));
+        if token_features.is_empty() || token_features.len() != features.ncols() {
+            return Err(crate::Error::Format(format!(
+                "Token '{}' on line {} has {} features, expected {}",
+                token,
+                line_idx + 1,
+                token_features.len(),
+                features.ncols()
+            )));
+        }
+
+        if let Some(idx) = vocab.idx(token) {
+            features
+                .row_mut(idx.word_idx() as usize)
+                .iter_mut()
+                .zip(token_features)
+                .for_each(|(feature, token_feature)| *feature = token_feature);
+        }
+    }
+
+    features.ok_or_else(|| {
+        crate::Error::Format("The side features file does not contain any features".to_string())
+    })
+}
+
+/// Write a vocabulary as JSON.
+///
+/// The vocabulary is written as an array of objects with the fields
+/// `token`, `count` and `index`, in the order of the vocabulary indices.
+pub fn write_vocab_json<W, V>(write: &mut W, vocab: &V) -> Result<(), Error>
+where
+    W: Write,
+    V: Vocab,
+    V::VocabType: ToString,
+{
+    #[derive(Serialize)]
+    struct VocabEntry {
+        token: String,
+        count: usize,
+        index: usize,
+    }
+
+    let entries = vocab
+        .types()
+        .iter()
+        .enumerate()
+        .map(|(index, t)| VocabEntry {
+            token: t.label().to_string(),
+            count: t.count(),
+            index,
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer(&mut *write, &entries)?;
+    writeln!(write)?;
+
+    Ok(())
+}
+
+/// Keys of the training metadata that is written with a model.
+///
+/// User-provided metadata cannot use these keys, see
+/// `TrainInfo::set_metadata`.
+pub const RESERVED_METADATA_KEYS: &[&str] = &[
+    "common_config",
+    "input_vocab_config",
+    "model_config",
+    "output_vocab_config",
+    "subword_composition",
+    "training_info",
+    "version_info",
+    "vocab_config",
+];
+
 /// Meta information about training.
 #[derive(Clone, Serialize)]
 pub struct TrainInfo {
@@ -178,6 +1006,8 @@ pub struct TrainInfo {
     n_threads: usize,
     start_datetime: String,
     end_datetime: Option<String>,
+    #[serde(skip)]
+    metadata: HashMap<String, String>,
 }
 
 impl TrainInfo {
@@ -193,6 +1023,7 @@ impl TrainInfo {
             n_threads,
             start_datetime: start_datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
             end_datetime: None,
+            metadata: HashMap::new(),
         }
     }
 
@@ -221,6 +1052,33 @@ impl TrainInfo {
         self.end_datetime.as_ref().map(|s| s.as_str())
     }
 
+    /// Get the user-provided metadata.
+    pub fn metadata(&self) -> &HashMap<String, String> {
+        &self.metadata
+    }
+
+    /// Set user-provided metadata.
+    ///
+    /// The metadata is added to the metadata that is written with the
+    /// model. Returns an error if a key is one of the keys of the
+    /// training metadata (`RESERVED_METADATA_KEYS`), such as
+    /// `common_config` or `training_info`.
+    pub fn set_metadata(&mut self, metadata: HashMap<String, String>) -> Result<(), Error> {
+        if let Some(key) = metadata
+            .keys()
+            .find(|key| RESERVED_METADATA_KEYS.contains(&key.as_str()))
+        {
+            return Err(err_msg(format!(
+                "Metadata key '{}' is used by the training metadata",
+                key
+            )));
+        }
+
+        self.metadata = metadata;
+
+        Ok(())
+    }
+
     /// Set the end datetime to current datetime.
     pub fn set_end(&mut self) {
         let start_datetime: DateTime<Local> = Local::now();
@@ -229,6 +1087,11 @@ impl TrainInfo {
 }
 
 /// Trait for writing models in binary format.
+///
+/// Models are written in the finalfusion format. The embedding matrix is
+/// padded to start at a multiple of the size of `f32` in the output, so
+/// that the matrix can be memory mapped (e.g. with finalfusion's
+/// `MmapEmbeddings`) and used without copying.
 pub trait WriteModelBinary<W>
 where
     W: Write,
@@ -236,15 +1099,168 @@ where
     fn write_model_binary(self, write: &mut W, train_info: TrainInfo) -> Result<(), Error>;
 }
 
-fn whitespace_tokenize(line: &str) -> Vec<String> {
-    let mut tokens = line
-        .split_whitespace()
-        .map(ToOwned::to_owned)
-        .collect::<Vec<_>>();
+/// Tokenizer of corpus lines.
+///
+/// A tokenizer splits a line of a plain text corpus into tokens. Tokens
+/// are normalized, lowercased and filtered by length after tokenization.
+/// A custom tokenizer can be used for languages that do not separate
+/// words with whitespace, see `SentenceIterator::tokenizer` and
+/// `TextCorpus::tokenizer`. The same tokenizer should be used when the
+/// vocabulary is constructed and during training.
+pub trait Tokenizer: Send + Sync {
+    /// Split `line` into tokens, which are appended to `out`.
+    fn tokenize(&self, line: &str, out: &mut Vec<String>);
+}
+
+impl fmt::Debug for dyn Tokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tokenizer")
+    }
+}
+
+/// Tokenizer that splits lines on whitespace.
+///
+/// This is the default tokenizer.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, line: &str, out: &mut Vec<String>) {
+        out.extend(line.split_whitespace().map(ToOwned::to_owned));
+    }
+}
+
+/// Tokenize a line of plain text.
+///
+/// The tokens are written to `tokens`, which should be empty, and are
+/// prepared in place, so that the tokens of the tokenizer are not copied.
+fn text_tokenize(
+    line: &str,
+    tokenizer: &dyn Tokenizer,
+    max_token_len: Option<usize>,
+    normalize_digits: bool,
+    n_skipped: &mut usize,
+    mut tokens: Vec<String>,
+) -> Vec<String> {
+    tokenizer.tokenize(line, &mut tokens);
+    if normalize_digits {
+        for token in &mut tokens {
+            *token = self::normalize_digits(token);
+        }
+    }
+    tokens.retain(|token| keep_token(token, max_token_len, n_skipped));
     tokens.push(EOS.to_string());
     tokens
 }
 
+/// Extract the tokens from the given field of a JSON object.
+///
+/// Returns `None` if the line is not a JSON object with an array of
+/// strings in `field`.
+fn jsonl_tokenize(
+    line: &str,
+    field: &str,
+    max_token_len: Option<usize>,
+    normalize_digits: bool,
+    n_skipped: &mut usize,
+) -> Option<Vec<String>> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    let json_tokens = value.get(field)?.as_array()?;
+
+    let mut tokens = Vec::with_capacity(json_tokens.len() + 1);
+    for token in json_tokens {
+        let token = prepare_token(token.as_str()?, normalize_digits);
+        if keep_token(&token, max_token_len, n_skipped) {
+            tokens.push(token);
+        }
+    }
+    tokens.push(EOS.to_string());
+
+    Some(tokens)
+}
+
+/// Extract the focus and context word of a tab-separated pair.
+///
+/// Returns `None` if the line does not consist of two non-empty fields.
+/// Words that are too long are left out of the returned pair.
+fn pairs_tokenize(
+    line: &str,
+    max_token_len: Option<usize>,
+    normalize_digits: bool,
+    n_skipped: &mut usize,
+) -> Option<Vec<String>> {
+    let mut fields = line.split('\t').map(str::trim);
+    let (focus, context) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(focus), Some(context), None) if !focus.is_empty() && !context.is_empty() => {
+            (focus, context)
+        }
+        _ => return None,
+    };
+
+    Some(
+        [focus, context]
+            .iter()
+            .map(|token| prepare_token(token, normalize_digits))
+            .filter(|token| keep_token(token, max_token_len, n_skipped))
+            .collect(),
+    )
+}
+
+fn prepare_token(token: &str, normalize: bool) -> String {
+    if normalize {
+        normalize_digits(token)
+    } else {
+        token.to_owned()
+    }
+}
+
+/// Replace every run of ASCII digits in a token by a single `0`.
+///
+/// For example, both *year1999* and *year2020* are normalized to *year0*.
+pub fn normalize_digits(token: &str) -> String {
+    let mut normalized = String::with_capacity(token.len());
+    let mut in_digits = false;
+    for c in token.chars() {
+        if c.is_ascii_digit() {
+            if !in_digits {
+                normalized.push('0');
+            }
+            in_digits = true;
+        } else {
+            normalized.push(c);
+            in_digits = false;
+        }
+    }
+
+    normalized
+}
+
+fn keep_token(token: &str, max_token_len: Option<usize>, n_skipped: &mut usize) -> bool {
+    match max_token_len {
+        Some(max_token_len) if token.len() > max_token_len => {
+            *n_skipped += 1;
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Trait for writing models as NumPy `.npz` archives.
+pub trait WriteModelNpz<W>
+where
+    W: Write + Seek,
+{
+    /// Write the model as a NumPy `.npz` archive.
+    ///
+    /// The archive contains the word embeddings as the `embeddings`
+    /// array, with one row per word. The words are written to
+    /// `vocab_write`, one word per line, in the order of the rows. The
+    /// subword embeddings are discarded.
+    fn write_model_npz<VW>(&self, write: &mut W, vocab_write: &mut VW) -> Result<(), Error>
+    where
+        VW: Write;
+}
+
 /// Trait for writing models in text format.
 pub trait WriteModelText<W>
 where
@@ -260,22 +1276,49 @@ where
     fn write_model_text(&self, write: &mut W, write_dims: bool) -> Result<(), Error>;
 }
 
+/// Trait for writing the norms of word embeddings.
+pub trait WriteModelNorms<W>
+where
+    W: Write,
+{
+    /// Write the l2 norms of the word embeddings as text.
+    ///
+    /// Every line contains a word and the norm of its embedding,
+    /// separated by a tab. These are the norms that are stored in
+    /// finalfusion models, the norms of the embeddings before
+    /// normalization. Subword embeddings are not written.
+    fn write_model_norms(&self, write: &mut W) -> Result<(), Error>;
+}
+
 /// Trait for writing models in binary format.
 pub trait WriteModelWord2Vec<W>
 where
     W: Write,
 {
+    /// Write the model in word2vec binary format.
+    ///
+    /// This function only writes the word embeddings. The subword
+    /// embeddings are discarded.
     fn write_model_word2vec(&self, write: &mut W) -> Result<(), Error>;
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs::File;
-    use std::io::Cursor;
+    use std::io::{self, BufReader, Cursor};
+    use std::sync::Arc;
+    use std::thread;
 
-    use super::SentenceIterator;
-    use super::{thread_data_conllx, thread_data_text};
+    use super::{
+        normalize_digits, read_frozen_tokens, read_negative_distribution, read_sentence_weights,
+        read_side_features, read_token_weights, shard_data_text, thread_data_conllx,
+        thread_data_text, write_vocab_json,
+    };
+    use super::{ChunkQueue, InputFormat, SentenceIterator};
+    use crate::dedup::SentenceDedup;
+    use crate::idx::WordIdx;
     use crate::util::EOS;
+    use crate::{SimpleVocab, SimpleVocabConfig, Vocab, VocabBuilder};
 
     #[test]
     fn sentence_iterator_test() {
@@ -309,6 +1352,25 @@ mod tests {
         assert!(iter.next().is_none());
     }
 
+    #[test]
+    fn sentence_iterator_dedup_test() {
+        let v = b"a b\nc d\na b\na b\nc d\na b\nc d\nc d\ne\n".to_vec();
+        let mut iter = SentenceIterator::new(Cursor::new(v)).dedup(SentenceDedup::new(2, 10));
+        let sentences = iter.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            sentences,
+            vec![
+                vec!["a", "b", EOS],
+                vec!["c", "d", EOS],
+                vec!["a", "b", EOS],
+                vec!["c", "d", EOS],
+                vec!["e", EOS],
+            ]
+        );
+        assert_eq!(iter.n_duplicates(), 4);
+        assert_eq!(iter.n_lines(), 9);
+    }
+
     #[test]
     fn sentence_iterator_empty_test() {
         let v = b"".to_vec();
@@ -325,6 +1387,329 @@ mod tests {
         assert!(iter.next().is_none());
     }
 
+    #[test]
+    fn read_token_weights_test() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1e-4,
+        });
+        for token in &["a", "a", "b", "c"] {
+            builder.count(token.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let c = Cursor::new(b"b\t2.5\nunknown\t3\n\nc\t0.5\n".to_vec());
+        let weights = read_token_weights(c, &vocab).unwrap();
+        assert_eq!(weights.len(), 3);
+        assert_eq!(weights[vocab.idx("a").unwrap().word_idx() as usize], 1.0);
+        assert_eq!(weights[vocab.idx("b").unwrap().word_idx() as usize], 2.5);
+        assert_eq!(weights[vocab.idx("c").unwrap().word_idx() as usize], 0.5);
+
+        for &invalid in &[
+            &b"b 2.5\n"[..],
+            b"b\tmuch\n",
+            b"b\t-1\n",
+            b"b\tNaN\n",
+            b"b\tinf\n",
+            b"b\t-inf\n",
+        ] {
+            let c = Cursor::new(invalid.to_vec());
+            assert!(matches!(
+                read_token_weights(c, &vocab),
+                Err(crate::Error::Format(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn read_negative_distribution_test() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1e-4,
+        });
+        for token in &["a", "a", "b", "c"] {
+            builder.count(token.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let c = Cursor::new(b"b\t3\n\na\t1\n".to_vec());
+        let probs = read_negative_distribution(c, &vocab).unwrap();
+        assert_eq!(probs[vocab.idx("a").unwrap().word_idx() as usize], 0.25);
+        assert_eq!(probs[vocab.idx("b").unwrap().word_idx() as usize], 0.75);
+        assert_eq!(probs[vocab.idx("c").unwrap().word_idx() as usize], 0.);
+
+        for &invalid in &[
+            &b"unknown\t1\n"[..],
+            b"a\t1\na\t2\n",
+            b"a\t-1\n",
+            b"a\t1\nb\t0\n",
+            b"a 1\n",
+        ] {
+            let c = Cursor::new(invalid.to_vec());
+            assert!(read_negative_distribution(c, &vocab).is_err());
+        }
+    }
+
+    #[test]
+    fn read_side_features_test() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1e-4,
+        });
+        for token in &["a", "a", "b", "c"] {
+            builder.count(token.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let c = Cursor::new(b"b\t0.5 -1\n\nunknown\t1 1\na\t2 3\n".to_vec());
+        let features = read_side_features(c, &vocab).unwrap();
+        assert_eq!(features.dim(), (3, 2));
+        let row = |token| {
+            features
+                .row(vocab.idx(token).unwrap().word_idx() as usize)
+                .to_vec()
+        };
+        assert_eq!(row("a"), vec![2., 3.]);
+        assert_eq!(row("b"), vec![0.5, -1.]);
+        assert_eq!(row("c"), vec![0., 0.]);
+
+        for &invalid in &[&b""[..], b"a\t1 2\nb\t1\n", b"a\t1 x\n", b"a\t\n", b"a 1\n"] {
+            let c = Cursor::new(invalid.to_vec());
+            assert!(read_side_features(c, &vocab).is_err());
+        }
+    }
+
+    #[test]
+    fn read_frozen_tokens_test() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1e-4,
+        });
+        for token in &["a", "a", "b", "c"] {
+            builder.count(token.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let c = Cursor::new(b"b\nunknown\n\n c \n".to_vec());
+        let frozen = read_frozen_tokens(c, &vocab).unwrap();
+        assert_eq!(frozen.len(), 3);
+        assert!(!frozen[vocab.idx("a").unwrap().word_idx() as usize]);
+        assert!(frozen[vocab.idx("b").unwrap().word_idx() as usize]);
+        assert!(frozen[vocab.idx("c").unwrap().word_idx() as usize]);
+    }
+
+    #[test]
+    fn read_sentence_weights_test() {
+        let weights = read_sentence_weights(Cursor::new("1\n 2.5 \n0\n")).unwrap();
+        assert_eq!(weights, vec![1., 2.5, 0.]);
+
+        assert!(read_sentence_weights(Cursor::new("1\n\n2\n")).is_err());
+        assert!(read_sentence_weights(Cursor::new("1\n-1\n")).is_err());
+        assert!(read_sentence_weights(Cursor::new("inf\n")).is_err());
+    }
+
+    #[test]
+    fn write_vocab_json_test() {
+        let sentences = SentenceIterator::new(Cursor::new("a b a\nc a\n"))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1e-4,
+        });
+        for token in sentences.into_iter().flatten() {
+            builder.count(token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let mut json = Vec::new();
+        write_vocab_json(&mut json, &vocab).unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&json).unwrap();
+
+        assert_eq!(entries.len(), vocab.len());
+        for (index, (entry, t)) in entries.iter().zip(vocab.types()).enumerate() {
+            assert_eq!(entry["token"], t.label().as_str());
+            assert_eq!(entry["count"], t.count());
+            assert_eq!(entry["index"], index);
+            assert_eq!(
+                vocab
+                    .idx(entry["token"].as_str().unwrap())
+                    .unwrap()
+                    .word_idx() as usize,
+                index
+            );
+        }
+        assert_eq!(entries[0]["token"], "a");
+        assert_eq!(entries[0]["count"], 3);
+    }
+
+    #[test]
+    fn sentence_iterator_interleaved_empty_lines_test() {
+        let with_blanks = b"\nThis is a sentence .\n\n  \nAnd another one .\n\t\n".to_vec();
+        let without_blanks = b"This is a sentence .\nAnd another one .\n".to_vec();
+
+        let with_blanks = SentenceIterator::new(Cursor::new(with_blanks))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let without_blanks = SentenceIterator::new(Cursor::new(without_blanks))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let n_tokens = |sents: &[Vec<String>]| sents.iter().map(Vec::len).sum::<usize>();
+        let n_eos = |sents: &[Vec<String>]| {
+            sents
+                .iter()
+                .flat_map(|sent| sent.iter())
+                .filter(|&token| token == EOS)
+                .count()
+        };
+        assert_eq!(n_tokens(&with_blanks), n_tokens(&without_blanks));
+        assert_eq!(n_eos(&with_blanks), 2);
+        assert_eq!(n_eos(&without_blanks), 2);
+        assert_eq!(with_blanks, without_blanks);
+    }
+
+    #[test]
+    fn sentence_iterator_max_token_len_test() {
+        let long_token = "x".repeat(10_000);
+        let text = format!("a {} b\n{}\nc\n", long_token, long_token);
+
+        let mut iter = SentenceIterator::with_max_token_len(Cursor::new(text), 100);
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["a".to_string(), "b".to_string(), EOS.to_string()]
+        );
+        assert_eq!(iter.next().unwrap().unwrap(), vec![EOS.to_string()]);
+        assert_eq!(
+            iter.next().unwrap().unwrap(),
+            vec!["c".to_string(), EOS.to_string()]
+        );
+        assert!(iter.next().is_none());
+        assert_eq!(iter.n_skipped(), 2);
+
+        // Tokens are only skipped when the maximum length is set.
+        let text = format!("a {} b\n", long_token);
+        let mut iter = SentenceIterator::new(Cursor::new(text));
+        assert_eq!(iter.next().unwrap().unwrap()[1], long_token);
+        assert_eq!(iter.n_skipped(), 0);
+    }
+
+    #[test]
+    fn normalize_digits_test() {
+        assert_eq!(normalize_digits("year2020"), "year0");
+        assert_eq!(normalize_digits("year1999"), normalize_digits("year2020"));
+        assert_eq!(normalize_digits("3.14"), "0.0");
+        assert_eq!(normalize_digits("Danië"), "Danië");
+    }
+
+    #[test]
+    fn sentence_iterator_normalize_digits_test() {
+        let text = "in year2020 and year1999 </s>\n";
+        let mut iter = SentenceIterator::new(Cursor::new(text)).normalize_digits(true);
+        let sentence = iter.next().unwrap().unwrap();
+        assert_eq!(sentence, ["in", "year0", "and", "year0", "</s>", EOS]);
+
+        // Tokens are normalized before their length is checked.
+        let text = "12345 abcde\n";
+        let mut iter =
+            SentenceIterator::with_max_token_len(Cursor::new(text), 3).normalize_digits(true);
+        assert_eq!(iter.next().unwrap().unwrap(), ["0", EOS]);
+        assert_eq!(iter.n_skipped(), 1);
+    }
+
+    #[test]
+    fn sentence_iterator_comment_prefix_test() {
+        let text = "# source: test\n  # tokens: 3\nin a # sentence\n#\n";
+        let sentences = SentenceIterator::new(Cursor::new(text))
+            .comment_prefix("#")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        // Only lines that start with the prefix are comments.
+        assert_eq!(sentences, vec![vec!["in", "a", "#", "sentence", EOS]]);
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1e-4,
+        });
+        for token in sentences.into_iter().flatten() {
+            builder.count(token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        assert_eq!(vocab.n_types(), 5);
+        assert!(vocab.idx("source:").is_none());
+    }
+
+    #[test]
+    fn sentence_iterator_lossy_utf8_test() {
+        let text = b"a b\xffc\nd e\n".to_vec();
+
+        let mut sentences = SentenceIterator::new(Cursor::new(text.clone()));
+        let err = sentences.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(sentences.n_lines(), 1);
+
+        let mut sentences = SentenceIterator::new(Cursor::new(text)).lossy_utf8(true);
+        let decoded = (&mut sentences).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            decoded,
+            vec![vec!["a", "b\u{FFFD}c", EOS], vec!["d", "e", EOS]]
+        );
+        assert_eq!(sentences.n_lossy(), 1);
+        assert_eq!(sentences.n_lines(), 2);
+    }
+
+    #[test]
+    fn sentence_iterator_jsonl_test() {
+        let f = File::open("testdata/sentences.jsonl").unwrap();
+        let mut iter = SentenceIterator::new(BufReader::new(f)).input_format(InputFormat::Jsonl {
+            field: "tokens".to_string(),
+        });
+
+        let sentences = iter.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            sentences,
+            vec![
+                vec!["This", "is", "a", "sentence", ".", EOS],
+                vec!["And", "another", "one", ".", EOS],
+                vec!["Tokens", "may contain", "spaces", EOS],
+            ]
+        );
+        assert_eq!(iter.n_malformed(), 4);
+    }
+
+    #[test]
+    fn sentence_iterator_splits_long_sentences() {
+        let line = (0..10).map(|i| i.to_string()).collect::<Vec<_>>().join(" ");
+        let text = format!("a b c\n{}\nd e f g\n", line);
+        let mut sentences = SentenceIterator::new(text.as_bytes()).max_sentence_len(4);
+
+        let split = sentences.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            split,
+            vec![
+                vec!["a", "b", "c", EOS],
+                vec!["0", "1", "2", "3", EOS],
+                vec!["4", "5", "6", "7", EOS],
+                vec!["8", "9", EOS],
+                vec!["d", "e", "f", "g", EOS],
+            ]
+        );
+        assert_eq!(sentences.n_split(), 1);
+        assert_eq!(sentences.n_lines(), 3);
+    }
+
+    #[test]
+    fn sentence_iterator_pairs_test() {
+        let f = File::open("testdata/pairs.txt").unwrap();
+        let mut iter = SentenceIterator::with_max_token_len(BufReader::new(f), 3)
+            .input_format(InputFormat::Pairs);
+
+        let pairs = iter.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(pairs, vec![vec!["cat", "dog"], vec!["dog", "cat"]]);
+        assert_eq!(iter.n_malformed(), 3);
+        assert_eq!(iter.n_skipped(), 2);
+    }
+
     static CHUNKING_TEST_DATA: &str =
         "a b c\nd e f\ng h i\nj k l\nm n o\np q r\ns t u\nv w x\ny z\n";
 
@@ -360,6 +1745,82 @@ mod tests {
         assert_eq!(start, 36, "Incorrect start index");
     }
 
+    #[test]
+    fn shard_data_test() {
+        let f = File::open("testdata/chunking.txt").unwrap();
+
+        let mut shards = Vec::new();
+        for shard in 0..3 {
+            let (mmap, range) = shard_data_text(&f, shard, 3).unwrap();
+            assert_eq!(
+                &*mmap,
+                CHUNKING_TEST_DATA.as_bytes(),
+                "Memory mapping is incorrect"
+            );
+            shards.push(range);
+        }
+
+        // Shards start where the threads start and cover the corpus.
+        assert_eq!(shards, vec![0..18, 18..36, 36..CHUNKING_TEST_DATA.len()]);
+    }
+
+    #[test]
+    fn chunk_queue_test() {
+        let f = File::open("testdata/chunking.txt").unwrap();
+
+        // Chunks are extended to the end of the line.
+        let queue = ChunkQueue::new(&f, 7).unwrap();
+        assert_eq!(queue.len(), 5);
+        let chunks = (0..queue.len())
+            .map(|_| queue.next_chunk().unwrap())
+            .collect::<Vec<_>>();
+        assert!(chunks.iter().all(|&(pass, _)| pass == 0));
+        assert_eq!(chunks[0].1, b"a b c\nd e f\n");
+        assert_eq!(chunks[4].1, b"y z\n");
+
+        // The next pass starts with the first chunk.
+        assert_eq!(queue.next_chunk(), Some((1, chunks[0].1)));
+    }
+
+    #[test]
+    fn chunk_queue_sentences_processed_once_test() {
+        let f = File::open("testdata/chunking.txt").unwrap();
+        let mut expected = CHUNKING_TEST_DATA
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        expected.sort();
+
+        for &chunk_size in &[1, 7, 100] {
+            for n_threads in 1..=4 {
+                let queue = Arc::new(ChunkQueue::new(&f, chunk_size).unwrap());
+                let children = (0..n_threads)
+                    .map(|_| {
+                        let queue = queue.clone();
+                        thread::spawn(move || {
+                            let mut sentences = Vec::new();
+                            while let Some((0, chunk)) = queue.next_chunk() {
+                                for sentence in SentenceIterator::new(chunk) {
+                                    let mut sentence = sentence.unwrap();
+                                    assert_eq!(sentence.pop().unwrap(), EOS);
+                                    sentences.push(sentence.join(" "));
+                                }
+                            }
+                            sentences
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut sentences = children
+                    .into_iter()
+                    .flat_map(|child| child.join().unwrap())
+                    .collect::<Vec<_>>();
+                sentences.sort();
+                assert_eq!(sentences, expected);
+            }
+        }
+    }
+
     #[test]
     fn deps_thread_data_test() {
         // file size is 55 bytes
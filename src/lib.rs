@@ -1,40 +1,82 @@
 mod config;
 pub use crate::config::{
-    BucketConfig, CommonConfig, DepembedsConfig, LossType, ModelType, NGramConfig,
-    SimpleVocabConfig, SkipGramConfig, SubwordVocabConfig,
+    BucketConfig, CommonConfig, DepembedsConfig, LossType, LrAnchor, ModelType, NGramConfig,
+    SamplerType, SimpleVocabConfig, SkipGramConfig, SubwordComposition, SubwordHash,
+    SubwordVocabConfig,
 };
 
+mod cooc;
+pub use crate::cooc::{CoocWeighting, Cooccurrences};
+
+mod coverage;
+pub use crate::coverage::Coverage;
+
 mod deps;
 pub use crate::deps::{DepIter, Dependency, DependencyIterator};
 
 pub(crate) mod dep_trainer;
 pub use crate::dep_trainer::DepembedsTrainer;
 
+mod early_stopping;
+pub use crate::early_stopping::EarlyStopping;
+
+mod error;
+pub use crate::error::Error;
+
+mod dedup;
+pub use crate::dedup::{SentenceDedup, DEFAULT_DEDUP_COUNTERS_EXP};
+
+mod dim_stats;
+pub use crate::dim_stats::DimensionStats;
+
+mod displacement;
+pub use crate::displacement::{Displacements, TokenDisplacement};
+
+mod histogram;
+pub use crate::histogram::GradientHistogram;
+
 pub(crate) mod hogwild;
+pub use crate::hogwild::{HogwildCounters, ReadOnlyHogwildArray};
 
 pub mod idx;
 
 pub mod io;
-pub use io::{SentenceIterator, WriteModelBinary, WriteModelText, WriteModelWord2Vec};
+pub use io::{
+    SentenceIterator, Tokenizer, WhitespaceTokenizer, WriteModelBinary, WriteModelNorms,
+    WriteModelNpz, WriteModelText, WriteModelWord2Vec,
+};
 
 pub(crate) mod loss;
 
+mod lr;
+pub use crate::lr::LrSchedule;
+
+mod pause;
+pub use crate::pause::PausePoint;
+
 pub(crate) mod sampling;
 
 mod sgd;
-pub use crate::sgd::SGD;
+pub use crate::sgd::{EpochStats, LossMonitor, SGD};
 
 mod train_model;
 pub use crate::train_model::{TrainModel, Trainer};
 
+pub mod source;
+pub use crate::source::{SentenceSource, TextCorpus};
+
 pub(crate) mod skipgram_trainer;
-pub use crate::skipgram_trainer::SkipgramTrainer;
+pub use crate::skipgram_trainer::{context_windows, SkipgramTrainer};
+
+mod stats;
+pub use crate::stats::CorpusStats;
 
-pub(crate) mod util;
+pub mod util;
 
 pub(crate) mod vec_simd;
 
 mod vocab;
 pub use crate::vocab::{
-    simple::SimpleVocab, subword::SubwordVocab, CountedType, Vocab, VocabBuilder, Word,
+    simple::SimpleVocab, subword::SubwordVocab, surface_forms::SurfaceForms, CountedType,
+    LookupKind, Vocab, VocabBuilder, Word,
 };
@@ -104,7 +104,7 @@ fn logistic_function(a: f32) -> f32 {
 mod tests {
     use ndarray::Array1;
 
-    use crate::util::{all_close, close};
+    use crate::util::numeric::{all_close, close};
 
     use super::{log_logistic_loss, logistic_function};
 
@@ -0,0 +1,153 @@
+use crate::{CommonConfig, LrAnchor};
+
+/// Learning rate schedule.
+///
+/// The learning rate decays linearly from the initial learning rate to
+/// the minimum learning rate (by default zero) over the course of
+/// training. Optionally, the learning rate is
+/// warmed up first: during the first `warmup_tokens` tokens, the learning
+/// rate increases linearly from zero to the initial learning rate. The
+/// linear decay then spans the remaining tokens.
+///
+/// If the schedule is anchored to epochs (`LrAnchor::Epochs`), the
+/// learning rate does not decay per token. Instead, it is constant
+/// within an epoch and decreases by *(lr - min_lr) / epochs* at every
+/// epoch boundary. Warmup is still measured in tokens.
+#[derive(Clone, Copy, Debug)]
+pub struct LrSchedule {
+    start_lr: f32,
+    min_lr: f32,
+    anchor: LrAnchor,
+    warmup_tokens: usize,
+    epochs: usize,
+    n_epoch_tokens: usize,
+    n_tokens: usize,
+}
+
+impl LrSchedule {
+    /// Construct a learning rate schedule.
+    ///
+    /// `n_tokens` is the number of tokens in the training corpus, the
+    /// schedule spans `config.epochs * n_tokens` tokens.
+    pub fn new(config: &CommonConfig, n_tokens: usize) -> Self {
+        LrSchedule {
+            start_lr: config.lr,
+            min_lr: config.min_lr,
+            anchor: config.lr_anchor,
+            warmup_tokens: config.warmup_tokens as usize,
+            epochs: config.epochs as usize,
+            n_epoch_tokens: n_tokens,
+            n_tokens: config.epochs as usize * n_tokens,
+        }
+    }
+
+    /// Get the learning rate after processing `n_tokens_processed` tokens.
+    ///
+    /// When the schedule is anchored to epochs, the current epoch is
+    /// derived from the number of processed tokens.
+    pub fn lr(&self, n_tokens_processed: usize) -> f32 {
+        let epoch = n_tokens_processed / self.n_epoch_tokens.max(1);
+        self.lr_in_epoch(epoch, n_tokens_processed)
+    }
+
+    /// Get the learning rate in `epoch` after processing `n_tokens_processed`
+    /// tokens.
+    ///
+    /// This method can be used when the epoch boundaries are known, e.g.
+    /// when a source is read once per epoch. The epoch is only used when
+    /// the schedule is anchored to epochs.
+    pub fn lr_in_epoch(&self, epoch: usize, n_tokens_processed: usize) -> f32 {
+        if n_tokens_processed < self.warmup_tokens {
+            return (n_tokens_processed as f32 / self.warmup_tokens as f32) * self.start_lr;
+        }
+
+        if self.anchor == LrAnchor::Epochs {
+            if self.epochs == 0 {
+                return self.start_lr;
+            }
+
+            let decay_progress = epoch as f32 / self.epochs as f32;
+            return self.min_lr + (1.0 - decay_progress).max(0.0) * (self.start_lr - self.min_lr);
+        }
+
+        let n_decay_tokens = self.n_tokens.saturating_sub(self.warmup_tokens);
+        if n_decay_tokens == 0 {
+            return self.start_lr;
+        }
+
+        let decay_progress =
+            (n_tokens_processed - self.warmup_tokens) as f32 / n_decay_tokens as f32;
+        self.min_lr + (1.0 - decay_progress).max(0.0) * (self.start_lr - self.min_lr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LrSchedule;
+    use crate::config;
+    use crate::util::numeric::close;
+    use crate::{CommonConfig, LrAnchor};
+
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+        epochs: 2,
+        ..config::TEST_COMMON_CONFIG
+    };
+
+    #[test]
+    fn linear_lr_schedule() {
+        let schedule = LrSchedule::new(&TEST_COMMON_CONFIG, 50);
+        assert!(close(schedule.lr(0), 0.05, 1e-6));
+        assert!(close(schedule.lr(50), 0.025, 1e-6));
+        assert!(close(schedule.lr(100), 0.0, 1e-6));
+    }
+
+    #[test]
+    fn lr_schedule_decays_to_min_lr() {
+        let mut config = TEST_COMMON_CONFIG;
+        config.min_lr = 0.01;
+        let schedule = LrSchedule::new(&config, 50);
+        assert!(close(schedule.lr(0), 0.05, 1e-6));
+        assert!(close(schedule.lr(50), 0.03, 1e-6));
+        assert!(close(schedule.lr(100), 0.01, 1e-6));
+
+        // The learning rate does not drop below the floor after the
+        // scheduled number of tokens.
+        assert!(close(schedule.lr(150), 0.01, 1e-6));
+    }
+
+    #[test]
+    fn warmup_lr_schedule() {
+        let mut config = TEST_COMMON_CONFIG;
+        config.warmup_tokens = 20;
+        let schedule = LrSchedule::new(&config, 50);
+
+        // Linear increase during warmup.
+        assert_eq!(schedule.lr(0), 0.0);
+        assert!(close(schedule.lr(10), 0.025, 1e-6));
+        assert_eq!(schedule.lr(20), 0.05);
+
+        // Linear decay over the remaining 80 tokens.
+        assert!(close(schedule.lr(60), 0.025, 1e-6));
+        assert!(close(schedule.lr(100), 0.0, 1e-6));
+    }
+
+    #[test]
+    fn epoch_anchored_lr_schedule() {
+        let mut config = TEST_COMMON_CONFIG;
+        config.lr_anchor = LrAnchor::Epochs;
+        let schedule = LrSchedule::new(&config, 50);
+
+        // The learning rate is constant within an epoch.
+        for n_tokens_processed in 0..50 {
+            assert_eq!(schedule.lr(n_tokens_processed), 0.05);
+        }
+        for n_tokens_processed in 50..100 {
+            assert!(close(schedule.lr(n_tokens_processed), 0.025, 1e-6));
+        }
+        assert!(close(schedule.lr(100), 0.0, 1e-6));
+
+        // Known epoch boundaries take precedence over the token count.
+        assert!(close(schedule.lr_in_epoch(1, 10), 0.025, 1e-6));
+        assert_eq!(schedule.lr_in_epoch(0, 70), 0.05);
+    }
+}
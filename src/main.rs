@@ -13,7 +13,13 @@ static DEFAULT_CLAP_SETTINGS: &[AppSettings] = &[
 
 fn main() {
     // Known subapplications.
-    let apps = vec![subcommands::DepsApp::app(), subcommands::SkipgramApp::app()];
+    let apps = vec![
+        subcommands::CoocApp::app(),
+        subcommands::CoverageApp::app(),
+        subcommands::DepsApp::app(),
+        subcommands::SkipgramApp::app(),
+        subcommands::StatsApp::app(),
+    ];
 
     let cli = App::new("finalfrontier")
         .settings(DEFAULT_CLAP_SETTINGS)
@@ -34,10 +40,15 @@ fn main() {
                 .unwrap();
             write_completion_script(cli, shell.parse::<Shell>().unwrap());
         }
+        "cooc" => subcommands::CoocApp::parse(matches.subcommand_matches("cooc").unwrap()).run(),
+        "coverage" => {
+            subcommands::CoverageApp::parse(matches.subcommand_matches("coverage").unwrap()).run()
+        }
         "deps" => subcommands::DepsApp::parse(matches.subcommand_matches("deps").unwrap()).run(),
         "skipgram" => {
             subcommands::SkipgramApp::parse(matches.subcommand_matches("skipgram").unwrap()).run()
         }
+        "stats" => subcommands::StatsApp::parse(matches.subcommand_matches("stats").unwrap()).run(),
         _unknown => unreachable!(),
     }
 }
@@ -0,0 +1,221 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A point at which Hogwild training workers pause together.
+///
+/// Some tasks, such as checkpointing or scanning for non-finite values,
+/// need all training workers to stop updating the parameters for a
+/// moment. A pause is requested with `request_pause`. Workers poll the
+/// pause point between training examples with `pause_if_requested`.
+/// Once all workers have reached the pause point, one of them runs the
+/// task, after which all workers resume together.
+///
+/// Polling only reads an atomic flag, so it is cheap enough to do after
+/// every example. The pause point is shared between workers by cloning.
+///
+/// A pause waits for all workers that are still training. A worker that
+/// finished training must call `finish`, so that the other workers do
+/// not wait for it. If all workers finished, a requested pause is never
+/// handled.
+#[derive(Clone)]
+pub struct PausePoint {
+    inner: Arc<PauseInner>,
+}
+
+struct PauseInner {
+    requested: AtomicBool,
+    state: Mutex<PauseState>,
+    resumed: Condvar,
+}
+
+struct PauseState {
+    /// Workers that are still training.
+    n_workers: usize,
+
+    /// Workers that reached the current pause.
+    n_paused: usize,
+
+    /// Number of pauses that were handled.
+    n_pauses: usize,
+}
+
+impl PausePoint {
+    /// Construct a pause point for `n_workers` workers.
+    pub fn new(n_workers: usize) -> Self {
+        assert!(n_workers > 0, "A pause point needs at least one worker");
+
+        PausePoint {
+            inner: Arc::new(PauseInner {
+                requested: AtomicBool::new(false),
+                state: Mutex::new(PauseState {
+                    n_workers,
+                    n_paused: 0,
+                    n_pauses: 0,
+                }),
+                resumed: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Deregister a worker that finished training.
+    ///
+    /// The worker should not use the pause point afterwards. If the other
+    /// workers are paused, the pause is handled once they have all
+    /// reached the pause point.
+    pub fn finish(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        assert!(state.n_workers > 0, "All workers already finished");
+        state.n_workers -= 1;
+
+        // Paused workers may only have waited for this worker.
+        self.inner.resumed.notify_all();
+    }
+
+    /// Returns `true` if a pause was requested and not yet handled.
+    pub fn is_pause_requested(&self) -> bool {
+        self.inner.requested.load(Ordering::Acquire)
+    }
+
+    /// Pause if a pause was requested.
+    ///
+    /// If a pause was requested, this method blocks until all workers
+    /// that are still training have reached the pause point. Then
+    /// `at_pause` is called in one of the workers, while the other
+    /// workers wait. All workers return after `at_pause` has finished.
+    ///
+    /// Returns `true` if the worker paused.
+    pub fn pause_if_requested<F>(&self, at_pause: F) -> bool
+    where
+        F: FnOnce(),
+    {
+        if !self.is_pause_requested() {
+            return false;
+        }
+
+        let mut state = self.inner.state.lock().unwrap();
+
+        // The pause may have been handled since the flag was read.
+        if !self.is_pause_requested() {
+            return false;
+        }
+
+        state.n_paused += 1;
+        let n_pauses = state.n_pauses;
+        loop {
+            if state.n_pauses != n_pauses {
+                return true;
+            }
+
+            if state.n_paused == state.n_workers {
+                // Every worker has passed the check, so the flag can be
+                // cleared before the workers resume.
+                self.inner.requested.store(false, Ordering::Release);
+                at_pause();
+                state.n_paused = 0;
+                state.n_pauses += 1;
+                self.inner.resumed.notify_all();
+                return true;
+            }
+
+            state = self.inner.resumed.wait(state).unwrap();
+        }
+    }
+
+    /// Request that all workers pause.
+    pub fn request_pause(&self) {
+        self.inner.requested.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::PausePoint;
+
+    #[test]
+    fn workers_pause_and_resume_together() {
+        const N_WORKERS: usize = 4;
+
+        let pause = PausePoint::new(N_WORKERS);
+        let n_examples = Arc::new(AtomicUsize::new(0));
+        let n_tasks = Arc::new(AtomicUsize::new(0));
+
+        let workers = (0..N_WORKERS)
+            .map(|_| {
+                let pause = pause.clone();
+                let n_examples = n_examples.clone();
+                let n_tasks = n_tasks.clone();
+                thread::spawn(move || loop {
+                    n_examples.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(1));
+
+                    // The task sees the number of examples of the paused
+                    // workers, since no worker trains during the pause.
+                    let paused = pause.pause_if_requested(|| {
+                        n_tasks.fetch_add(1, Ordering::SeqCst);
+                    });
+                    if paused {
+                        return n_examples.load(Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        thread::sleep(Duration::from_millis(20));
+        pause.request_pause();
+
+        let seen = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect::<Vec<_>>();
+
+        // The task ran once and all workers resumed after all of them
+        // stopped training.
+        assert_eq!(n_tasks.load(Ordering::SeqCst), 1);
+        assert!(!pause.is_pause_requested());
+        let n_examples = n_examples.load(Ordering::SeqCst);
+        assert!(seen.iter().all(|&seen| seen == n_examples));
+    }
+
+    #[test]
+    fn finished_workers_are_not_waited_for() {
+        const N_WORKERS: usize = 3;
+
+        let pause = PausePoint::new(N_WORKERS);
+        let n_tasks = Arc::new(AtomicUsize::new(0));
+
+        let workers = (0..N_WORKERS)
+            .map(|worker| {
+                let pause = pause.clone();
+                let n_tasks = n_tasks.clone();
+                thread::spawn(move || {
+                    // The first worker finishes while the others train.
+                    if worker == 0 {
+                        thread::sleep(Duration::from_millis(20));
+                        pause.finish();
+                        return;
+                    }
+
+                    while !pause.pause_if_requested(|| {
+                        n_tasks.fetch_add(1, Ordering::SeqCst);
+                    }) {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Request the pause before the first worker finishes.
+        pause.request_pause();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(n_tasks.load(Ordering::SeqCst), 1);
+        assert!(!pause.is_pause_requested());
+    }
+}
@@ -1,10 +1,17 @@
+use std::sync::Arc;
+
 use rand::distributions::{Distribution, Uniform};
 use rand::Rng;
 use zipf::ZipfDistribution;
 
+use crate::SamplerType;
+
 pub trait RangeGenerator: Iterator<Item = usize> {
     /// Get the upper bound in *[0, upper_bound)*.
     fn upper_bound(&self) -> usize;
+
+    /// Get the probability of every integer in *[0, upper_bound)*.
+    fn probabilities(&self) -> Vec<f32>;
 }
 
 /// Exponent to use for the Zipf's distribution.
@@ -12,6 +19,13 @@ pub trait RangeGenerator: Iterator<Item = usize> {
 /// This is the exponent s in f(k) = 1 / (k^s H_{N, s})
 const ZIPF_RANGE_GENERATOR_DEFAULT_EXPONENT: f64 = 0.5;
 
+/// Maximum number of draws to find a negative that is not rejected.
+///
+/// When the negative distribution has (almost) no mass outside the
+/// rejected outputs, e.g. for tiny vocabularies, the last draw is used
+/// rather than sampling forever.
+pub(crate) const MAX_NEGATIVE_DRAWS: usize = 100;
+
 /// An iterator that draws from *[0, n)* with integer weights.
 ///
 /// This iterator returns integers from *[0, n)*, where the probability of
@@ -73,6 +87,19 @@ where
     fn upper_bound(&self) -> usize {
         self.prefix_sum.len()
     }
+
+    fn probabilities(&self) -> Vec<f32> {
+        let sum = (self.upper - 1) as f32;
+        let mut prev = 0;
+        self.prefix_sum
+            .iter()
+            .map(|&prefix_sum| {
+                let weight = prefix_sum - prev;
+                prev = prefix_sum;
+                weight as f32 / sum
+            })
+            .collect()
+    }
 }
 
 /// An iterator that draws from *[0, n)* with a Zipfian distribution.
@@ -144,6 +171,200 @@ where
     fn upper_bound(&self) -> usize {
         self.upper_bound
     }
+
+    /// Get the probability of every integer in *[0, upper_bound)*.
+    ///
+    /// The probability of *k* is *(k + 1)^-s / H_{N, s}*.
+    fn probabilities(&self) -> Vec<f32> {
+        let weights = (1..=self.upper_bound)
+            .map(|rank| (rank as f64).powf(-self.exponent))
+            .collect::<Vec<_>>();
+        let norm: f64 = weights.iter().sum();
+        weights.into_iter().map(|w| (w / norm) as f32).collect()
+    }
+}
+
+/// An iterator that draws from *[0, n)* with Walker's alias method.
+///
+/// Every draw takes constant time, regardless of *n*. The alias table
+/// stores a probability and an alias for every integer, so it uses
+/// *O(n)* memory. The table is shared between clones.
+///
+/// See: Vose, 1991, A linear algorithm for generating random numbers
+/// with a given distribution.
+#[derive(Clone)]
+pub struct AliasRangeGenerator<R> {
+    table: Arc<AliasTable>,
+    uniform: Uniform<usize>,
+    rng: R,
+}
+
+#[derive(Debug)]
+struct AliasTable {
+    /// Probability of drawing the integer itself, rather than its alias.
+    prob: Vec<f32>,
+
+    /// Alias of every integer.
+    alias: Vec<usize>,
+
+    /// Normalized probabilities of the integers.
+    weights: Vec<f32>,
+}
+
+impl<R> AliasRangeGenerator<R>
+where
+    R: Rng,
+{
+    /// Construct an alias generator that draws integers with the given weights.
+    pub fn new(rng: R, weights: &[f64]) -> Self {
+        assert!(!weights.is_empty(), "Cannot sample from zero elements.");
+
+        let n = weights.len();
+        let norm: f64 = weights.iter().sum();
+        let mut scaled = weights
+            .iter()
+            .map(|&w| w / norm * n as f64)
+            .collect::<Vec<_>>();
+
+        let mut prob = vec![1f32; n];
+        let mut alias = (0..n).collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<_>, Vec<_>) = (0..n).partition(|&i| scaled[i] < 1.);
+        while let (Some(&s), Some(&l)) = (small.last(), large.last()) {
+            small.pop();
+            prob[s] = scaled[s] as f32;
+            alias[s] = l;
+
+            scaled[l] -= 1. - scaled[s];
+            if scaled[l] < 1. {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // Remaining integers only differ from 1 by rounding errors and
+        // keep the probability 1.
+
+        AliasRangeGenerator {
+            table: Arc::new(AliasTable {
+                prob,
+                alias,
+                weights: weights.iter().map(|&w| (w / norm) as f32).collect(),
+            }),
+            uniform: Uniform::new(0, n),
+            rng,
+        }
+    }
+
+    /// Construct an alias generator with the distribution of `ZipfRangeGenerator`.
+    pub fn zipf(rng: R, upper_bound: usize, exponent: f64) -> Self {
+        let weights = (1..=upper_bound)
+            .map(|rank| (rank as f64).powf(-exponent))
+            .collect::<Vec<_>>();
+        Self::new(rng, &weights)
+    }
+
+    /// Get the number of entries in the alias table.
+    #[cfg(test)]
+    fn table_len(&self) -> usize {
+        self.table.prob.len()
+    }
+}
+
+impl<R> Iterator for AliasRangeGenerator<R>
+where
+    R: Rng,
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let idx = self.uniform.sample(&mut self.rng);
+        if self.rng.gen_range(0f32, 1f32) < self.table.prob[idx] {
+            Some(idx)
+        } else {
+            Some(self.table.alias[idx])
+        }
+    }
+}
+
+impl<R> RangeGenerator for AliasRangeGenerator<R>
+where
+    R: Rng,
+{
+    fn upper_bound(&self) -> usize {
+        self.table.prob.len()
+    }
+
+    fn probabilities(&self) -> Vec<f32> {
+        self.table.weights.clone()
+    }
+}
+
+/// Generator of negative samples.
+///
+/// Both samplers draw *k* in *[0, n)* with probability proportional to
+/// *(k + 1)^-s*, they differ in speed and memory use.
+#[derive(Clone)]
+pub enum NegativeRangeGenerator<R> {
+    /// Sample with `ZipfRangeGenerator`.
+    Zipf(ZipfRangeGenerator<R>),
+
+    /// Sample with `AliasRangeGenerator`.
+    Alias(AliasRangeGenerator<R>),
+}
+
+impl<R> NegativeRangeGenerator<R>
+where
+    R: Rng,
+{
+    pub fn new(rng: R, upper_bound: usize, exponent: f64, sampler: SamplerType) -> Self {
+        match sampler {
+            SamplerType::Zipf => NegativeRangeGenerator::Zipf(
+                ZipfRangeGenerator::new_with_exponent(rng, upper_bound, exponent),
+            ),
+            SamplerType::Alias => {
+                NegativeRangeGenerator::Alias(AliasRangeGenerator::zipf(rng, upper_bound, exponent))
+            }
+        }
+    }
+
+    /// Construct a generator that draws *k* with probability `probabilities[k]`.
+    ///
+    /// Draws use the alias method.
+    pub fn from_probabilities(rng: R, probabilities: &[f64]) -> Self {
+        NegativeRangeGenerator::Alias(AliasRangeGenerator::new(rng, probabilities))
+    }
+}
+
+impl<R> Iterator for NegativeRangeGenerator<R>
+where
+    R: Rng,
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            NegativeRangeGenerator::Zipf(range_gen) => range_gen.next(),
+            NegativeRangeGenerator::Alias(range_gen) => range_gen.next(),
+        }
+    }
+}
+
+impl<R> RangeGenerator for NegativeRangeGenerator<R>
+where
+    R: Rng,
+{
+    fn upper_bound(&self) -> usize {
+        match self {
+            NegativeRangeGenerator::Zipf(range_gen) => range_gen.upper_bound(),
+            NegativeRangeGenerator::Alias(range_gen) => range_gen.upper_bound(),
+        }
+    }
+
+    fn probabilities(&self) -> Vec<f32> {
+        match self {
+            NegativeRangeGenerator::Zipf(range_gen) => range_gen.probabilities(),
+            NegativeRangeGenerator::Alias(range_gen) => range_gen.probabilities(),
+        }
+    }
 }
 
 /// A banded range generator.
@@ -202,6 +423,17 @@ where
     fn upper_bound(&self) -> usize {
         self.inner.upper_bound() * self.band_size
     }
+
+    fn probabilities(&self) -> Vec<f32> {
+        let mut probs = Vec::with_capacity(self.upper_bound());
+        for band_prob in self.inner.probabilities() {
+            for _ in 0..self.band_size {
+                probs.push(band_prob / self.band_size as f32);
+            }
+        }
+
+        probs
+    }
 }
 
 #[cfg(test)]
@@ -209,8 +441,11 @@ mod tests {
     use rand::SeedableRng;
     use rand_xorshift::XorShiftRng;
 
-    use super::{BandedRangeGenerator, RangeGenerator, WeightedRangeGenerator, ZipfRangeGenerator};
-    use crate::util::{all_close, close};
+    use super::{
+        AliasRangeGenerator, BandedRangeGenerator, RangeGenerator, WeightedRangeGenerator,
+        ZipfRangeGenerator,
+    };
+    use crate::util::numeric::{all_close, close};
 
     const SEED: [u8; 16] = [
         0xe9, 0xfe, 0xf0, 0xfb, 0x6a, 0x23, 0x2a, 0xb3, 0x7c, 0xce, 0x27, 0x9b, 0x56, 0xac, 0xdb,
@@ -252,6 +487,40 @@ mod tests {
         assert!(all_close(&[0.4, 0.1, 0.3, 0.2], &probs, 1e-2));
     }
 
+    #[test]
+    fn weighted_range_generator_probabilities() {
+        let rng = XorShiftRng::from_seed(SEED);
+        let weighted_gen = WeightedRangeGenerator::new(rng, &[4, 1, 3, 2]);
+        assert!(all_close(
+            &[0.4, 0.1, 0.3, 0.2],
+            &weighted_gen.probabilities(),
+            1e-6
+        ));
+    }
+
+    #[test]
+    fn zipf_range_generator_probabilities() {
+        let rng = XorShiftRng::from_seed(SEED);
+        let zipf_gen = ZipfRangeGenerator::new_with_exponent(rng, 4, 0.5);
+        let probs = zipf_gen.probabilities();
+        assert!(close(1.0f32, probs.iter().sum(), 1e-6));
+
+        // Probabilities are proportional to rank^-exponent.
+        let norm: f32 = (1..=4).map(|rank| (rank as f32).powf(-0.5)).sum();
+        let expected = (1..=4)
+            .map(|rank| (rank as f32).powf(-0.5) / norm)
+            .collect::<Vec<_>>();
+        assert!(all_close(&expected, &probs, 1e-6));
+
+        let rng = XorShiftRng::from_seed(SEED2);
+        let banded_gen = BandedRangeGenerator::new(rng, zipf_gen, 2);
+        let banded_probs = banded_gen.probabilities();
+        assert_eq!(banded_probs.len(), 8);
+        assert!(close(1.0f32, banded_probs.iter().sum(), 1e-6));
+        assert!(close(banded_probs[0], probs[0] / 2., 1e-6));
+        assert!(close(banded_probs[7], probs[3] / 2., 1e-6));
+    }
+
     #[test]
     fn zipf_range_generator_test() {
         const DRAWS: usize = 20_000;
@@ -315,4 +584,51 @@ mod tests {
         ));
         assert!(close(1.0f32, probs.iter().cloned().sum(), 1e-2));
     }
+
+    #[test]
+    fn alias_range_generator_matches_zipf_distribution() {
+        const DRAWS: usize = 100_000;
+        const N: usize = 50;
+
+        let zipf_gen = ZipfRangeGenerator::new_with_exponent(XorShiftRng::from_seed(SEED), N, 0.75);
+        let alias_gen = AliasRangeGenerator::zipf(XorShiftRng::from_seed(SEED2), N, 0.75);
+        let expected = zipf_gen.probabilities();
+        assert!(all_close(&expected, &alias_gen.probabilities(), 1e-6));
+
+        // The alias table has one entry per integer.
+        assert_eq!(alias_gen.table_len(), N);
+        assert_eq!(alias_gen.upper_bound(), N);
+
+        let mut hits = vec![0; N];
+        for idx in alias_gen.take(DRAWS) {
+            hits[idx] += 1;
+        }
+        let probs = hits
+            .into_iter()
+            .map(|count| count as f32 / DRAWS as f32)
+            .collect::<Vec<_>>();
+        assert!(all_close(&expected, &probs, 5e-3));
+    }
+
+    #[test]
+    fn alias_range_generator_weights() {
+        const DRAWS: usize = 20_000;
+
+        let alias_gen = AliasRangeGenerator::new(XorShiftRng::from_seed(SEED), &[4., 1., 3., 2.]);
+        assert!(all_close(
+            &[0.4, 0.1, 0.3, 0.2],
+            &alias_gen.probabilities(),
+            1e-6
+        ));
+
+        let mut hits = vec![0; 4];
+        for idx in alias_gen.take(DRAWS) {
+            hits[idx] += 1;
+        }
+        let probs = hits
+            .into_iter()
+            .map(|count| count as f32 / DRAWS as f32)
+            .collect::<Vec<_>>();
+        assert!(all_close(&[0.4, 0.1, 0.3, 0.2], &probs, 1e-2));
+    }
 }
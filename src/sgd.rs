@@ -1,10 +1,163 @@
-use ndarray::{Array1, ArrayView1, ArrayViewMut1};
+use std::iter;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::hogwild::Hogwild;
+use ndarray::{s, Array1, ArrayView1, ArrayView2, ArrayViewMut1};
+
+use crate::early_stopping::EarlyStopping;
+use crate::histogram::GradientHistogram;
+use crate::hogwild::{Hogwild, HogwildCounters};
 use crate::idx::WordIdx;
 use crate::loss::log_logistic_loss;
+use crate::lr::LrSchedule;
+use crate::source::SentenceSource;
 use crate::train_model::{NegativeSamples, TrainIterFrom, TrainModel, Trainer};
-use crate::vec_simd::scaled_add;
+use crate::util::EOS;
+use crate::vec_simd::{dot, scaled_add};
+use crate::{Error, Vocab};
+
+/// Smoothing factor of the running loss.
+///
+/// The running loss is updated with the mean loss of every sentence,
+/// weighted by this factor.
+const LOSS_SMOOTHING: f32 = 0.01;
+
+/// Handle to the smoothed running loss of training.
+///
+/// The loss is an exponential moving average of the per-sentence loss
+/// that is updated by all clones of an SGD. The loss is stored in an
+/// atomic, so it can be polled from another thread at any time, e.g.
+/// for live monitoring. Clones of a handle refer to the same loss.
+#[derive(Clone)]
+pub struct LossMonitor(Arc<AtomicU32>);
+
+impl LossMonitor {
+    fn new() -> Self {
+        LossMonitor(Arc::new(AtomicU32::new(f32::NAN.to_bits())))
+    }
+
+    /// Get the smoothed loss.
+    ///
+    /// Returns NaN when no sentence was trained on yet.
+    pub fn current_loss(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Add the mean loss of a sentence to the running loss.
+    fn update(&self, loss: f32) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let current = f32::from_bits(bits);
+                let smoothed = if current.is_nan() {
+                    loss
+                } else {
+                    (1. - LOSS_SMOOTHING) * current + LOSS_SMOOTHING * loss
+                };
+                Some(smoothed.to_bits())
+            });
+    }
+}
+
+/// Training statistics of an epoch.
+///
+/// The statistics show how much of the corpus drove updates: tokens
+/// that are discarded by subsampling and unknown tokens are never focus
+/// tokens.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EpochStats {
+    /// Number of focus tokens that were trained on.
+    pub n_tokens: usize,
+
+    /// Number of focus-context training examples.
+    pub n_examples: usize,
+
+    /// Number of negatives that were drawn.
+    pub n_negatives: usize,
+
+    /// Number of tokens that were discarded by subsampling.
+    pub n_discarded: usize,
+
+    /// Number of unknown tokens.
+    pub n_oov: usize,
+}
+
+impl EpochStats {
+    /// Add the statistics of another thread.
+    pub fn merge(&mut self, other: &EpochStats) {
+        self.n_tokens += other.n_tokens;
+        self.n_examples += other.n_examples;
+        self.n_negatives += other.n_negatives;
+        self.n_discarded += other.n_discarded;
+        self.n_oov += other.n_oov;
+    }
+}
+
+/// Per-epoch counts of the examples of focus tokens.
+///
+/// The counts are shared between the clones of an SGD. Two sets of
+/// counts are used alternately: the counts of the next epoch are reset
+/// before the epoch is published, so that clones never count examples
+/// in counts that are being reset.
+#[derive(Clone)]
+struct ExampleCounts {
+    counts: Arc<[HogwildCounters; 2]>,
+    /// The last epoch for which the counts were reset.
+    claimed_epoch: Arc<AtomicUsize>,
+    /// The epoch in which examples are counted.
+    epoch: Arc<AtomicUsize>,
+    max_examples: u64,
+}
+
+impl ExampleCounts {
+    fn new(len: usize, max_examples: u64) -> Self {
+        ExampleCounts {
+            counts: Arc::new([HogwildCounters::new(len), HogwildCounters::new(len)]),
+            // The counts of the first epoch start at zero.
+            claimed_epoch: Arc::new(AtomicUsize::new(1)),
+            epoch: Arc::new(AtomicUsize::new(1)),
+            max_examples,
+        }
+    }
+
+    fn current(&self) -> &HogwildCounters {
+        &self.counts[self.epoch.load(Ordering::Acquire) % 2]
+    }
+
+    /// Count an example of the focus token `idx`.
+    ///
+    /// Returns `false` if the token already has the maximum number of
+    /// examples in this epoch.
+    fn count(&self, idx: usize) -> bool {
+        self.current().fetch_increment(idx) < self.max_examples
+    }
+
+    /// Start epoch `epoch`, which must follow `epoch - 1`.
+    ///
+    /// Only the first clone to start the epoch resets its counts. Clones
+    /// that did not start the epoch yet count their examples in the new
+    /// epoch once it is published.
+    fn start_epoch(&self, epoch: usize) {
+        if self
+            .claimed_epoch
+            .compare_exchange(epoch - 1, epoch, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+        {
+            self.counts[epoch % 2].reset();
+            self.epoch.store(epoch, Ordering::Release);
+        }
+    }
+
+    /// Get the number of trained examples of every focus token.
+    fn snapshot(&self) -> Vec<u64> {
+        // Counts are incremented past the cap by rejected examples.
+        self.current()
+            .snapshot()
+            .into_iter()
+            .map(|count| count.min(self.max_examples))
+            .collect()
+    }
+}
 
 /// Stochastic gradient descent
 ///
@@ -16,6 +169,15 @@ pub struct SGD<T> {
     n_examples: Hogwild<usize>,
     n_tokens_processed: Hogwild<usize>,
     sgd_impl: NegativeSamplingSGD,
+    token_weights: Option<Arc<Vec<f32>>>,
+    freq_lr_scales: Option<Arc<Vec<f32>>>,
+    frozen_inputs: Option<Arc<Vec<bool>>>,
+    input_updates: Option<HogwildCounters>,
+    micro_batch: usize,
+    loss_monitor: LossMonitor,
+    epoch_stats: Option<Vec<EpochStats>>,
+    epoch: usize,
+    example_counts: Option<ExampleCounts>,
 }
 
 impl<T> SGD<T>
@@ -28,7 +190,22 @@ where
 
     /// Construct a new SGD instance,
     pub fn new(model: TrainModel<T>) -> Self {
-        let sgd_impl = NegativeSamplingSGD::new(model.config().negative_samples as usize);
+        let sgd_impl = NegativeSamplingSGD::new(model.config().negative_samples as usize)
+            .dropout(model.config().dropout)
+            .positive_weight(model.config().positive_weight);
+        let example_counts = if model.config().max_examples_per_token > 0 {
+            Some(ExampleCounts::new(
+                model.input_vocab().len(),
+                model.config().max_examples_per_token as u64,
+            ))
+        } else {
+            None
+        };
+        let freq_lr_scales = if model.config().freq_lr_scaling {
+            Some(Arc::new(freq_lr_scales(model.input_vocab())))
+        } else {
+            None
+        };
 
         SGD {
             loss: Hogwild::default(),
@@ -36,13 +213,202 @@ where
             n_examples: Hogwild::default(),
             n_tokens_processed: Hogwild::default(),
             sgd_impl,
+            token_weights: None,
+            freq_lr_scales,
+            frozen_inputs: None,
+            input_updates: None,
+            micro_batch: 1,
+            loss_monitor: LossMonitor::new(),
+            epoch_stats: None,
+            epoch: 0,
+            example_counts,
+        }
+    }
+
+    /// Set the weights of focus tokens.
+    ///
+    /// The learning rate of each training example is multiplied by the
+    /// weight of its focus token. `weights` must contain a weight for
+    /// every word in the input vocabulary, indexed by word index. The
+    /// weights are shared between clones of this SGD.
+    pub fn set_token_weights(&mut self, weights: Vec<f32>) {
+        assert_eq!(
+            weights.len(),
+            self.model.input_vocab().len(),
+            "Number of token weights does not match the vocabulary size"
+        );
+        self.token_weights = Some(Arc::new(weights));
+    }
+
+    /// Freeze the input embeddings of focus tokens.
+    ///
+    /// The input rows of a frozen token (its word and subword rows) are
+    /// not updated when the token is the focus token. Output embeddings
+    /// are still updated, so that other words adapt to frozen words. In
+    /// subword vocabularies, the rows of n-grams that are shared with
+    /// other tokens are still updated through those tokens. `frozen`
+    /// must contain a flag for every word in the input vocabulary,
+    /// indexed by word index.
+    pub fn freeze_inputs(&mut self, frozen: Vec<bool>) {
+        assert_eq!(
+            frozen.len(),
+            self.model.input_vocab().len(),
+            "Number of frozen flags does not match the vocabulary size"
+        );
+        self.frozen_inputs = Some(Arc::new(frozen));
+    }
+
+    /// Freeze output embeddings.
+    ///
+    /// Frozen output embeddings are not updated when they are predicted
+    /// as a positive or negative output. `frozen` must contain a flag for
+    /// every output row, indexed by output index.
+    pub fn freeze_outputs(&mut self, frozen: Vec<bool>) {
+        assert_eq!(
+            frozen.len(),
+            self.model.trainer().n_output_types(),
+            "Number of frozen flags does not match the number of outputs"
+        );
+        self.sgd_impl.frozen_outputs = Some(Arc::new(frozen));
+    }
+
+    /// Train with fixed side features.
+    ///
+    /// The side features of a token are stored in the last dimensions of
+    /// its word row in the input matrix, so that the learned embedding and
+    /// the features are concatenated. The feature dimensions are used in
+    /// the dot products with the output embeddings, but they are never
+    /// updated, only the learned dimensions are. The feature dimensions of
+    /// subword rows are set to zero, so that the features of a token are
+    /// scaled like the rest of its mean input embedding.
+    ///
+    /// `features` must contain a row for every word in the input
+    /// vocabulary, indexed by word index, and it must have fewer columns
+    /// than the model has dimensions.
+    pub fn set_side_features(&mut self, features: ArrayView2<f32>) {
+        self.model.set_side_features(features);
+        self.sgd_impl.n_frozen_dims = features.ncols();
+    }
+
+    /// Count the updates of input embeddings.
+    ///
+    /// Every time a focus token is trained on, the counts of its input
+    /// rows (its word and subword rows) are incremented. The counts are
+    /// shared between clones of this SGD that are made after calling this
+    /// method. The counts can be used to find rows that were never trained,
+    /// see `TrainModel::zero_untrained_inputs`.
+    pub fn track_input_updates(&mut self) {
+        self.input_updates = Some(HogwildCounters::new(
+            self.model.input_matrix().view().nrows(),
+        ));
+    }
+
+    /// Get the number of updates of each input embedding.
+    ///
+    /// The word rows of frozen tokens (see `freeze_inputs`) are counted
+    /// as updated once, since they hold given rather than random
+    /// embeddings. Returns `None` if counting updates was not enabled.
+    pub fn input_update_counts(&self) -> Option<Vec<u64>> {
+        let mut counts = self.input_updates.as_ref().map(HogwildCounters::snapshot)?;
+        if let Some(ref frozen) = self.frozen_inputs {
+            for (count, _) in counts.iter_mut().zip(frozen.iter()).filter(|(_, &f)| f) {
+                *count = (*count).max(1);
+            }
+        }
+
+        Some(counts)
+    }
+
+    /// Update input embeddings in micro-batches.
+    ///
+    /// By default, the input embeddings of a focus token are updated after
+    /// every training example. With a micro-batch size *n > 1*, the input
+    /// deltas of up to *n* consecutive examples of a focus token are
+    /// accumulated locally and added to the shared input embeddings at
+    /// once. This reduces writes to rows that are shared between threads,
+    /// at the cost of other threads seeing the updates later. The size *1*
+    /// gives the unbatched updates.
+    pub fn set_micro_batch(&mut self, micro_batch: usize) {
+        assert!(micro_batch > 0, "The micro-batch size should be positive");
+        self.micro_batch = micro_batch;
+    }
+
+    /// Collect a histogram of gradient norms.
+    ///
+    /// Every clone of this SGD collects its own histogram of the norms of
+    /// the per-example gradients of the input embedding, starting with
+    /// the clone. The histograms should be merged after training, see
+    /// `GradientHistogram::merge`.
+    pub fn enable_gradient_histogram(&mut self) {
+        self.sgd_impl.grad_hist = Some(GradientHistogram::new());
+    }
+
+    /// Get the histogram of gradient norms.
+    ///
+    /// Returns `None` if collection of gradient norms was not enabled.
+    pub fn gradient_histogram(&self) -> Option<&GradientHistogram> {
+        self.sgd_impl.grad_hist.as_ref()
+    }
+
+    /// Collect training statistics per epoch.
+    ///
+    /// Every clone of this SGD collects its own statistics, starting with
+    /// the clone. The statistics of an epoch are collected until the next
+    /// call of `start_epoch`, which is called by the `train_source_*`
+    /// methods before every epoch. The statistics of threads should be
+    /// merged after training, see `EpochStats::merge`.
+    pub fn enable_epoch_stats(&mut self) {
+        self.epoch_stats = Some(Vec::new());
+    }
+
+    /// Start a new epoch.
+    ///
+    /// This starts collecting the statistics of a new epoch, if collection
+    /// of statistics is enabled. If the number of examples per focus token
+    /// is capped (see `CommonConfig::max_examples_per_token`), the counts
+    /// of examples are reset. The counts are shared between the clones of
+    /// this SGD. Every clone must start each epoch once, including the
+    /// first epoch. The counts are only reset by the first clone that
+    /// starts an epoch, so the cap applies across all clones.
+    pub fn start_epoch(&mut self) {
+        if let Some(ref mut epoch_stats) = self.epoch_stats {
+            epoch_stats.push(EpochStats::default());
+        }
+        self.epoch += 1;
+        if let Some(ref counts) = self.example_counts {
+            counts.start_epoch(self.epoch);
         }
     }
+
+    /// Get the number of examples of each focus token in the current epoch.
+    ///
+    /// Returns `None` if the number of examples per focus token is not
+    /// capped.
+    pub fn example_counts(&self) -> Option<Vec<u64>> {
+        self.example_counts.as_ref().map(ExampleCounts::snapshot)
+    }
+
+    /// Get the training statistics of every epoch.
+    ///
+    /// Returns `None` if collection of statistics was not enabled.
+    pub fn epoch_stats(&self) -> Option<&[EpochStats]> {
+        self.epoch_stats.as_deref()
+    }
+
     /// Get the training model associated with this SGD.
     pub fn model(&self) -> &TrainModel<T> {
         &self.model
     }
 
+    /// Find input embeddings with non-finite values.
+    ///
+    /// See `TrainModel::check_finite_inputs`. Since the embedding matrices
+    /// are shared between clones of this SGD, this can be called from a
+    /// thread other than the training threads.
+    pub fn check_finite_inputs(&mut self, reset: bool) -> Vec<usize> {
+        self.model.check_finite_inputs(reset)
+    }
+
     /// Get the number of tokens that are processed by this SGD.
     pub fn n_tokens_processed(&self) -> usize {
         *self.n_tokens_processed
@@ -56,6 +422,14 @@ where
         *self.loss / *self.n_examples as f32
     }
 
+    /// Get a handle to the smoothed running loss.
+    ///
+    /// The handle is shared between clones of this SGD, so that the loss
+    /// of all training threads can be monitored from another thread.
+    pub fn loss_monitor(&self) -> LossMonitor {
+        self.loss_monitor.clone()
+    }
+
     /// Update the model parameters using the given sentence.
     ///
     /// This applies a gradient descent step on the sentence, with the given
@@ -67,24 +441,436 @@ where
         for<'a> &'a T::Focus: IntoIterator<Item = u64>,
         T::Focus: WordIdx,
     {
-        for (focus, contexts) in self.model.trainer().train_iter_from(sentence) {
+        let max_ngrams = match self.model.config().max_ngrams_per_token {
+            0 => usize::MAX,
+            max_ngrams => max_ngrams as usize,
+        };
+
+        let mut sentence_loss = 0.;
+        let mut sentence_examples = 0;
+        let mut sentence_tokens = 0;
+        let n_negatives = self.sgd_impl.n_negatives;
+
+        let mut train_iter = self.model.trainer().train_iter_from(sentence);
+        for (focus, contexts) in &mut train_iter {
+            let contexts = contexts.into_iter().collect::<Vec<_>>();
+
+            // With multiple senses, the word row of the focus token is
+            // replaced by the row of the sense that fits the context best.
+            let sense = self.closest_sense(&focus, &contexts);
+            let sense_idx = if sense > 0 {
+                Some(self.model.sense_input_idx(focus.word_idx() as usize, sense) as u64)
+            } else {
+                None
+            };
+
             // Update parameters for the token focus token i and the
             // context token j.
-            let input_embed = self.model.mean_input_embedding(&focus);
+            let input_embed = self.model.mean_sense_embedding(&focus, sense);
+            let mut focus_lr = lr;
+            if let Some(ref weights) = self.token_weights {
+                focus_lr *= weights[focus.word_idx() as usize];
+            }
+            if let Some(ref scales) = self.freq_lr_scales {
+                focus_lr *= scales[focus.word_idx() as usize];
+            }
+            let frozen = self
+                .frozen_inputs
+                .as_ref()
+                .is_some_and(|frozen| frozen[focus.word_idx() as usize]);
 
+            let mut n_focus_examples = 0;
+            let mut batch_delta: Option<Array1<f32>> = None;
             for context in contexts {
-                *self.loss += self.sgd_impl.sgd_step(
+                if let Some(ref counts) = self.example_counts {
+                    if !counts.count(focus.word_idx() as usize) {
+                        break;
+                    }
+                }
+
+                let loss = if self.micro_batch > 1 {
+                    let (loss, input_delta) = self.sgd_impl.output_step(
+                        &mut self.model,
+                        focus.word_idx() as usize,
+                        input_embed.view(),
+                        context,
+                        focus_lr,
+                    );
+                    match batch_delta {
+                        Some(ref mut batch_delta) => *batch_delta += &input_delta,
+                        None => batch_delta = Some(input_delta),
+                    }
+                    if (n_focus_examples + 1) % self.micro_batch == 0 {
+                        if let Some(batch_delta) = batch_delta.take() {
+                            update_inputs(
+                                &mut self.model,
+                                sense_input(&focus, max_ngrams, sense_idx).filter(|_| !frozen),
+                                batch_delta.view(),
+                            );
+                        }
+                    }
+                    loss
+                } else {
+                    self.sgd_impl.sgd_step(
+                        &mut self.model,
+                        focus.word_idx() as usize,
+                        sense_input(&focus, max_ngrams, sense_idx).filter(|_| !frozen),
+                        input_embed.view(),
+                        context,
+                        focus_lr,
+                    )
+                };
+                *self.loss += loss;
+                *self.n_examples += 1;
+                sentence_loss += loss;
+                sentence_examples += 1;
+                n_focus_examples += 1;
+            }
+
+            // Commit the remainder of the last micro-batch.
+            if let Some(batch_delta) = batch_delta {
+                update_inputs(
                     &mut self.model,
-                    (&focus).into_iter(),
-                    input_embed.view(),
-                    context,
-                    lr,
+                    sense_input(&focus, max_ngrams, sense_idx).filter(|_| !frozen),
+                    batch_delta.view(),
                 );
-                *self.n_examples += 1;
+            }
+
+            if let Some(ref updates) = self.input_updates {
+                if n_focus_examples > 0 && !frozen {
+                    for idx in capped_input(&focus, max_ngrams) {
+                        // Sense rows are not part of the input matrix.
+                        if sense == 0 || idx != focus.word_idx() {
+                            updates.increment(idx as usize);
+                        }
+                    }
+                }
             }
             *self.n_tokens_processed += 1;
+            sentence_tokens += 1;
+        }
+        self.model.trainer().recycle(train_iter);
+
+        if let Some(ref mut epoch_stats) = self.epoch_stats {
+            if epoch_stats.is_empty() {
+                epoch_stats.push(EpochStats::default());
+            }
+            let skipped = self.model.trainer().skipped_tokens();
+            let stats = epoch_stats.last_mut().unwrap();
+            stats.n_tokens += sentence_tokens;
+            stats.n_examples += sentence_examples;
+            stats.n_negatives += self.sgd_impl.n_negatives - n_negatives;
+            stats.n_discarded += skipped.n_discarded;
+            stats.n_oov += skipped.n_oov;
+        }
+
+        if sentence_examples > 0 {
+            self.loss_monitor
+                .update(sentence_loss / sentence_examples as f32);
         }
     }
+
+    /// Get the sense of a focus token that fits its contexts best.
+    ///
+    /// The sense is the sense whose embedding has the largest cosine
+    /// similarity to the mean output embedding of the contexts. With
+    /// a projection, the sense embeddings are projected first. Returns
+    /// the first sense if the model has a single sense or if there are
+    /// no contexts.
+    fn closest_sense<'a, I>(&self, focus: &'a I, contexts: &[usize]) -> usize
+    where
+        I: WordIdx,
+        &'a I: IntoIterator<Item = u64>,
+    {
+        if self.model.n_senses() == 1 || contexts.is_empty() {
+            return 0;
+        }
+
+        let mut context_embed = Array1::zeros(self.model.config().output_dims as usize);
+        for &context in contexts {
+            scaled_add(
+                context_embed.view_mut(),
+                self.model.output_embedding(context),
+                1.,
+            );
+        }
+
+        let mut best = (0, f32::NEG_INFINITY);
+        for sense in 0..self.model.n_senses() {
+            let mut embed = self.model.mean_sense_embedding(focus, sense);
+            if let Some(projection) = self.model.projection() {
+                embed = projection.view().dot(&embed);
+            }
+            let norm = dot(embed.view(), embed.view()).sqrt();
+            let similarity = dot(embed.view(), context_embed.view()) / norm.max(f32::MIN_POSITIVE);
+            if similarity > best.1 {
+                best = (sense, similarity);
+            }
+        }
+
+        best.0
+    }
+
+    /// Update the model parameters using a weighted sentence.
+    ///
+    /// The learning rate of every training example in the sentence is
+    /// scaled by `weight`. A sentence with weight *2* has (approximately)
+    /// the same effect as two occurrences of the sentence.
+    pub fn update_weighted_sentence<'b, S>(&mut self, sentence: &S, weight: f32, lr: f32)
+    where
+        S: ?Sized,
+        T: TrainIterFrom<'b, S> + Trainer + NegativeSamples,
+        for<'a> &'a T::Focus: IntoIterator<Item = u64>,
+        T::Focus: WordIdx,
+    {
+        self.update_sentence(sentence, weight * lr);
+    }
+
+    /// Train the model on the sentences of a source.
+    ///
+    /// This makes one pass over the source for every epoch in the model's
+    /// configuration, using the learning rate schedule of the
+    /// configuration. An end-of-sentence marker is added to each sentence.
+    ///
+    /// Training is performed on the current thread. For multi-threaded
+    /// training, `update_sentence` can be used on clones of this SGD.
+    ///
+    /// Training does not use global state: all state is owned by this SGD
+    /// (and shared with its clones). Independent models can be trained
+    /// one after another or concurrently in the same process.
+    pub fn train_source<'b, S>(&mut self, source: &S) -> Result<(), Error>
+    where
+        S: SentenceSource + ?Sized,
+        T: TrainIterFrom<'b, [String]> + Trainer + NegativeSamples,
+        for<'a> &'a T::Focus: IntoIterator<Item = u64>,
+        T::Focus: WordIdx,
+    {
+        let n_tokens = self.model.input_vocab().n_types();
+        let lr_schedule = LrSchedule::new(self.model.config(), n_tokens);
+
+        for epoch in 0..self.model.config().epochs as usize {
+            self.train_epoch(source, &lr_schedule, epoch, None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Train the model on the sentences of a source until cancelled.
+    ///
+    /// This method is like `train_source`, but checks `cancelled` before
+    /// every sentence. When `cancelled` is set, training stops and
+    /// `Error::Cancelled` is returned. The parameters that were
+    /// learned so far are kept, so the partially trained model can still
+    /// be retrieved with `into_model`.
+    ///
+    /// Multi-threaded training can be cancelled by sharing `cancelled`
+    /// between threads that train clones of this SGD.
+    pub fn train_source_cancellable<'b, S>(
+        &mut self,
+        source: &S,
+        cancelled: &AtomicBool,
+    ) -> Result<(), Error>
+    where
+        S: SentenceSource + ?Sized,
+        T: TrainIterFrom<'b, [String]> + Trainer + NegativeSamples,
+        for<'a> &'a T::Focus: IntoIterator<Item = u64>,
+        T::Focus: WordIdx,
+    {
+        let n_tokens = self.model.input_vocab().n_types();
+        let lr_schedule = LrSchedule::new(self.model.config(), n_tokens);
+
+        for epoch in 0..self.model.config().epochs as usize {
+            self.train_epoch(source, &lr_schedule, epoch, Some(cancelled))?;
+        }
+
+        Ok(())
+    }
+
+    /// Train the model on the sentences of a source with early stopping.
+    ///
+    /// This method is like `train_source`, but calls `validation_loss`
+    /// after every epoch. Training stops before the configured number of
+    /// epochs when `early_stopping` decides that the validation loss stopped
+    /// improving. The learning rate schedule is not shortened by early
+    /// stopping.
+    ///
+    /// Returns the number of epochs that the model was trained for.
+    pub fn train_source_early_stopping<'b, S, F>(
+        &mut self,
+        source: &S,
+        mut early_stopping: EarlyStopping,
+        mut validation_loss: F,
+    ) -> Result<usize, Error>
+    where
+        S: SentenceSource + ?Sized,
+        F: FnMut(&Self) -> f32,
+        T: TrainIterFrom<'b, [String]> + Trainer + NegativeSamples,
+        for<'a> &'a T::Focus: IntoIterator<Item = u64>,
+        T::Focus: WordIdx,
+    {
+        let n_tokens = self.model.input_vocab().n_types();
+        let lr_schedule = LrSchedule::new(self.model.config(), n_tokens);
+
+        let epochs = self.model.config().epochs as usize;
+        for epoch in 1..=epochs {
+            self.train_epoch(source, &lr_schedule, epoch - 1, None)?;
+
+            if early_stopping.update(validation_loss(self)) {
+                return Ok(epoch);
+            }
+        }
+
+        Ok(epochs)
+    }
+
+    /// Get the loss of the model on the sentences of a source.
+    ///
+    /// The loss is the mean training loss per focus-context example,
+    /// including the losses of negative samples, for sentences that are
+    /// not trained on, e.g. held-out validation sentences. The examples
+    /// are drawn as in training, but neither the model nor the training
+    /// loss of this SGD are updated. Returns NaN if there are no examples.
+    pub fn validation_loss<'b, S>(&self, source: &S) -> Result<f32, Error>
+    where
+        S: SentenceSource + ?Sized,
+        T: TrainIterFrom<'b, [String]> + Trainer + NegativeSamples + Clone,
+        for<'a> &'a T::Focus: IntoIterator<Item = u64>,
+        T::Focus: WordIdx,
+    {
+        // The clone shares the embedding matrices, but has its own trainer
+        // to draw examples and negatives.
+        let mut model = self.model.clone();
+        let mut loss = 0.;
+        let mut n_examples = 0;
+        for sentence in source.sentences()? {
+            let mut sentence = sentence?;
+            sentence.push(EOS.to_string());
+
+            let train_iter = model.trainer().train_iter_from(sentence.as_slice());
+            for (focus, contexts) in train_iter {
+                let contexts = contexts.into_iter().collect::<Vec<_>>();
+                let sense = self.closest_sense(&focus, &contexts);
+                let input_embed = model.mean_sense_embedding(&focus, sense);
+                for context in contexts {
+                    loss += self.sgd_impl.loss(
+                        &mut model,
+                        focus.word_idx() as usize,
+                        input_embed.view(),
+                        context,
+                    );
+                    n_examples += 1;
+                }
+            }
+        }
+
+        Ok(loss / n_examples as f32)
+    }
+
+    fn train_epoch<'b, S>(
+        &mut self,
+        source: &S,
+        lr_schedule: &LrSchedule,
+        epoch: usize,
+        cancelled: Option<&AtomicBool>,
+    ) -> Result<(), Error>
+    where
+        S: SentenceSource + ?Sized,
+        T: TrainIterFrom<'b, [String]> + Trainer + NegativeSamples,
+        for<'a> &'a T::Focus: IntoIterator<Item = u64>,
+        T::Focus: WordIdx,
+    {
+        self.start_epoch();
+
+        for sentence in source.sentences()? {
+            if cancelled.is_some_and(|cancelled| cancelled.load(Ordering::Relaxed)) {
+                return Err(Error::Cancelled);
+            }
+
+            let mut sentence = sentence?;
+            sentence.push(EOS.to_string());
+
+            let lr = lr_schedule.lr_in_epoch(epoch, self.n_tokens_processed());
+            self.update_sentence(sentence.as_slice(), lr);
+        }
+
+        Ok(())
+    }
+}
+
+/// Bound of the frequency-based learning rate scales.
+const MAX_FREQ_LR_SCALE: f32 = 10.;
+
+/// Compute the frequency-based learning rate scale of every word.
+///
+/// The scale of a word is *sqrt(mean_count / count)*, bounded to
+/// *[1 / MAX_FREQ_LR_SCALE, MAX_FREQ_LR_SCALE]*.
+fn freq_lr_scales<V>(vocab: &V) -> Vec<f32>
+where
+    V: Vocab,
+{
+    let types = vocab.types();
+    let mean_count =
+        types.iter().map(|t| t.count()).sum::<usize>() as f32 / types.len().max(1) as f32;
+
+    types
+        .iter()
+        .map(|t| {
+            (mean_count / t.count() as f32)
+                .sqrt()
+                .clamp(1. / MAX_FREQ_LR_SCALE, MAX_FREQ_LR_SCALE)
+        })
+        .collect()
+}
+
+/// Get the input rows of a focus token that are updated.
+///
+/// At most `max_ngrams` subword indices are returned, followed by the
+/// word index. The subwords are truncated rather than sampled, so that
+/// every occurrence of a token updates the same rows.
+fn capped_input<'a, I>(focus: &'a I, max_ngrams: usize) -> impl Iterator<Item = u64> + 'a
+where
+    I: WordIdx,
+    &'a I: IntoIterator<Item = u64>,
+{
+    let word_idx = focus.word_idx();
+    focus
+        .into_iter()
+        .filter(move |&idx| idx != word_idx)
+        .take(max_ngrams)
+        .chain(iter::once(word_idx))
+}
+
+/// Get the input indices of a focus token with a sense.
+///
+/// This returns the indices of `capped_input`, where the word index is
+/// replaced by `sense_idx` if it is not `None`, see
+/// `TrainModel::sense_input_idx`.
+fn sense_input<'a, I>(
+    focus: &'a I,
+    max_ngrams: usize,
+    sense_idx: Option<u64>,
+) -> impl Iterator<Item = u64> + 'a
+where
+    I: WordIdx,
+    &'a I: IntoIterator<Item = u64>,
+{
+    let word_idx = focus.word_idx();
+    capped_input(focus, max_ngrams).map(move |idx| match sense_idx {
+        Some(sense_idx) if idx == word_idx => sense_idx,
+        _ => idx,
+    })
+}
+
+/// Add `input_delta` to the input embeddings with the indices `input`.
+fn update_inputs<T>(
+    model: &mut TrainModel<T>,
+    input: impl IntoIterator<Item = u64>,
+    input_delta: ArrayView1<f32>,
+) {
+    for idx in input {
+        let input_embed = model.input_embedding_mut(idx as usize);
+        scaled_add(input_embed, input_delta.view(), 1.0);
+    }
 }
 
 /// Log-logistic loss SGD with negative sampling.
@@ -108,12 +894,47 @@ where
 #[derive(Clone)]
 pub struct NegativeSamplingSGD {
     negative_samples: usize,
+    dropout: f32,
+    positive_weight: f32,
+    grad_hist: Option<GradientHistogram>,
+    frozen_outputs: Option<Arc<Vec<bool>>>,
+    n_frozen_dims: usize,
+    n_negatives: usize,
 }
 
 impl NegativeSamplingSGD {
     /// Create a new loss function.
     pub fn new(negative_samples: usize) -> Self {
-        NegativeSamplingSGD { negative_samples }
+        NegativeSamplingSGD {
+            negative_samples,
+            dropout: 0.,
+            positive_weight: 1.,
+            grad_hist: None,
+            frozen_outputs: None,
+            n_frozen_dims: 0,
+            n_negatives: 0,
+        }
+    }
+
+    /// Drop a fraction of the input embedding dimensions in every step.
+    ///
+    /// See `CommonConfig::dropout`.
+    pub fn dropout(mut self, dropout: f32) -> Self {
+        assert!((0. ..1.).contains(&dropout), "Dropout should be in [0, 1)");
+        self.dropout = dropout;
+        self
+    }
+
+    /// Multiply the gradient of the positive output by `positive_weight`.
+    ///
+    /// See `CommonConfig::positive_weight`.
+    pub fn positive_weight(mut self, positive_weight: f32) -> Self {
+        assert!(
+            positive_weight > 0.,
+            "The positive weight should be positive"
+        );
+        self.positive_weight = positive_weight;
+        self
     }
 
     /// Perform a step of gradient descent.
@@ -121,41 +942,199 @@ impl NegativeSamplingSGD {
     /// This method will estimate the probability of `output` and randomly
     /// chosen negative samples, given the input. It will then update the
     /// embeddings of the positive/negative outputs and the input (and its
-    /// subwords).
+    /// subwords). `focus` is the word index of the input.
+    ///
+    /// If the model has a projection matrix, the input embedding is
+    /// projected before predicting the outputs and the projection is
+    /// updated as well.
+    ///
+    /// If dropout is used, a random subset of the dimensions of the input
+    /// embedding is dropped in this step. Dropped dimensions are neither
+    /// used for prediction nor updated.
+    ///
+    /// The dimensions of side features (see `SGD::set_side_features`) are
+    /// used for prediction, but are not updated.
+    ///
+    /// If a gradient histogram is collected, the norm of the gradient of
+    /// the input embedding is added to the histogram.
     ///
     /// The function returns the sum of losses.
     pub fn sgd_step<T>(
         &mut self,
         model: &mut TrainModel<T>,
+        focus: usize,
         input: impl IntoIterator<Item = u64>,
         input_embed: ArrayView1<f32>,
         output: usize,
         lr: f32,
     ) -> f32
+    where
+        T: NegativeSamples,
+    {
+        let (loss, input_delta) = self.output_step(model, focus, input_embed, output, lr);
+        update_inputs(model, input, input_delta.view());
+
+        loss
+    }
+
+    /// Perform a step of gradient descent, except for the input update.
+    ///
+    /// This updates the outputs (and the projection) like `sgd_step`, but
+    /// returns the delta of the input embedding together with the sum of
+    /// losses rather than updating the input embeddings. The delta is the
+    /// gradient scaled by the learning rate.
+    pub fn output_step<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        focus: usize,
+        input_embed: ArrayView1<f32>,
+        output: usize,
+        lr: f32,
+    ) -> (f32, Array1<f32>)
     where
         T: NegativeSamples,
     {
         let mut loss = 0.0;
-        let mut input_delta = Array1::zeros(input_embed.shape()[0]);
 
-        // Update the output embedding of the positive instance.
-        loss += self.update_output(
+        let mask = if self.dropout > 0. {
+            let mut mask = vec![false; input_embed.len()];
+            model.trainer().dropout_mask(1. - self.dropout, &mut mask);
+            Some(mask)
+        } else {
+            None
+        };
+
+        let masked_embed = mask
+            .as_ref()
+            .map(|mask| self.apply_dropout(input_embed.to_owned(), mask));
+        let input_embed = match masked_embed {
+            Some(ref masked_embed) => masked_embed.view(),
+            None => input_embed.view(),
+        };
+
+        let mut input_delta = match model.projection().cloned() {
+            Some(mut projection) => {
+                let hidden = projection.view().dot(&input_embed);
+                let mut hidden_delta = Array1::zeros(hidden.len());
+                loss += self.update_outputs(
+                    model,
+                    focus,
+                    hidden.view(),
+                    hidden_delta.view_mut(),
+                    output,
+                    lr,
+                );
+
+                // Backpropagate through the projection, before updating it.
+                let input_delta = projection.view().t().dot(&hidden_delta);
+                let mut projection = projection.view_mut();
+                for (row, &delta) in projection.outer_iter_mut().zip(hidden_delta.iter()) {
+                    scaled_add(row, input_embed.view(), delta);
+                }
+
+                input_delta
+            }
+            None => {
+                let mut input_delta = Array1::zeros(input_embed.len());
+                loss += self.update_outputs(
+                    model,
+                    focus,
+                    input_embed.view(),
+                    input_delta.view_mut(),
+                    output,
+                    lr,
+                );
+
+                input_delta
+            }
+        };
+
+        if let Some(ref mask) = mask {
+            input_delta = self.apply_dropout(input_delta, mask);
+        }
+
+        if self.n_frozen_dims > 0 {
+            let learned_dims = input_delta.len() - self.n_frozen_dims;
+            input_delta.slice_mut(s![learned_dims..]).fill(0.);
+        }
+
+        // The input delta is the gradient, scaled by the learning rate.
+        if let Some(ref mut grad_hist) = self.grad_hist {
+            if lr > 0. {
+                grad_hist.add(dot(input_delta.view(), input_delta.view()).sqrt() / lr);
+            }
+        }
+
+        (loss, input_delta)
+    }
+
+    /// Get the loss of an example without updating the model.
+    ///
+    /// The loss is the loss of predicting `output` and of the negative
+    /// samples, as in `sgd_step`, but without dropout.
+    pub fn loss<T>(
+        &self,
+        model: &mut TrainModel<T>,
+        focus: usize,
+        input_embed: ArrayView1<f32>,
+        output: usize,
+    ) -> f32
+    where
+        T: NegativeSamples,
+    {
+        let hidden = model
+            .projection()
+            .map(|projection| projection.view().dot(&input_embed));
+        let input_embed = match hidden {
+            Some(ref hidden) => hidden.view(),
+            None => input_embed.view(),
+        };
+
+        let mut loss = log_logistic_loss(input_embed, model.output_embedding(output), true).0;
+        for _ in 0..self.negative_samples {
+            let negative = model.trainer().focus_negative_sample(focus, output);
+            loss += log_logistic_loss(input_embed, model.output_embedding(negative), false).0;
+        }
+
+        loss
+    }
+
+    /// Zero the dropped dimensions and scale the kept dimensions.
+    fn apply_dropout(&self, mut embed: Array1<f32>, mask: &[bool]) -> Array1<f32> {
+        let scale = 1. / (1. - self.dropout);
+        for (v, &keep) in embed.iter_mut().zip(mask) {
+            *v = if keep { *v * scale } else { 0. };
+        }
+
+        embed
+    }
+
+    /// Predict and update the positive output and negative samples.
+    fn update_outputs<T>(
+        &mut self,
+        model: &mut TrainModel<T>,
+        focus: usize,
+        input_embed: ArrayView1<f32>,
+        mut input_delta: ArrayViewMut1<f32>,
+        output: usize,
+        lr: f32,
+    ) -> f32
+    where
+        T: NegativeSamples,
+    {
+        // Update the output embedding of the positive instance. Scaling
+        // the learning rate scales the gradient of the positive output.
+        let mut loss = self.update_output(
             model,
             input_embed.view(),
             input_delta.view_mut(),
             output,
             true,
-            lr,
+            lr * self.positive_weight,
         );
 
         // Pick the negative examples and update their output embeddings.
-        loss += self.negative_samples(model, input_embed, input_delta.view_mut(), output, lr);
-
-        // Update the input embeddings with the accumulated gradient.
-        for idx in input {
-            let input_embed = model.input_embedding_mut(idx as usize);
-            scaled_add(input_embed, input_delta.view(), 1.0);
-        }
+        loss += self.negative_samples(model, focus, input_embed, input_delta, output, lr);
 
         loss
     }
@@ -164,6 +1143,7 @@ impl NegativeSamplingSGD {
     fn negative_samples<T>(
         &mut self,
         model: &mut TrainModel<T>,
+        focus: usize,
         input_embed: ArrayView1<f32>,
         mut input_delta: ArrayViewMut1<f32>,
         output: usize,
@@ -175,7 +1155,8 @@ impl NegativeSamplingSGD {
         let mut loss = 0f32;
 
         for _ in 0..self.negative_samples {
-            let negative = model.trainer().negative_sample(output);
+            let negative = model.trainer().focus_negative_sample(focus, output);
+            self.n_negatives += 1;
             // Update input and output for this negative sample.
             loss += self.update_output(
                 model,
@@ -217,12 +1198,874 @@ impl NegativeSamplingSGD {
         );
 
         // Update the output weight: v_n += lr * v_n' u_n.
-        scaled_add(
-            model.output_embedding_mut(output),
+        let frozen = self
+            .frozen_outputs
+            .as_ref()
+            .is_some_and(|frozen| frozen[output]);
+        if !frozen {
+            scaled_add(
+                model.output_embedding_mut(output),
+                input_embed.view(),
+                lr * part_gradient,
+            );
+        }
+
+        loss
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use finalfusion::subword::FinalfusionHashIndexer;
+    use ndarray::{s, Array1, Array2, ArrayView1};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::{EpochStats, NegativeSamplingSGD, SGD};
+    use crate::config;
+    use crate::histogram::GradientHistogram;
+    use crate::idx::WordIdx;
+    use crate::loss::log_logistic_loss;
+    use crate::train_model::{TrainModel, Trainer};
+    use crate::util::numeric::close;
+    use crate::util::ReseedOnCloneRng;
+    use crate::{
+        BucketConfig, CommonConfig, ModelType, SimpleVocab, SimpleVocabConfig, SkipGramConfig,
+        SkipgramTrainer, SubwordHash, SubwordVocab, SubwordVocabConfig, Vocab, VocabBuilder,
+        WriteModelText,
+    };
+
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+        negative_samples: 0,
+        ..config::TEST_COMMON_CONFIG
+    };
+
+    const TEST_SKIP_CONFIG: SkipGramConfig = SkipGramConfig {
+        context_size: 1,
+        context_subsampling_threshold: 0.,
+        discard_boundary_tokens: false,
+        discard_oov_contexts: false,
+        exclude_focus_negatives: false,
+        min_context: 0,
+        model: ModelType::SkipGram,
+        tied_weights: false,
+    };
+
+    // With a discard threshold of 1, no tokens are discarded.
+    const TEST_VOCAB_CONFIG: SimpleVocabConfig = SimpleVocabConfig {
+        discard_threshold: 1.,
+        min_count: 1,
+    };
+
+    fn test_vocab() -> SimpleVocab<String> {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for token in &["a", "a", "b"] {
+            builder.count(token.to_string());
+        }
+        builder.into()
+    }
+
+    type TestSGD = SGD<SkipgramTrainer<ReseedOnCloneRng<XorShiftRng>, SimpleVocab<String>>>;
+
+    fn test_sgd() -> TestSGD {
+        test_sgd_with_config(TEST_COMMON_CONFIG)
+    }
+
+    fn test_sgd_with_config(common_config: CommonConfig) -> TestSGD {
+        let trainer = SkipgramTrainer::new(
+            test_vocab(),
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            TEST_SKIP_CONFIG,
+        );
+        let input = Array2::from_shape_vec((2, 3), vec![0.1, 0.2, 0.3, -0.3, 0.2, -0.1]).unwrap();
+        let output = Array2::from_shape_vec((2, 3), vec![0.2, -0.1, 0.1, 0.3, 0.1, -0.2]).unwrap();
+        SGD::new(TrainModel::from_parts(trainer, input.into(), output.into()))
+    }
+
+    #[test]
+    fn validation_loss_does_not_update_model() {
+        let sgd = test_sgd();
+        let rows = |sgd: &TestSGD| {
+            (0..2)
+                .map(|idx| {
+                    (
+                        sgd.model().input_embedding(idx).to_owned(),
+                        sgd.model().output_embedding(idx).to_owned(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let init_rows = rows(&sgd);
+        let sentences = vec![vec!["a".to_string(), "b".to_string()]];
+
+        // Without negatives, the loss is the loss of the a-b and b-a pairs.
+        // The end-of-sentence marker is unknown.
+        let idx = |word: &str| sgd.model().input_vocab().idx(word).unwrap().word_idx() as usize;
+        let (a, b) = (idx("a"), idx("b"));
+        let expected = (log_logistic_loss(init_rows[a].0.view(), init_rows[b].1.view(), true).0
+            + log_logistic_loss(init_rows[b].0.view(), init_rows[a].1.view(), true).0)
+            / 2.;
+        assert!(close(
+            sgd.validation_loss(&sentences).unwrap(),
+            expected,
+            1e-6
+        ));
+
+        assert_eq!(rows(&sgd), init_rows);
+        assert_eq!(sgd.n_tokens_processed(), 0);
+        assert!(sgd.train_loss().is_nan());
+    }
+
+    fn distance(u: ArrayView1<f32>, v: ArrayView1<f32>) -> f32 {
+        (&u - &v).mapv(|x| x * x).sum().sqrt()
+    }
+
+    #[test]
+    fn loss_monitor_tracks_running_loss() {
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.negative_samples = 1;
+        let mut sgd = test_sgd_with_config(common_config);
+        let monitor = sgd.loss_monitor();
+        assert!(monitor.current_loss().is_nan());
+
+        let sentence = vec!["a".to_string(), "b".to_string()];
+        sgd.update_sentence(&sentence, 0.1);
+        let first = monitor.current_loss();
+        assert!(first.is_finite());
+
+        // Poll the loss while another thread trains.
+        let mut trainer_sgd = sgd.clone();
+        let training = thread::spawn(move || {
+            for _ in 0..1000 {
+                trainer_sgd.update_sentence(&sentence, 0.1);
+            }
+        });
+        while !training.is_finished() {
+            assert!(monitor.current_loss().is_finite());
+        }
+        training.join().unwrap();
+
+        let last = monitor.current_loss();
+        assert!(last.is_finite());
+        assert_ne!(first, last);
+        assert_eq!(sgd.loss_monitor().current_loss(), last);
+    }
+
+    #[test]
+    fn epoch_stats_count_discarded_tokens() {
+        let source = vec![vec!["a".to_string(), "b".to_string(), "a".to_string()]; 20];
+
+        // Without subsampling, no tokens are discarded. The end-of-sentence
+        // marker is unknown.
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.epochs = 2;
+        common_config.negative_samples = 2;
+        let mut sgd = test_sgd_with_config(common_config);
+        sgd.enable_epoch_stats();
+        sgd.train_source(&source).unwrap();
+        let stats = sgd.epoch_stats().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0], stats[1]);
+        assert_eq!(stats[0].n_tokens, 60);
+        assert_eq!(stats[0].n_discarded, 0);
+        assert_eq!(stats[0].n_oov, 20);
+        assert_eq!(stats[0].n_negatives, 2 * stats[0].n_examples);
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            discard_threshold: 1e-4,
+            min_count: 1,
+        });
+        for token in &["a", "a", "b"] {
+            builder.count(token.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            TEST_SKIP_CONFIG,
+        );
+        let mut sgd = SGD::new(TrainModel::from_seed(trainer, 42));
+        sgd.enable_epoch_stats();
+        sgd.train_source(&source).unwrap();
+        let stats = sgd.epoch_stats().unwrap();
+        let mut total = EpochStats::default();
+        total.merge(&stats[0]);
+        total.merge(&stats[1]);
+        assert!(total.n_discarded > 0);
+        assert_eq!(total.n_tokens + total.n_discarded, 120);
+        assert_eq!(sgd.n_tokens_processed(), total.n_tokens);
+
+        assert!(test_sgd().epoch_stats().is_none());
+    }
+
+    #[test]
+    fn max_examples_per_token_caps_frequent_tokens() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let b = vocab.idx("b").unwrap().word_idx() as usize;
+
+        // Per epoch, "a" is the focus of 5 * 20 examples and "b" of 20.
+        let sentence = ["a", "a", "a", "b"]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let source = vec![sentence; 20];
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.epochs = 2;
+        common_config.negative_samples = 1;
+        common_config.max_examples_per_token = 30;
+        let mut sgd = test_sgd_with_config(common_config);
+        sgd.enable_epoch_stats();
+        sgd.train_source(&source).unwrap();
+
+        let counts = sgd.example_counts().unwrap();
+        assert_eq!(counts[a], 30);
+        assert_eq!(counts[b], 20);
+        let stats = sgd.epoch_stats().unwrap();
+        assert_eq!(stats.len(), 2);
+        assert!(stats.iter().all(|stats| stats.n_examples == 50));
+        // Focus tokens without examples are still processed.
+        assert!(stats.iter().all(|stats| stats.n_tokens == 80));
+
+        assert!(test_sgd().example_counts().is_none());
+    }
+
+    #[test]
+    fn max_examples_per_token_is_shared_between_threads() {
+        // Per epoch, "a" and "b" are the focus of 40 examples in every
+        // thread.
+        let sentence = vec!["a".to_string(), "b".to_string()];
+        let source = vec![sentence; 40];
+
+        let mut sgd = test_sgd_with_config(CommonConfig {
+            epochs: 2,
+            negative_samples: 1,
+            max_examples_per_token: 30,
+            ..TEST_COMMON_CONFIG
+        });
+        sgd.enable_epoch_stats();
+
+        let children = (0..4)
+            .map(|_| {
+                let mut sgd = sgd.clone();
+                let source = source.clone();
+                thread::spawn(move || {
+                    sgd.train_source(&source).unwrap();
+                    sgd.epoch_stats().unwrap().to_vec()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut n_examples = 0;
+        for child in children {
+            let stats = child.join().unwrap();
+            assert_eq!(stats.len(), 2);
+            n_examples += stats.iter().map(|stats| stats.n_examples).sum::<usize>();
+        }
+
+        // Across threads, at most 30 examples of each token are trained
+        // per epoch. The thread that starts the second epoch first has
+        // already filled the cap of the first epoch.
+        assert_eq!(n_examples, 2 * 2 * 30);
+        let counts = sgd.example_counts().unwrap();
+        assert!(counts.iter().all(|&count| count <= 30));
+    }
+
+    #[test]
+    fn frozen_tokens_are_not_updated() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let b = vocab.idx("b").unwrap().word_idx() as usize;
+        let sentence = vec!["a".to_string(), "b".to_string()];
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.negative_samples = 1;
+        let mut sgd = test_sgd_with_config(common_config);
+        let mut frozen = vec![false; vocab.len()];
+        frozen[a] = true;
+        sgd.freeze_inputs(frozen.clone());
+        sgd.freeze_outputs(frozen);
+
+        let init_a = sgd.model().input_embedding(a).to_owned();
+        let init_output_a = sgd.model().output_embedding(a).to_owned();
+        let init_b = sgd.model().input_embedding(b).to_owned();
+        let init_output_b = sgd.model().output_embedding(b).to_owned();
+        for _ in 0..10 {
+            sgd.update_sentence(&sentence, 0.1);
+        }
+
+        assert_eq!(sgd.model().input_embedding(a), init_a);
+        assert_eq!(sgd.model().output_embedding(a), init_output_a);
+        assert_ne!(sgd.model().input_embedding(b), init_b);
+        assert_ne!(sgd.model().output_embedding(b), init_output_b);
+    }
+
+    #[test]
+    fn side_features_are_not_updated() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let b = vocab.idx("b").unwrap().word_idx() as usize;
+        let sentence = vec!["a".to_string(), "b".to_string()];
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.negative_samples = 1;
+        let mut sgd = test_sgd_with_config(common_config);
+        let mut features = Array2::zeros((vocab.len(), 1));
+        features[(a, 0)] = 0.5;
+        features[(b, 0)] = -0.5;
+        sgd.set_side_features(features.view());
+
+        let init_a = sgd.model().input_embedding(a).to_owned();
+        let init_b = sgd.model().input_embedding(b).to_owned();
+        assert_eq!(init_a[2], 0.5);
+        assert_eq!(init_b[2], -0.5);
+        for _ in 0..10 {
+            sgd.update_sentence(&sentence, 0.1);
+        }
+
+        for (idx, init) in &[(a, init_a), (b, init_b)] {
+            let embed = sgd.model().input_embedding(*idx);
+            assert_eq!(embed[2], init[2]);
+            assert_ne!(embed.slice(s![..2]), init.slice(s![..2]));
+        }
+    }
+
+    #[test]
+    fn senses_diverge_in_distinct_contexts() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for (token, count) in &[("a", 6), ("x", 5), ("y", 4), ("z", 3)] {
+            for _ in 0..*count {
+                builder.count(token.to_string());
+            }
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        let idx = |token: &str| vocab.idx(token).unwrap().word_idx() as usize;
+        let (x, y) = (idx("x"), idx("y"));
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.dims = 10;
+        common_config.output_dims = 10;
+        common_config.negative_samples = 2;
+        common_config.senses = 2;
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            TEST_SKIP_CONFIG,
+        );
+        let mut model = TrainModel::from_seed(trainer, 42);
+
+        // The contexts are clearly distinct: their output embeddings point
+        // in opposite directions.
+        let context_embed = Array1::linspace(-0.5, 0.5, 10);
+        model.output_embedding_mut(x).assign(&context_embed);
+        model.output_embedding_mut(y).assign(&-&context_embed);
+        let mut sgd = SGD::new(model);
+        let init_distance = distance(
+            sgd.model().sense_embedding("a", 0).unwrap().view(),
+            sgd.model().sense_embedding("a", 1).unwrap().view(),
+        );
+
+        let with_x = vec!["a".to_string(), "x".to_string()];
+        let with_y = vec!["a".to_string(), "y".to_string()];
+        for _ in 0..200 {
+            sgd.update_sentence(&with_x, 0.1);
+            sgd.update_sentence(&with_y, 0.1);
+        }
+
+        // Each context is predicted best by a different sense.
+        let model = sgd.model();
+        let senses = [
+            model.sense_embedding("a", 0).unwrap(),
+            model.sense_embedding("a", 1).unwrap(),
+        ];
+        let best_sense = |context: usize| {
+            let output = model.output_embedding(context);
+            (0..2)
+                .max_by(|&s1, &s2| {
+                    senses[s1]
+                        .dot(&output)
+                        .partial_cmp(&senses[s2].dot(&output))
+                        .unwrap()
+                })
+                .unwrap()
+        };
+        assert_ne!(best_sense(x), best_sense(y));
+        assert!(distance(senses[0].view(), senses[1].view()) > init_distance);
+        assert!(model.sense_embedding("a", 2).is_none());
+    }
+
+    #[test]
+    fn token_weights_scale_updates() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let b = vocab.idx("b").unwrap().word_idx() as usize;
+        let sentence = vec!["a".to_string(), "b".to_string()];
+
+        let mut unweighted = test_sgd();
+        let init = unweighted.model().input_embedding(a).to_owned();
+        let init_b = unweighted.model().input_embedding(b).to_owned();
+        unweighted.update_sentence(&sentence, 0.1);
+
+        let mut weighted = test_sgd();
+        let mut weights = vec![1.0; vocab.len()];
+        weights[a] = 2.0;
+        weighted.set_token_weights(weights);
+        weighted.update_sentence(&sentence, 0.1);
+
+        let unweighted_step = distance(unweighted.model().input_embedding(a), init.view());
+        let weighted_step = distance(weighted.model().input_embedding(a), init.view());
+        assert!(unweighted_step > 0.);
+        assert!(close(weighted_step / unweighted_step, 2.0, 1e-3));
+
+        // The unweighted token is updated as before.
+        assert!(close(
+            distance(weighted.model().input_embedding(b), init_b.view()),
+            distance(unweighted.model().input_embedding(b), init_b.view()),
+            1e-6
+        ));
+    }
+
+    #[test]
+    fn sentence_weights_scale_updates() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let sentence = vec!["a".to_string(), "b".to_string()];
+
+        let mut unweighted = test_sgd();
+        let init = unweighted.model().input_embedding(a).to_owned();
+        unweighted.update_weighted_sentence(&sentence, 1.0, 0.1);
+
+        let mut weighted = test_sgd();
+        weighted.update_weighted_sentence(&sentence, 2.0, 0.1);
+
+        let unweighted_step = distance(unweighted.model().input_embedding(a), init.view());
+        let weighted_step = distance(weighted.model().input_embedding(a), init.view());
+        assert!(unweighted_step > 0.);
+        assert!(close(weighted_step / unweighted_step, 2.0, 1e-3));
+    }
+
+    #[test]
+    fn untrained_inputs_are_written_as_zeros() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let b = vocab.idx("b").unwrap().word_idx() as usize;
+
+        let sentence = vec!["a".to_string(), "a".to_string()];
+
+        // Updates of clones are counted as well.
+        let mut sgd = test_sgd();
+        sgd.track_input_updates();
+        sgd.clone().update_sentence(&sentence, 0.1);
+
+        let counts = sgd.input_update_counts().unwrap();
+        assert!(counts[a] > 0);
+        assert_eq!(counts[b], 0);
+
+        let mut model = sgd.into_model();
+        assert_eq!(model.zero_untrained_inputs(&counts), 1);
+        assert!(model.input_embedding(a).iter().all(|&v| v != 0.));
+
+        let mut data = Vec::new();
+        model.write_model_text(&mut data, false).unwrap();
+        let text = String::from_utf8(data).unwrap();
+        assert!(text.lines().any(|line| line == "b 0 0 0"));
+    }
+
+    #[test]
+    fn side_features_of_untrained_inputs_are_kept() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let b = vocab.idx("b").unwrap().word_idx() as usize;
+
+        let mut sgd = test_sgd();
+        let mut features = Array2::zeros((vocab.len(), 1));
+        features[(b, 0)] = -0.5;
+        sgd.set_side_features(features.view());
+        sgd.track_input_updates();
+        sgd.update_sentence(&["a".to_string(), "a".to_string()], 0.1);
+
+        let counts = sgd.input_update_counts().unwrap();
+        let mut model = sgd.into_model();
+        assert_eq!(model.zero_untrained_inputs(&counts), 1);
+        assert_eq!(model.input_embedding(b).to_vec(), [0., 0., -0.5]);
+        assert_ne!(model.input_embedding(a)[0], 0.);
+    }
+
+    #[test]
+    fn frozen_inputs_are_not_written_as_zeros() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let b = vocab.idx("b").unwrap().word_idx() as usize;
+
+        let mut frozen = vec![false; vocab.len()];
+        frozen[b] = true;
+
+        let mut sgd = test_sgd();
+        sgd.freeze_inputs(frozen);
+        sgd.track_input_updates();
+        sgd.update_sentence(&["b".to_string(), "b".to_string()], 0.1);
+
+        let counts = sgd.input_update_counts().unwrap();
+        assert_eq!(counts[a], 0);
+        assert_eq!(counts[b], 1);
+    }
+
+    #[test]
+    fn micro_batches_of_one_match_unbatched_updates() {
+        let sentence = vec!["a", "b", "a", "b"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+
+        let mut unbatched = test_sgd();
+        unbatched.update_sentence(&sentence, 0.1);
+
+        let mut batched = test_sgd();
+        batched.set_micro_batch(1);
+        batched.update_sentence(&sentence, 0.1);
+        assert!(unbatched.model() == batched.model());
+
+        // Larger micro-batches only change the order of additions, since
+        // the input embedding of a focus token is computed once.
+        let mut batched = test_sgd();
+        batched.set_micro_batch(2);
+        batched.update_sentence(&sentence, 0.1);
+        assert!(unbatched.model().approx_eq(batched.model(), 1e-6));
+        assert!(unbatched.model() != test_sgd().model());
+    }
+
+    #[test]
+    fn freq_lr_scaling_favors_rare_tokens() {
+        let vocab = test_vocab();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+        let b = vocab.idx("b").unwrap().word_idx() as usize;
+        let sentence = vec!["a".to_string(), "b".to_string()];
+
+        let mut unscaled = test_sgd();
+        let init_a = unscaled.model().input_embedding(a).to_owned();
+        let init_b = unscaled.model().input_embedding(b).to_owned();
+        unscaled.update_sentence(&sentence, 0.1);
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.freq_lr_scaling = true;
+        let mut scaled = test_sgd_with_config(common_config);
+        scaled.update_sentence(&sentence, 0.1);
+
+        let step = |sgd: &TestSGD, idx, init: &Array1<f32>| {
+            distance(sgd.model().input_embedding(idx), init.view())
+        };
+
+        // "a" occurs twice, "b" once, so the mean count is 1.5.
+        let scale_a = step(&scaled, a, &init_a) / step(&unscaled, a, &init_a);
+        let scale_b = step(&scaled, b, &init_b) / step(&unscaled, b, &init_b);
+        assert!(close(scale_a, (1.5f32 / 2.).sqrt(), 1e-3));
+        assert!(close(scale_b, 1.5f32.sqrt(), 1e-3));
+        assert!(step(&scaled, b, &init_b) > step(&unscaled, b, &init_b));
+        assert!(step(&scaled, a, &init_a) < step(&unscaled, a, &init_a));
+    }
+
+    #[test]
+    fn output_dims_use_projection() {
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.negative_samples = 1;
+        common_config.output_dims = 5;
+        let trainer = SkipgramTrainer::new(
+            test_vocab(),
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            TEST_SKIP_CONFIG,
+        );
+        let mut sgd = SGD::new(TrainModel::from(trainer));
+
+        assert_eq!(sgd.model().input_embedding(0).len(), 3);
+        assert_eq!(sgd.model().output_embedding(0).len(), 5);
+        let init_projection = sgd.model().projection().unwrap().view().to_owned();
+        assert_eq!(init_projection.shape(), &[5, 3]);
+
+        let init_input = sgd.model().input_embedding(0).to_owned();
+        let sentence = vec!["a".to_string(), "b".to_string()];
+        sgd.update_sentence(&sentence, 0.1);
+
+        assert!(sgd.train_loss().is_finite());
+        assert_ne!(sgd.model().input_embedding(0), init_input);
+        assert_ne!(
+            sgd.model().projection().unwrap().view(),
+            init_projection.view()
+        );
+    }
+
+    #[test]
+    fn tied_weights_share_input_and_output() {
+        let vocab = test_vocab();
+        let n_words = vocab.len();
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.negative_samples = 1;
+        let mut skipgram_config = TEST_SKIP_CONFIG;
+        skipgram_config.tied_weights = true;
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            skipgram_config,
+        );
+        let mut sgd = SGD::new(TrainModel::from(trainer));
+        let init = sgd.model().input_embedding(0).to_owned();
+
+        let sentence = vec!["a".to_string(), "b".to_string(), "a".to_string()];
+        for _ in 0..5 {
+            sgd.update_sentence(&sentence, 0.1);
+        }
+
+        assert!(distance(sgd.model().input_embedding(0), init.view()) > 0.);
+        for idx in 0..n_words {
+            assert_eq!(
+                sgd.model().input_embedding(idx),
+                sgd.model().output_embedding(idx)
+            );
+        }
+
+        // The shared matrix can be taken from the model.
+        let (_, input) = sgd.into_model().into_parts().unwrap();
+        assert_eq!(input.view().nrows(), n_words);
+    }
+
+    #[test]
+    fn dropout_skips_dimensions() {
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.dims = 10;
+        common_config.output_dims = 10;
+        let trainer = SkipgramTrainer::new(
+            test_vocab(),
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            TEST_SKIP_CONFIG,
+        );
+        let mut model = TrainModel::from(trainer);
+        let mut sgd_impl = NegativeSamplingSGD::new(0).dropout(0.5);
+
+        // Every step starts from the same parameters, so that the gradient
+        // does not vanish.
+        let init = model.input_embedding(0).to_owned();
+        let init_output = model.output_embedding(1).to_owned();
+
+        let mut n_skipped = 0;
+        let n_steps = 1000;
+        for _ in 0..n_steps {
+            model.input_embedding_mut(0).assign(&init);
+            model.output_embedding_mut(1).assign(&init_output);
+            sgd_impl.sgd_step(&mut model, 0, vec![0], init.view(), 1, 0.1);
+            n_skipped += model
+                .input_embedding(0)
+                .iter()
+                .zip(init.iter())
+                .filter(|(v, init)| v == init)
+                .count();
+        }
+
+        let skipped_fraction = n_skipped as f32 / (n_steps * 10) as f32;
+        assert!(close(skipped_fraction, 0.5, 0.03));
+    }
+
+    #[test]
+    fn positive_weight_scales_positive_update() {
+        let trainer = SkipgramTrainer::new(
+            test_vocab(),
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        );
+        let mut model = TrainModel::from(trainer);
+
+        // With two words, the negative of the output 1 is always 0. Every
+        // step starts from the same fixed example.
+        let mut output_steps = |positive_weight: f32| {
+            model.input_embedding_mut(0).fill(0.5);
+            model.output_embedding_mut(0).fill(0.1);
+            model.output_embedding_mut(1).fill(-0.1);
+            let input = model.input_embedding(0).to_owned();
+            let negative_init = model.output_embedding(0).to_owned();
+            let positive_init = model.output_embedding(1).to_owned();
+
+            let mut sgd_impl = NegativeSamplingSGD::new(1).positive_weight(positive_weight);
+            sgd_impl.sgd_step(&mut model, 0, vec![0], input.view(), 1, 0.1);
+
+            (
+                distance(model.output_embedding(1), positive_init.view()),
+                distance(model.output_embedding(0), negative_init.view()),
+            )
+        };
+
+        let (positive, negative) = output_steps(1.);
+        let (weighted_positive, weighted_negative) = output_steps(3.);
+        assert!(positive > 0. && negative > 0.);
+        assert!(close(weighted_positive / positive, 3., 1e-4));
+        assert!(close(weighted_negative, negative, 1e-6));
+        assert!(weighted_positive / weighted_negative > positive / negative);
+    }
+
+    #[test]
+    fn gradient_histogram_counts_gradient_norms() {
+        let trainer = SkipgramTrainer::new(
+            test_vocab(),
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        );
+        let mut model = TrainModel::from(trainer);
+        let mut sgd_impl = NegativeSamplingSGD::new(0);
+        sgd_impl.grad_hist = Some(GradientHistogram::new());
+
+        // With a zero input, the gradient of the input is -0.5 times the
+        // output embedding.
+        for &scale in &[1., 0.01, 4.] {
+            model.input_embedding_mut(0).fill(0.);
+            model.output_embedding_mut(1).fill(scale);
+            let input = model.input_embedding(0).to_owned();
+            sgd_impl.sgd_step(&mut model, 0, vec![0], input.view(), 1, 0.1);
+        }
+
+        // The gradient norms are 0.5 * sqrt(3) * scale: 0.87, 0.0087, 3.46.
+        assert_eq!(
+            sgd_impl.grad_hist.unwrap().counts(),
+            &[0, 0, 0, 1, 0, 1, 1, 0]
+        );
+    }
+
+    #[test]
+    fn max_ngrams_per_token_caps_updated_subwords() {
+        let long = "abcdefghijklmnopqrstuvwxyz";
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SubwordVocabConfig {
+            discard_threshold: 1.,
+            min_count: 1,
+            max_n: 3,
+            min_n: 2,
+            indexer: BucketConfig {
+                buckets_exp: 10,
+                hash: SubwordHash::FinalFusion,
+            },
+        });
+        for token in &[long, "b"] {
+            builder.count(token.to_string());
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        let focus = vocab.idx(long).unwrap();
+        assert!((&focus).into_iter().count() > 10);
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.max_ngrams_per_token = 4;
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            TEST_SKIP_CONFIG,
+        );
+
+        // With zero input embeddings, only the input rows receive updates.
+        let input = Array2::zeros((trainer.n_input_types(), 3));
+        let output = Array2::from_elem((trainer.n_output_types(), 3), 1.);
+        let mut sgd = SGD::new(TrainModel::from_parts(trainer, input.into(), output.into()));
+
+        // Both occurrences of the long token update the same rows.
+        let sentence = vec![long.to_string(), "b".to_string(), long.to_string()];
+        sgd.update_sentence(&sentence, 0.1);
+
+        let updated = (&focus)
+            .into_iter()
+            .filter(|&idx| {
+                sgd.model()
+                    .input_embedding(idx as usize)
+                    .iter()
+                    .any(|&v| v != 0.)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(updated.len(), 4 + 1);
+        assert!(updated.contains(&focus.word_idx()));
+    }
+
+    /// Perform a step for the focus word "a" and the context word "b".
+    ///
+    /// Returns the input rows of the focus word and its subwords, with
+    /// whether the rows were updated.
+    fn focus_negatives_step(exclude_focus_negatives: bool) -> Vec<(u64, bool)> {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SubwordVocabConfig {
+            discard_threshold: 1.,
+            min_count: 1,
+            max_n: 3,
+            min_n: 2,
+            indexer: BucketConfig {
+                buckets_exp: 10,
+                hash: SubwordHash::FinalFusion,
+            },
+        });
+        for token in &["a", "a", "b", "b", "c"] {
+            builder.count(token.to_string());
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        let focus = vocab.idx("a").unwrap();
+        let context = vocab.idx("b").unwrap().word_idx() as usize;
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.negative_samples = 50;
+        let mut skipgram_config = TEST_SKIP_CONFIG;
+        skipgram_config.exclude_focus_negatives = exclude_focus_negatives;
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            skipgram_config,
+        );
+
+        // The focus word and its subwords start out with zero embeddings,
+        // so that output embeddings are not updated. Only the focus word
+        // has a non-zero output embedding, so the focus word's rows are
+        // only updated when the focus word is sampled as a negative.
+        let n_inputs = trainer.n_input_types();
+        let input = Array2::from_elem((n_inputs, 3), 1.);
+        let mut output = Array2::zeros((3, 3));
+        output.row_mut(focus.word_idx() as usize).fill(1.);
+        let mut model = TrainModel::from_parts(trainer, input.into(), output.into());
+        for idx in &focus {
+            model.input_embedding_mut(idx as usize).fill(0.);
+        }
+
+        let input_embed = model.mean_input_embedding(&focus);
+        NegativeSamplingSGD::new(common_config.negative_samples as usize).sgd_step(
+            &mut model,
+            focus.word_idx() as usize,
+            &focus,
             input_embed.view(),
-            lr * part_gradient,
+            context,
+            0.1,
         );
 
-        loss
+        (&focus)
+            .into_iter()
+            .map(|idx| {
+                (
+                    idx,
+                    model.input_embedding(idx as usize).iter().any(|&v| v != 0.),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn focus_negatives_update_focus_subwords() {
+        let rows = focus_negatives_step(false);
+        assert!(rows.len() > 1);
+        assert!(rows.iter().all(|&(_, updated)| updated));
+    }
+
+    #[test]
+    fn exclude_focus_negatives_leaves_focus_subwords() {
+        let rows = focus_negatives_step(true);
+        assert!(rows.len() > 1);
+        assert!(rows.iter().all(|&(_, updated)| !updated));
     }
 }
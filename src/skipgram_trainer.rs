@@ -1,6 +1,8 @@
 use std::borrow::Borrow;
+use std::collections::HashSet;
 use std::hash::Hash;
 use std::iter::FusedIterator;
+use std::ops::Range;
 use std::sync::Arc;
 use std::{cmp, mem};
 
@@ -9,9 +11,12 @@ use rand::{Rng, SeedableRng};
 use serde::Serialize;
 
 use crate::idx::WordIdx;
-use crate::sampling::{BandedRangeGenerator, ZipfRangeGenerator};
-use crate::train_model::{NegativeSamples, TrainIterFrom, Trainer};
-use crate::util::ReseedOnCloneRng;
+use crate::sampling::{
+    BandedRangeGenerator, NegativeRangeGenerator, RangeGenerator, MAX_NEGATIVE_DRAWS,
+};
+use crate::train_model::{NegativeSamples, SkippedTokens, TrainIterFrom, Trainer};
+use crate::util::{dropout_mask, ReseedOnCloneRng};
+use crate::vocab::create_discards;
 use crate::{CommonConfig, ModelType, SkipGramConfig, Vocab};
 
 /// Skipgram Trainer
@@ -19,11 +24,29 @@ use crate::{CommonConfig, ModelType, SkipGramConfig, Vocab};
 /// The `SkipgramTrainer` holds the information and logic necessary to transform a tokenized
 /// sentence into an iterator of focus and context tuples. The struct is cheap to clone because
 /// the vocabulary is shared between clones.
+///
+/// The buffer for the token indices of a sentence is reused between sentences. Each clone
+/// has its own buffer, so that every training thread reuses its own buffer.
+///
+/// Context windows never cross the end of a sentence. Additional boundary tokens that
+/// terminate context windows can be set with `boundary_tokens`.
+///
+/// Instead of sentences, the trainer can also be trained on precomputed
+/// focus-context pairs, see `pairs`.
 #[derive(Clone)]
-pub struct SkipgramTrainer<R, V> {
+pub struct SkipgramTrainer<R, V>
+where
+    V: Vocab,
+{
     vocab: Arc<V>,
+    boundary_tokens: Arc<HashSet<V::VocabType>>,
+    ids: Vec<Option<V::IdxType>>,
+    segment_ends: Vec<usize>,
+    skipped: SkippedTokens,
+    pairs: bool,
     rng: R,
-    range_gen: BandedRangeGenerator<R, ZipfRangeGenerator<R>>,
+    range_gen: BandedRangeGenerator<R, NegativeRangeGenerator<R>>,
+    context_keep_probs: Option<Arc<Vec<f32>>>,
     common_config: CommonConfig,
     skipgram_config: SkipGramConfig,
 }
@@ -40,6 +63,16 @@ where
         common_config: CommonConfig,
         skipgram_config: SkipGramConfig,
     ) -> Self {
+        assert!(
+            !skipgram_config.tied_weights || skipgram_config.model == ModelType::SkipGram,
+            "Tied weights are only supported by the skipgram model"
+        );
+
+        assert!(
+            skipgram_config.context_subsampling_threshold >= 0.,
+            "The context subsampling threshold should not be negative"
+        );
+
         let vocab = Arc::new(vocab);
         let rng = ReseedOnCloneRng(rng);
         let band_size = match skipgram_config.model {
@@ -50,21 +83,131 @@ where
 
         let range_gen = BandedRangeGenerator::new(
             rng.clone(),
-            ZipfRangeGenerator::new_with_exponent(
+            NegativeRangeGenerator::new(
                 rng.clone(),
                 vocab.len(),
                 common_config.zipf_exponent,
+                common_config.sampler,
             ),
             band_size as usize,
         );
+        let context_keep_probs = if skipgram_config.context_subsampling_threshold > 0. {
+            Some(Arc::new(create_discards(
+                skipgram_config.context_subsampling_threshold,
+                vocab.types(),
+                vocab.n_types(),
+            )))
+        } else {
+            None
+        };
         SkipgramTrainer {
             vocab,
+            boundary_tokens: Arc::new(HashSet::new()),
+            ids: Vec::new(),
+            segment_ends: Vec::new(),
+            skipped: SkippedTokens::default(),
+            pairs: false,
             rng,
             range_gen,
+            context_keep_probs,
             common_config,
             skipgram_config,
         }
     }
+
+    /// Draw negatives from a fixed distribution.
+    ///
+    /// `probabilities` contains the sampling probability of every word,
+    /// indexed by word index, see `io::read_negative_distribution`. The
+    /// distribution replaces the distribution by word rank and the
+    /// sampler of the common configuration.
+    pub fn negative_distribution(mut self, probabilities: &[f64]) -> Self {
+        assert_eq!(
+            probabilities.len(),
+            self.vocab.len(),
+            "Number of probabilities does not match the vocabulary size"
+        );
+
+        self.range_gen = BandedRangeGenerator::new(
+            self.rng.clone(),
+            NegativeRangeGenerator::from_probabilities(self.rng.clone(), probabilities),
+            self.band_size(),
+        );
+        self
+    }
+}
+
+impl<R, V> SkipgramTrainer<R, V>
+where
+    V: Vocab,
+{
+    /// Set the boundary tokens.
+    ///
+    /// Every boundary token terminates the context windows of the tokens
+    /// preceding it, a context window never spans a boundary token. The
+    /// boundary token itself is part of the window that it terminates,
+    /// unless `SkipGramConfig::discard_boundary_tokens` is set.
+    pub fn boundary_tokens(mut self, tokens: impl IntoIterator<Item = V::VocabType>) -> Self {
+        self.boundary_tokens = Arc::new(tokens.into_iter().collect());
+        self
+    }
+
+    /// Train on focus-context pairs rather than sentences.
+    ///
+    /// Every sequence is then read as a list of pairs: a word at an even
+    /// position is a focus word and the word that follows it is its only
+    /// context. Words are not subsampled and no context windows are
+    /// formed, so every pair with known words is one positive example.
+    ///
+    /// The context word of a pair is also returned as a focus word
+    /// without contexts, so that both words of a pair count as processed
+    /// tokens, as in a sentence.
+    pub fn pairs(mut self, pairs: bool) -> Self {
+        self.pairs = pairs;
+        self
+    }
+
+    /// Get the output rows of a word.
+    ///
+    /// Structured and directional skip-gram models have several outputs
+    /// per word, the outputs of a word are consecutive rows.
+    pub fn output_rows(&self, word: usize) -> Range<usize> {
+        let band_size = self.band_size();
+        word * band_size..(word + 1) * band_size
+    }
+
+    /// Get the number of outputs per word, see `SkipGramIter::output_`.
+    fn band_size(&self) -> usize {
+        match self.skipgram_config.model {
+            ModelType::SkipGram => 1,
+            ModelType::StructuredSkipGram => self.skipgram_config.context_size as usize * 2,
+            ModelType::DirectionalSkipgram => 2,
+        }
+    }
+}
+
+impl<R, V> SkipgramTrainer<R, V>
+where
+    R: Rng,
+    V: Vocab,
+{
+    /// Get the negative sampling distribution.
+    ///
+    /// Returns the sampling probability of every output, together with
+    /// the word of the output. Structured and directional skip-gram models
+    /// have several outputs per word. Negatives are drawn by word rank,
+    /// so that the probability of the word with rank *k* is proportional
+    /// to *k^-zipf_exponent*, unless a fixed distribution was set with
+    /// `negative_distribution`.
+    pub fn negative_sampling_table(&self) -> Vec<(&V::VocabType, f32)> {
+        let probs = self.range_gen.probabilities();
+        let band_size = probs.len() / self.vocab.len();
+        probs
+            .into_iter()
+            .enumerate()
+            .map(|(output, prob)| (self.vocab.types()[output / band_size].label(), prob))
+            .collect()
+    }
 }
 
 impl<'a, S, R, V, I> TrainIterFrom<'a, [S]> for SkipgramTrainer<R, V>
@@ -80,29 +223,110 @@ where
     type Contexts = Vec<usize>;
 
     fn train_iter_from(&mut self, sequence: &[S]) -> Self::Iter {
-        let mut ids = Vec::new();
+        let mut ids = mem::take(&mut self.ids);
+        ids.clear();
+        let mut segment_ends = mem::take(&mut self.segment_ends);
+        segment_ends.clear();
+        let mut skipped = SkippedTokens::default();
+
+        if self.pairs {
+            self.skipped = skipped;
+            ids.extend(sequence.iter().map(|t| self.vocab.idx(t)));
+            return SkipGramIter::new(self.rng.clone(), ids, self.skipgram_config)
+                .segment_ends(segment_ends)
+                .pairs(true);
+        }
+
         for t in sequence {
-            if let Some(idx) = self.vocab.idx(t) {
-                if self.rng.gen_range(0f32, 1f32) < self.vocab.discard(idx.word_idx() as usize) {
-                    ids.push(idx);
+            let is_boundary = self.boundary_tokens.contains(t);
+            if is_boundary && self.skipgram_config.discard_boundary_tokens {
+                segment_ends.push(ids.len());
+                continue;
+            }
+
+            match self.vocab.idx(t) {
+                Some(idx) => {
+                    if self.rng.gen_range(0f32, 1f32) < self.vocab.discard(idx.word_idx() as usize)
+                    {
+                        ids.push(Some(idx));
+                    } else {
+                        skipped.n_discarded += 1;
+                    }
+                }
+                // Unknown tokens hold their position, unless they should
+                // be discarded.
+                None => {
+                    skipped.n_oov += 1;
+                    if !self.skipgram_config.discard_oov_contexts {
+                        ids.push(None);
+                    }
                 }
             }
+
+            if is_boundary {
+                segment_ends.push(ids.len());
+            }
         }
+        self.skipped = skipped;
+
         SkipGramIter::new(self.rng.clone(), ids, self.skipgram_config)
+            .segment_ends(segment_ends)
+            .context_keep_probs(self.context_keep_probs.clone())
+    }
+
+    fn recycle(&mut self, iter: Self::Iter) {
+        let mut ids = iter.ids;
+        ids.clear();
+        self.ids = ids;
+
+        let mut segment_ends = iter.segment_ends;
+        segment_ends.clear();
+        self.segment_ends = segment_ends;
+    }
+
+    fn skipped_tokens(&self) -> SkippedTokens {
+        self.skipped
     }
 }
 
 impl<R, V> NegativeSamples for SkipgramTrainer<R, V>
 where
     R: Rng,
+    V: Vocab,
 {
+    fn dropout_mask(&mut self, keep_prob: f32, mask: &mut [bool]) {
+        dropout_mask(&mut self.rng, keep_prob, mask);
+    }
+
     fn negative_sample(&mut self, output: usize) -> usize {
-        loop {
-            let negative = self.range_gen.next().unwrap();
+        let mut negative = self.range_gen.next().unwrap();
+        for _ in 1..MAX_NEGATIVE_DRAWS {
             if negative != output {
+                break;
+            }
+            negative = self.range_gen.next().unwrap();
+        }
+
+        negative
+    }
+
+    fn focus_negative_sample(&mut self, focus: usize, output: usize) -> usize {
+        if !self.skipgram_config.exclude_focus_negatives {
+            return self.negative_sample(output);
+        }
+
+        // Outputs are banded by word.
+        let band_size = self.band_size();
+
+        for _ in 1..MAX_NEGATIVE_DRAWS {
+            let negative = self.negative_sample(output);
+            if negative / band_size != focus {
                 return negative;
             }
         }
+
+        // Fall back to a negative that may belong to the focus word.
+        self.negative_sample(output)
     }
 }
 
@@ -144,6 +368,10 @@ where
         &self.common_config
     }
 
+    fn tied_weights(&self) -> bool {
+        self.skipgram_config.tied_weights
+    }
+
     fn to_metadata(&self) -> SkipgramMetadata<V::Config> {
         SkipgramMetadata {
             common_config: self.common_config,
@@ -154,12 +382,25 @@ where
 }
 
 /// Iterator over focus identifier and associated context identifiers in a sentence.
+///
+/// Positions that are `None` are out-of-vocabulary tokens. They are not
+/// used as focus or context tokens, but they occupy a position in the
+/// context window.
+///
+/// A sentence can be split into segments, context windows do not cross
+/// segment boundaries.
 pub struct SkipGramIter<R, I> {
-    ids: Vec<I>,
+    ids: Vec<Option<I>>,
+    segment_ends: Vec<usize>,
+    segment: usize,
+    segment_start: usize,
+    pairs: bool,
     rng: R,
+    context_keep_probs: Option<Arc<Vec<f32>>>,
     i: usize,
     model_type: ModelType,
     ctx_size: usize,
+    min_context: usize,
 }
 
 impl<R, I> SkipGramIter<R, I>
@@ -170,16 +411,74 @@ where
     /// Constructs a new `SkipGramIter`.
     ///
     /// The `rng` is used to determine the window size for each focus token.
-    pub fn new(rng: R, ids: Vec<I>, skip_config: SkipGramConfig) -> Self {
+    pub fn new(rng: R, ids: Vec<Option<I>>, skip_config: SkipGramConfig) -> Self {
         SkipGramIter {
             ids,
+            segment_ends: Vec::new(),
+            segment: 0,
+            segment_start: 0,
+            pairs: false,
             rng,
+            context_keep_probs: None,
             i: 0,
             model_type: skip_config.model,
             ctx_size: skip_config.context_size as usize,
+            min_context: skip_config.min_context as usize,
         }
     }
 
+    /// Split the sentence into segments.
+    ///
+    /// `segment_ends` contains the (exclusive) end positions of the
+    /// segments in ascending order. The last segment ends at the end of
+    /// the sentence.
+    pub fn segment_ends(mut self, segment_ends: Vec<usize>) -> Self {
+        self.segment_ends = segment_ends;
+        self
+    }
+
+    /// Subsample context words.
+    ///
+    /// `keep_probs` contains the probability of keeping a word as a
+    /// context word, indexed by word index. Every context word of every
+    /// window is kept with its probability. Pairs are not subsampled.
+    pub fn context_keep_probs(mut self, keep_probs: Option<Arc<Vec<f32>>>) -> Self {
+        self.context_keep_probs = keep_probs;
+        self
+    }
+
+    /// Read the sentence as focus-context pairs.
+    ///
+    /// See `SkipgramTrainer::pairs`.
+    pub fn pairs(mut self, pairs: bool) -> Self {
+        self.pairs = pairs;
+        self
+    }
+
+    /// Get the context of the focus word at the current position of a
+    /// sequence of pairs.
+    ///
+    /// Only focus words at even positions have a context.
+    fn pair_contexts(&self) -> Vec<usize> {
+        if self.i % 2 == 1 {
+            return Vec::new();
+        }
+
+        self.ids
+            .get(self.i + 1)
+            .and_then(Option::as_ref)
+            .map(|id| self.output_(id.word_idx() as usize, self.i, self.i + 1))
+            .into_iter()
+            .collect()
+    }
+
+    fn segment_end(&self) -> usize {
+        self.segment_ends
+            .get(self.segment)
+            .cloned()
+            .unwrap_or(self.ids.len())
+    }
+
     fn output_(&self, token: usize, focus_idx: usize, offset_idx: usize) -> usize {
         match self.model_type {
             ModelType::StructuredSkipGram => {
@@ -209,25 +508,54 @@ where
     type Item = (I, Vec<usize>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.i < self.ids.len() {
-            // Bojanowski, et al., 2017 uniformly sample the context size between 1 and c.
-            let context_size = self.rng.gen_range(1, self.ctx_size + 1) as usize;
-            let left = self.i - cmp::min(self.i, context_size);
-            let right = cmp::min(self.i + context_size + 1, self.ids.len());
-            let contexts = (left..right)
-                .filter(|&idx| idx != self.i)
-                .map(|idx| self.output_(self.ids[idx].word_idx() as usize, self.i, idx))
-                .fold(Vec::with_capacity(right - left), |mut contexts, idx| {
-                    contexts.push(idx);
-                    contexts
-                });
+        while self.i < self.ids.len() {
+            while self.i >= self.segment_end() {
+                self.segment_start = self.segment_end();
+                self.segment += 1;
+            }
+
+            let focus_word_idx = match self.ids[self.i] {
+                Some(ref idx) => idx.word_idx(),
+                None => {
+                    // Unknown tokens are not used as focus tokens.
+                    self.i += 1;
+                    continue;
+                }
+            };
+
+            let contexts = if self.pairs {
+                self.pair_contexts()
+            } else {
+                let segment = self.segment_start..self.segment_end();
+                let window = context_window(&mut self.rng, self.i, segment, self.ctx_size);
+                let mut contexts = Vec::with_capacity(window.len());
+                let focus = self.i;
+                for idx in window.filter(|&idx| idx != focus) {
+                    let word_idx = match self.ids[idx] {
+                        Some(ref id) => id.word_idx() as usize,
+                        None => continue,
+                    };
+                    if let Some(ref keep_probs) = self.context_keep_probs {
+                        if self.rng.gen_range(0f32, 1f32) >= keep_probs[word_idx] {
+                            continue;
+                        }
+                    }
+                    contexts.push(self.output_(word_idx, focus, idx));
+                }
+
+                // Focus tokens with too few context words are not trained on.
+                // They are still returned, so that they count as processed.
+                if contexts.len() < self.min_context {
+                    contexts.clear();
+                }
+                contexts
+            };
 
             // swap the representation possibly containing multiple indices with one that only
             // contains the distinct word index since we need the word index for context lookups.
-            let mut word_idx = WordIdx::from_word_idx(self.ids[self.i].word_idx());
-            mem::swap(&mut self.ids[self.i], &mut word_idx);
+            let focus = self.ids[self.i].replace(WordIdx::from_word_idx(focus_word_idx));
             self.i += 1;
-            return Some((word_idx, contexts));
+            return focus.map(|focus| (focus, contexts));
         }
         None
     }
@@ -240,6 +568,110 @@ where
 {
 }
 
+/// Iterate over the context windows of a sentence.
+///
+/// Returns a `(focus, contexts)` pair for every known token in `ids`,
+/// where `focus` is the position of the token and `contexts` are the
+/// positions of the known tokens in its context window. Unknown tokens
+/// (`None`) hold their position, but are neither focus nor context tokens.
+///
+/// This is the windowing of `SkipgramTrainer`: the window size of every
+/// focus token is drawn uniformly from *[1, context_size]* with `rng`.
+/// Windows do not cross the ends of the sentence or the segment ends in
+/// `segment_ends` (exclusive end positions in ascending order, e.g. the
+/// positions after end-of-sentence markers). Focus tokens with fewer than
+/// `min_context` context tokens have no contexts.
+pub fn context_windows<'a, R, I>(
+    rng: &'a mut R,
+    ids: &'a [Option<I>],
+    segment_ends: &'a [usize],
+    skip_config: SkipGramConfig,
+) -> impl Iterator<Item = (usize, Vec<usize>)> + 'a
+where
+    R: Rng,
+{
+    let context_size = skip_config.context_size as usize;
+    segment_windows(
+        ids,
+        segment_ends,
+        skip_config.min_context as usize,
+        move |focus, segment| context_window(rng, focus, segment, context_size),
+    )
+}
+
+/// Iterate over the fixed-size context windows of a sentence.
+///
+/// This is `context_windows`, except that every window has `context_size`
+/// tokens on both sides of the focus token (up to the ends of the sentence
+/// and the segments), so that the windows are deterministic.
+pub(crate) fn fixed_context_windows<'a, I>(
+    ids: &'a [Option<I>],
+    segment_ends: &'a [usize],
+    context_size: usize,
+) -> impl Iterator<Item = (usize, Vec<usize>)> + 'a {
+    segment_windows(ids, segment_ends, 0, move |focus, segment| {
+        truncated_window(focus, segment, context_size)
+    })
+}
+
+/// Iterate over the windows of a sentence, where `window` gets the window
+/// of a focus position within its segment.
+fn segment_windows<'a, I, F>(
+    ids: &'a [Option<I>],
+    segment_ends: &'a [usize],
+    min_context: usize,
+    mut window: F,
+) -> impl Iterator<Item = (usize, Vec<usize>)> + 'a
+where
+    F: FnMut(usize, Range<usize>) -> Range<usize> + 'a,
+{
+    let segment_end = move |segment: usize| segment_ends.get(segment).cloned().unwrap_or(ids.len());
+    let mut segment = 0;
+    let mut segment_start = 0;
+
+    (0..ids.len()).filter_map(move |focus| {
+        while focus >= segment_end(segment) {
+            segment_start = segment_end(segment);
+            segment += 1;
+        }
+
+        ids[focus].as_ref()?;
+
+        let mut contexts = window(focus, segment_start..segment_end(segment))
+            .filter(|&idx| idx != focus && ids[idx].is_some())
+            .collect::<Vec<_>>();
+        if contexts.len() < min_context {
+            contexts.clear();
+        }
+
+        Some((focus, contexts))
+    })
+}
+/// Get the context window of the focus token at position `focus`.
+///
+/// The window size is drawn uniformly from *[1, context_size]*, as in
+/// Bojanowski et al., 2017. The window is truncated to `segment`. The
+/// returned range includes the focus position.
+fn context_window<R>(
+    rng: &mut R,
+    focus: usize,
+    segment: Range<usize>,
+    context_size: usize,
+) -> Range<usize>
+where
+    R: Rng,
+{
+    truncated_window(focus, segment, rng.gen_range(1, context_size + 1))
+}
+
+/// Get the window of `context_size` tokens on both sides of `focus`,
+/// truncated to `segment`.
+fn truncated_window(focus: usize, segment: Range<usize>, context_size: usize) -> Range<usize> {
+    let left = cmp::max(segment.start, focus - cmp::min(focus, context_size));
+    let right = cmp::min(focus + context_size + 1, segment.end);
+    left..right
+}
+
 /// Metadata for Skipgramlike training algorithms.
 #[derive(Clone, Copy, Debug, Serialize)]
 pub struct SkipgramMetadata<V> {
@@ -248,3 +680,693 @@ pub struct SkipgramMetadata<V> {
     skipgram_config: SkipGramConfig,
     vocab_config: V,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::{BufReader, Cursor};
+
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::{context_windows, SkipGramIter, SkipgramTrainer};
+    use crate::config::TEST_COMMON_CONFIG;
+    use crate::idx::{SingleIdx, WordIdx};
+    use crate::io::{read_negative_distribution, InputFormat, SentenceIterator};
+    use crate::train_model::{NegativeSamples, TrainIterFrom};
+    use crate::util::numeric::close;
+    use crate::util::EOS;
+    use crate::{ModelType, SimpleVocab, SimpleVocabConfig, SkipGramConfig, Vocab, VocabBuilder};
+
+    // With a discard threshold of 1, no tokens are discarded.
+    const TEST_VOCAB_CONFIG: SimpleVocabConfig = SimpleVocabConfig {
+        min_count: 2,
+        discard_threshold: 1.,
+    };
+
+    /// Get the focus and context word indices of a sentence with an unknown token.
+    fn focus_contexts(discard_oov_contexts: bool) -> Vec<(usize, Vec<usize>)> {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for token in &["a", "a", "b", "b", "c", "c", "oov"] {
+            builder.count(*token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        assert!(vocab.idx("oov").is_none());
+        let idx = |word: &str| vocab.idx(word).unwrap().word_idx() as usize;
+        let words = [idx("a"), idx("b"), idx("c")];
+
+        let mut trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 1,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+
+        let sentence = vec!["a", "oov", "b", "c"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<String>>();
+
+        // Map word indices to positions in a, b, c for readability.
+        let pos = |word_idx: usize| words.iter().position(|&w| w == word_idx).unwrap();
+        trainer
+            .train_iter_from(sentence.as_slice())
+            .map(|(focus, contexts)| {
+                (
+                    pos(focus.word_idx() as usize),
+                    contexts.into_iter().map(pos).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn oov_tokens_hold_position() {
+        // The unknown token separates a and b.
+        assert_eq!(
+            focus_contexts(false),
+            vec![(0, vec![]), (1, vec![2]), (2, vec![1])]
+        );
+    }
+
+    #[test]
+    fn discard_oov_contexts_closes_up_window() {
+        // a and b are adjacent after removing the unknown token.
+        assert_eq!(
+            focus_contexts(true),
+            vec![(0, vec![1]), (1, vec![0, 2]), (2, vec![1])]
+        );
+    }
+
+    /// Get the fraction of context positions of `a` and `b` that are kept.
+    fn context_keep_rates(context_subsampling_threshold: f32) -> (f32, f32) {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for _ in 0..98 {
+            builder.count("a");
+        }
+        builder.count("b");
+        builder.count("b");
+        let vocab: SimpleVocab<String> = builder.into();
+        let a = vocab.idx("a").unwrap().word_idx() as usize;
+
+        let mut trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 1,
+                context_subsampling_threshold,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+
+        // Every inner token has its two neighbours as contexts.
+        let sentence = ["a", "b"]
+            .iter()
+            .cycle()
+            .take(2002)
+            .map(|&token| token.to_owned())
+            .collect::<Vec<String>>();
+        let (mut n_a, mut n_b) = (0, 0);
+        for (_, contexts) in trainer.train_iter_from(sentence.as_slice()) {
+            for context in contexts {
+                if context == a {
+                    n_a += 1;
+                } else {
+                    n_b += 1;
+                }
+            }
+        }
+
+        (n_a as f32 / 2001., n_b as f32 / 2001.)
+    }
+
+    #[test]
+    fn context_subsampling_drops_frequent_contexts() {
+        assert_eq!(context_keep_rates(0.), (1., 1.));
+
+        let (keep_a, keep_b) = context_keep_rates(1e-3);
+        assert!(keep_a < 0.1);
+        assert!(keep_b > 0.2);
+        assert!(keep_a < keep_b);
+    }
+
+    /// Get the focus and context words of a sentence with two boundary tokens.
+    fn boundary_focus_contexts(discard_boundary_tokens: bool) -> Vec<(String, Vec<String>)> {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for token in "a b <p> c <doc> d a b <p> c <doc> d".split_whitespace() {
+            builder.count(token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        let words = vocab
+            .types()
+            .iter()
+            .map(|t| t.label().clone())
+            .collect::<Vec<_>>();
+
+        // Window sizes are drawn from [1, 2], use a context size of 2 to
+        // get contexts that would cross the boundaries.
+        let mut trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 2,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        )
+        .boundary_tokens(vec!["<p>".to_string(), "<doc>".to_string()]);
+
+        let sentence = "a b <p> c <doc> d"
+            .split_whitespace()
+            .map(ToOwned::to_owned)
+            .collect::<Vec<String>>();
+
+        trainer
+            .train_iter_from(sentence.as_slice())
+            .map(|(focus, contexts)| {
+                (
+                    words[focus.word_idx() as usize].clone(),
+                    contexts.into_iter().map(|idx| words[idx].clone()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn boundary_tokens_terminate_windows() {
+        let allowed = |focus: &str| match focus {
+            "a" | "b" | "<p>" => vec!["a", "b", "<p>"],
+            "c" | "<doc>" => vec!["c", "<doc>"],
+            "d" => vec!["d"],
+            _ => unreachable!(),
+        };
+
+        let focus_contexts = boundary_focus_contexts(false);
+        assert_eq!(focus_contexts.len(), 6);
+        for (focus, contexts) in focus_contexts {
+            assert!(!contexts.is_empty() || focus == "d");
+            for context in contexts {
+                assert!(
+                    allowed(&focus).contains(&context.as_str()),
+                    "Context {} of {} crosses a boundary",
+                    context,
+                    focus
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn discard_boundary_tokens_removes_boundaries() {
+        assert_eq!(
+            boundary_focus_contexts(true),
+            vec![
+                ("a".to_string(), vec!["b".to_string()]),
+                ("b".to_string(), vec!["a".to_string()]),
+                ("c".to_string(), vec![]),
+                ("d".to_string(), vec![]),
+            ]
+        );
+    }
+
+    const WINDOW_CONFIG: SkipGramConfig = SkipGramConfig {
+        context_size: 1,
+        context_subsampling_threshold: 0.,
+        discard_boundary_tokens: false,
+        discard_oov_contexts: false,
+        exclude_focus_negatives: false,
+        min_context: 0,
+        model: ModelType::SkipGram,
+        tied_weights: false,
+    };
+
+    #[test]
+    fn context_windows_with_fixed_size() {
+        // Window sizes are drawn from [1, 1]. The unknown token holds its
+        // position.
+        let ids = [Some(0u64), Some(1), None, Some(2), Some(3)];
+        let mut rng = XorShiftRng::seed_from_u64(42);
+        assert_eq!(
+            context_windows(&mut rng, &ids, &[], WINDOW_CONFIG).collect::<Vec<_>>(),
+            vec![(0, vec![1]), (1, vec![0]), (3, vec![4]), (4, vec![3])]
+        );
+    }
+
+    #[test]
+    fn context_windows_with_dynamic_size() {
+        let ids = (0..20)
+            .map(|idx| Some(SingleIdx::from_word_idx(idx)))
+            .collect::<Vec<_>>();
+        let config = SkipGramConfig {
+            context_size: 4,
+            context_subsampling_threshold: 0.,
+            ..WINDOW_CONFIG
+        };
+
+        let windows = |seed| {
+            let mut rng = XorShiftRng::seed_from_u64(seed);
+            context_windows(&mut rng, &ids, &[], config).collect::<Vec<_>>()
+        };
+        let windows_42 = windows(42);
+        assert_eq!(windows_42, windows(42));
+        assert_ne!(windows_42, windows(43));
+
+        // Windows are contiguous and symmetric around the focus token,
+        // up to the sentence ends.
+        let mut sizes = Vec::new();
+        for (focus, contexts) in &windows_42 {
+            let size = contexts
+                .iter()
+                .map(|&idx| focus.max(&idx) - focus.min(&idx))
+                .max();
+            let size = size.unwrap();
+            assert!((1..=4).contains(&size));
+            let left = focus.saturating_sub(size);
+            let right = (focus + size + 1).min(ids.len());
+            let expected = (left..right).filter(|idx| idx != focus).collect::<Vec<_>>();
+            assert_eq!(contexts, &expected);
+            sizes.push(size);
+        }
+        assert!(sizes.iter().any(|&size| size != sizes[0]));
+
+        // The trainer uses the same windows.
+        let trainer_windows =
+            SkipGramIter::new(XorShiftRng::seed_from_u64(42), ids.clone(), config)
+                .map(|(focus, contexts)| (focus.word_idx() as usize, contexts))
+                .collect::<Vec<_>>();
+        assert_eq!(windows_42, trainer_windows);
+    }
+
+    #[test]
+    fn context_windows_are_truncated_at_sentence_ends() {
+        // a b </s> c d </s>
+        let ids = [Some(0u64), Some(1), Some(2), Some(3), Some(4), Some(2)];
+        let config = SkipGramConfig {
+            context_size: 5,
+            context_subsampling_threshold: 0.,
+            ..WINDOW_CONFIG
+        };
+
+        for seed in 0..10 {
+            let mut rng = XorShiftRng::seed_from_u64(seed);
+            for (focus, contexts) in context_windows(&mut rng, &ids, &[3], config) {
+                let segment = if focus < 3 { 0..3 } else { 3..6 };
+                assert!(!contexts.is_empty());
+                assert!(contexts.iter().all(|idx| segment.contains(idx)));
+            }
+        }
+
+        // The first and last token only have right and left contexts.
+        let mut rng = XorShiftRng::seed_from_u64(42);
+        let windows = context_windows(&mut rng, &ids, &[3], WINDOW_CONFIG).collect::<Vec<_>>();
+        assert_eq!(windows[0], (0, vec![1]));
+        assert_eq!(windows[2], (2, vec![1]));
+        assert_eq!(windows[3], (3, vec![4]));
+        assert_eq!(windows[5], (5, vec![4]));
+    }
+
+    #[test]
+    fn context_windows_do_not_cross_document_markers() {
+        let text = "<doc>\na b\nc d\n<doc>\n\n<doc>\ne f\ng\n";
+        let documents = SentenceIterator::new(text.as_bytes())
+            .document_marker("<doc>")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            documents,
+            vec![
+                vec!["a", "b", EOS, "c", "d", EOS],
+                vec!["e", "f", EOS, "g", EOS]
+            ]
+        );
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1.,
+        });
+        for token in documents.iter().flatten() {
+            builder.count(token.clone());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        let mut trainer = SkipgramTrainer::new(
+            vocab.clone(),
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            WINDOW_CONFIG,
+        );
+        let mut contexts = |document: &[String]| {
+            trainer
+                .train_iter_from(document)
+                .map(|(_, contexts)| {
+                    contexts
+                        .into_iter()
+                        .map(|idx| vocab.types()[idx].label().clone())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Windows cross line breaks within a document, but not the document
+        // boundary: the first and last tokens of a document only have right
+        // and left contexts.
+        assert_eq!(
+            contexts(&documents[0]),
+            vec![
+                vec!["b"],
+                vec!["a", EOS],
+                vec!["b", "c"],
+                vec![EOS, "d"],
+                vec!["c", EOS],
+                vec!["d"]
+            ]
+        );
+        assert_eq!(
+            contexts(&documents[1]),
+            vec![
+                vec!["f"],
+                vec!["e", EOS],
+                vec!["f", "g"],
+                vec![EOS, EOS],
+                vec!["g"]
+            ]
+        );
+
+        // Without a document marker, the marker is a sentence.
+        let sentences = SentenceIterator::new(text.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(sentences.len(), 7);
+    }
+
+    #[test]
+    fn min_context_skips_sparse_windows() {
+        // The unknown token at position 2 leaves only the token at position
+        // 4 with two context words. Skipped focus tokens are returned without
+        // contexts.
+        let ids = [Some(0), Some(1), None, Some(2), Some(3), Some(4)]
+            .iter()
+            .map(|idx| idx.map(SingleIdx::from_word_idx))
+            .collect::<Vec<_>>();
+        let config = SkipGramConfig {
+            context_size: 1,
+            context_subsampling_threshold: 0.,
+            discard_boundary_tokens: false,
+            discard_oov_contexts: false,
+            exclude_focus_negatives: false,
+            min_context: 0,
+            model: ModelType::SkipGram,
+            tied_weights: false,
+        };
+
+        let examples = |min_context| {
+            SkipGramIter::new(
+                XorShiftRng::seed_from_u64(42),
+                ids.clone(),
+                SkipGramConfig {
+                    min_context,
+                    ..config
+                },
+            )
+            .map(|(focus, contexts)| (focus.word_idx(), contexts))
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            examples(0),
+            vec![
+                (0, vec![1]),
+                (1, vec![0]),
+                (2, vec![3]),
+                (3, vec![2, 4]),
+                (4, vec![3])
+            ]
+        );
+        assert_eq!(
+            examples(2),
+            vec![
+                (0, vec![]),
+                (1, vec![]),
+                (2, vec![]),
+                (3, vec![2, 4]),
+                (4, vec![])
+            ]
+        );
+    }
+
+    #[test]
+    fn negatives_follow_fixed_distribution() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for token in "a a a a a b b b b c c c d d".split_whitespace() {
+            builder.count(token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        let distribution = Cursor::new(b"a\t1\nc\t2\nd\t1\n".to_vec());
+        let probs = read_negative_distribution(distribution, &vocab).unwrap();
+
+        let mut trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 1,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        )
+        .negative_distribution(&probs);
+
+        let table = trainer.negative_sampling_table();
+        let table_probs = table.iter().map(|&(_, prob)| prob).collect::<Vec<_>>();
+        assert_eq!(table_probs, [0.25, 0., 0.5, 0.25]);
+
+        const N_DRAWS: usize = 100_000;
+        let mut counts = [0usize; 4];
+        for _ in 0..N_DRAWS {
+            counts[trainer.range_gen.next().unwrap()] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        for (&count, &prob) in counts.iter().zip(&table_probs) {
+            assert!(close(count as f32 / N_DRAWS as f32, prob, 0.01));
+        }
+    }
+
+    #[test]
+    fn negative_sampling_terminates_for_tiny_vocab() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        builder.count("a");
+        builder.count("a");
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let mut trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 1,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: true,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+
+        // The only output is both the positive and the focus word.
+        assert_eq!(trainer.negative_sample(0), 0);
+        assert_eq!(trainer.focus_negative_sample(0, 0), 0);
+    }
+
+    #[test]
+    fn negative_sampling_table_is_normalized() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for token in "a a a a b b b c c".split_whitespace() {
+            builder.count(token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 1,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::DirectionalSkipgram,
+                tied_weights: false,
+            },
+        );
+
+        let table = trainer.negative_sampling_table();
+        let words = table
+            .iter()
+            .map(|&(word, _)| word.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(words, ["a", "a", "b", "b", "c", "c"]);
+        assert!(close(table.iter().map(|&(_, prob)| prob).sum(), 1.0, 1e-6));
+
+        // The probabilities follow rank^-0.5, split over the directions.
+        let norm = 1. + 2f32.powf(-0.5) + 3f32.powf(-0.5);
+        for (output, &(_, prob)) in table.iter().enumerate() {
+            let rank = (output / 2 + 1) as f32;
+            assert!(close(prob, rank.powf(-0.5) / norm / 2., 1e-6));
+        }
+    }
+
+    #[test]
+    fn recycled_buffer_gives_identical_examples() {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(TEST_VOCAB_CONFIG);
+        for token in "a b c d a b c d".split_whitespace() {
+            builder.count(token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        // A context size of 1 makes the window sizes deterministic.
+        let config = SkipGramConfig {
+            context_size: 1,
+            context_subsampling_threshold: 0.,
+            discard_boundary_tokens: false,
+            discard_oov_contexts: false,
+            exclude_focus_negatives: false,
+            min_context: 0,
+            model: ModelType::StructuredSkipGram,
+            tied_weights: false,
+        };
+        let mut recycling = SkipgramTrainer::new(
+            vocab.clone(),
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            config,
+        );
+        let mut fresh = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            config,
+        );
+
+        let sentences = ["a b c d a", "d c oov b", "b a"]
+            .iter()
+            .map(|s| s.split_whitespace().map(ToOwned::to_owned).collect())
+            .collect::<Vec<Vec<String>>>();
+
+        let mut buffer_ptr = None;
+        for sentence in &sentences {
+            let mut iter = recycling.train_iter_from(sentence.as_slice());
+            if let Some(ptr) = buffer_ptr {
+                // The sentence fits in the buffer, so it is reused.
+                assert_eq!(iter.ids.as_ptr(), ptr);
+            }
+            let recycled = iter
+                .by_ref()
+                .map(|(focus, contexts)| (focus.word_idx(), contexts))
+                .collect::<Vec<_>>();
+            buffer_ptr = Some(iter.ids.as_ptr());
+            TrainIterFrom::<[String]>::recycle(&mut recycling, iter);
+
+            let expected = fresh
+                .train_iter_from(sentence.as_slice())
+                .map(|(focus, contexts)| (focus.word_idx(), contexts))
+                .collect::<Vec<_>>();
+
+            assert!(!recycled.is_empty());
+            assert_eq!(recycled, expected);
+        }
+    }
+
+    #[test]
+    fn pairs_give_exact_positive_examples() {
+        let read_pairs = || {
+            let f = File::open("testdata/pairs.txt").unwrap();
+            SentenceIterator::new(BufReader::new(f))
+                .input_format(InputFormat::Pairs)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1e-4,
+        });
+        for token in read_pairs().into_iter().flatten() {
+            builder.count(token);
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        let n_types = vocab.n_types();
+        let word = |idx: usize| vocab.types()[idx].label().clone();
+
+        // Windows would give the context words the focus words as context.
+        let mut trainer = SkipgramTrainer::new(
+            vocab.clone(),
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 5,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        )
+        .pairs(true);
+
+        let mut n_focus = 0;
+        let mut examples = Vec::new();
+        for pair in read_pairs() {
+            for (focus, contexts) in trainer.train_iter_from(pair.as_slice()) {
+                n_focus += 1;
+                for context in contexts {
+                    examples.push((word(focus.word_idx() as usize), word(context)));
+                }
+            }
+        }
+
+        let expected = [
+            ("cat", "dog"),
+            ("cat", "mouse"),
+            ("dog", "cat"),
+            ("mouse", "cat"),
+        ]
+        .iter()
+        .map(|&(focus, context)| (focus.to_owned(), context.to_owned()))
+        .collect::<Vec<_>>();
+        assert_eq!(examples, expected);
+
+        // Both words of every pair are processed tokens.
+        assert_eq!(n_focus, n_types);
+    }
+}
@@ -0,0 +1,678 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::dedup::{SentenceDedup, DEFAULT_DEDUP_COUNTERS_EXP};
+use crate::io::{InputFormat, SentenceIterator, Tokenizer};
+use crate::util::EOS;
+use crate::{Error, VocabBuilder};
+
+/// Boxed iterator over the sentences of a `SentenceSource`.
+pub type Sentences<'a> = Box<dyn Iterator<Item = Result<Vec<String>, Error>> + 'a>;
+
+/// A source of training sentences.
+///
+/// Training requires multiple passes over the data: one pass to construct
+/// the vocabulary and one pass per training epoch. A source must therefore
+/// be re-iterable: every call of `sentences` starts a new pass over all
+/// sentences of the source.
+///
+/// Sentences are sequences of tokens **without** an end-of-sentence
+/// marker, the marker is added by the consumers of the source.
+pub trait SentenceSource {
+    /// Start a pass over the sentences of the source.
+    fn sentences(&self) -> Result<Sentences<'_>, Error>;
+}
+
+impl SentenceSource for Vec<Vec<String>> {
+    fn sentences(&self) -> Result<Sentences<'_>, Error> {
+        Ok(Box::new(self.iter().cloned().map(Ok)))
+    }
+}
+
+/// Sources that are read one after another.
+///
+/// This can be used to train on a corpus that is split across several
+/// files (shards).
+impl<S> SentenceSource for [S]
+where
+    S: SentenceSource,
+{
+    fn sentences(&self) -> Result<Sentences<'_>, Error> {
+        let mut passes = Vec::with_capacity(self.len());
+        for source in self {
+            passes.push(source.sentences()?);
+        }
+
+        Ok(Box::new(passes.into_iter().flatten()))
+    }
+}
+
+/// A corpus file as a sentence source.
+///
+/// The file is read with `SentenceIterator`, the file is opened anew for
+/// every pass. Files with the `.zst` extension are decompressed while
+/// reading, this requires the `zstd` feature.
+#[derive(Clone, Debug)]
+pub struct TextCorpus {
+    path: PathBuf,
+    format: InputFormat,
+    comment_prefix: Option<String>,
+    document_marker: Option<String>,
+    max_token_len: Option<usize>,
+    max_sentence_len: Option<usize>,
+    normalize_digits: bool,
+    lowercase: bool,
+    lossy_utf8: bool,
+    tokenizer: Option<Arc<dyn Tokenizer>>,
+    dedup: Option<u32>,
+}
+
+impl TextCorpus {
+    /// Construct a plain text corpus source.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        TextCorpus {
+            path: path.into(),
+            format: InputFormat::Text,
+            comment_prefix: None,
+            document_marker: None,
+            max_token_len: None,
+            max_sentence_len: None,
+            normalize_digits: false,
+            lowercase: false,
+            lossy_utf8: false,
+            tokenizer: None,
+            dedup: None,
+        }
+    }
+
+    /// Set the input format of the corpus.
+    pub fn input_format(mut self, format: InputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Skip lines that start with `prefix`.
+    pub fn comment_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.comment_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Join the lines of documents that are separated by `marker` lines.
+    pub fn document_marker(mut self, marker: impl Into<String>) -> Self {
+        self.document_marker = Some(marker.into());
+        self
+    }
+
+    /// Skip tokens that are longer than `max_token_len` bytes.
+    pub fn max_token_len(mut self, max_token_len: usize) -> Self {
+        self.max_token_len = Some(max_token_len);
+        self
+    }
+
+    /// Split sentences that are longer than `max_sentence_len` tokens.
+    pub fn max_sentence_len(mut self, max_sentence_len: usize) -> Self {
+        self.max_sentence_len = Some(max_sentence_len);
+        self
+    }
+
+    /// Replace runs of digits in tokens by a single `0`.
+    pub fn normalize_digits(mut self, normalize_digits: bool) -> Self {
+        self.normalize_digits = normalize_digits;
+        self
+    }
+
+    /// Lowercase tokens.
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    /// Replace invalid UTF-8 sequences by U+FFFD.
+    pub fn lossy_utf8(mut self, lossy_utf8: bool) -> Self {
+        self.lossy_utf8 = lossy_utf8;
+        self
+    }
+
+    /// Skip sentences that occurred `max_repeats` times before.
+    ///
+    /// Repeats are counted in every pass over the corpus, so the same
+    /// sentences are skipped when the vocabulary is constructed and
+    /// during training.
+    pub fn dedup(mut self, max_repeats: u32) -> Self {
+        self.dedup = Some(max_repeats);
+        self
+    }
+
+    /// Split lines with `tokenizer`, rather than on whitespace.
+    pub fn tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+}
+
+impl SentenceSource for TextCorpus {
+    fn sentences(&self) -> Result<Sentences<'_>, Error> {
+        let read = open_corpus(&self.path)?;
+        let mut iter = match self.max_token_len {
+            Some(max_token_len) => SentenceIterator::with_max_token_len(read, max_token_len),
+            None => SentenceIterator::new(read),
+        }
+        .input_format(self.format.clone())
+        .normalize_digits(self.normalize_digits)
+        .lowercase(self.lowercase)
+        .lossy_utf8(self.lossy_utf8);
+        if let Some(ref prefix) = self.comment_prefix {
+            iter = iter.comment_prefix(prefix.as_str());
+        }
+        if let Some(ref marker) = self.document_marker {
+            iter = iter.document_marker(marker.as_str());
+        }
+        if let Some(max_sentence_len) = self.max_sentence_len {
+            iter = iter.max_sentence_len(max_sentence_len);
+        }
+        if let Some(ref tokenizer) = self.tokenizer {
+            iter = iter.tokenizer(tokenizer.clone());
+        }
+        if let Some(max_repeats) = self.dedup {
+            iter = iter.dedup(SentenceDedup::new(max_repeats, DEFAULT_DEDUP_COUNTERS_EXP));
+        }
+
+        let path = &self.path;
+        Ok(Box::new(iter::from_fn(move || {
+            let sentence = iter.next()?.map_err(|error| Error::Io {
+                path: path.clone(),
+                line: Some(iter.n_lines()),
+                error,
+            });
+
+            // Remove the end-of-sentence marker.
+            Some(sentence.map(|mut sentence| {
+                sentence.pop();
+                sentence
+            }))
+        })))
+    }
+}
+
+/// Open a corpus file for reading.
+///
+/// Files with the `.zst` extension are decompressed.
+fn open_corpus(path: &Path) -> Result<Box<dyn BufRead>, Error> {
+    let f = File::open(path).map_err(|error| Error::io(path, error))?;
+
+    if path.extension().is_some_and(|ext| ext == "zst") {
+        return open_zstd(path, f);
+    }
+
+    Ok(Box::new(BufReader::new(f)))
+}
+
+#[cfg(feature = "zstd")]
+fn open_zstd(path: &Path, f: File) -> Result<Box<dyn BufRead>, Error> {
+    // The decoder reads all frames of the file, not only the first one.
+    let decoder = zstd::stream::read::Decoder::new(f).map_err(|error| Error::io(path, error))?;
+    Ok(Box::new(BufReader::new(decoder)))
+}
+
+#[cfg(not(feature = "zstd"))]
+fn open_zstd(path: &Path, _f: File) -> Result<Box<dyn BufRead>, Error> {
+    Err(Error::Config(format!(
+        "Cannot read {}, finalfrontier was compiled without zstd support",
+        path.display()
+    )))
+}
+
+/// Construct a vocabulary from the sentences of a source.
+pub fn build_vocab<S, C, V>(source: &S, config: C) -> Result<V, Error>
+where
+    S: SentenceSource + ?Sized,
+    V: From<VocabBuilder<C, String>>,
+{
+    let mut builder = VocabBuilder::new(config);
+    for sentence in source.sentences()? {
+        for token in sentence? {
+            builder.count(token);
+        }
+        builder.count(EOS);
+    }
+
+    Ok(builder.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use super::{build_vocab, SentenceSource, Sentences, TextCorpus};
+    use crate::config;
+    use crate::util::EOS;
+    use crate::{
+        CommonConfig, EarlyStopping, Error, ModelType, SimpleVocab, SimpleVocabConfig,
+        SkipGramConfig, SkipgramTrainer, Tokenizer, Vocab, WriteModelText, SGD,
+    };
+
+    /// Tokenizer that splits lines on a fixed delimiter.
+    struct DelimiterTokenizer(char);
+
+    impl Tokenizer for DelimiterTokenizer {
+        fn tokenize(&self, line: &str, out: &mut Vec<String>) {
+            out.extend(
+                line.split(self.0)
+                    .filter(|token| !token.is_empty())
+                    .map(ToOwned::to_owned),
+            );
+        }
+    }
+
+    /// Source that generates its sentences on every pass.
+    struct Repeat {
+        sentence: &'static str,
+        n: usize,
+    }
+
+    impl SentenceSource for Repeat {
+        fn sentences(&self) -> Result<Sentences<'_>, Error> {
+            let sentence = self.sentence;
+            Ok(Box::new((0..self.n).map(move |_| {
+                Ok(sentence.split_whitespace().map(ToOwned::to_owned).collect())
+            })))
+        }
+    }
+
+    /// Source that sets `cancelled` when its `n`-th sentence is read.
+    struct CancelAfter<'a> {
+        source: Repeat,
+        n: usize,
+        cancelled: &'a AtomicBool,
+    }
+
+    impl SentenceSource for CancelAfter<'_> {
+        fn sentences(&self) -> Result<Sentences<'_>, Error> {
+            let n = self.n;
+            let cancelled = self.cancelled;
+            Ok(Box::new(self.source.sentences()?.enumerate().map(
+                move |(idx, sentence)| {
+                    if idx + 1 == n {
+                        cancelled.store(true, Ordering::SeqCst);
+                    }
+                    sentence
+                },
+            )))
+        }
+    }
+
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+        dims: 5,
+        epochs: 3,
+        negative_samples: 2,
+        output_dims: 5,
+        ..config::TEST_COMMON_CONFIG
+    };
+
+    const TEST_VOCAB_CONFIG: SimpleVocabConfig = SimpleVocabConfig {
+        min_count: 1,
+        discard_threshold: 1.,
+    };
+
+    #[test]
+    fn train_from_custom_source() {
+        let source = Repeat {
+            sentence: "the cat sat on the mat",
+            n: 10,
+        };
+
+        let vocab: SimpleVocab<String> = build_vocab(&source, TEST_VOCAB_CONFIG).unwrap();
+        assert_eq!(vocab.len(), 6);
+        assert_eq!(vocab.types()[0].count(), 20);
+        assert_eq!(vocab.n_types(), 70);
+        assert!(vocab.idx(EOS).is_some());
+
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 2,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+        let mut sgd = SGD::new(trainer.into());
+        sgd.train_source(&source).unwrap();
+
+        assert_eq!(sgd.n_tokens_processed(), 3 * 70);
+        assert!(sgd.train_loss().is_finite());
+    }
+
+    #[test]
+    fn train_independent_models_in_one_process() {
+        let source = Repeat {
+            sentence: "the cat sat on the mat",
+            n: 10,
+        };
+        let vocab: SimpleVocab<String> = build_vocab(&source, TEST_VOCAB_CONFIG).unwrap();
+
+        let train = |common_config: CommonConfig| {
+            let trainer = SkipgramTrainer::new(
+                vocab.clone(),
+                XorShiftRng::seed_from_u64(42),
+                common_config,
+                SkipGramConfig {
+                    context_size: 2,
+                    context_subsampling_threshold: 0.,
+                    discard_boundary_tokens: false,
+                    discard_oov_contexts: false,
+                    exclude_focus_negatives: false,
+                    min_context: 0,
+                    model: ModelType::SkipGram,
+                    tied_weights: false,
+                },
+            );
+            let mut sgd = SGD::new(trainer.into());
+            sgd.train_source(&source).unwrap();
+            sgd
+        };
+
+        let first = train(TEST_COMMON_CONFIG);
+        let mut first_embeds = Vec::new();
+        first
+            .model()
+            .write_model_text(&mut first_embeds, true)
+            .unwrap();
+
+        let mut other_config = TEST_COMMON_CONFIG;
+        other_config.dims = 7;
+        other_config.output_dims = 7;
+        other_config.epochs = 2;
+        other_config.lr = 0.1;
+        let second = train(other_config);
+
+        assert_eq!(first.n_tokens_processed(), 3 * 70);
+        assert_eq!(second.n_tokens_processed(), 2 * 70);
+        assert_eq!(first.model().config().dims, 5);
+        assert_eq!(second.model().config().dims, 7);
+        assert!(first.train_loss().is_finite());
+        assert!(second.train_loss().is_finite());
+
+        // The second training does not touch the first model.
+        let mut embeds = Vec::new();
+        first.model().write_model_text(&mut embeds, true).unwrap();
+        assert_eq!(embeds, first_embeds);
+    }
+
+    #[test]
+    fn train_from_source_stops_early() {
+        let source = Repeat {
+            sentence: "the cat sat on the mat",
+            n: 10,
+        };
+
+        let vocab: SimpleVocab<String> = build_vocab(&source, TEST_VOCAB_CONFIG).unwrap();
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.epochs = 10;
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            SkipGramConfig {
+                context_size: 2,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+        let mut sgd = SGD::new(trainer.into());
+
+        // Synthetic validation loss that plateaus after the third epoch.
+        let mut validation_losses = vec![1.0, 0.8, 0.7, 0.7, 0.69, 0.7, 0.6].into_iter();
+        let epochs = sgd
+            .train_source_early_stopping(&source, EarlyStopping::new(0.05, 2), |_| {
+                validation_losses.next().unwrap()
+            })
+            .unwrap();
+
+        assert_eq!(epochs, 5);
+        assert_eq!(sgd.n_tokens_processed(), 5 * 70);
+    }
+
+    #[test]
+    fn train_from_source_cancelled() {
+        let source = Repeat {
+            sentence: "the cat sat on the mat",
+            n: 10,
+        };
+
+        let vocab: SimpleVocab<String> = build_vocab(&source, TEST_VOCAB_CONFIG).unwrap();
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 2,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+        let mut sgd = SGD::new(trainer.into());
+
+        let cancelled = AtomicBool::new(false);
+        let err = sgd
+            .train_source_cancellable(
+                &CancelAfter {
+                    source,
+                    n: 3,
+                    cancelled: &cancelled,
+                },
+                &cancelled,
+            )
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+
+        // Training stops before the sentence that is read after cancelling.
+        assert_eq!(sgd.n_tokens_processed(), 2 * 7);
+        assert!(sgd.train_loss().is_finite());
+    }
+
+    #[test]
+    fn train_on_corpus_with_invalid_utf8() {
+        let corpus = TextCorpus::new("testdata/invalid_utf8.txt");
+        assert!(build_vocab::<_, _, SimpleVocab<String>>(&corpus, TEST_VOCAB_CONFIG).is_err());
+
+        let corpus = corpus.lossy_utf8(true);
+        let vocab: SimpleVocab<String> = build_vocab(&corpus, TEST_VOCAB_CONFIG).unwrap();
+        assert!(vocab.idx("caf\u{FFFD}").is_some());
+
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 2,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+        let mut sgd = SGD::new(trainer.into());
+        sgd.train_source(&corpus).unwrap();
+        assert!(sgd.train_loss().is_finite());
+    }
+
+    #[test]
+    fn text_corpus_source() {
+        let source = TextCorpus::new("testdata/chunking.txt");
+
+        // Every pass yields the same sentences.
+        for _ in 0..2 {
+            let sentences = source
+                .sentences()
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(sentences.len(), 9);
+            assert_eq!(sentences[0], vec!["a", "b", "c"]);
+        }
+
+        let in_memory = vec![vec!["a".to_string(), "b".to_string()]];
+        let vocab: SimpleVocab<String> = build_vocab(&in_memory, TEST_VOCAB_CONFIG).unwrap();
+        assert_eq!(vocab.n_types(), 3);
+    }
+
+    #[test]
+    fn sharded_source() {
+        let shards = [
+            TextCorpus::new("testdata/chunking.txt"),
+            TextCorpus::new("testdata/chunking.txt"),
+        ];
+        let sentences = shards[..]
+            .sentences()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(sentences.len(), 18);
+        assert_eq!(sentences[..9], sentences[9..]);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_corpus_source() {
+        use std::fs;
+
+        let text = fs::read_to_string("testdata/chunking.txt").unwrap();
+        let expected = TextCorpus::new("testdata/chunking.txt")
+            .sentences()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        // Compress the two halves of the corpus as separate frames.
+        let split = text.len() / 2;
+        let split = split + text[split..].find('\n').unwrap() + 1;
+        let mut compressed = zstd::encode_all(&text.as_bytes()[..split], 3).unwrap();
+        compressed.extend(zstd::encode_all(&text.as_bytes()[split..], 3).unwrap());
+
+        let path = std::env::temp_dir().join(format!("ff-zstd-{}.txt.zst", std::process::id()));
+        fs::write(&path, compressed).unwrap();
+        let sentences = TextCorpus::new(&path)
+            .sentences()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(sentences.unwrap(), expected);
+    }
+
+    #[test]
+    fn missing_corpus_is_io_error() {
+        let source = Repeat {
+            sentence: "the cat sat on the mat",
+            n: 10,
+        };
+        let vocab: SimpleVocab<String> = build_vocab(&source, TEST_VOCAB_CONFIG).unwrap();
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 2,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+        let mut sgd = SGD::new(trainer.into());
+
+        let err = sgd
+            .train_source(&TextCorpus::new("testdata/nonexistent.txt"))
+            .unwrap_err();
+        match &err {
+            Error::Io { path, line, .. } => {
+                assert_eq!(path, Path::new("testdata/nonexistent.txt"));
+                assert_eq!(*line, None);
+            }
+            _ => panic!("Expected an I/O error, got: {}", err),
+        }
+        assert!(err.to_string().contains("testdata/nonexistent.txt"));
+        assert_eq!(sgd.n_tokens_processed(), 0);
+    }
+
+    #[test]
+    fn custom_tokenizer_is_used_for_vocab_and_training() {
+        let path = std::env::temp_dir().join(format!("ff-tokenizer-{}.txt", std::process::id()));
+        std::fs::write(&path, "the|cat|sat\nthe|mat\n").unwrap();
+        let corpus = TextCorpus::new(&path).tokenizer(Arc::new(DelimiterTokenizer('|')));
+
+        let sentences = corpus
+            .sentences()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            sentences,
+            vec![vec!["the", "cat", "sat"], vec!["the", "mat"]]
+        );
+
+        let vocab: SimpleVocab<String> = build_vocab(&corpus, TEST_VOCAB_CONFIG).unwrap();
+        assert!(vocab.idx("the").is_some());
+        assert!(vocab.idx("mat").is_some());
+        assert!(vocab.idx("the|mat").is_none());
+
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 2,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+        let mut sgd = SGD::new(trainer.into());
+        sgd.enable_epoch_stats();
+        let result = sgd.train_source(&corpus);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        // With whitespace tokenization, every line would be a single
+        // unknown token.
+        for stats in sgd.epoch_stats().unwrap() {
+            assert_eq!(stats.n_oov, 0);
+            assert!(stats.n_tokens >= 5);
+        }
+    }
+}
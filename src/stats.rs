@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::source::SentenceSource;
+use crate::util::EOS;
+use crate::Error;
+
+/// Statistics of a corpus.
+///
+/// The statistics are collected in a single pass over the sentences of
+/// a corpus. As during training, every sentence ends with an
+/// end-of-sentence marker. The markers are counted separately, they do
+/// not count as tokens or types and do not contribute to sentence
+/// lengths.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CorpusStats {
+    n_eos: usize,
+    n_sentences: usize,
+    n_tokens: usize,
+    lengths: BTreeMap<usize, usize>,
+    types: HashSet<String>,
+}
+
+impl CorpusStats {
+    /// Construct empty statistics.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect the statistics of all sentences of a source.
+    pub fn from_source<S>(source: &S) -> Result<Self, Error>
+    where
+        S: SentenceSource + ?Sized,
+    {
+        let mut stats = CorpusStats::new();
+        for sentence in source.sentences()? {
+            stats.count_sentence(&sentence?);
+        }
+
+        Ok(stats)
+    }
+
+    /// Count a sentence without an end-of-sentence marker.
+    ///
+    /// End-of-sentence markers in the sentence are counted as markers,
+    /// not as tokens.
+    pub fn count_sentence<S>(&mut self, sentence: &[S])
+    where
+        S: AsRef<str>,
+    {
+        let mut len = 0;
+        for token in sentence {
+            let token = token.as_ref();
+            if token == EOS {
+                self.n_eos += 1;
+                continue;
+            }
+
+            len += 1;
+            if !self.types.contains(token) {
+                self.types.insert(token.to_owned());
+            }
+        }
+
+        self.n_eos += 1;
+        self.n_sentences += 1;
+        self.n_tokens += len;
+        *self.lengths.entry(len).or_insert(0) += 1;
+    }
+
+    /// Get the mean sentence length in tokens.
+    ///
+    /// Returns *0* for an empty corpus.
+    pub fn mean_sentence_len(&self) -> f64 {
+        if self.n_sentences == 0 {
+            0.
+        } else {
+            self.n_tokens as f64 / self.n_sentences as f64
+        }
+    }
+
+    /// Get the median sentence length in tokens.
+    ///
+    /// For an even number of sentences, this is the mean of the two
+    /// middle lengths. Returns *0* for an empty corpus.
+    pub fn median_sentence_len(&self) -> f64 {
+        if self.n_sentences == 0 {
+            return 0.;
+        }
+
+        let lower = self.nth_sentence_len((self.n_sentences - 1) / 2);
+        let upper = self.nth_sentence_len(self.n_sentences / 2);
+        (lower + upper) as f64 / 2.
+    }
+
+    /// Get the number of end-of-sentence markers.
+    pub fn n_eos(&self) -> usize {
+        self.n_eos
+    }
+
+    /// Get the number of sentences.
+    pub fn n_sentences(&self) -> usize {
+        self.n_sentences
+    }
+
+    /// Get the number of tokens.
+    pub fn n_tokens(&self) -> usize {
+        self.n_tokens
+    }
+
+    /// Get the number of distinct token types.
+    pub fn n_types(&self) -> usize {
+        self.types.len()
+    }
+
+    /// Get the length of the `n`-th shortest sentence.
+    fn nth_sentence_len(&self, n: usize) -> usize {
+        let mut n_seen = 0;
+        for (&len, &count) in &self.lengths {
+            n_seen += count;
+            if n_seen > n {
+                return len;
+            }
+        }
+
+        unreachable!("Sentence index out of bounds")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CorpusStats;
+    use crate::util::EOS;
+    use crate::TextCorpus;
+
+    fn sentence(tokens: &[&str]) -> Vec<String> {
+        tokens.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn stats_match_hand_counts() {
+        let sentences = vec![
+            sentence(&["a", "b", "c"]),
+            sentence(&["a"]),
+            sentence(&["b", "d", "e", "a", "f"]),
+            sentence(&["c", EOS, "c"]),
+        ];
+        let stats = CorpusStats::from_source(&sentences).unwrap();
+
+        assert_eq!(stats.n_sentences(), 4);
+        assert_eq!(stats.n_tokens(), 11);
+        assert_eq!(stats.n_types(), 6);
+        // One marker per sentence and the marker in the last sentence.
+        assert_eq!(stats.n_eos(), 5);
+        assert_eq!(stats.mean_sentence_len(), 2.75);
+        // Lengths 1, 2, 3, 5.
+        assert_eq!(stats.median_sentence_len(), 2.5);
+
+        let mut odd = stats.clone();
+        odd.count_sentence(&sentence(&["g", "h", "i", "j"]));
+        assert_eq!(odd.median_sentence_len(), 3.);
+    }
+
+    #[test]
+    fn stats_of_text_corpus() {
+        let corpus = TextCorpus::new("testdata/chunking.txt");
+        let stats = CorpusStats::from_source(&corpus).unwrap();
+        assert_eq!(stats.n_sentences(), 9);
+        assert_eq!(stats.n_eos(), 9);
+        assert_eq!(stats.n_tokens(), 26);
+        assert_eq!(stats.n_types(), 26);
+        assert_eq!(stats.median_sentence_len(), 3.);
+
+        let empty = CorpusStats::new();
+        assert_eq!(empty.mean_sentence_len(), 0.);
+        assert_eq!(empty.median_sentence_len(), 0.);
+    }
+}
@@ -0,0 +1,151 @@
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::source::build_vocab;
+use finalfrontier::{
+    CoocWeighting, Cooccurrences, SimpleVocab, SubwordHash, SubwordVocab, TextCorpus, Vocab,
+};
+use finalfusion::compat::fasttext::FastTextIndexer;
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use stdinout::OrExit;
+
+use crate::subcommands::{finish_file, AtomicFile, FinalfrontierApp, VocabConfig};
+
+static CONTEXT: &str = "context";
+static WEIGHTING: &str = "weighting";
+
+/// Subcommand for counting word co-occurrences.
+pub struct CoocApp {
+    corpus: String,
+    output: String,
+    context_size: usize,
+    weighting: CoocWeighting,
+    vocab_config: VocabConfig,
+}
+
+impl CoocApp {
+    /// Get the corpus path.
+    pub fn corpus(&self) -> &str {
+        &self.corpus
+    }
+
+    /// Get the output path.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Get the context size.
+    pub fn context_size(&self) -> usize {
+        self.context_size
+    }
+
+    /// Get the weighting of co-occurrences by distance.
+    pub fn weighting(&self) -> CoocWeighting {
+        self.weighting
+    }
+
+    /// Get the vocab config.
+    pub fn vocab_config(&self) -> VocabConfig {
+        self.vocab_config
+    }
+}
+
+impl FinalfrontierApp for CoocApp {
+    fn app() -> App<'static, 'static> {
+        Self::vocab_opts("cooc")
+            .about("Count weighted word co-occurrences")
+            .arg(
+                Arg::with_name(CONTEXT)
+                    .long("context")
+                    .value_name("CONTEXT_SIZE")
+                    .help("Context size")
+                    .takes_value(true)
+                    .default_value("10"),
+            )
+            .arg(
+                Arg::with_name(WEIGHTING)
+                    .long("weighting")
+                    .value_name("WEIGHTING")
+                    .help("Weighting of co-occurrences by distance")
+                    .takes_value(true)
+                    .possible_values(&["harmonic", "uniform"])
+                    .default_value("harmonic"),
+            )
+            .arg(
+                Arg::with_name(Self::CORPUS)
+                    .help("Tokenized corpus")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name(Self::OUTPUT)
+                    .help("Co-occurrences output")
+                    .index(2)
+                    .required(true),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Self {
+        let context_size = matches
+            .value_of(CONTEXT)
+            .map(|v| v.parse().or_exit("Cannot parse context size", 1))
+            .unwrap();
+        let weighting = matches
+            .value_of(WEIGHTING)
+            .map(|v| CoocWeighting::try_from_str(v).or_exit("Cannot parse weighting", 1))
+            .unwrap();
+
+        CoocApp {
+            corpus: matches.value_of(Self::CORPUS).unwrap().into(),
+            output: matches.value_of(Self::OUTPUT).unwrap().into(),
+            context_size,
+            weighting,
+            vocab_config: Self::parse_vocab_config(matches),
+        }
+    }
+
+    fn run(&self) {
+        let corpus = TextCorpus::new(self.corpus());
+
+        match self.vocab_config() {
+            VocabConfig::SubwordVocab(config) => match config.indexer.hash {
+                SubwordHash::FinalFusion => {
+                    let vocab: SubwordVocab<_, FinalfusionHashIndexer> =
+                        build_vocab(&corpus, config).or_exit("Cannot build vocabulary", 1);
+                    count_cooccurrences(&vocab, &corpus, self);
+                }
+                SubwordHash::FastText => {
+                    let vocab: SubwordVocab<_, FastTextIndexer> =
+                        build_vocab(&corpus, config).or_exit("Cannot build vocabulary", 1);
+                    count_cooccurrences(&vocab, &corpus, self);
+                }
+            },
+            VocabConfig::NGramVocab(config) => {
+                let vocab: SubwordVocab<_, ExplicitIndexer> =
+                    build_vocab(&corpus, config).or_exit("Cannot build vocabulary", 1);
+                count_cooccurrences(&vocab, &corpus, self);
+            }
+            VocabConfig::SimpleVocab(config) => {
+                let vocab: SimpleVocab<String> =
+                    build_vocab(&corpus, config).or_exit("Cannot build vocabulary", 1);
+                count_cooccurrences(&vocab, &corpus, self);
+            }
+        }
+    }
+}
+
+/// Count the co-occurrences of the words in `vocab` and write them.
+fn count_cooccurrences<V>(vocab: &V, corpus: &TextCorpus, app: &CoocApp)
+where
+    V: Vocab<VocabType = String>,
+{
+    let mut cooc = Cooccurrences::new(app.context_size(), app.weighting());
+    cooc.count_source(vocab, corpus)
+        .or_exit("Cannot count co-occurrences", 1);
+
+    let mut writer =
+        AtomicFile::create(app.output()).or_exit("Cannot open output file for writing", 1);
+    cooc.write_triples(&mut writer, vocab)
+        .or_exit("Cannot write co-occurrences", 1);
+    finish_file(writer, false);
+
+    eprintln!("Co-occurring word pairs: {}", cooc.len());
+}
@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::source::build_vocab;
+use finalfrontier::{Coverage, SimpleVocab, SubwordHash, SubwordVocab, TextCorpus};
+use finalfusion::compat::fasttext::FastTextIndexer;
+use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer};
+use stdinout::OrExit;
+
+use crate::subcommands::{FinalfrontierApp, VocabConfig};
+
+static WORDS: &str = "WORDS";
+
+/// Subcommand for computing the vocabulary coverage of a word list.
+pub struct CoverageApp {
+    corpus: String,
+    words: String,
+    vocab_config: VocabConfig,
+}
+
+impl CoverageApp {
+    /// Get the corpus path.
+    pub fn corpus(&self) -> &str {
+        &self.corpus
+    }
+
+    /// Get the path of the reference word list.
+    pub fn words(&self) -> &str {
+        &self.words
+    }
+
+    /// Get the vocab config.
+    pub fn vocab_config(&self) -> VocabConfig {
+        self.vocab_config
+    }
+
+    fn read_words(&self) -> Vec<String> {
+        let f = File::open(self.words()).or_exit("Cannot open word list for reading", 1);
+        let mut words = Vec::new();
+        for line in BufReader::new(f).lines() {
+            let line = line.or_exit("Cannot read word list", 1);
+            let word = line.trim();
+            if !word.is_empty() {
+                words.push(word.to_owned());
+            }
+        }
+
+        words
+    }
+}
+
+impl FinalfrontierApp for CoverageApp {
+    fn app() -> App<'static, 'static> {
+        Self::vocab_opts("coverage")
+            .about("Compute the vocabulary coverage of a word list")
+            .arg(
+                Arg::with_name(Self::CORPUS)
+                    .help("Tokenized corpus")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name(WORDS)
+                    .help("Reference word list (one word per line)")
+                    .index(2)
+                    .required(true),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Self {
+        CoverageApp {
+            corpus: matches.value_of(Self::CORPUS).unwrap().into(),
+            words: matches.value_of(WORDS).unwrap().into(),
+            vocab_config: Self::parse_vocab_config(matches),
+        }
+    }
+
+    fn run(&self) {
+        let corpus = TextCorpus::new(self.corpus());
+        let words = self.read_words();
+
+        let coverage = match self.vocab_config() {
+            VocabConfig::SubwordVocab(config) => match config.indexer.hash {
+                SubwordHash::FinalFusion => {
+                    let vocab: SubwordVocab<_, FinalfusionHashIndexer> =
+                        build_vocab(&corpus, config).or_exit("Cannot build vocabulary", 1);
+                    Coverage::subwords(&vocab, &words)
+                }
+                SubwordHash::FastText => {
+                    let vocab: SubwordVocab<_, FastTextIndexer> =
+                        build_vocab(&corpus, config).or_exit("Cannot build vocabulary", 1);
+                    Coverage::subwords(&vocab, &words)
+                }
+            },
+            VocabConfig::NGramVocab(config) => {
+                let vocab: SubwordVocab<_, ExplicitIndexer> =
+                    build_vocab(&corpus, config).or_exit("Cannot build vocabulary", 1);
+                Coverage::subwords(&vocab, &words)
+            }
+            VocabConfig::SimpleVocab(config) => {
+                let vocab: SimpleVocab<String> =
+                    build_vocab(&corpus, config).or_exit("Cannot build vocabulary", 1);
+                Coverage::new(&vocab, &words)
+            }
+        };
+
+        println!("Reference words: {}", coverage.n_words);
+        println!(
+            "In vocabulary: {} ({:.2}%)",
+            coverage.n_in_vocab,
+            coverage.in_vocab_fraction() * 100.
+        );
+        if let (Some(n_oov_with_subwords), Some(oov_subword_fraction)) = (
+            coverage.n_oov_with_subwords,
+            coverage.oov_subword_fraction(),
+        ) {
+            println!(
+                "Out of vocabulary with subwords: {} ({:.2}% of {} OOV words)",
+                n_oov_with_subwords,
+                oov_subword_fraction * 100.,
+                coverage.n_oov()
+            );
+        }
+    }
+}
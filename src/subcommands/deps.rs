@@ -1,6 +1,6 @@
 use std::cmp;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter};
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::Duration;
@@ -9,18 +9,29 @@ use clap::{App, Arg, ArgMatches};
 use conllx::graph::{Node, Sentence};
 use conllx::io::{ReadSentence, Reader};
 use conllx::proj::{HeadProjectivizer, Projectivize};
-use finalfrontier::io::{thread_data_conllx, FileProgress, TrainInfo};
+use finalfrontier::io::{
+    normalize_digits, read_frozen_tokens, read_token_weights, thread_data_conllx, FileProgress,
+    TrainInfo,
+};
 use finalfrontier::{
-    CommonConfig, DepembedsConfig, DepembedsTrainer, Dependency, DependencyIterator, SimpleVocab,
-    SimpleVocabConfig, SubwordVocab, Vocab, VocabBuilder, WriteModelBinary, SGD,
+    CommonConfig, DepembedsConfig, DepembedsTrainer, Dependency, DependencyIterator, LrSchedule,
+    SimpleVocab, SimpleVocabConfig, SubwordComposition, SubwordHash, SubwordVocab, TrainModel,
+    Trainer, Vocab, VocabBuilder, SGD,
 };
+use finalfusion::compat::fasttext::FastTextIndexer;
 use finalfusion::prelude::VocabWrap;
+use finalfusion::subword::FinalfusionHashIndexer;
 use rand::{FromEntropy, Rng};
 use rand_xorshift::XorShiftRng;
 use serde::Serialize;
 use stdinout::OrExit;
 
-use crate::subcommands::{show_progress, FinalfrontierApp, VocabConfig};
+use crate::subcommands::{
+    guard_finite, join_training_threads, print_displacement_report, print_epoch_stats,
+    print_gradient_histogram, show_progress, write_model, write_norms_file, write_vocab_json_file,
+    AtomicFile, FinalfrontierApp, LearningCurve, NanGuard, OutputFormat, ReportInterval,
+    ThreadReport, VocabConfig,
+};
 
 static CONTEXT_MINCOUNT: &str = "context_mincount";
 static CONTEXT_DISCARD: &str = "context_discard";
@@ -35,6 +46,22 @@ const PROGRESS_UPDATE_INTERVAL: u64 = 200;
 /// Dependency embeddings subcommand.
 pub struct DepsApp {
     train_info: TrainInfo,
+    output_format: OutputFormat,
+    token_weights: Option<String>,
+    freeze_tokens: Option<String>,
+    export_vocab_json: Option<String>,
+    nan_guard: Option<NanGuard>,
+    grad_hist: bool,
+    dim_stats: bool,
+    epoch_stats: bool,
+    displacement_report: Option<usize>,
+    curve_csv: Option<String>,
+    write_norms: Option<String>,
+    fsync: bool,
+    report_interval: ReportInterval,
+    sparse_output: bool,
+    subword_composition: SubwordComposition,
+    micro_batch: usize,
     common_config: CommonConfig,
     depembeds_config: DepembedsConfig,
     input_vocab_config: VocabConfig,
@@ -99,6 +126,86 @@ impl DepsApp {
     pub fn train_info(&self) -> &TrainInfo {
         &self.train_info
     }
+
+    /// Get the output format.
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    /// Get the path of the token weights file.
+    pub fn token_weights(&self) -> Option<&str> {
+        self.token_weights.as_deref()
+    }
+
+    /// Get the path of the file with tokens that are frozen.
+    pub fn freeze_tokens(&self) -> Option<&str> {
+        self.freeze_tokens.as_deref()
+    }
+
+    /// Get the path to export the vocabulary to as JSON.
+    pub fn export_vocab_json(&self) -> Option<&str> {
+        self.export_vocab_json.as_deref()
+    }
+
+    /// Get the action for non-finite embeddings.
+    pub fn nan_guard(&self) -> Option<NanGuard> {
+        self.nan_guard
+    }
+
+    /// Get whether a histogram of gradient norms is collected.
+    pub fn grad_hist(&self) -> bool {
+        self.grad_hist
+    }
+
+    /// Get whether per-dimension statistics of the input matrix are printed.
+    pub fn dim_stats(&self) -> bool {
+        self.dim_stats
+    }
+
+    /// Get whether training statistics of every epoch are printed.
+    pub fn epoch_stats(&self) -> bool {
+        self.epoch_stats
+    }
+
+    /// Get the number of tokens to print in the displacement report.
+    pub fn displacement_report(&self) -> Option<usize> {
+        self.displacement_report
+    }
+
+    /// Get the path to write the learning curve to.
+    pub fn curve_csv(&self) -> Option<&str> {
+        self.curve_csv.as_deref()
+    }
+
+    /// Get the path to write the norms of the word embeddings to.
+    pub fn write_norms(&self) -> Option<&str> {
+        self.write_norms.as_deref()
+    }
+
+    /// Returns `true` if output files should be synchronized to disk.
+    pub fn fsync(&self) -> bool {
+        self.fsync
+    }
+
+    /// Get the interval between progress reports.
+    pub fn report_interval(&self) -> ReportInterval {
+        self.report_interval
+    }
+
+    /// Returns `true` if untrained input embeddings are written as zeros.
+    pub fn sparse_output(&self) -> bool {
+        self.sparse_output
+    }
+
+    /// Get the composition of token embeddings from subwords.
+    pub fn subword_composition(&self) -> SubwordComposition {
+        self.subword_composition
+    }
+
+    /// Get the number of examples per input update of a focus token.
+    pub fn micro_batch(&self) -> usize {
+        self.micro_batch
+    }
 }
 
 impl FinalfrontierApp for DepsApp {
@@ -152,12 +259,23 @@ impl FinalfrontierApp for DepsApp {
     }
 
     fn parse(matches: &ArgMatches) -> Self {
-        let corpus = matches.value_of(Self::CORPUS).unwrap().into();
+        let corpus: String = matches.value_of(Self::CORPUS).unwrap().into();
+        if corpus.ends_with(".zst") {
+            eprintln!(
+                "Cannot train on compressed corpus {}, the corpus is memory-mapped",
+                corpus
+            );
+            std::process::exit(1);
+        }
         let output = matches.value_of(Self::OUTPUT).unwrap().into();
         let n_threads = matches
             .value_of(Self::THREADS)
             .map(|v| v.parse().or_exit("Cannot parse number of threads", 1))
             .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
+        let token_weights = matches.value_of(Self::TOKEN_WEIGHTS).map(ToOwned::to_owned);
+        let export_vocab_json = matches
+            .value_of(Self::EXPORT_VOCAB_JSON)
+            .map(ToOwned::to_owned);
 
         let discard_threshold = matches
             .value_of(CONTEXT_DISCARD)
@@ -172,10 +290,29 @@ impl FinalfrontierApp for DepsApp {
             min_count,
             discard_threshold,
         };
-        let train_info = TrainInfo::new(corpus, output, n_threads);
+        let mut train_info = TrainInfo::new(corpus, output, n_threads);
+        train_info
+            .set_metadata(Self::parse_metadata(matches))
+            .or_exit("Invalid metadata", 1);
 
         DepsApp {
             train_info,
+            output_format: Self::parse_output_format(matches),
+            token_weights,
+            freeze_tokens: matches.value_of(Self::FREEZE_TOKENS).map(ToOwned::to_owned),
+            export_vocab_json,
+            nan_guard: Self::parse_nan_guard(matches),
+            grad_hist: Self::parse_grad_hist(matches),
+            dim_stats: Self::parse_dim_stats(matches),
+            epoch_stats: Self::parse_epoch_stats(matches),
+            displacement_report: Self::parse_displacement_report(matches),
+            curve_csv: Self::parse_curve_csv(matches),
+            write_norms: Self::parse_write_norms(matches),
+            fsync: Self::parse_fsync(matches),
+            report_interval: Self::parse_report_interval(matches),
+            sparse_output: Self::parse_sparse_output(matches),
+            subword_composition: Self::parse_subword_composition(matches),
+            micro_batch: Self::parse_micro_batch(matches),
             common_config: Self::parse_common_config(&matches),
             depembeds_config: Self::depembeds_config_from_matches(&matches),
             input_vocab_config: Self::parse_vocab_config(&matches),
@@ -191,24 +328,41 @@ impl FinalfrontierApp for DepsApp {
                     self.output_vocab_config(),
                     self.depembeds_config(),
                     self.corpus(),
+                    self.common_config(),
                 );
                 train(input_vocab, output_vocab, self);
             }
-            VocabConfig::SubwordVocab(config) => {
-                let (input_vocab, output_vocab) = build_vocab::<_, SubwordVocab<_, _>, _>(
-                    config,
-                    self.output_vocab_config(),
-                    self.depembeds_config(),
-                    self.corpus(),
-                );
-                train(input_vocab, output_vocab, self);
-            }
+            VocabConfig::SubwordVocab(config) => match config.indexer.hash {
+                SubwordHash::FinalFusion => {
+                    let (input_vocab, output_vocab) =
+                        build_vocab::<_, SubwordVocab<_, FinalfusionHashIndexer>, _>(
+                            config,
+                            self.output_vocab_config(),
+                            self.depembeds_config(),
+                            self.corpus(),
+                            self.common_config(),
+                        );
+                    train(input_vocab, output_vocab, self);
+                }
+                SubwordHash::FastText => {
+                    let (input_vocab, output_vocab) =
+                        build_vocab::<_, SubwordVocab<_, FastTextIndexer>, _>(
+                            config,
+                            self.output_vocab_config(),
+                            self.depembeds_config(),
+                            self.corpus(),
+                            self.common_config(),
+                        );
+                    train(input_vocab, output_vocab, self);
+                }
+            },
             VocabConfig::NGramVocab(config) => {
                 let (input_vocab, output_vocab) = build_vocab::<_, SubwordVocab<_, _>, _>(
                     config,
                     self.output_vocab_config(),
                     self.depembeds_config(),
                     self.corpus(),
+                    self.common_config(),
                 );
                 train(input_vocab, output_vocab, self);
             }
@@ -226,9 +380,8 @@ where
     let common_config = app.common_config();
     let n_threads = app.n_threads();
 
-    let mut output_writer = BufWriter::new(
-        File::create(app.output()).or_exit("Cannot open output file for writing.", 1),
-    );
+    let output_writer =
+        AtomicFile::create(app.output()).or_exit("Cannot open output file for writing.", 1);
     let trainer = DepembedsTrainer::new(
         input_vocab,
         output_vocab,
@@ -236,7 +389,47 @@ where
         app.depembeds_config(),
         XorShiftRng::from_entropy(),
     );
-    let sgd = SGD::new(trainer.into());
+    if let Some(path) = app.export_vocab_json() {
+        write_vocab_json_file(trainer.input_vocab(), path, app.fsync());
+    }
+    let n_words = trainer.input_vocab().len();
+    let estimated_bytes = common_config.estimated_bytes(
+        n_words,
+        trainer.n_input_types() - n_words,
+        trainer.n_output_types(),
+    );
+    eprintln!(
+        "Estimated model size: {:.1} MiB",
+        estimated_bytes as f64 / (1024 * 1024) as f64
+    );
+    let mut model: TrainModel<_> = trainer.into();
+    model.set_subword_composition(app.subword_composition());
+    let mut sgd = SGD::new(model);
+    if let Some(token_weights) = app.token_weights() {
+        let f = File::open(token_weights).or_exit("Cannot open token weights file", 1);
+        let weights = read_token_weights(BufReader::new(f), sgd.model().input_vocab())
+            .or_exit("Cannot read token weights", 1);
+        sgd.set_token_weights(weights);
+    }
+    if let Some(freeze_tokens) = app.freeze_tokens() {
+        let f = File::open(freeze_tokens).or_exit("Cannot open frozen tokens file", 1);
+        let frozen = read_frozen_tokens(BufReader::new(f), sgd.model().input_vocab())
+            .or_exit("Cannot read frozen tokens", 1);
+        sgd.freeze_inputs(frozen);
+    }
+    if app.epoch_stats() {
+        sgd.enable_epoch_stats();
+    }
+    if app.grad_hist() {
+        sgd.enable_gradient_histogram();
+    }
+    if app.sparse_output() {
+        sgd.track_input_updates();
+    }
+    sgd.set_micro_batch(app.micro_batch());
+    let init_embeds = app
+        .displacement_report()
+        .map(|_| sgd.model().input_word_embeddings());
 
     let projectivize = app.depembeds_config().projectivize;
     let mut children = Vec::with_capacity(n_threads);
@@ -251,26 +444,81 @@ where
                 thread,
                 n_threads,
                 common_config.epochs,
-                common_config.lr,
                 projectivize,
-            );
+            )
         }));
     }
 
+    let n_tokens = sgd.model().input_vocab().n_types();
+    let guard = app.nan_guard().map(|nan_guard| {
+        let sgd = sgd.clone();
+        let n_tokens_total = n_tokens * common_config.epochs as usize;
+        thread::spawn(move || {
+            guard_finite(
+                sgd,
+                nan_guard,
+                n_tokens_total,
+                Duration::from_millis(PROGRESS_UPDATE_INTERVAL),
+            )
+        })
+    });
+
+    let mut curve = app.curve_csv().map(|path| {
+        LearningCurve::create(path, &app.common_config(), n_tokens)
+            .or_exit("Cannot open learning curve file for writing", 1)
+    });
     show_progress(
         &app.common_config(),
         &sgd,
-        Duration::from_millis(PROGRESS_UPDATE_INTERVAL),
+        n_tokens,
+        app.report_interval(),
+        curve.as_mut(),
+        None,
     );
 
+    if let Some(curve) = curve {
+        curve.finish(app.fsync());
+    }
+
     // Wait until all threads have finished.
-    for child in children {
-        let _ = child.join();
+    let report = join_training_threads(children);
+    if let Some(guard) = guard {
+        let _ = guard.join();
+    }
+    if let Some(epoch_stats) = report.epoch_stats {
+        print_epoch_stats(&epoch_stats);
+    }
+    if let Some(grad_hist) = report.grad_hist {
+        print_gradient_histogram(&grad_hist);
+    }
+
+    let update_counts = sgd.input_update_counts();
+    let mut model = sgd.into_model();
+    if let (Some(k), Some(init_embeds)) = (app.displacement_report(), init_embeds) {
+        // Report before zeroing untrained inputs, which would move them.
+        print_displacement_report(&model.displacements(init_embeds.view()), k);
+    }
+    if let Some(update_counts) = update_counts {
+        let n_zeroed = model.zero_untrained_inputs(&update_counts);
+        eprintln!("Untrained embeddings written as zeros: {}", n_zeroed);
+    }
+    if app.dim_stats() {
+        eprintln!("Input matrix dimensions (dimension, mean, variance):");
+        eprint!("{}", model.input_dimension_stats());
     }
 
-    sgd.into_model()
-        .write_model_binary(&mut output_writer, app.train_info().clone())
-        .or_exit("Cannot write model", 1);
+    if let Some(path) = app.write_norms() {
+        write_norms_file(&model, path, app.fsync());
+    }
+
+    write_model(
+        model,
+        app.output_format(),
+        output_writer,
+        app.output(),
+        app.fsync(),
+        app.train_info().clone(),
+    );
 }
 
 fn do_work<P, R, V>(
@@ -279,9 +527,9 @@ fn do_work<P, R, V>(
     thread: usize,
     n_threads: usize,
     epochs: u32,
-    start_lr: f32,
     projectivize: bool,
-) where
+) -> ThreadReport
+where
     P: Into<PathBuf>,
     R: Clone + Rng,
     V: Vocab<VocabType = String>,
@@ -289,6 +537,7 @@ fn do_work<P, R, V>(
     for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
 {
     let n_tokens = sgd.model().input_vocab().n_types();
+    let lr_schedule = LrSchedule::new(sgd.model().config(), n_tokens);
 
     let f = File::open(corpus_path.into()).or_exit("Cannot open corpus for reading", 1);
     let (data, start) =
@@ -299,20 +548,34 @@ fn do_work<P, R, V>(
         None
     };
 
-    let mut sentences = SentenceIter::new(BufReader::new(&data[start..]), projectivizer);
+    let normalize_digits = sgd.model().config().normalize_digits;
+    let mut sentences = SentenceIter::new(BufReader::new(&data[start..]), projectivizer)
+        .normalize_digits(normalize_digits);
+    // Threads wrap around the data independently, an epoch ends when the
+    // threads processed the number of tokens of the corpus.
+    let mut epoch = 0;
+    sgd.start_epoch();
     while sgd.n_tokens_processed() < epochs as usize * n_tokens {
         let sentence = sentences
             .next()
             .or_else(|| {
-                sentences = SentenceIter::new(BufReader::new(&*data), projectivizer);
+                sentences = SentenceIter::new(BufReader::new(&*data), projectivizer)
+                    .normalize_digits(normalize_digits);
                 sentences.next()
             })
             .or_exit("Cannot read sentence.", 1);
 
-        let lr = (1.0 - (sgd.n_tokens_processed() as f32 / (epochs as usize * n_tokens) as f32))
-            * start_lr;
+        let lr = lr_schedule.lr_in_epoch(epoch, sgd.n_tokens_processed());
         sgd.update_sentence(&sentence, lr);
+
+        // Example caps are per epoch.
+        while sgd.n_tokens_processed() / n_tokens > epoch {
+            epoch += 1;
+            sgd.start_epoch();
+        }
     }
+
+    ThreadReport::new(&sgd)
 }
 
 fn build_vocab<P, V, C>(
@@ -320,6 +583,7 @@ fn build_vocab<P, V, C>(
     output_config: SimpleVocabConfig,
     dep_config: DepembedsConfig,
     corpus_path: P,
+    common_config: CommonConfig,
 ) -> (V, SimpleVocab<Dependency>)
 where
     P: AsRef<Path>,
@@ -337,8 +601,16 @@ where
         None
     };
 
-    for sentence in SentenceIter::new(BufReader::new(file_progress), projectivizer) {
+    let max_token_len = common_config.max_token_len as usize;
+    let mut n_skipped = 0;
+    for sentence in SentenceIter::new(BufReader::new(file_progress), projectivizer)
+        .normalize_digits(common_config.normalize_digits)
+    {
         for token in sentence.iter().filter_map(Node::token) {
+            if token.form().len() > max_token_len {
+                n_skipped += 1;
+                continue;
+            }
             input_builder.count(token.form());
         }
 
@@ -347,12 +619,20 @@ where
         }
     }
 
+    if n_skipped > 0 {
+        eprintln!(
+            "Skipped {} tokens longer than {} bytes",
+            n_skipped, max_token_len
+        );
+    }
+
     (input_builder.into(), output_builder.into())
 }
 
 struct SentenceIter<P, R> {
     inner: Reader<R>,
     projectivizer: Option<P>,
+    normalize_digits: bool,
 }
 
 impl<P, R> SentenceIter<P, R> {
@@ -363,8 +643,15 @@ impl<P, R> SentenceIter<P, R> {
         SentenceIter {
             inner: Reader::new(read),
             projectivizer,
+            normalize_digits: false,
         }
     }
+
+    /// Replace runs of digits in word forms by a single `0`.
+    fn normalize_digits(mut self, normalize_digits: bool) -> Self {
+        self.normalize_digits = normalize_digits;
+        self
+    }
 }
 
 impl<P, R> Iterator for SentenceIter<P, R>
@@ -383,6 +670,12 @@ where
             proj.projectivize(&mut sentence)
                 .or_exit("Cannot projectivize sentence.", 1);
         }
+        if self.normalize_digits {
+            for token in sentence.iter_mut().filter_map(Node::token_mut) {
+                let form = normalize_digits(token.form());
+                token.set_form(form);
+            }
+        }
         Some(sentence)
     }
 }
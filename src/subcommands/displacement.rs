@@ -0,0 +1,28 @@
+use finalfrontier::Displacements;
+
+/// Print the tokens that moved the most and the least to stderr.
+pub fn print_displacement_report(displacements: &Displacements, k: usize) {
+    eprintln!("Most moved tokens (token, initial norm, norm, displacement):");
+    for displacement in displacements.most_moved(k) {
+        print_displacement(
+            displacement.token(),
+            displacement.init_norm(),
+            displacement.norm(),
+            displacement.displacement(),
+        );
+    }
+
+    eprintln!("Least moved tokens (token, initial norm, norm, displacement):");
+    for displacement in displacements.least_moved(k) {
+        print_displacement(
+            displacement.token(),
+            displacement.init_norm(),
+            displacement.norm(),
+            displacement.displacement(),
+        );
+    }
+}
+
+fn print_displacement(token: &str, init_norm: f32, norm: f32, displacement: f32) {
+    eprintln!("{}\t{:e}\t{:e}\t{:e}", token, init_norm, norm, displacement);
+}
@@ -0,0 +1,68 @@
+use std::thread;
+use std::time::Duration;
+
+use failure::{err_msg, Error};
+use finalfrontier::{Trainer, SGD};
+
+/// Number of tokens between two checks for non-finite embeddings.
+const GUARD_INTERVAL_TOKENS: usize = 1_000_000;
+
+/// Action to take when an embedding has non-finite values.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum NanGuard {
+    /// Reset the embedding to a random initialization.
+    Reset,
+
+    /// Only warn about the embedding.
+    Warn,
+}
+
+impl NanGuard {
+    pub fn try_from_str(guard: &str) -> Result<NanGuard, Error> {
+        match guard {
+            "reset" => Ok(NanGuard::Reset),
+            "warn" => Ok(NanGuard::Warn),
+            _ => Err(err_msg(format!("Unknown NaN guard: {}", guard))),
+        }
+    }
+}
+
+/// Check for non-finite input embeddings until training is finished.
+///
+/// The input embeddings are checked after every `GUARD_INTERVAL_TOKENS`
+/// processed tokens. `n_tokens_total` is the number of tokens that are
+/// trained on over all epochs.
+pub fn guard_finite<T>(
+    mut sgd: SGD<T>,
+    guard: NanGuard,
+    n_tokens_total: usize,
+    poll_interval: Duration,
+) where
+    T: Trainer,
+{
+    let mut next_check = GUARD_INTERVAL_TOKENS;
+    while sgd.n_tokens_processed() < n_tokens_total {
+        if sgd.n_tokens_processed() >= next_check {
+            check_finite(&mut sgd, guard);
+            next_check = sgd.n_tokens_processed() + GUARD_INTERVAL_TOKENS;
+        }
+
+        thread::sleep(poll_interval);
+    }
+
+    check_finite(&mut sgd, guard);
+}
+
+fn check_finite<T>(sgd: &mut SGD<T>, guard: NanGuard)
+where
+    T: Trainer,
+{
+    let reset = guard == NanGuard::Reset;
+    for idx in sgd.check_finite_inputs(reset) {
+        if reset {
+            eprintln!("Embedding {} has non-finite values, resetting", idx);
+        } else {
+            eprintln!("Embedding {} has non-finite values", idx);
+        }
+    }
+}
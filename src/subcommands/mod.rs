@@ -1,14 +1,39 @@
 mod config;
 pub use self::config::VocabConfig;
 
+mod cooc;
+pub use self::cooc::CoocApp;
+
+mod coverage;
+pub use self::coverage::CoverageApp;
+
 mod deps;
 pub use self::deps::DepsApp;
 
+mod displacement;
+pub use self::displacement::print_displacement_report;
+
+mod guard;
+pub use self::guard::{guard_finite, NanGuard};
+
+mod output;
+pub use self::output::{
+    finish_file, write_model, write_norms_file, write_vocab_json_file, AtomicFile, OutputFormat,
+};
+
 mod progress;
-pub use self::progress::show_progress;
+pub use self::progress::{show_progress, LearningCurve, ReportInterval};
+
+mod report;
+pub use self::report::{
+    join_training_threads, print_epoch_stats, print_gradient_histogram, ThreadReport,
+};
 
 mod skipgram;
 pub use self::skipgram::SkipgramApp;
 
+mod stats;
+pub use self::stats::StatsApp;
+
 mod traits;
 pub use self::traits::FinalfrontierApp;
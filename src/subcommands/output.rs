@@ -0,0 +1,280 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use failure::{err_msg, Error};
+use finalfrontier::io::{write_vocab_json, TrainInfo};
+use finalfrontier::{
+    Vocab, WriteModelBinary, WriteModelNorms, WriteModelNpz, WriteModelText, WriteModelWord2Vec,
+};
+use stdinout::OrExit;
+
+/// Embedding output formats.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// finalfusion format.
+    FinalFusion,
+
+    /// NumPy `.npz` archive.
+    ///
+    /// Only the embeddings of in-vocabulary words are stored. The words
+    /// are written to a separate vocabulary file, see `npz_vocab_path`.
+    Npz,
+
+    /// word2vec text format.
+    ///
+    /// Only the embeddings of in-vocabulary words are stored.
+    Text,
+
+    /// word2vec binary format.
+    ///
+    /// Only the embeddings of in-vocabulary words are stored.
+    Word2VecBinary,
+}
+
+impl OutputFormat {
+    pub fn try_from_str(format: &str) -> Result<OutputFormat, Error> {
+        match format {
+            "finalfusion" => Ok(OutputFormat::FinalFusion),
+            "npz" => Ok(OutputFormat::Npz),
+            "text" => Ok(OutputFormat::Text),
+            "word2vec-binary" => Ok(OutputFormat::Word2VecBinary),
+            _ => Err(err_msg(format!("Unknown output format: {}", format))),
+        }
+    }
+}
+
+/// Get the path of the vocabulary file of an `.npz` output.
+///
+/// The vocabulary file replaces the extension of the output, e.g. the
+/// vocabulary of `model.npz` is written to `model.vocab.txt`.
+pub fn npz_vocab_path(output: &str) -> PathBuf {
+    Path::new(output).with_extension("vocab.txt")
+}
+
+/// An output file that only appears at its path once it is complete.
+///
+/// The data is written to a temporary file in the directory of the
+/// output, which is renamed to the output path by `finish_file`. Since
+/// a rename within a file system is atomic, readers never see a
+/// partially written file. If writing fails or the program is killed,
+/// only the temporary file is left.
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl AtomicFile {
+    /// Create the temporary file of the output at `path`.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let tmp_path = tmp_path(&path);
+        let writer = BufWriter::new(File::create(&tmp_path)?);
+
+        Ok(AtomicFile {
+            path,
+            tmp_path,
+            writer,
+        })
+    }
+
+    /// Close the temporary file and rename it to the output path.
+    fn commit(self, fsync: bool) -> io::Result<()> {
+        let AtomicFile {
+            path,
+            tmp_path,
+            writer,
+        } = self;
+
+        close_file(writer, fsync)?;
+        fs::rename(&tmp_path, &path)?;
+
+        // Synchronize the directory, so that the rename is stored as well.
+        if fsync {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Seek for AtomicFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.writer.seek(pos)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Get the path of the temporary file of an output.
+///
+/// The temporary file is a hidden file in the directory of the output,
+/// so that it can be renamed to the output without copying. The process
+/// ID is part of the name, so that concurrent runs do not share a file.
+fn tmp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()))
+}
+
+/// Write a trained model in the given format and finish the output file.
+///
+/// `output` is the path of the output file, it is used to derive the
+/// path of the vocabulary file of the `npz` format. The vocabulary file
+/// is moved to its path after the output file, so that the vocabulary of
+/// an `npz` output only appears once the output is complete. See
+/// `finish_file` for `fsync`, which is applied to both files.
+pub fn write_model<M>(
+    model: M,
+    format: OutputFormat,
+    mut write: AtomicFile,
+    output: &str,
+    fsync: bool,
+    train_info: TrainInfo,
+) where
+    M: WriteModelBinary<AtomicFile>
+        + WriteModelNpz<AtomicFile>
+        + WriteModelText<AtomicFile>
+        + WriteModelWord2Vec<AtomicFile>,
+{
+    let mut vocab_writer = None;
+    match format {
+        OutputFormat::FinalFusion => model.write_model_binary(&mut write, train_info),
+        OutputFormat::Npz => {
+            let vocab_writer = vocab_writer.get_or_insert(
+                AtomicFile::create(npz_vocab_path(output))
+                    .or_exit("Cannot open vocabulary file for writing", 1),
+            );
+            model.write_model_npz(&mut write, vocab_writer)
+        }
+        OutputFormat::Text => model.write_model_text(&mut write, true),
+        OutputFormat::Word2VecBinary => model.write_model_word2vec(&mut write),
+    }
+    .or_exit("Cannot write model", 1);
+
+    finish_file(write, fsync);
+    if let Some(vocab_writer) = vocab_writer {
+        finish_file(vocab_writer, fsync);
+    }
+}
+
+/// Flush and close an output file and move it to its path.
+///
+/// If `fsync` is `true`, the file is also synchronized to disk, so that
+/// it is completely stored when the program exits.
+pub fn finish_file(file: AtomicFile, fsync: bool) {
+    file.commit(fsync).or_exit("Cannot write output file", 1);
+}
+
+fn close_file(writer: BufWriter<File>, fsync: bool) -> io::Result<()> {
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+    if fsync {
+        file.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Write the norms of the word embeddings to the file at `path`.
+///
+/// See `finish_file` for `fsync`.
+pub fn write_norms_file<M>(model: &M, path: &str, fsync: bool)
+where
+    M: WriteModelNorms<AtomicFile>,
+{
+    let mut writer = AtomicFile::create(path).or_exit("Cannot open norms file for writing", 1);
+    model
+        .write_model_norms(&mut writer)
+        .or_exit("Cannot write norms", 1);
+    finish_file(writer, fsync);
+}
+
+/// Write a vocabulary as JSON to the file at `path`.
+///
+/// See `finish_file` for `fsync`.
+pub fn write_vocab_json_file<V>(vocab: &V, path: &str, fsync: bool)
+where
+    V: Vocab,
+    V::VocabType: ToString,
+{
+    let mut writer = AtomicFile::create(path).or_exit("Cannot open vocabulary file for writing", 1);
+    write_vocab_json(&mut writer, vocab).or_exit("Cannot write vocabulary", 1);
+    finish_file(writer, fsync);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::io::{BufWriter, Write};
+
+    use super::{close_file, npz_vocab_path, tmp_path, AtomicFile};
+
+    #[test]
+    fn fsync_does_not_change_written_bytes() {
+        let data = (0..100_000u32).map(|v| v as u8).collect::<Vec<_>>();
+        let path = std::env::temp_dir().join(format!("ff-fsync-{}.bin", std::process::id()));
+
+        let mut written = Vec::new();
+        for &fsync in &[false, true] {
+            // The buffer is smaller than the data, so that it is flushed
+            // while writing and when closing.
+            let mut writer = BufWriter::with_capacity(4096, File::create(&path).unwrap());
+            writer.write_all(&data).unwrap();
+            close_file(writer, fsync).unwrap();
+            written.push(fs::read(&path).unwrap());
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(written[0], data);
+        assert_eq!(written[1], data);
+    }
+
+    #[test]
+    fn failed_write_leaves_no_output() {
+        let path = std::env::temp_dir().join(format!("ff-atomic-{}.bin", std::process::id()));
+        let tmp = tmp_path(&path);
+
+        // Writing fails before the file is finished.
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"partial model").unwrap();
+        file.flush().unwrap();
+        drop(file);
+        assert!(!path.exists());
+        assert!(tmp.exists());
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"complete model").unwrap();
+        file.commit(true).unwrap();
+        assert!(!tmp.exists());
+        assert_eq!(fs::read(&path).unwrap(), b"complete model");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn npz_vocab_path_replaces_extension() {
+        assert_eq!(
+            npz_vocab_path("model.npz").to_str(),
+            Some("model.vocab.txt")
+        );
+        assert_eq!(
+            npz_vocab_path("out/model").to_str(),
+            Some("out/model.vocab.txt")
+        );
+    }
+}
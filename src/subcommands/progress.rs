@@ -1,37 +1,426 @@
+use std::io::{self, Write};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use finalfrontier::{CommonConfig, Trainer, Vocab, SGD};
+use finalfrontier::{CommonConfig, LrSchedule, Trainer, SGD};
 use indicatif::{ProgressBar, ProgressStyle};
+use stdinout::OrExit;
 
-pub fn show_progress<T, V>(config: &CommonConfig, sgd: &SGD<T>, update_interval: Duration)
-where
-    T: Trainer<InputVocab = V>,
-    V: Vocab,
+use crate::subcommands::{finish_file, AtomicFile};
+
+/// Interval between two progress reports.
+///
+/// A report is made when either interval has passed since the last
+/// report, whichever comes first. An interval of zero disables that
+/// criterion.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReportInterval {
+    /// Number of processed tokens between two reports.
+    pub tokens: usize,
+
+    /// Time between two reports.
+    pub duration: Duration,
+}
+
+/// Time between two checks whether a report is due.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Function that computes the validation loss of a model.
+pub type ValidationLoss<'a, T> = &'a dyn Fn(&SGD<T>) -> f32;
+
+/// Show training progress until training is finished.
+///
+/// `n_tokens` is the number of tokens that are trained on per epoch. The
+/// progress bar is updated at every report. If a learning curve is
+/// given, a row is added to the curve on every report. `validation_loss`
+/// is added to the first row of every epoch after the first epoch and to
+/// the row of the final report, which is made when training is finished.
+pub fn show_progress<T, W>(
+    config: &CommonConfig,
+    sgd: &SGD<T>,
+    n_tokens: usize,
+    report_interval: ReportInterval,
+    mut curve: Option<&mut LearningCurve<W>>,
+    validation_loss: Option<ValidationLoss<T>>,
+) where
+    T: Trainer,
+    W: Write,
 {
-    let n_tokens = sgd.model().input_vocab().n_types();
+    let lr_schedule = LrSchedule::new(config, n_tokens);
+
+    let n_tokens_total = n_tokens * config.epochs as usize;
+    let progress = TrainProgress::new(n_tokens_total as u64);
+    let loss_monitor = sgd.loss_monitor();
+    let mut last_epoch = 0;
 
-    let pb = ProgressBar::new(u64::from(config.epochs) * n_tokens as u64);
-    pb.set_style(
-        ProgressStyle::default_bar().template("{bar:30} {percent}% {msg} ETA: {eta_precise}"),
+    report_until(
+        report_interval,
+        n_tokens_total,
+        || sgd.n_tokens_processed(),
+        |n_tokens_processed| {
+            let lr = lr_schedule.lr(n_tokens_processed);
+            progress.update(n_tokens_processed, sgd.train_loss(), lr);
+            if let Some(ref mut curve) = curve {
+                let epoch = n_tokens_processed / n_tokens.max(1);
+                let val_loss = validation_loss
+                    .filter(|_| epoch != last_epoch)
+                    .map(|loss| loss(sgd));
+                last_epoch = epoch;
+                curve
+                    .add(
+                        n_tokens_processed,
+                        lr,
+                        loss_monitor.current_loss(),
+                        val_loss,
+                    )
+                    .or_exit("Cannot write learning curve", 1);
+            }
+        },
+        POLL_INTERVAL,
     );
 
-    while sgd.n_tokens_processed() < n_tokens * config.epochs as usize {
-        let lr = (1.0
-            - (sgd.n_tokens_processed() as f32 / (config.epochs as usize * n_tokens) as f32))
-            * config.lr;
-
-        pb.set_position(sgd.n_tokens_processed() as u64);
-        pb.set_message(&format!(
-            "loss: {:.*} lr: {:.*}",
-            5,
-            sgd.train_loss(),
-            5,
-            lr
+    progress.finish();
+}
+
+/// Make reports until `n_tokens_total` tokens are processed.
+///
+/// `report` is called with the number of processed tokens whenever a
+/// report is due and once more when all tokens are processed.
+fn report_until<F, R>(
+    report_interval: ReportInterval,
+    n_tokens_total: usize,
+    n_tokens_processed: F,
+    mut report: R,
+    poll_interval: Duration,
+) where
+    F: Fn() -> usize,
+    R: FnMut(usize),
+{
+    let mut last_tokens = 0;
+    let mut last_time = Instant::now();
+
+    loop {
+        let n_tokens = n_tokens_processed();
+        if n_tokens >= n_tokens_total {
+            report(n_tokens);
+            break;
+        }
+
+        let tokens_due =
+            report_interval.tokens != 0 && n_tokens - last_tokens >= report_interval.tokens;
+        let time_due = report_interval.duration != Duration::from_secs(0)
+            && last_time.elapsed() >= report_interval.duration;
+        if tokens_due || time_due {
+            report(n_tokens);
+            last_tokens = n_tokens;
+            last_time = Instant::now();
+        }
+
+        thread::sleep(poll_interval);
+    }
+}
+
+/// Learning curve in CSV format.
+///
+/// Every row contains the number of processed tokens, the (1-based)
+/// epoch, the learning rate, the smoothed training loss and optionally
+/// the validation loss. Rows are flushed when they are added, so that the curve can be followed while
+/// training. A curve file is written to a temporary file (see
+/// `AtomicFile`), which is moved to its path by `LearningCurve::finish`.
+pub struct LearningCurve<W> {
+    write: W,
+    n_tokens: usize,
+    epochs: u32,
+}
+
+impl LearningCurve<AtomicFile> {
+    /// Create a learning curve file.
+    pub fn create(path: &str, config: &CommonConfig, n_tokens: usize) -> io::Result<Self> {
+        LearningCurve::new(AtomicFile::create(path)?, config, n_tokens)
+    }
+
+    /// Move the learning curve file to its path.
+    ///
+    /// See `finish_file` for `fsync`.
+    pub fn finish(self, fsync: bool) {
+        finish_file(self.write, fsync);
+    }
+}
+
+impl<W> LearningCurve<W>
+where
+    W: Write,
+{
+    /// Construct a learning curve, this writes the CSV header.
+    ///
+    /// `n_tokens` is the number of tokens that are trained on per epoch.
+    pub fn new(mut write: W, config: &CommonConfig, n_tokens: usize) -> io::Result<Self> {
+        writeln!(write, "tokens,epoch,lr,train_loss,val_loss")?;
+        write.flush()?;
+
+        Ok(LearningCurve {
+            write,
+            n_tokens,
+            epochs: config.epochs,
+        })
+    }
+
+    /// Add a row to the learning curve.
+    ///
+    /// The validation loss is left empty when `val_loss` is `None`. No row
+    /// is added while the training loss is NaN, that is, before the first
+    /// sentence is trained on.
+    pub fn add(
+        &mut self,
+        n_tokens_processed: usize,
+        lr: f32,
+        loss: f32,
+        val_loss: Option<f32>,
+    ) -> io::Result<()> {
+        if loss.is_nan() {
+            return Ok(());
+        }
+
+        let epoch = (n_tokens_processed / self.n_tokens.max(1) + 1).min(self.epochs as usize);
+        let val_loss = val_loss.map(|loss| loss.to_string()).unwrap_or_default();
+        writeln!(
+            self.write,
+            "{},{},{},{},{}",
+            n_tokens_processed, epoch, lr, loss, val_loss
+        )?;
+        self.write.flush()
+    }
+
+    /// Get the underlying writer.
+    #[cfg(test)]
+    fn into_inner(self) -> W {
+        self.write
+    }
+}
+
+/// Training progress bar.
+///
+/// The bar shows the progress over the total number of training tokens,
+/// the loss, the learning rate, the training rate in tokens per second
+/// and an ETA. The bar is drawn to stderr and is hidden when stderr is
+/// not a terminal.
+struct TrainProgress {
+    pb: ProgressBar,
+    start: Instant,
+}
+
+impl TrainProgress {
+    fn new(n_tokens_total: u64) -> Self {
+        let pb = ProgressBar::new(n_tokens_total);
+        pb.set_style(
+            ProgressStyle::default_bar().template("{bar:30} {percent}% {msg} ETA: {eta_precise}"),
+        );
+
+        TrainProgress {
+            pb,
+            start: Instant::now(),
+        }
+    }
+
+    fn update(&self, n_tokens_processed: usize, loss: f32, lr: f32) {
+        let elapsed = self.start.elapsed();
+        let elapsed_secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) * 1e-9;
+        let tokens_per_sec = if elapsed_secs > 0. {
+            n_tokens_processed as f64 / elapsed_secs
+        } else {
+            0.
+        };
+
+        self.pb.set_position(n_tokens_processed as u64);
+        self.pb.set_message(&format!(
+            "loss: {:.*} lr: {:.*} tokens/s: {:.0}",
+            5, loss, 5, lr, tokens_per_sec
         ));
+    }
 
-        thread::sleep(update_interval);
+    fn finish(&self) {
+        self.pb.finish();
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::thread;
+    use std::time::Duration;
 
-    pb.finish();
+    use finalfrontier::{
+        CommonConfig, LossType, LrAnchor, ModelType, SamplerType, SimpleVocab, SimpleVocabConfig,
+        SkipGramConfig, SkipgramTrainer, Vocab, VocabBuilder, SGD,
+    };
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use super::{report_until, show_progress, LearningCurve, ReportInterval, TrainProgress};
+
+    // The test configuration of the library is not available in the
+    // binary's tests.
+    const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
+        dims: 5,
+        dropout: 0.,
+        epochs: 2,
+        freq_lr_scaling: false,
+        init_scale: None,
+        loss: LossType::LogisticNegativeSampling,
+        lr: 0.05,
+        lr_anchor: LrAnchor::Tokens,
+        max_examples_per_token: 0,
+        max_ngrams_per_token: 0,
+        max_token_len: 100,
+        min_lr: 0.,
+        negative_samples: 5,
+        normalize_digits: false,
+        output_dims: 5,
+        positive_weight: 1.,
+        sampler: SamplerType::Zipf,
+        senses: 1,
+        warmup_tokens: 0,
+        zipf_exponent: 0.5,
+    };
+
+    #[test]
+    fn train_progress_runs_to_completion() {
+        let progress = TrainProgress::new(1000);
+        for n_tokens_processed in (0..=1000).step_by(100) {
+            progress.update(n_tokens_processed, 0.5, 0.01);
+        }
+        progress.finish();
+    }
+
+    #[test]
+    fn learning_curve_has_header_and_rows() {
+        let mut curve = LearningCurve::new(Vec::new(), &TEST_COMMON_CONFIG, 100).unwrap();
+        curve.add(10, 0.05, f32::NAN, None).unwrap();
+        curve.add(50, 0.04, 0.75, None).unwrap();
+        curve.add(200, 0., 0.5, Some(0.625)).unwrap();
+
+        let csv = String::from_utf8(curve.into_inner()).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "tokens,epoch,lr,train_loss,val_loss",
+                "50,1,0.04,0.75,",
+                "200,2,0,0.5,0.625"
+            ]
+        );
+        for line in lines {
+            assert_eq!(line.split(',').count(), 5);
+        }
+    }
+
+    #[test]
+    fn learning_curve_is_written_during_training() {
+        let sentences = vec![vec!["a".to_string(), "b".to_string(), "c".to_string()]; 100];
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            discard_threshold: 1.,
+            min_count: 1,
+        });
+        for token in sentences.iter().flatten() {
+            builder.count(token.clone());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+        // The end-of-sentence marker is unknown, so every token of the
+        // vocabulary is processed once per epoch.
+        let n_tokens = vocab.n_types();
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            SkipGramConfig {
+                context_size: 1,
+                context_subsampling_threshold: 0.,
+                discard_boundary_tokens: false,
+                discard_oov_contexts: false,
+                exclude_focus_negatives: false,
+                min_context: 0,
+                model: ModelType::SkipGram,
+                tied_weights: false,
+            },
+        );
+        let sgd = SGD::new(trainer.into());
+
+        let mut curve = LearningCurve::new(Vec::new(), &TEST_COMMON_CONFIG, n_tokens).unwrap();
+        let validation = sentences[..10].to_vec();
+        let validation_loss = |sgd: &SGD<_>| sgd.validation_loss(&validation).unwrap();
+        let mut train_sgd = sgd.clone();
+        thread::scope(|scope| {
+            scope.spawn(|| train_sgd.train_source(&sentences).unwrap());
+            show_progress(
+                &TEST_COMMON_CONFIG,
+                &sgd,
+                n_tokens,
+                ReportInterval {
+                    tokens: 100,
+                    duration: Duration::from_secs(0),
+                },
+                Some(&mut curve),
+                Some(&validation_loss),
+            );
+        });
+
+        let csv = String::from_utf8(curve.into_inner()).unwrap();
+        let rows = csv
+            .lines()
+            .map(|line| line.split(',').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(rows[0], ["tokens", "epoch", "lr", "train_loss", "val_loss"]);
+        assert!(rows.len() > 1);
+        for row in &rows[1..] {
+            assert_eq!(row.len(), 5);
+            assert!(row[3].parse::<f32>().unwrap().is_finite());
+        }
+
+        // The final row is made after training and has a validation loss.
+        let last = rows.last().unwrap();
+        assert_eq!(last[0], "600");
+        assert_eq!(last[1], "2");
+        assert!(last[4].parse::<f32>().unwrap().is_finite());
+    }
+
+    /// Count the reports while 1000 tokens are processed, 10 per poll.
+    fn count_reports(report_interval: ReportInterval) -> Vec<usize> {
+        let n_tokens = Cell::new(0);
+        let mut reports = Vec::new();
+        report_until(
+            report_interval,
+            1000,
+            || n_tokens.replace(n_tokens.get() + 10),
+            |n_tokens_processed| reports.push(n_tokens_processed),
+            Duration::from_secs(0),
+        );
+        reports
+    }
+
+    #[test]
+    fn small_report_interval_reports_repeatedly() {
+        let reports = count_reports(ReportInterval {
+            tokens: 100,
+            duration: Duration::from_secs(3600),
+        });
+        assert_eq!(reports.len(), 10);
+        assert_eq!(reports[0], 100);
+        assert_eq!(reports.last(), Some(&1000));
+    }
+
+    #[test]
+    fn large_report_interval_only_reports_at_end() {
+        let reports = count_reports(ReportInterval {
+            tokens: 1_000_000,
+            duration: Duration::from_secs(3600),
+        });
+        assert_eq!(reports, vec![1000]);
+
+        // Disabled token interval.
+        let reports = count_reports(ReportInterval {
+            tokens: 0,
+            duration: Duration::from_secs(3600),
+        });
+        assert_eq!(reports, vec![1000]);
+    }
 }
@@ -0,0 +1,125 @@
+use std::thread::JoinHandle;
+
+use finalfrontier::{EpochStats, GradientHistogram, Trainer, SGD};
+
+/// Statistics that are collected by a training thread.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadReport {
+    /// Training statistics of every epoch, if collected.
+    pub epoch_stats: Option<Vec<EpochStats>>,
+
+    /// Histogram of gradient norms, if collected.
+    pub grad_hist: Option<GradientHistogram>,
+}
+
+impl ThreadReport {
+    /// Get the statistics that were collected by the SGD of a thread.
+    pub fn new<T>(sgd: &SGD<T>) -> Self
+    where
+        T: Trainer,
+    {
+        ThreadReport {
+            epoch_stats: sgd.epoch_stats().map(ToOwned::to_owned),
+            grad_hist: sgd.gradient_histogram().cloned(),
+        }
+    }
+
+    /// Add the statistics of another thread.
+    ///
+    /// The statistics of an epoch are merged with the statistics of the
+    /// same epoch of the other thread.
+    fn merge(&mut self, other: &ThreadReport) {
+        if let Some(ref other_stats) = other.epoch_stats {
+            let epoch_stats = self.epoch_stats.get_or_insert_with(Vec::new);
+            if epoch_stats.len() < other_stats.len() {
+                epoch_stats.resize(other_stats.len(), EpochStats::default());
+            }
+            for (stats, other_stats) in epoch_stats.iter_mut().zip(other_stats) {
+                stats.merge(other_stats);
+            }
+        }
+
+        if let Some(ref other_hist) = other.grad_hist {
+            self.grad_hist
+                .get_or_insert_with(GradientHistogram::new)
+                .merge(other_hist);
+        }
+    }
+}
+
+/// Wait for the training threads to finish.
+///
+/// Every training thread returns the statistics that it collected. The
+/// statistics of the threads are merged into a single report.
+pub fn join_training_threads(children: Vec<JoinHandle<ThreadReport>>) -> ThreadReport {
+    let mut merged = ThreadReport::default();
+    for child in children {
+        if let Ok(report) = child.join() {
+            merged.merge(&report);
+        }
+    }
+
+    merged
+}
+
+/// Print the training statistics of every epoch to stderr.
+///
+/// Epochs without statistics are not printed. They occur when a thread
+/// starts a new epoch right before training finishes.
+pub fn print_epoch_stats(epoch_stats: &[EpochStats]) {
+    eprintln!("Epoch statistics (epoch, tokens, examples, negatives, discarded, unknown):");
+    for (epoch, stats) in epoch_stats.iter().enumerate() {
+        if *stats == EpochStats::default() {
+            continue;
+        }
+
+        eprintln!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            epoch + 1,
+            stats.n_tokens,
+            stats.n_examples,
+            stats.n_negatives,
+            stats.n_discarded,
+            stats.n_oov
+        );
+    }
+}
+
+/// Print a histogram of gradient norms to stderr.
+pub fn print_gradient_histogram(grad_hist: &GradientHistogram) {
+    eprintln!("Gradient norms ({} gradients):", grad_hist.n_gradients());
+    eprint!("{}", grad_hist);
+}
+
+#[cfg(test)]
+mod tests {
+    use finalfrontier::EpochStats;
+
+    use super::ThreadReport;
+
+    fn stats(n_tokens: usize) -> EpochStats {
+        EpochStats {
+            n_tokens,
+            ..EpochStats::default()
+        }
+    }
+
+    #[test]
+    fn epoch_stats_of_threads_are_merged_per_epoch() {
+        let mut report = ThreadReport::default();
+        report.merge(&ThreadReport {
+            epoch_stats: Some(vec![stats(1), stats(2)]),
+            grad_hist: None,
+        });
+        report.merge(&ThreadReport {
+            epoch_stats: Some(vec![stats(10), stats(20), stats(30)]),
+            grad_hist: None,
+        });
+
+        assert_eq!(
+            report.epoch_stats.unwrap(),
+            [stats(11), stats(22), stats(30)]
+        );
+        assert!(report.grad_hist.is_none());
+    }
+}
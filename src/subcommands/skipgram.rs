@@ -1,32 +1,108 @@
 use std::cmp;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
 use clap::{App, Arg, ArgMatches};
-use finalfrontier::io::{thread_data_text, FileProgress, TrainInfo};
+use finalfrontier::io::{
+    read_frozen_tokens, read_negative_distribution, read_sentence_weights, read_side_features,
+    read_token_weights, shard_data_text, thread_start, ChunkQueue, FileProgress, InputFormat,
+    TrainInfo,
+};
 use finalfrontier::{
-    CommonConfig, ModelType, SentenceIterator, SimpleVocab, SkipGramConfig, SkipgramTrainer,
-    SubwordVocab, Vocab, VocabBuilder, WriteModelBinary, SGD,
+    CommonConfig, LrSchedule, ModelType, SentenceDedup, SentenceIterator, SimpleVocab,
+    SkipGramConfig, SkipgramTrainer, SubwordComposition, SubwordHash, SubwordVocab, SurfaceForms,
+    TextCorpus, TrainModel, Trainer, Vocab, VocabBuilder, DEFAULT_DEDUP_COUNTERS_EXP, SGD,
 };
+use finalfusion::compat::fasttext::FastTextIndexer;
 use finalfusion::prelude::VocabWrap;
+use finalfusion::subword::FinalfusionHashIndexer;
 use rand::{FromEntropy, Rng};
 use rand_xorshift::XorShiftRng;
 use serde::Serialize;
 use stdinout::OrExit;
 
-use crate::subcommands::{show_progress, FinalfrontierApp, VocabConfig};
+use crate::subcommands::{
+    finish_file, guard_finite, join_training_threads, print_displacement_report, print_epoch_stats,
+    print_gradient_histogram, show_progress, write_model, write_norms_file, write_vocab_json_file,
+    AtomicFile, FinalfrontierApp, LearningCurve, NanGuard, OutputFormat, ReportInterval,
+    ThreadReport, VocabConfig,
+};
 
+static BOUNDARY_TOKENS: &str = "boundary_tokens";
+static COMMENT_PREFIX: &str = "comment_prefix";
 static CONTEXT: &str = "context";
+static CONTEXT_SUBSAMPLING_THRESHOLD: &str = "context_subsampling_threshold";
+static DEDUP: &str = "dedup";
+static DEDUP_MAX_REPEATS: &str = "dedup_max_repeats";
+static DISCARD_BOUNDARY_TOKENS: &str = "discard_boundary_tokens";
+static DISCARD_OOV_CONTEXTS: &str = "discard_oov_contexts";
+static DOCUMENT_MARKER: &str = "document_marker";
+static DUMP_SAMPLING_TABLE: &str = "dump_sampling_table";
+static EXCLUDE_FOCUS_NEGATIVES: &str = "exclude_focus_negatives";
+static FREEZE_OUTPUTS: &str = "freeze_outputs";
+static INPUT_FORMAT: &str = "input_format";
+static JSONL_FIELD: &str = "jsonl_field";
+static LOSSY_UTF8: &str = "lossy_utf8";
+static LOWERCASE: &str = "lowercase";
+static MAX_SENTENCE_LEN: &str = "max_sentence_len";
+static MIN_CONTEXT: &str = "min_context";
 static MODEL: &str = "model";
+static N_SHARDS: &str = "n_shards";
+static NEGATIVE_DISTRIBUTION: &str = "negative_distribution";
+static SEED: &str = "seed";
+static SENTENCE_WEIGHTS: &str = "sentence_weights";
+static SHARD: &str = "shard";
+static SIDE_FEATURES: &str = "side_features";
+static TIED_WEIGHTS: &str = "tied_weights";
+static VALIDATION: &str = "validation";
+static WORK_STEALING: &str = "work_stealing";
 
 const PROGRESS_UPDATE_INTERVAL: u64 = 200;
 
+/// Chunk size in bytes for work-stealing.
+const WORK_STEALING_CHUNK_SIZE: usize = 1024 * 1024;
+
 /// Subcommand for training skipgram models.
 pub struct SkipgramApp {
     train_info: TrainInfo,
+    output_format: OutputFormat,
+    boundary_tokens: Vec<String>,
+    input_format: InputFormat,
+    comment_prefix: Option<String>,
+    document_marker: Option<String>,
+    lowercase: bool,
+    lossy_utf8: bool,
+    max_sentence_len: Option<usize>,
+    dedup: Option<u32>,
+    token_weights: Option<String>,
+    sentence_weights: Option<String>,
+    validation: Option<String>,
+    freeze_tokens: Option<String>,
+    freeze_outputs: bool,
+    dump_sampling_table: Option<String>,
+    negative_distribution: Option<String>,
+    side_features: Option<String>,
+    export_vocab_json: Option<String>,
+    nan_guard: Option<NanGuard>,
+    grad_hist: bool,
+    dim_stats: bool,
+    epoch_stats: bool,
+    displacement_report: Option<usize>,
+    curve_csv: Option<String>,
+    write_norms: Option<String>,
+    fsync: bool,
+    report_interval: ReportInterval,
+    sparse_output: bool,
+    subword_composition: SubwordComposition,
+    micro_batch: usize,
+    work_stealing: bool,
+    seed: Option<u64>,
+    shard: usize,
+    n_shards: usize,
     common_config: CommonConfig,
     skipgram_config: SkipGramConfig,
     vocab_config: VocabConfig,
@@ -68,19 +144,247 @@ impl SkipgramApp {
         &self.train_info
     }
 
+    /// Get the output format.
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    /// Get the boundary tokens.
+    pub fn boundary_tokens(&self) -> &[String] {
+        &self.boundary_tokens
+    }
+
+    /// Get the corpus input format.
+    pub fn input_format(&self) -> &InputFormat {
+        &self.input_format
+    }
+
+    /// Get the prefix of comment lines in the corpus.
+    pub fn comment_prefix(&self) -> Option<&str> {
+        self.comment_prefix.as_deref()
+    }
+
+    /// Get the marker of document boundaries in the corpus.
+    pub fn document_marker(&self) -> Option<&str> {
+        self.document_marker.as_deref()
+    }
+
+    /// Returns `true` if corpus tokens should be lowercased.
+    pub fn lowercase(&self) -> bool {
+        self.lowercase
+    }
+
+    /// Returns `true` if invalid UTF-8 in the corpus should be replaced.
+    pub fn lossy_utf8(&self) -> bool {
+        self.lossy_utf8
+    }
+
+    /// Get the maximum sentence length, longer sentences are split.
+    pub fn max_sentence_len(&self) -> Option<usize> {
+        self.max_sentence_len
+    }
+
+    /// Get the maximum number of repeats of a sentence, if deduplication is enabled.
+    pub fn dedup(&self) -> Option<u32> {
+        self.dedup
+    }
+
+    /// Get the path of the token weights file.
+    pub fn token_weights(&self) -> Option<&str> {
+        self.token_weights.as_deref()
+    }
+
+    /// Get the path of the sentence weights file.
+    pub fn sentence_weights(&self) -> Option<&str> {
+        self.sentence_weights.as_deref()
+    }
+
+    /// Get the path of the validation corpus.
+    pub fn validation(&self) -> Option<&str> {
+        self.validation.as_deref()
+    }
+
+    /// Get the path of the file with tokens that are frozen.
+    pub fn freeze_tokens(&self) -> Option<&str> {
+        self.freeze_tokens.as_deref()
+    }
+
+    /// Returns `true` if the output embeddings of frozen tokens are frozen as well.
+    pub fn freeze_outputs(&self) -> bool {
+        self.freeze_outputs
+    }
+
+    /// Get the path to export the vocabulary to as JSON.
+    pub fn export_vocab_json(&self) -> Option<&str> {
+        self.export_vocab_json.as_deref()
+    }
+
+    /// Get the path to dump the negative sampling table to.
+    pub fn dump_sampling_table(&self) -> Option<&str> {
+        self.dump_sampling_table.as_deref()
+    }
+
+    /// Get the path of the negative sampling distribution file.
+    pub fn negative_distribution(&self) -> Option<&str> {
+        self.negative_distribution.as_deref()
+    }
+
+    /// Get the path of the side features file.
+    pub fn side_features(&self) -> Option<&str> {
+        self.side_features.as_deref()
+    }
+
+    /// Get the action for non-finite embeddings.
+    pub fn nan_guard(&self) -> Option<NanGuard> {
+        self.nan_guard
+    }
+
+    /// Get whether a histogram of gradient norms is collected.
+    pub fn grad_hist(&self) -> bool {
+        self.grad_hist
+    }
+
+    /// Get whether per-dimension statistics of the input matrix are printed.
+    pub fn dim_stats(&self) -> bool {
+        self.dim_stats
+    }
+
+    /// Get whether training statistics of every epoch are printed.
+    pub fn epoch_stats(&self) -> bool {
+        self.epoch_stats
+    }
+
+    /// Get the number of tokens to print in the displacement report.
+    pub fn displacement_report(&self) -> Option<usize> {
+        self.displacement_report
+    }
+
+    /// Get the path to write the learning curve to.
+    pub fn curve_csv(&self) -> Option<&str> {
+        self.curve_csv.as_deref()
+    }
+
+    /// Get the path to write the norms of the word embeddings to.
+    pub fn write_norms(&self) -> Option<&str> {
+        self.write_norms.as_deref()
+    }
+
+    /// Returns `true` if output files should be synchronized to disk.
+    pub fn fsync(&self) -> bool {
+        self.fsync
+    }
+
+    /// Get the interval between progress reports.
+    pub fn report_interval(&self) -> ReportInterval {
+        self.report_interval
+    }
+
+    /// Returns `true` if untrained input embeddings are written as zeros.
+    pub fn sparse_output(&self) -> bool {
+        self.sparse_output
+    }
+
+    /// Get the composition of token embeddings from subwords.
+    pub fn subword_composition(&self) -> SubwordComposition {
+        self.subword_composition
+    }
+
+    /// Get the number of examples per input update of a focus token.
+    pub fn micro_batch(&self) -> usize {
+        self.micro_batch
+    }
+
+    /// Returns `true` if threads should take corpus chunks from a shared queue.
+    pub fn work_stealing(&self) -> bool {
+        self.work_stealing
+    }
+
+    /// Get the seed for initializing the embedding matrices.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Get the corpus shard to train on.
+    pub fn shard(&self) -> usize {
+        self.shard
+    }
+
+    /// Get the number of corpus shards.
+    pub fn n_shards(&self) -> usize {
+        self.n_shards
+    }
+
+    fn corpus_format(&self) -> CorpusFormat {
+        CorpusFormat {
+            input_format: self.input_format().clone(),
+            comment_prefix: self.comment_prefix().map(ToOwned::to_owned),
+            document_marker: self.document_marker().map(ToOwned::to_owned),
+            lowercase: self.lowercase(),
+            lossy_utf8: self.lossy_utf8(),
+            max_sentence_len: self.max_sentence_len(),
+            dedup: self
+                .dedup()
+                .map(|max_repeats| SentenceDedup::new(max_repeats, DEFAULT_DEDUP_COUNTERS_EXP)),
+            sentence_weights: None,
+        }
+    }
+
+    fn input_format_from_matches(matches: &ArgMatches) -> InputFormat {
+        match matches.value_of(INPUT_FORMAT).unwrap() {
+            "text" => InputFormat::Text,
+            "jsonl" => InputFormat::Jsonl {
+                field: matches.value_of(JSONL_FIELD).unwrap().to_owned(),
+            },
+            "pairs" => InputFormat::Pairs,
+            format => {
+                eprintln!("Unknown input format: {}", format);
+                std::process::exit(1);
+            }
+        }
+    }
+
     fn skipgram_config_from_matches(matches: &ArgMatches) -> SkipGramConfig {
         let context_size = matches
             .value_of(CONTEXT)
             .map(|v| v.parse().or_exit("Cannot parse context size", 1))
             .unwrap();
+        let context_subsampling_threshold: f32 = matches
+            .value_of(CONTEXT_SUBSAMPLING_THRESHOLD)
+            .map(|v| {
+                v.parse()
+                    .or_exit("Cannot parse context subsampling threshold", 1)
+            })
+            .unwrap();
+        if !context_subsampling_threshold.is_finite() || context_subsampling_threshold < 0. {
+            eprintln!("The context subsampling threshold should not be negative");
+            std::process::exit(1);
+        }
+        let min_context = matches
+            .value_of(MIN_CONTEXT)
+            .map(|v| v.parse().or_exit("Cannot parse minimum context size", 1))
+            .unwrap();
         let model = matches
             .value_of(MODEL)
             .map(|v| ModelType::try_from_str(v).or_exit("Cannot parse model type", 1))
             .unwrap();
+        let discard_boundary_tokens = matches.is_present(DISCARD_BOUNDARY_TOKENS);
+        let discard_oov_contexts = matches.is_present(DISCARD_OOV_CONTEXTS);
+        let exclude_focus_negatives = matches.is_present(EXCLUDE_FOCUS_NEGATIVES);
+        let tied_weights = matches.is_present(TIED_WEIGHTS);
+        if tied_weights && model != ModelType::SkipGram {
+            eprintln!("Tied weights are only supported by the skipgram model");
+            std::process::exit(1);
+        }
 
         SkipGramConfig {
             context_size,
+            context_subsampling_threshold,
+            discard_boundary_tokens,
+            discard_oov_contexts,
+            exclude_focus_negatives,
+            min_context,
             model,
+            tied_weights,
         }
     }
 }
@@ -89,6 +393,21 @@ impl FinalfrontierApp for SkipgramApp {
     fn app() -> App<'static, 'static> {
         Self::common_opts("skipgram")
             .about("Train a skip-gram model")
+            .arg(
+                Arg::with_name(BOUNDARY_TOKENS)
+                    .long("boundary_tokens")
+                    .value_name("TOKENS")
+                    .help("Comma-separated tokens that terminate context windows")
+                    .takes_value(true)
+                    .use_delimiter(true),
+            )
+            .arg(
+                Arg::with_name(COMMENT_PREFIX)
+                    .long("comment_prefix")
+                    .value_name("PREFIX")
+                    .help("Skip corpus lines that start with PREFIX")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(CONTEXT)
                     .long("context")
@@ -97,6 +416,104 @@ impl FinalfrontierApp for SkipgramApp {
                     .takes_value(true)
                     .default_value("10"),
             )
+            .arg(
+                Arg::with_name(CONTEXT_SUBSAMPLING_THRESHOLD)
+                    .long("context_subsampling_threshold")
+                    .value_name("THRESHOLD")
+                    .help("Context word subsampling threshold, 0 disables context subsampling")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(DEDUP)
+                    .long("dedup")
+                    .help("Skip repeated sentences (approximate)"),
+            )
+            .arg(
+                Arg::with_name(DEDUP_MAX_REPEATS)
+                    .long("dedup_max_repeats")
+                    .value_name("N")
+                    .help("Keep at most N occurrences of a sentence with --dedup")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name(DISCARD_BOUNDARY_TOKENS)
+                    .long("discard_boundary_tokens")
+                    .help("Remove boundary tokens before extracting contexts"),
+            )
+            .arg(
+                Arg::with_name(DISCARD_OOV_CONTEXTS)
+                    .long("discard_oov_contexts")
+                    .help("Remove unknown tokens before extracting contexts"),
+            )
+            .arg(
+                Arg::with_name(DOCUMENT_MARKER)
+                    .long("document_marker")
+                    .value_name("MARKER")
+                    .help("Join the lines of documents that are separated by MARKER lines")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(DUMP_SAMPLING_TABLE)
+                    .long("dump_sampling_table")
+                    .value_name("FILE")
+                    .help("Write the negative sampling distribution to FILE")
+                    .takes_value(true)
+                    .hidden(true),
+            )
+            .arg(
+                Arg::with_name(EXCLUDE_FOCUS_NEGATIVES)
+                    .long("exclude_focus_negatives")
+                    .help("Do not sample the focus word as a negative"),
+            )
+            .arg(
+                Arg::with_name(FREEZE_OUTPUTS)
+                    .long("freeze_outputs")
+                    .help("Also freeze the output embeddings of frozen tokens"),
+            )
+            .arg(
+                Arg::with_name(INPUT_FORMAT)
+                    .long("input_format")
+                    .value_name("FORMAT")
+                    .help("Corpus format")
+                    .takes_value(true)
+                    .possible_values(&["text", "jsonl", "pairs"])
+                    .default_value("text"),
+            )
+            .arg(
+                Arg::with_name(JSONL_FIELD)
+                    .long("jsonl_field")
+                    .value_name("FIELD")
+                    .help("Field with the tokens in the JSONL format")
+                    .takes_value(true)
+                    .default_value("tokens"),
+            )
+            .arg(
+                Arg::with_name(LOSSY_UTF8)
+                    .long("lossy_utf8")
+                    .help("Replace invalid UTF-8 in the corpus by U+FFFD"),
+            )
+            .arg(
+                Arg::with_name(LOWERCASE)
+                    .long("lowercase")
+                    .help("Lowercase tokens, the output contains all observed surface forms"),
+            )
+            .arg(
+                Arg::with_name(MAX_SENTENCE_LEN)
+                    .long("max_sentence_len")
+                    .value_name("N")
+                    .help("Split sentences that are longer than N tokens")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(MIN_CONTEXT)
+                    .long("min_context")
+                    .value_name("N")
+                    .help("Skip focus tokens with fewer than N context words")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
             .arg(
                 Arg::with_name(MODEL)
                     .long(MODEL)
@@ -106,135 +523,782 @@ impl FinalfrontierApp for SkipgramApp {
                     .possible_values(&["dirgram", "skipgram", "structgram"])
                     .default_value("skipgram"),
             )
+            .arg(
+                Arg::with_name(N_SHARDS)
+                    .long("n_shards")
+                    .value_name("N")
+                    .help("Number of corpus shards")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name(NEGATIVE_DISTRIBUTION)
+                    .long("negative_distribution")
+                    .value_name("FILE")
+                    .help("Draw negatives with the token<TAB>probability distribution in FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SEED)
+                    .long("seed")
+                    .value_name("SEED")
+                    .help("Seed for initializing the embedding matrices")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SENTENCE_WEIGHTS)
+                    .long("sentence_weights")
+                    .value_name("FILE")
+                    .help("Weights of the corpus sentences, one per line")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SHARD)
+                    .long("shard")
+                    .value_name("INDEX")
+                    .help("Corpus shard to train on (0-based)")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(SIDE_FEATURES)
+                    .long("side_features")
+                    .value_name("FILE")
+                    .help("Concatenate fixed token<TAB>f1 f2 ... features from FILE to the embeddings")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(TIED_WEIGHTS)
+                    .long("tied_weights")
+                    .help("Use the input embeddings as output embeddings (skipgram only)"),
+            )
+            .arg(
+                Arg::with_name(VALIDATION)
+                    .long("validation")
+                    .value_name("CORPUS")
+                    .help("Add the loss on a validation corpus to the learning curve")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(WORK_STEALING)
+                    .long("work_stealing")
+                    .help("Threads take corpus chunks from a shared queue"),
+            )
     }
 
     fn parse(matches: &ArgMatches) -> Self {
-        let corpus = matches.value_of(Self::CORPUS).unwrap().into();
+        let corpus: String = matches.value_of(Self::CORPUS).unwrap().into();
+        if corpus.ends_with(".zst") {
+            eprintln!(
+                "Cannot train on compressed corpus {}, the corpus is memory-mapped",
+                corpus
+            );
+            std::process::exit(1);
+        }
         let output = matches.value_of(Self::OUTPUT).unwrap().into();
         let n_threads = matches
             .value_of(Self::THREADS)
             .map(|v| v.parse().or_exit("Cannot parse number of threads", 1))
             .unwrap_or_else(|| cmp::min(num_cpus::get() / 2, 20));
-        let train_info = TrainInfo::new(corpus, output, n_threads);
+        let token_weights = matches.value_of(Self::TOKEN_WEIGHTS).map(ToOwned::to_owned);
+        let export_vocab_json = matches
+            .value_of(Self::EXPORT_VOCAB_JSON)
+            .map(ToOwned::to_owned);
+        let boundary_tokens = matches
+            .values_of(BOUNDARY_TOKENS)
+            .map(|v| v.map(ToOwned::to_owned).collect())
+            .unwrap_or_default();
+        let seed = matches
+            .value_of(SEED)
+            .map(|v| v.parse().or_exit("Cannot parse seed", 1));
+        let shard = matches
+            .value_of(SHARD)
+            .map(|v| v.parse().or_exit("Cannot parse shard index", 1))
+            .unwrap();
+        let n_shards = matches
+            .value_of(N_SHARDS)
+            .map(|v| v.parse().or_exit("Cannot parse number of shards", 1))
+            .unwrap();
+        if shard >= n_shards {
+            eprintln!("Shard {} out of index [0, {})", shard, n_shards);
+            std::process::exit(1);
+        }
+        let work_stealing = matches.is_present(WORK_STEALING);
+        if work_stealing && n_shards > 1 {
+            eprintln!("Work stealing cannot be used with corpus shards");
+            std::process::exit(1);
+        }
+        let sentence_weights = matches.value_of(SENTENCE_WEIGHTS).map(ToOwned::to_owned);
+        if work_stealing && sentence_weights.is_some() {
+            eprintln!("Work stealing cannot be used with sentence weights");
+            std::process::exit(1);
+        }
+        let validation = matches.value_of(VALIDATION).map(ToOwned::to_owned);
+        if validation.is_some() && Self::parse_curve_csv(matches).is_none() {
+            eprintln!("A validation corpus can only be used with a learning curve");
+            std::process::exit(1);
+        }
+        let max_sentence_len = matches
+            .value_of(MAX_SENTENCE_LEN)
+            .map(|v| v.parse().or_exit("Cannot parse maximum sentence length", 1));
+        if max_sentence_len == Some(0) {
+            eprintln!("The maximum sentence length should be positive");
+            std::process::exit(1);
+        }
+        let dedup_max_repeats = matches
+            .value_of(DEDUP_MAX_REPEATS)
+            .map(|v| {
+                v.parse()
+                    .or_exit("Cannot parse maximum number of sentence repeats", 1)
+            })
+            .unwrap();
+        if dedup_max_repeats == 0 {
+            eprintln!("The maximum number of sentence repeats should be positive");
+            std::process::exit(1);
+        }
+        let dedup = if matches.is_present(DEDUP) {
+            Some(dedup_max_repeats)
+        } else {
+            None
+        };
+        let common_config = Self::parse_common_config(&matches);
+        let skipgram_config = Self::skipgram_config_from_matches(&matches);
+        if skipgram_config.tied_weights && common_config.output_dims != common_config.dims {
+            eprintln!("Tied weights cannot be used with a different output dimensionality");
+            std::process::exit(1);
+        }
+        let side_features = matches.value_of(SIDE_FEATURES).map(ToOwned::to_owned);
+        if skipgram_config.tied_weights && side_features.is_some() {
+            eprintln!("Tied weights cannot be used with side features");
+            std::process::exit(1);
+        }
+        if skipgram_config.tied_weights && Self::parse_sparse_output(matches) {
+            // Updates of the input matrix as output matrix are not counted.
+            eprintln!("Tied weights cannot be used with sparse output");
+            std::process::exit(1);
+        }
+        let input_format = Self::input_format_from_matches(matches);
+        if input_format == InputFormat::Pairs && skipgram_config.model != ModelType::SkipGram {
+            eprintln!("The pairs input format is only supported by the skipgram model");
+            std::process::exit(1);
+        }
+        let document_marker = matches.value_of(DOCUMENT_MARKER).map(ToOwned::to_owned);
+        if document_marker.is_some() && input_format == InputFormat::Pairs {
+            eprintln!("Document markers cannot be used with the pairs input format");
+            std::process::exit(1);
+        }
+        if document_marker.is_some() && sentence_weights.is_some() {
+            eprintln!("Document markers cannot be used with sentence weights");
+            std::process::exit(1);
+        }
+        let mut train_info = TrainInfo::new(corpus, output, n_threads);
+        train_info
+            .set_metadata(Self::parse_metadata(matches))
+            .or_exit("Invalid metadata", 1);
         SkipgramApp {
             train_info,
-            common_config: Self::parse_common_config(&matches),
-            skipgram_config: Self::skipgram_config_from_matches(&matches),
+            output_format: Self::parse_output_format(matches),
+            boundary_tokens,
+            input_format,
+            comment_prefix: matches.value_of(COMMENT_PREFIX).map(ToOwned::to_owned),
+            document_marker,
+            lowercase: matches.is_present(LOWERCASE),
+            lossy_utf8: matches.is_present(LOSSY_UTF8),
+            max_sentence_len,
+            dedup,
+            token_weights,
+            sentence_weights,
+            validation,
+            freeze_tokens: matches.value_of(Self::FREEZE_TOKENS).map(ToOwned::to_owned),
+            freeze_outputs: matches.is_present(FREEZE_OUTPUTS),
+            export_vocab_json,
+            dump_sampling_table: matches.value_of(DUMP_SAMPLING_TABLE).map(ToOwned::to_owned),
+            negative_distribution: matches
+                .value_of(NEGATIVE_DISTRIBUTION)
+                .map(ToOwned::to_owned),
+            side_features,
+            nan_guard: Self::parse_nan_guard(matches),
+            grad_hist: Self::parse_grad_hist(matches),
+            dim_stats: Self::parse_dim_stats(matches),
+            epoch_stats: Self::parse_epoch_stats(matches),
+            displacement_report: Self::parse_displacement_report(matches),
+            curve_csv: Self::parse_curve_csv(matches),
+            write_norms: Self::parse_write_norms(matches),
+            fsync: Self::parse_fsync(matches),
+            report_interval: Self::parse_report_interval(matches),
+            sparse_output: Self::parse_sparse_output(matches),
+            subword_composition: Self::parse_subword_composition(matches),
+            micro_batch: Self::parse_micro_batch(matches),
+            work_stealing,
+            seed,
+            shard,
+            n_shards,
+            common_config,
+            skipgram_config,
             vocab_config: Self::parse_vocab_config(&matches),
         }
     }
 
     fn run(&self) {
         match self.vocab_config() {
-            VocabConfig::SubwordVocab(config) => {
-                let vocab: SubwordVocab<_, _> = build_vocab(config, self.corpus());
-                train(vocab, self);
-            }
+            VocabConfig::SubwordVocab(config) => match config.indexer.hash {
+                SubwordHash::FinalFusion => {
+                    let (vocab, surface_forms): (SubwordVocab<_, FinalfusionHashIndexer>, _) =
+                        build_vocab(
+                            config,
+                            self.corpus(),
+                            self.common_config(),
+                            &self.corpus_format(),
+                        );
+                    train(vocab, surface_forms, self);
+                }
+                SubwordHash::FastText => {
+                    let (vocab, surface_forms): (SubwordVocab<_, FastTextIndexer>, _) = build_vocab(
+                        config,
+                        self.corpus(),
+                        self.common_config(),
+                        &self.corpus_format(),
+                    );
+                    train(vocab, surface_forms, self);
+                }
+            },
             VocabConfig::SimpleVocab(config) => {
-                let vocab: SimpleVocab<String> = build_vocab(config, self.corpus());
-                train(vocab, self);
+                let (vocab, surface_forms): (SimpleVocab<String>, _) = build_vocab(
+                    config,
+                    self.corpus(),
+                    self.common_config(),
+                    &self.corpus_format(),
+                );
+                train(vocab, surface_forms, self);
             }
             VocabConfig::NGramVocab(config) => {
-                let vocab: SubwordVocab<_, _> = build_vocab(config, self.corpus());
-                train(vocab, self);
+                let (vocab, surface_forms): (SubwordVocab<_, _>, _) = build_vocab(
+                    config,
+                    self.corpus(),
+                    self.common_config(),
+                    &self.corpus_format(),
+                );
+                train(vocab, surface_forms, self);
             }
         }
     }
 }
 
-fn train<V>(vocab: V, app: &SkipgramApp)
+fn train<V>(vocab: V, surface_forms: Option<SurfaceForms>, app: &SkipgramApp)
 where
     V: Vocab<VocabType = String> + Into<VocabWrap> + Clone + Send + Sync + 'static,
     V::Config: Serialize,
+    V::IdxType: Send,
     for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
 {
     let common_config = app.common_config();
     let n_threads = app.n_threads();
     let corpus = app.corpus();
-    let mut output_writer = BufWriter::new(
-        File::create(app.output()).or_exit("Cannot open output file for writing.", 1),
-    );
+    let output_writer =
+        AtomicFile::create(app.output()).or_exit("Cannot open output file for writing.", 1);
     let trainer = SkipgramTrainer::new(
         vocab,
         XorShiftRng::from_entropy(),
         common_config,
         app.skipgram_config(),
+    )
+    .boundary_tokens(app.boundary_tokens().iter().cloned())
+    .pairs(*app.input_format() == InputFormat::Pairs);
+    let trainer = match app.negative_distribution() {
+        Some(path) => {
+            let f = File::open(path).or_exit("Cannot open negative distribution file", 1);
+            let probs = read_negative_distribution(BufReader::new(f), trainer.input_vocab())
+                .or_exit("Cannot read negative distribution", 1);
+            trainer.negative_distribution(&probs)
+        }
+        None => trainer,
+    };
+    if let Some(path) = app.dump_sampling_table() {
+        write_sampling_table(&trainer, path, app.fsync());
+    }
+    if let Some(path) = app.export_vocab_json() {
+        write_vocab_json_file(trainer.input_vocab(), path, app.fsync());
+    }
+    let n_words = trainer.input_vocab().len();
+    let n_outputs = if trainer.tied_weights() {
+        0
+    } else {
+        trainer.n_output_types()
+    };
+    let estimated_bytes =
+        common_config.estimated_bytes(n_words, trainer.n_input_types() - n_words, n_outputs);
+    eprintln!(
+        "Estimated model size: {:.1} MiB",
+        estimated_bytes as f64 / (1024 * 1024) as f64
     );
-    let sgd = SGD::new(trainer.into());
+    let frozen = app.freeze_tokens().map(|freeze_tokens| {
+        let f = File::open(freeze_tokens).or_exit("Cannot open frozen tokens file", 1);
+        read_frozen_tokens(BufReader::new(f), trainer.input_vocab())
+            .or_exit("Cannot read frozen tokens", 1)
+    });
+    let frozen_outputs = frozen
+        .as_ref()
+        .filter(|_| app.freeze_outputs())
+        .map(|frozen| frozen_output_rows(&trainer, frozen));
+    let mut model = match app.seed() {
+        Some(seed) => TrainModel::from_seed(trainer, seed),
+        None => trainer.into(),
+    };
+    model.set_subword_composition(app.subword_composition());
+    let mut sgd = SGD::new(model);
+    if let Some(token_weights) = app.token_weights() {
+        let f = File::open(token_weights).or_exit("Cannot open token weights file", 1);
+        let weights = read_token_weights(BufReader::new(f), sgd.model().input_vocab())
+            .or_exit("Cannot read token weights", 1);
+        sgd.set_token_weights(weights);
+    }
+    if let Some(side_features) = app.side_features() {
+        let f = File::open(side_features).or_exit("Cannot open side features file", 1);
+        let features = read_side_features(BufReader::new(f), sgd.model().input_vocab())
+            .or_exit("Cannot read side features", 1);
+        if features.ncols() >= common_config.dims as usize {
+            eprintln!(
+                "The embeddings have {} dimensions, but there are {} side features",
+                common_config.dims,
+                features.ncols()
+            );
+            std::process::exit(1);
+        }
+        sgd.set_side_features(features.view());
+    }
+    if let Some(frozen) = frozen {
+        sgd.freeze_inputs(frozen);
+    }
+    if let Some(frozen_outputs) = frozen_outputs {
+        sgd.freeze_outputs(frozen_outputs);
+    }
+    if app.epoch_stats() {
+        sgd.enable_epoch_stats();
+    }
+    if app.grad_hist() {
+        sgd.enable_gradient_histogram();
+    }
+    if app.sparse_output() {
+        sgd.track_input_updates();
+    }
+    sgd.set_micro_batch(app.micro_batch());
+    let init_embeds = app
+        .displacement_report()
+        .map(|_| sgd.model().input_word_embeddings());
+    let sentence_weights = app.sentence_weights().map(|sentence_weights| {
+        let f = File::open(sentence_weights).or_exit("Cannot open sentence weights file", 1);
+        let weights =
+            read_sentence_weights(BufReader::new(f)).or_exit("Cannot read sentence weights", 1);
+        let f = File::open(corpus).or_exit("Cannot open corpus for reading", 1);
+        let n_lines = BufReader::new(f).split(b'\n').count();
+        if weights.len() != n_lines {
+            eprintln!(
+                "The corpus has {} lines, but there are {} sentence weights",
+                n_lines,
+                weights.len()
+            );
+            std::process::exit(1);
+        }
+        Arc::new(weights)
+    });
 
+    let queue = if app.work_stealing() {
+        let f = File::open(corpus).or_exit("Cannot open corpus for reading", 1);
+        Some(Arc::new(
+            ChunkQueue::new(&f, WORK_STEALING_CHUNK_SIZE)
+                .or_exit("Cannot split corpus into chunks", 1),
+        ))
+    } else {
+        None
+    };
+
+    // The threads share the filter of repeated sentences.
+    let corpus_format = CorpusFormat {
+        sentence_weights,
+        ..app.corpus_format()
+    };
     let mut children = Vec::with_capacity(n_threads);
     for thread in 0..n_threads {
         let corpus = corpus.to_owned();
+        let corpus_format = corpus_format.clone();
+        let queue = queue.clone();
         let sgd = sgd.clone();
+        let shard = app.shard();
+        let n_shards = app.n_shards();
 
-        children.push(thread::spawn(move || {
-            do_work(
+        children.push(thread::spawn(move || match queue {
+            Some(queue) => do_work_stealing(&queue, &corpus_format, sgd, common_config.epochs),
+            None => do_work(
                 corpus,
+                &corpus_format,
                 sgd,
                 thread,
                 n_threads,
-                common_config.epochs,
-                common_config.lr,
-            );
+                shard,
+                n_shards,
+            ),
         }));
     }
 
+    let n_tokens = sgd.model().input_vocab().n_types() / app.n_shards();
+    let guard = app.nan_guard().map(|nan_guard| {
+        let sgd = sgd.clone();
+        let n_tokens_total = n_tokens * common_config.epochs as usize;
+        thread::spawn(move || {
+            guard_finite(
+                sgd,
+                nan_guard,
+                n_tokens_total,
+                Duration::from_millis(PROGRESS_UPDATE_INTERVAL),
+            )
+        })
+    });
+
+    let mut curve = app.curve_csv().map(|path| {
+        LearningCurve::create(path, &common_config, n_tokens)
+            .or_exit("Cannot open learning curve file for writing", 1)
+    });
+    let validation = app
+        .validation()
+        .map(|path| corpus_format.text_corpus(path, &common_config));
+    let validation_loss = validation.as_ref().map(|corpus| {
+        move |sgd: &SGD<_>| {
+            sgd.validation_loss(corpus)
+                .or_exit("Cannot compute validation loss", 1)
+        }
+    });
     show_progress(
         &common_config,
         &sgd,
-        Duration::from_millis(PROGRESS_UPDATE_INTERVAL),
+        n_tokens,
+        app.report_interval(),
+        curve.as_mut(),
+        validation_loss.as_ref().map(|loss| loss as _),
     );
 
+    if let Some(curve) = curve {
+        curve.finish(app.fsync());
+    }
+
     // Wait until all threads have finished.
-    for child in children {
-        let _ = child.join();
+    let report = join_training_threads(children);
+    if let Some(guard) = guard {
+        let _ = guard.join();
+    }
+    if let Some(epoch_stats) = report.epoch_stats {
+        print_epoch_stats(&epoch_stats);
+    }
+    if let Some(grad_hist) = report.grad_hist {
+        print_gradient_histogram(&grad_hist);
+    }
+
+    let update_counts = sgd.input_update_counts();
+    let mut model = sgd.into_model();
+    if let (Some(k), Some(init_embeds)) = (app.displacement_report(), init_embeds) {
+        // Report before zeroing untrained inputs, which would move them.
+        print_displacement_report(&model.displacements(init_embeds.view()), k);
+    }
+    if let Some(update_counts) = update_counts {
+        let n_zeroed = model.zero_untrained_inputs(&update_counts);
+        eprintln!("Untrained embeddings written as zeros: {}", n_zeroed);
+    }
+    if app.dim_stats() {
+        eprintln!("Input matrix dimensions (dimension, mean, variance):");
+        eprint!("{}", model.input_dimension_stats());
     }
+    if let Some(surface_forms) = surface_forms {
+        model.set_surface_forms(surface_forms);
+    }
+
+    if let Some(path) = app.write_norms() {
+        write_norms_file(&model, path, app.fsync());
+    }
+
+    write_model(
+        model,
+        app.output_format(),
+        output_writer,
+        app.output(),
+        app.fsync(),
+        app.train_info().clone(),
+    );
+}
 
-    sgd.into_model()
-        .write_model_binary(&mut output_writer, app.train_info().clone())
-        .or_exit("Cannot write model", 1);
+/// Options for reading sentences from a corpus.
+#[derive(Clone)]
+struct CorpusFormat {
+    input_format: InputFormat,
+    comment_prefix: Option<String>,
+    document_marker: Option<String>,
+    lowercase: bool,
+    lossy_utf8: bool,
+    max_sentence_len: Option<usize>,
+    dedup: Option<SentenceDedup>,
+    sentence_weights: Option<Arc<Vec<f32>>>,
+}
+
+impl CorpusFormat {
+    /// Construct a sentence source for `path` in this format.
+    ///
+    /// The source does not filter repeated sentences. The maximum token
+    /// length and digit normalization are taken from `config`.
+    fn text_corpus(&self, path: &str, config: &CommonConfig) -> TextCorpus {
+        let corpus = TextCorpus::new(path)
+            .input_format(self.input_format.clone())
+            .max_token_len(config.max_token_len as usize)
+            .normalize_digits(config.normalize_digits)
+            .lowercase(self.lowercase)
+            .lossy_utf8(self.lossy_utf8);
+        let corpus = match self.document_marker {
+            Some(ref marker) => corpus.document_marker(marker.as_str()),
+            None => corpus,
+        };
+        let corpus = match self.max_sentence_len {
+            Some(max_sentence_len) => corpus.max_sentence_len(max_sentence_len),
+            None => corpus,
+        };
+        match self.comment_prefix {
+            Some(ref prefix) => corpus.comment_prefix(prefix.as_str()),
+            None => corpus,
+        }
+    }
+
+    /// Construct a sentence iterator for this format.
+    ///
+    /// The maximum token length and digit normalization are taken from
+    /// `config`.
+    fn sentences<R>(&self, read: R, config: &CommonConfig) -> SentenceIterator<R>
+    where
+        R: BufRead,
+    {
+        let sentences = SentenceIterator::with_max_token_len(read, config.max_token_len as usize)
+            .input_format(self.input_format.clone())
+            .normalize_digits(config.normalize_digits)
+            .lowercase(self.lowercase)
+            .lossy_utf8(self.lossy_utf8);
+        let sentences = match self.document_marker {
+            Some(ref marker) => sentences.document_marker(marker.as_str()),
+            None => sentences,
+        };
+        let sentences = match self.max_sentence_len {
+            Some(max_sentence_len) => sentences.max_sentence_len(max_sentence_len),
+            None => sentences,
+        };
+        let sentences = match self.dedup {
+            Some(ref dedup) => sentences.dedup(dedup.clone()),
+            None => sentences,
+        };
+        match self.comment_prefix {
+            Some(ref prefix) => sentences.comment_prefix(prefix.as_str()),
+            None => sentences,
+        }
+    }
+
+    /// Get the pass over the corpus of the filter of repeated sentences.
+    fn dedup_pass(&self) -> u32 {
+        self.dedup.as_ref().map(SentenceDedup::pass).unwrap_or(0)
+    }
+
+    /// Start counting repeated sentences of a pass over the corpus.
+    ///
+    /// Deduplication applies per pass, see `SentenceDedup::start_pass`.
+    fn start_dedup_pass(&self, pass: u32) {
+        if let Some(ref dedup) = self.dedup {
+            dedup.start_pass(pass);
+        }
+    }
+
+    /// Get the weight of the sentence on corpus line `line` (0-based).
+    ///
+    /// Sentences have the weight *1* when no sentence weights are used.
+    fn sentence_weight(&self, line: usize) -> f32 {
+        match self.sentence_weights {
+            Some(ref weights) => weights[line],
+            None => 1.,
+        }
+    }
+}
+
+/// Count the line breaks in `data`.
+fn count_line_breaks(data: &[u8]) -> usize {
+    data.iter().filter(|&&b| b == b'\n').count()
 }
 
 fn do_work<P, R, V>(
     corpus_path: P,
+    corpus_format: &CorpusFormat,
     mut sgd: SGD<SkipgramTrainer<R, V>>,
     thread: usize,
     n_threads: usize,
-    epochs: u32,
-    start_lr: f32,
-) where
+    shard: usize,
+    n_shards: usize,
+) -> ThreadReport
+where
     P: Into<PathBuf>,
     R: Clone + Rng,
     V: Vocab<VocabType = String>,
     V::Config: Serialize,
     for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
 {
-    let n_tokens = sgd.model().input_vocab().n_types();
+    // A shard is (approximately) a fraction of the corpus tokens.
+    let n_tokens = sgd.model().input_vocab().n_types() / n_shards;
+    let epochs = sgd.model().config().epochs;
+    let lr_schedule = LrSchedule::new(sgd.model().config(), n_tokens);
 
     let f = File::open(corpus_path.into()).or_exit("Cannot open corpus for reading", 1);
-    let (data, start) =
-        thread_data_text(&f, thread, n_threads).or_exit("Could not get thread-specific data", 1);
+    let (mmap, shard_range) =
+        shard_data_text(&f, shard, n_shards).or_exit("Could not get shard-specific data", 1);
+    // Line of the corpus at which the data of the shard starts, to look up
+    // sentence weights.
+    let shard_line = if corpus_format.sentence_weights.is_some() {
+        count_line_breaks(&mmap[..shard_range.start])
+    } else {
+        0
+    };
+    let data = &mmap[shard_range];
+    let start = thread_start(data, thread, n_threads);
 
-    let mut sentences = SentenceIterator::new(&data[start..]);
+    let config = *sgd.model().config();
+    let mut sentences = corpus_format.sentences(&data[start..], &config);
+    let mut first_line = if corpus_format.sentence_weights.is_some() {
+        shard_line + count_line_breaks(&data[..start])
+    } else {
+        0
+    };
+    // Threads wrap around the data independently, an epoch ends when the
+    // threads processed the number of tokens of the (shard of the) corpus.
+    let mut epoch = 0;
+    sgd.start_epoch();
+    // Whether the sentences are read from all data of the shard, rather
+    // than from the thread's start.
+    let mut full_pass = false;
+    let mut dedup_pass = corpus_format.dedup_pass();
+    let mut pass_sentences = 0;
     while sgd.n_tokens_processed() < epochs as usize * n_tokens {
-        let sentence = if let Some(sentence) = sentences.next() {
-            sentence
-        } else {
-            sentences = SentenceIterator::new(&*data);
-            sentences
-                .next()
-                .or_exit("Iterator does not provide sentences", 1)
+        let sentence = match sentences.next() {
+            Some(sentence) => sentence.or_exit("Cannot read sentence", 1),
+            None => {
+                if full_pass && pass_sentences == 0 {
+                    if sentences.n_duplicates() == 0 {
+                        eprintln!("Iterator does not provide sentences");
+                        std::process::exit(1);
+                    }
+
+                    // Every sentence was rejected as a repeat, the repeats
+                    // of this pass over the corpus were used up.
+                    corpus_format.start_dedup_pass(dedup_pass + 1);
+                }
+
+                sentences = corpus_format.sentences(data, &config);
+                first_line = shard_line;
+                full_pass = true;
+                dedup_pass = corpus_format.dedup_pass();
+                pass_sentences = 0;
+                continue;
+            }
+        };
+        pass_sentences += 1;
+
+        let lr = lr_schedule.lr_in_epoch(epoch, sgd.n_tokens_processed());
+        let weight = corpus_format.sentence_weight(first_line + sentences.n_lines() - 1);
+
+        sgd.update_weighted_sentence(&sentence, weight, lr);
+
+        // Example caps are per epoch.
+        while sgd.n_tokens_processed() / n_tokens > epoch {
+            epoch += 1;
+            sgd.start_epoch();
+        }
+    }
+
+    ThreadReport::new(&sgd)
+}
+
+fn do_work_stealing<R, V>(
+    queue: &ChunkQueue,
+    corpus_format: &CorpusFormat,
+    mut sgd: SGD<SkipgramTrainer<R, V>>,
+    epochs: u32,
+) -> ThreadReport
+where
+    R: Clone + Rng,
+    V: Vocab<VocabType = String>,
+    V::Config: Serialize,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    let n_tokens = sgd.model().input_vocab().n_types();
+    let lr_schedule = LrSchedule::new(sgd.model().config(), n_tokens);
+
+    let config = *sgd.model().config();
+    let mut epoch = 0;
+    sgd.start_epoch();
+    while sgd.n_tokens_processed() < epochs as usize * n_tokens {
+        // Every pass over the chunks of the queue is an epoch.
+        let (pass, chunk) = queue
+            .next_chunk()
+            .or_exit("Iterator does not provide sentences", 1);
+
+        // Example caps are per epoch.
+        while pass > epoch {
+            epoch += 1;
+            sgd.start_epoch();
         }
-        .or_exit("Cannot read sentence", 1);
+        corpus_format.start_dedup_pass(pass as u32);
+
+        let sentences = corpus_format.sentences(chunk, &config);
+        for sentence in sentences {
+            let sentence = sentence.or_exit("Cannot read sentence", 1);
 
-        let lr = (1.0 - (sgd.n_tokens_processed() as f32 / (epochs as usize * n_tokens) as f32))
-            * start_lr;
+            let lr = lr_schedule.lr_in_epoch(epoch, sgd.n_tokens_processed());
 
-        sgd.update_sentence(&sentence, lr);
+            sgd.update_sentence(&sentence, lr);
+        }
+    }
+
+    ThreadReport::new(&sgd)
+}
+
+/// Get the output rows of frozen words.
+fn frozen_output_rows<R, V>(trainer: &SkipgramTrainer<R, V>, frozen: &[bool]) -> Vec<bool>
+where
+    R: Rng + Clone,
+    V: Vocab,
+    V::Config: Serialize,
+{
+    let mut frozen_outputs = vec![false; trainer.n_output_types()];
+    for word in (0..frozen.len()).filter(|&word| frozen[word]) {
+        for output in trainer.output_rows(word) {
+            frozen_outputs[output] = true;
+        }
+    }
+
+    frozen_outputs
+}
+
+/// Write the negative sampling distribution of a trainer.
+///
+/// Every line contains an output index, the word of the output and its
+/// probability, separated by tabs.
+fn write_sampling_table<R, V>(trainer: &SkipgramTrainer<R, V>, path: &str, fsync: bool)
+where
+    R: Rng,
+    V: Vocab<VocabType = String>,
+{
+    let mut writer =
+        AtomicFile::create(path).or_exit("Cannot open sampling table file for writing", 1);
+    for (output, (word, prob)) in trainer.negative_sampling_table().into_iter().enumerate() {
+        writeln!(writer, "{}\t{}\t{}", output, word, prob)
+            .or_exit("Cannot write sampling table", 1);
     }
+    finish_file(writer, fsync);
 }
 
-fn build_vocab<P, V, C>(config: C, corpus_path: P) -> V
+fn build_vocab<P, V, C>(
+    config: C,
+    corpus_path: P,
+    common_config: CommonConfig,
+    corpus_format: &CorpusFormat,
+) -> (V, Option<SurfaceForms>)
 where
     P: AsRef<Path>,
     V: Vocab<VocabType = String> + From<VocabBuilder<C, String>>,
@@ -243,16 +1307,58 @@ where
     let f = File::open(corpus_path).or_exit("Cannot open corpus for reading", 1);
     let file_progress = FileProgress::new(f).or_exit("Cannot create progress bar", 1);
 
-    let sentences = SentenceIterator::new(BufReader::new(file_progress));
+    // Tokens are lowercased while counting, so that their surface forms
+    // can be recorded.
+    let mut sentences = corpus_format
+        .sentences(BufReader::new(file_progress), &common_config)
+        .lowercase(false);
 
     let mut builder = VocabBuilder::new(config);
-    for sentence in sentences {
+    let mut surface_forms = if corpus_format.lowercase {
+        Some(SurfaceForms::new())
+    } else {
+        None
+    };
+    for sentence in &mut sentences {
         let sentence = sentence.or_exit("Cannot read sentence", 1);
 
         for token in sentence {
-            builder.count(token);
+            match surface_forms {
+                Some(ref mut surface_forms) => builder.count(surface_forms.count(&token)),
+                None => builder.count(token),
+            }
         }
     }
 
-    builder.into()
+    if sentences.n_lossy() > 0 {
+        eprintln!("Replaced invalid UTF-8 in {} lines", sentences.n_lossy());
+    }
+
+    if sentences.n_malformed() > 0 {
+        eprintln!("Skipped {} malformed lines", sentences.n_malformed());
+    }
+
+    if let Some(max_sentence_len) = corpus_format.max_sentence_len {
+        if sentences.n_split() > 0 {
+            eprintln!(
+                "Split {} sentences longer than {} tokens",
+                sentences.n_split(),
+                max_sentence_len
+            );
+        }
+    }
+
+    if sentences.n_duplicates() > 0 {
+        eprintln!("Skipped {} repeated sentences", sentences.n_duplicates());
+    }
+
+    if sentences.n_skipped() > 0 {
+        eprintln!(
+            "Skipped {} tokens longer than {} bytes",
+            sentences.n_skipped(),
+            common_config.max_token_len
+        );
+    }
+
+    (builder.into(), surface_forms)
 }
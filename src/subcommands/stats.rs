@@ -0,0 +1,48 @@
+use clap::{App, Arg, ArgMatches};
+use finalfrontier::{CorpusStats, TextCorpus};
+use stdinout::OrExit;
+
+use crate::subcommands::FinalfrontierApp;
+
+/// Subcommand for printing corpus statistics.
+pub struct StatsApp {
+    corpus: String,
+}
+
+impl StatsApp {
+    /// Get the corpus path.
+    pub fn corpus(&self) -> &str {
+        &self.corpus
+    }
+}
+
+impl FinalfrontierApp for StatsApp {
+    fn app() -> App<'static, 'static> {
+        Self::base_opts("stats")
+            .about("Print corpus statistics")
+            .arg(
+                Arg::with_name(Self::CORPUS)
+                    .help("Tokenized corpus")
+                    .index(1)
+                    .required(true),
+            )
+    }
+
+    fn parse(matches: &ArgMatches) -> Self {
+        StatsApp {
+            corpus: matches.value_of(Self::CORPUS).unwrap().into(),
+        }
+    }
+
+    fn run(&self) {
+        let corpus = TextCorpus::new(self.corpus());
+        let stats = CorpusStats::from_source(&corpus).or_exit("Cannot read corpus", 1);
+
+        println!("Sentences: {}", stats.n_sentences());
+        println!("Tokens: {}", stats.n_tokens());
+        println!("Types: {}", stats.n_types());
+        println!("End-of-sentence markers: {}", stats.n_eos());
+        println!("Mean sentence length: {:.2}", stats.mean_sentence_len());
+        println!("Median sentence length: {:.1}", stats.median_sentence_len());
+    }
+}
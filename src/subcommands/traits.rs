@@ -1,10 +1,14 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use clap::{App, AppSettings, Arg, ArgMatches};
 use finalfrontier::{
-    BucketConfig, CommonConfig, LossType, NGramConfig, SimpleVocabConfig, SubwordVocabConfig,
+    BucketConfig, CommonConfig, LossType, LrAnchor, NGramConfig, SamplerType, SimpleVocabConfig,
+    SubwordComposition, SubwordHash, SubwordVocabConfig,
 };
 use stdinout::OrExit;
 
-use crate::subcommands::VocabConfig;
+use crate::subcommands::{NanGuard, OutputFormat, ReportInterval, VocabConfig};
 
 static DEFAULT_CLAP_SETTINGS: &[AppSettings] = &[
     AppSettings::DontCollapseArgsInUsage,
@@ -13,22 +17,55 @@ static DEFAULT_CLAP_SETTINGS: &[AppSettings] = &[
 
 // Option constants
 static BUCKETS: &str = "buckets";
+static CURVE_CSV: &str = "curve_csv";
+static DIM_STATS: &str = "dim_stats";
 static DIMS: &str = "dims";
 static DISCARD: &str = "discard";
+static DISPLACEMENT_REPORT: &str = "displacement_report";
+static DROPOUT: &str = "dropout";
+static EPOCH_STATS: &str = "epoch_stats";
 static EPOCHS: &str = "epochs";
+static FORMAT: &str = "format";
+static FREQ_LR_SCALING: &str = "freq_lr_scaling";
+static FSYNC: &str = "fsync";
+static GRAD_HIST: &str = "grad_hist";
+static HASH: &str = "hash";
+static INIT_SCALE: &str = "init_scale";
 static LR: &str = "lr";
+static LR_ANCHOR: &str = "lr_anchor";
 static MINCOUNT: &str = "mincount";
+static MIN_LR: &str = "min_lr";
 static MINN: &str = "minn";
 static MAXN: &str = "maxn";
+static MAX_EXAMPLES_PER_TOKEN: &str = "max_examples_per_token";
+static MAX_NGRAMS_PER_TOKEN: &str = "max_ngrams_per_token";
+static MAX_TOKEN_LEN: &str = "max_token_len";
+static METADATA: &str = "metadata";
+static MICRO_BATCH: &str = "micro_batch";
+static NAN_GUARD: &str = "nan_guard";
 static NGRAM_MINCOUNT: &str = "ngram_mincount";
+static NORMALIZE_DIGITS: &str = "normalize_digits";
 static SUBWORDS: &str = "subwords";
 static NS: &str = "ns";
+static OUTPUT_DIMS: &str = "output_dims";
+static POSITIVE_WEIGHT: &str = "positive_weight";
+static REPORT_INTERVAL_SECS: &str = "report_interval_secs";
+static REPORT_INTERVAL_TOKENS: &str = "report_interval_tokens";
+static SAMPLER: &str = "sampler";
+static SENSES: &str = "senses";
+static SPARSE_OUTPUT: &str = "sparse_output";
+static SUBWORD_COMPOSITION: &str = "subword_composition";
+static WARMUP_TOKENS: &str = "warmup_tokens";
+static WRITE_NORMS: &str = "write_norms";
 static ZIPF_EXPONENT: &str = "zipf";
 
 pub trait FinalfrontierApp {
     const CORPUS: &'static str = "CORPUS";
+    const EXPORT_VOCAB_JSON: &'static str = "EXPORT_VOCAB_JSON";
+    const FREEZE_TOKENS: &'static str = "FREEZE_TOKENS";
     const OUTPUT: &'static str = "OUTPUT";
     const THREADS: &'static str = "THREADS";
+    const TOKEN_WEIGHTS: &'static str = "TOKEN_WEIGHTS";
 
     fn app() -> App<'static, 'static>;
 
@@ -37,21 +74,18 @@ pub trait FinalfrontierApp {
     fn run(&self);
 
     fn common_opts<'a, 'b>(name: &str) -> App<'a, 'b> {
-        let version = if let Some(git_desc) = option_env!("MAYBE_FINALFRONTIER_GIT_DESC") {
-            git_desc
-        } else {
-            env!("CARGO_PKG_VERSION")
-        };
-        App::new(name)
-            .settings(DEFAULT_CLAP_SETTINGS)
-            .version(version)
+        Self::vocab_opts(name)
             .arg(
-                Arg::with_name(BUCKETS)
-                    .long("buckets")
-                    .value_name("EXP")
-                    .help("Number of buckets: 2^EXP")
-                    .takes_value(true)
-                    .default_value("21"),
+                Arg::with_name(CURVE_CSV)
+                    .long("curve_csv")
+                    .value_name("FILE")
+                    .help("Write the learning curve as CSV to FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(DIM_STATS)
+                    .long("dim_stats")
+                    .help("Print the mean and variance of every dimension after training"),
             )
             .arg(
                 Arg::with_name(DIMS)
@@ -62,12 +96,24 @@ pub trait FinalfrontierApp {
                     .default_value("300"),
             )
             .arg(
-                Arg::with_name(DISCARD)
-                    .long("discard")
-                    .value_name("THRESHOLD")
-                    .help("Discard threshold")
+                Arg::with_name(DISPLACEMENT_REPORT)
+                    .long("displacement_report")
+                    .value_name("K")
+                    .help("Print the K tokens that moved the most and the least during training")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(DROPOUT)
+                    .long("dropout")
+                    .value_name("P")
+                    .help("Fraction of embedding dimensions to drop in each update")
                     .takes_value(true)
-                    .default_value("1e-4"),
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(EPOCH_STATS)
+                    .long("epoch_stats")
+                    .help("Print training statistics of every epoch after training"),
             )
             .arg(
                 Arg::with_name(EPOCHS)
@@ -77,6 +123,51 @@ pub trait FinalfrontierApp {
                     .takes_value(true)
                     .default_value("15"),
             )
+            .arg(
+                Arg::with_name(Self::EXPORT_VOCAB_JSON)
+                    .long("export_vocab_json")
+                    .value_name("FILE")
+                    .help("Write the vocabulary with counts as JSON to FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(FORMAT)
+                    .long("format")
+                    .value_name("FORMAT")
+                    .help("Output format")
+                    .takes_value(true)
+                    .possible_values(&["finalfusion", "npz", "text", "word2vec-binary"])
+                    .default_value("finalfusion"),
+            )
+            .arg(
+                Arg::with_name(Self::FREEZE_TOKENS)
+                    .long("freeze_tokens")
+                    .value_name("FILE")
+                    .help("Do not update the input embeddings of the tokens in FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(FREQ_LR_SCALING)
+                    .long("freq_lr_scaling")
+                    .help("Scale the learning rate of focus tokens by their frequency"),
+            )
+            .arg(
+                Arg::with_name(FSYNC)
+                    .long("fsync")
+                    .help("Synchronize output files to disk after writing them"),
+            )
+            .arg(
+                Arg::with_name(GRAD_HIST)
+                    .long("grad_hist")
+                    .help("Print a histogram of gradient norms after training"),
+            )
+            .arg(
+                Arg::with_name(INIT_SCALE)
+                    .long("init_scale")
+                    .value_name("SCALE")
+                    .help("Initialize embeddings uniformly in [-SCALE, SCALE] (default: 1 / dims)")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(LR)
                     .long("lr")
@@ -86,45 +177,70 @@ pub trait FinalfrontierApp {
                     .default_value("0.05"),
             )
             .arg(
-                Arg::with_name(MINCOUNT)
-                    .long("mincount")
-                    .value_name("FREQ")
-                    .help("Minimum token frequency")
+                Arg::with_name(LR_ANCHOR)
+                    .long("lr_anchor")
+                    .value_name("ANCHOR")
+                    .help("Decay the learning rate per token or per epoch")
                     .takes_value(true)
-                    .default_value("5"),
+                    .possible_values(&["epochs", "tokens"])
+                    .default_value("tokens"),
             )
             .arg(
-                Arg::with_name(MINN)
-                    .long("minn")
-                    .value_name("LEN")
-                    .help("Minimum ngram length")
+                Arg::with_name(MAX_EXAMPLES_PER_TOKEN)
+                    .long("max_examples_per_token")
+                    .value_name("N")
+                    .help("Train at most N examples per focus token per epoch (0: no limit)")
                     .takes_value(true)
-                    .default_value("3"),
+                    .default_value("0"),
             )
             .arg(
-                Arg::with_name(MAXN)
-                    .long("maxn")
+                Arg::with_name(MAX_NGRAMS_PER_TOKEN)
+                    .long("max_ngrams_per_token")
+                    .value_name("N")
+                    .help("Update at most N subword n-grams per token (0: no limit)")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(MAX_TOKEN_LEN)
+                    .long("max_token_len")
                     .value_name("LEN")
-                    .help("Maximum ngram length")
+                    .help("Skip tokens longer than LEN bytes")
                     .takes_value(true)
-                    .default_value("6"),
+                    .default_value("100"),
             )
             .arg(
-                Arg::with_name(SUBWORDS)
-                    .long("subwords")
+                Arg::with_name(METADATA)
+                    .long("metadata")
+                    .value_name("KEY=VALUE")
+                    .help("Add metadata to the model (can be used multiple times)")
                     .takes_value(true)
-                    .value_name("SUBWORDS")
-                    .possible_values(&["buckets", "ngrams", "none"])
-                    .default_value("buckets")
-                    .help("What kind of subwords to use."),
+                    .multiple(true)
+                    .number_of_values(1),
             )
             .arg(
-                Arg::with_name(NGRAM_MINCOUNT)
-                    .long("ngram_mincount")
-                    .value_name("FREQ")
-                    .help("Minimum ngram frequency.")
+                Arg::with_name(MICRO_BATCH)
+                    .long("micro_batch")
+                    .value_name("N")
+                    .help("Number of examples per input update of a focus token")
                     .takes_value(true)
-                    .default_value("5"),
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name(MIN_LR)
+                    .long("min_lr")
+                    .value_name("LEARNING_RATE")
+                    .help("Learning rate at the end of training")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(NAN_GUARD)
+                    .long("nan_guard")
+                    .value_name("ACTION")
+                    .help("Periodically check embeddings for NaN/Inf values")
+                    .takes_value(true)
+                    .possible_values(&["reset", "warn"]),
             )
             .arg(
                 Arg::with_name(NS)
@@ -134,6 +250,59 @@ pub trait FinalfrontierApp {
                     .takes_value(true)
                     .default_value("5"),
             )
+            .arg(
+                Arg::with_name(NORMALIZE_DIGITS)
+                    .long("normalize_digits")
+                    .help("Replace runs of digits in tokens by 0"),
+            )
+            .arg(
+                Arg::with_name(OUTPUT_DIMS)
+                    .long("output_dims")
+                    .value_name("DIMENSIONS")
+                    .help("Output embedding dimensionality (default: dims)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(POSITIVE_WEIGHT)
+                    .long("positive_weight")
+                    .value_name("WEIGHT")
+                    .help("Weight of the positive example in the loss")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name(REPORT_INTERVAL_SECS)
+                    .long("report_interval_secs")
+                    .value_name("SECS")
+                    .help("Seconds between progress reports (0: disable)")
+                    .takes_value(true)
+                    .default_value("0.2"),
+            )
+            .arg(
+                Arg::with_name(REPORT_INTERVAL_TOKENS)
+                    .long("report_interval_tokens")
+                    .value_name("N")
+                    .help("Processed tokens between progress reports (0: disable)")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(SAMPLER)
+                    .long("sampler")
+                    .value_name("SAMPLER")
+                    .help("Negative sampler")
+                    .takes_value(true)
+                    .possible_values(&["alias", "zipf"])
+                    .default_value("zipf"),
+            )
+            .arg(
+                Arg::with_name(SENSES)
+                    .long("senses")
+                    .value_name("K")
+                    .help("Number of sense vectors per word")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
             .arg(
                 Arg::with_name(Self::THREADS)
                     .long("threads")
@@ -141,6 +310,42 @@ pub trait FinalfrontierApp {
                     .help("Number of threads (default: min(logical_cpus / 2, 20))")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name(Self::TOKEN_WEIGHTS)
+                    .long("token_weights")
+                    .value_name("FILE")
+                    .help("Learning rate weights of focus tokens (token<TAB>weight)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name(SPARSE_OUTPUT)
+                    .long("sparse_output")
+                    .help("Write input embeddings that were never updated as zeros"),
+            )
+            .arg(
+                Arg::with_name(SUBWORD_COMPOSITION)
+                    .long("subword_composition")
+                    .value_name("COMPOSITION")
+                    .help("Composition of token embeddings from subwords")
+                    .takes_value(true)
+                    .possible_values(&["mean", "sum"])
+                    .default_value("mean"),
+            )
+            .arg(
+                Arg::with_name(WARMUP_TOKENS)
+                    .long("warmup_tokens")
+                    .value_name("N")
+                    .help("Number of tokens for learning rate warmup")
+                    .takes_value(true)
+                    .default_value("0"),
+            )
+            .arg(
+                Arg::with_name(WRITE_NORMS)
+                    .long("write_norms")
+                    .value_name("FILE")
+                    .help("Write the l2 norms of the word embeddings to FILE")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name(ZIPF_EXPONENT)
                     .long("zipf")
@@ -163,20 +368,148 @@ pub trait FinalfrontierApp {
             )
     }
 
+    /// Construct an application without options.
+    fn base_opts<'a, 'b>(name: &str) -> App<'a, 'b> {
+        let version = if let Some(git_desc) = option_env!("MAYBE_FINALFRONTIER_GIT_DESC") {
+            git_desc
+        } else {
+            env!("CARGO_PKG_VERSION")
+        };
+        App::new(name)
+            .settings(DEFAULT_CLAP_SETTINGS)
+            .version(version)
+    }
+
+    /// Construct an application with the vocabulary options.
+    fn vocab_opts<'a, 'b>(name: &str) -> App<'a, 'b> {
+        Self::base_opts(name)
+            .arg(
+                Arg::with_name(BUCKETS)
+                    .long("buckets")
+                    .value_name("EXP")
+                    .help("Number of buckets: 2^EXP")
+                    .takes_value(true)
+                    .default_value("21"),
+            )
+            .arg(
+                Arg::with_name(DISCARD)
+                    .long("discard")
+                    .value_name("THRESHOLD")
+                    .help("Discard threshold")
+                    .takes_value(true)
+                    .default_value("1e-4"),
+            )
+            .arg(
+                Arg::with_name(HASH)
+                    .long("hash")
+                    .value_name("HASH")
+                    .help("Subword hash function for buckets")
+                    .takes_value(true)
+                    .possible_values(&["finalfusion", "fasttext"])
+                    .default_value("finalfusion"),
+            )
+            .arg(
+                Arg::with_name(MINCOUNT)
+                    .long("mincount")
+                    .value_name("FREQ")
+                    .help("Minimum token frequency")
+                    .takes_value(true)
+                    .default_value("5"),
+            )
+            .arg(
+                Arg::with_name(MINN)
+                    .long("minn")
+                    .value_name("LEN")
+                    .help("Minimum ngram length")
+                    .takes_value(true)
+                    .default_value("3"),
+            )
+            .arg(
+                Arg::with_name(MAXN)
+                    .long("maxn")
+                    .value_name("LEN")
+                    .help("Maximum ngram length")
+                    .takes_value(true)
+                    .default_value("6"),
+            )
+            .arg(
+                Arg::with_name(SUBWORDS)
+                    .long("subwords")
+                    .takes_value(true)
+                    .value_name("SUBWORDS")
+                    .possible_values(&["buckets", "ngrams", "none"])
+                    .default_value("buckets")
+                    .help("What kind of subwords to use."),
+            )
+            .arg(
+                Arg::with_name(NGRAM_MINCOUNT)
+                    .long("ngram_mincount")
+                    .value_name("FREQ")
+                    .help("Minimum ngram frequency.")
+                    .takes_value(true)
+                    .default_value("5"),
+            )
+    }
+
     /// Construct `CommonConfig` from `matches`.
     fn parse_common_config(matches: &ArgMatches) -> CommonConfig {
         let dims = matches
             .value_of(DIMS)
             .map(|v| v.parse().or_exit("Cannot parse dimensionality", 1))
             .unwrap();
+        let dropout = matches
+            .value_of(DROPOUT)
+            .map(|v| v.parse().or_exit("Cannot parse dropout", 1))
+            .unwrap();
+        if !(0. ..1.).contains(&dropout) {
+            eprintln!("Dropout should be in [0, 1): {}", dropout);
+            std::process::exit(1);
+        }
         let epochs = matches
             .value_of(EPOCHS)
             .map(|v| v.parse().or_exit("Cannot parse number of epochs", 1))
             .unwrap();
+        let output_dims = matches
+            .value_of(OUTPUT_DIMS)
+            .map(|v| v.parse().or_exit("Cannot parse output dimensionality", 1))
+            .unwrap_or(dims);
         let lr = matches
             .value_of(LR)
             .map(|v| v.parse().or_exit("Cannot parse learning rate", 1))
             .unwrap();
+        let lr_anchor = matches
+            .value_of(LR_ANCHOR)
+            .map(|v| LrAnchor::try_from_str(v).or_exit("Cannot parse learning rate anchor", 1))
+            .unwrap();
+        let max_examples_per_token = matches
+            .value_of(MAX_EXAMPLES_PER_TOKEN)
+            .map(|v| {
+                v.parse()
+                    .or_exit("Cannot parse maximum number of examples per token", 1)
+            })
+            .unwrap();
+        let max_ngrams_per_token = matches
+            .value_of(MAX_NGRAMS_PER_TOKEN)
+            .map(|v| {
+                v.parse()
+                    .or_exit("Cannot parse maximum number of n-grams per token", 1)
+            })
+            .unwrap();
+        let max_token_len = matches
+            .value_of(MAX_TOKEN_LEN)
+            .map(|v| v.parse().or_exit("Cannot parse maximum token length", 1))
+            .unwrap();
+        let min_lr = matches
+            .value_of(MIN_LR)
+            .map(|v| v.parse().or_exit("Cannot parse minimum learning rate", 1))
+            .unwrap();
+        if !(0. ..=lr).contains(&min_lr) {
+            eprintln!(
+                "The minimum learning rate should be in [0, {}]: {}",
+                lr, min_lr
+            );
+            std::process::exit(1);
+        }
         let negative_samples = matches
             .value_of(NS)
             .map(|v| {
@@ -184,6 +517,45 @@ pub trait FinalfrontierApp {
                     .or_exit("Cannot parse number of negative samples", 1)
             })
             .unwrap();
+        let init_scale = matches
+            .value_of(INIT_SCALE)
+            .map(|v| v.parse().or_exit("Cannot parse initialization scale", 1));
+        if let Some(init_scale) = init_scale {
+            if !(init_scale > 0. && f32::is_finite(init_scale)) {
+                eprintln!(
+                    "The initialization scale should be positive: {}",
+                    init_scale
+                );
+                std::process::exit(1);
+            }
+        }
+        let positive_weight = matches
+            .value_of(POSITIVE_WEIGHT)
+            .map(|v| v.parse().or_exit("Cannot parse positive weight", 1))
+            .unwrap();
+        if !(positive_weight > 0. && f32::is_finite(positive_weight)) {
+            eprintln!(
+                "The positive weight should be positive: {}",
+                positive_weight
+            );
+            std::process::exit(1);
+        }
+        let sampler = matches
+            .value_of(SAMPLER)
+            .map(|v| SamplerType::try_from_str(v).or_exit("Cannot parse sampler", 1))
+            .unwrap();
+        let senses = matches
+            .value_of(SENSES)
+            .map(|v| v.parse().or_exit("Cannot parse number of senses", 1))
+            .unwrap();
+        if senses == 0 {
+            eprintln!("The number of senses should be positive");
+            std::process::exit(1);
+        }
+        let warmup_tokens = matches
+            .value_of(WARMUP_TOKENS)
+            .map(|v| v.parse().or_exit("Cannot parse number of warmup tokens", 1))
+            .unwrap();
         let zipf_exponent = matches
             .value_of(ZIPF_EXPONENT)
             .map(|v| {
@@ -195,13 +567,159 @@ pub trait FinalfrontierApp {
         CommonConfig {
             loss: LossType::LogisticNegativeSampling,
             dims,
+            dropout,
             epochs,
+            freq_lr_scaling: matches.is_present(FREQ_LR_SCALING),
+            init_scale,
             lr,
+            lr_anchor,
+            max_examples_per_token,
+            max_ngrams_per_token,
+            max_token_len,
+            min_lr,
             negative_samples,
+            normalize_digits: matches.is_present(NORMALIZE_DIGITS),
+            output_dims,
+            positive_weight,
+            sampler,
+            senses,
+            warmup_tokens,
             zipf_exponent,
         }
     }
 
+    /// Get the output format from `matches`.
+    fn parse_output_format(matches: &ArgMatches) -> OutputFormat {
+        matches
+            .value_of(FORMAT)
+            .map(|v| OutputFormat::try_from_str(v).or_exit("Cannot parse output format", 1))
+            .unwrap()
+    }
+
+    /// Get the action for non-finite embeddings from `matches`.
+    fn parse_nan_guard(matches: &ArgMatches) -> Option<NanGuard> {
+        matches
+            .value_of(NAN_GUARD)
+            .map(|v| NanGuard::try_from_str(v).or_exit("Cannot parse NaN guard", 1))
+    }
+
+    /// Get whether per-dimension statistics should be printed.
+    fn parse_dim_stats(matches: &ArgMatches) -> bool {
+        matches.is_present(DIM_STATS)
+    }
+
+    /// Get the number of tokens to print in the displacement report.
+    fn parse_displacement_report(matches: &ArgMatches) -> Option<usize> {
+        matches.value_of(DISPLACEMENT_REPORT).map(|v| {
+            v.parse()
+                .or_exit("Cannot parse number of displacement report tokens", 1)
+        })
+    }
+
+    /// Get whether training statistics of every epoch should be printed.
+    fn parse_epoch_stats(matches: &ArgMatches) -> bool {
+        matches.is_present(EPOCH_STATS)
+    }
+
+    /// Get whether a gradient norm histogram should be collected.
+    fn parse_grad_hist(matches: &ArgMatches) -> bool {
+        matches.is_present(GRAD_HIST)
+    }
+
+    /// Get whether output files should be synchronized to disk.
+    fn parse_fsync(matches: &ArgMatches) -> bool {
+        matches.is_present(FSYNC)
+    }
+
+    /// Get whether untrained embeddings should be written as zeros.
+    fn parse_sparse_output(matches: &ArgMatches) -> bool {
+        matches.is_present(SPARSE_OUTPUT)
+    }
+
+    /// Get the composition of token embeddings from subwords.
+    fn parse_subword_composition(matches: &ArgMatches) -> SubwordComposition {
+        matches
+            .value_of(SUBWORD_COMPOSITION)
+            .map(|v| {
+                SubwordComposition::try_from_str(v).or_exit("Cannot parse subword composition", 1)
+            })
+            .unwrap()
+    }
+
+    /// Get the interval between progress reports from `matches`.
+    fn parse_report_interval(matches: &ArgMatches) -> ReportInterval {
+        let secs: f64 = matches
+            .value_of(REPORT_INTERVAL_SECS)
+            .map(|v| {
+                v.parse()
+                    .or_exit("Cannot parse report interval in seconds", 1)
+            })
+            .unwrap();
+        if !secs.is_finite() || secs < 0. {
+            eprintln!("The report interval should be non-negative: {}", secs);
+            std::process::exit(1);
+        }
+        let tokens = matches
+            .value_of(REPORT_INTERVAL_TOKENS)
+            .map(|v| {
+                v.parse()
+                    .or_exit("Cannot parse report interval in tokens", 1)
+            })
+            .unwrap();
+        if secs == 0. && tokens == 0 {
+            eprintln!("Report intervals in seconds and tokens cannot both be disabled");
+            std::process::exit(1);
+        }
+
+        ReportInterval {
+            tokens,
+            duration: Duration::from_secs_f64(secs),
+        }
+    }
+
+    /// Get the micro-batch size for input updates from `matches`.
+    fn parse_micro_batch(matches: &ArgMatches) -> usize {
+        let micro_batch = matches
+            .value_of(MICRO_BATCH)
+            .map(|v| v.parse().or_exit("Cannot parse micro-batch size", 1))
+            .unwrap();
+        if micro_batch == 0 {
+            eprintln!("The micro-batch size should be positive");
+            std::process::exit(1);
+        }
+
+        micro_batch
+    }
+
+    /// Get the path of the learning curve file from `matches`.
+    fn parse_curve_csv(matches: &ArgMatches) -> Option<String> {
+        matches.value_of(CURVE_CSV).map(ToOwned::to_owned)
+    }
+
+    /// Get the path of the embedding norms file from `matches`.
+    fn parse_write_norms(matches: &ArgMatches) -> Option<String> {
+        matches.value_of(WRITE_NORMS).map(ToOwned::to_owned)
+    }
+
+    /// Get the user-provided metadata from `matches`.
+    fn parse_metadata(matches: &ArgMatches) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        for key_value in matches.values_of(METADATA).into_iter().flatten() {
+            let mut parts = key_value.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if !key.is_empty() => {
+                    metadata.insert(key.to_owned(), value.to_owned());
+                }
+                _ => {
+                    eprintln!("Metadata should be of the form KEY=VALUE: {}", key_value);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        metadata
+    }
+
     /// Construct `SubwordVocabConfig` from `matches`.
     fn parse_vocab_config(matches: &ArgMatches) -> VocabConfig {
         let discard_threshold = matches
@@ -226,12 +744,16 @@ pub trait FinalfrontierApp {
                     .value_of(BUCKETS)
                     .map(|v| v.parse().or_exit("Cannot parse bucket exponent", 1))
                     .unwrap();
+                let hash = matches
+                    .value_of(HASH)
+                    .map(|v| SubwordHash::try_from_str(v).or_exit("Cannot parse subword hash", 1))
+                    .unwrap();
                 VocabConfig::SubwordVocab(SubwordVocabConfig {
                     discard_threshold,
                     min_count,
                     max_n,
                     min_n,
-                    indexer: BucketConfig { buckets_exp },
+                    indexer: BucketConfig { buckets_exp, hash },
                 })
             }
             "ngrams" => {
@@ -1,4 +1,8 @@
-use std::io::{Seek, Write};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{Cursor, Seek, Write};
+use std::mem;
 use std::sync::Arc;
 
 use failure::{err_msg, Error};
@@ -7,18 +11,32 @@ use finalfusion::metadata::Metadata;
 use finalfusion::norms::NdNorms;
 use finalfusion::prelude::{Embeddings, VocabWrap};
 use finalfusion::storage::NdArray;
-use ndarray::{Array1, Array2, ArrayView1, ArrayView2, ArrayViewMut1, Axis};
+use finalfusion::subword::Indexer;
+use finalfusion::vocab::{
+    SimpleVocab as FiFuSimpleVocab, SubwordVocab as FiFuSubwordVocab, Vocab as FiFuVocab,
+};
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2, ArrayViewMut1, Axis, Ix2};
+use ndarray_npy::WriteNpyExt;
+use ndarray_rand::rand::rngs::StdRng;
+use ndarray_rand::rand::{self, Rng, SeedableRng};
 use ndarray_rand::rand_distr::Uniform;
 use ndarray_rand::RandomExt;
 use serde::Serialize;
 use toml::Value;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
 
-use crate::hogwild::HogwildArray2;
+use crate::hogwild::{HogwildArray2, HogwildStorage, ReadOnlyHogwildArray};
 use crate::idx::WordIdx;
 use crate::io::TrainInfo;
+use crate::util::numeric::array_all_close;
 use crate::util::VersionInfo;
-use crate::vec_simd::{l2_normalize, scale, scaled_add};
-use crate::{CommonConfig, Vocab, WriteModelBinary};
+use crate::vec_simd::{dot, l2_normalize, scale, scaled_add};
+use crate::{
+    CommonConfig, CountedType, DimensionStats, Displacements, LookupKind, SubwordComposition,
+    SubwordVocab, SurfaceForms, TokenDisplacement, Vocab, WriteModelBinary, WriteModelNorms,
+    WriteModelNpz, WriteModelText, WriteModelWord2Vec,
+};
 
 /// Training model.
 ///
@@ -31,6 +49,17 @@ use crate::{CommonConfig, Vocab, WriteModelBinary};
 /// provides the logic to transform some input to an iterator of training
 /// examples.
 ///
+/// If the output dimensionality differs from the input dimensionality,
+/// the model also has a projection matrix, which maps input embeddings
+/// to the dimensionality of the output embeddings.
+///
+/// If `CommonConfig::senses` is larger than one, the model also has a
+/// matrix with the word embeddings of the additional senses of words.
+///
+/// A model that was trained on a lowercased corpus can have the surface
+/// forms of its words. The model is then written with every surface form
+/// of a word, using the word's embedding.
+///
 /// `TrainModel` stores the matrices as `HogwildArray`s to share parameters
 /// between clones of the same model. The trainer is also shared between
 /// clones due to memory considerations.
@@ -39,6 +68,13 @@ pub struct TrainModel<T> {
     trainer: T,
     input: HogwildArray2<f32>,
     output: HogwildArray2<f32>,
+    projection: Option<HogwildArray2<f32>>,
+    senses: Option<HogwildArray2<f32>>,
+    n_frozen_dims: usize,
+    pruned_subwords: Vec<usize>,
+    reset_rng: StdRng,
+    subword_composition: SubwordComposition,
+    surface_forms: Option<SurfaceForms>,
 }
 
 impl<T> From<T> for TrainModel<T>
@@ -48,42 +84,307 @@ where
     /// Construct a model from a Trainer.
     ///
     /// This randomly initializes the input and output matrices using a
-    /// uniform distribution in the range [-1/dims, 1/dims).
+    /// uniform distribution in the range [-1/dims, 1/dims], or in the
+    /// range [-init_scale, init_scale] if `CommonConfig::init_scale` is set.
     ///
     /// The number of rows of the input matrix is the vocabulary size
     /// plus the number of buckets for subword units. The number of rows
     /// of the output matrix is the number of possible outputs for the model.
+    /// If the trainer uses tied weights, the input matrix is also used as
+    /// the output matrix.
+    ///
+    /// If the output dimensionality differs from the input dimensionality,
+    /// the projection matrix is initialized to a (truncated) identity
+    /// matrix. The embeddings of additional word senses are initialized
+    /// like the input matrix.
     fn from(trainer: T) -> TrainModel<T> {
-        let config = *trainer.config();
-        let init_bound = 1.0 / config.dims as f32;
-        let distribution = Uniform::new_inclusive(-init_bound, init_bound);
+        Self::from_rng(trainer, &mut rand::thread_rng())
+    }
+}
 
-        let input = Array2::random(
-            (trainer.input_vocab().n_input_types(), config.dims as usize),
-            distribution,
-        )
-        .into();
-        let output = Array2::random(
-            (trainer.n_output_types(), config.dims as usize),
-            distribution,
+impl<T> TrainModel<T>
+where
+    T: Trainer,
+{
+    /// Construct a model from a Trainer with seeded initialization.
+    ///
+    /// This initializes the matrices in the same manner as
+    /// `TrainModel::from`, but draws the initial weights from a random
+    /// number generator with the given seed. Models that are constructed
+    /// with the same seed and vocabulary start from identical matrices
+    /// (using the same finalfrontier build). This is useful for training
+    /// models on shards of a corpus on different machines.
+    pub fn from_seed(trainer: T, seed: u64) -> TrainModel<T> {
+        Self::from_rng(trainer, &mut StdRng::seed_from_u64(seed))
+    }
+
+    /// Construct a model with the input matrix in a memory-mapped file.
+    ///
+    /// The model is initialized in the same manner as with
+    /// `TrainModel::from_seed`, with the same `seed` both models are
+    /// identical. However, the input matrix is stored in `input_file`, so
+    /// that the operating system can page out parts of the matrix. This
+    /// makes it possible to train models with input matrices that are
+    /// larger than the available memory. The caveats of
+    /// `HogwildArray::mmap_zeros` apply: training is much slower when the
+    /// matrix does not fit in the page cache.
+    ///
+    /// With tied weights, the output matrix is the input matrix and is
+    /// also stored in the file. When the model is written, the embeddings
+    /// are normalized in place and written from the mapped storage.
+    pub fn from_seed_mmap(
+        trainer: T,
+        seed: u64,
+        input_file: &File,
+    ) -> Result<TrainModel<T>, Error> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let distribution = init_distribution(trainer.config());
+
+        let mut input = HogwildArray2::mmap_zeros(
+            input_file,
+            Ix2(
+                trainer.input_vocab().n_input_types(),
+                trainer.config().dims as usize,
+            ),
+        )?;
+        input
+            .view_mut()
+            .map_inplace(|v| *v = rng.sample(distribution));
+
+        Ok(Self::from_input(trainer, input, &mut rng))
+    }
+
+    fn from_rng<R>(trainer: T, rng: &mut R) -> TrainModel<T>
+    where
+        R: Rng,
+    {
+        let input = Array2::random_using(
+            (
+                trainer.input_vocab().n_input_types(),
+                trainer.config().dims as usize,
+            ),
+            init_distribution(trainer.config()),
+            rng,
         )
         .into();
+
+        Self::from_input(trainer, input, rng)
+    }
+
+    /// Construct a model from an initialized input matrix.
+    ///
+    /// The output matrix is initialized with `rng`.
+    fn from_input<R>(trainer: T, input: HogwildArray2<f32>, rng: &mut R) -> TrainModel<T>
+    where
+        R: Rng,
+    {
+        let config = *trainer.config();
+        assert!(config.senses > 0, "Words should have at least one sense");
+        let distribution = init_distribution(&config);
+        let output = if trainer.tied_weights() {
+            assert!(
+                trainer.n_output_types() <= trainer.input_vocab().n_input_types(),
+                "Tied weights require that every output has an input row"
+            );
+            assert_eq!(
+                config.dims, config.output_dims,
+                "Tied weights require that the input and output dimensionality are equal"
+            );
+            input.clone()
+        } else {
+            Array2::random_using(
+                (trainer.n_output_types(), config.output_dims as usize),
+                distribution,
+                rng,
+            )
+            .into()
+        };
+        let projection = if config.dims != config.output_dims {
+            Some(
+                Array2::eye(config.output_dims.max(config.dims) as usize)
+                    .slice_move(s![..config.output_dims as usize, ..config.dims as usize])
+                    .into(),
+            )
+        } else {
+            None
+        };
+        let senses = if config.senses > 1 {
+            Some(
+                Array2::random_using(
+                    (
+                        trainer.input_vocab().len() * (config.senses as usize - 1),
+                        config.dims as usize,
+                    ),
+                    distribution,
+                    rng,
+                )
+                .into(),
+            )
+        } else {
+            None
+        };
         TrainModel {
             trainer,
             input,
             output,
+            projection,
+            senses,
+            n_frozen_dims: 0,
+            pruned_subwords: Vec::new(),
+            reset_rng: StdRng::seed_from_u64(rng.gen()),
+            subword_composition: SubwordComposition::Mean,
+            surface_forms: None,
         }
     }
-}
 
-impl<T> TrainModel<T>
-where
-    T: Trainer,
-{
     /// Get the model configuration.
     pub fn config(&self) -> &CommonConfig {
         &self.trainer.config()
     }
+
+    /// Get the number of senses per word.
+    pub fn n_senses(&self) -> usize {
+        self.config().senses as usize
+    }
+
+    /// Get the number of learned dimensions of input embeddings.
+    ///
+    /// These are the dimensions before the side features, see
+    /// `TrainModel::set_side_features`.
+    pub fn learned_dims(&self) -> usize {
+        self.config().dims as usize - self.n_frozen_dims
+    }
+
+    /// Set fixed side features of words.
+    ///
+    /// The side features of a word are stored in the last dimensions of
+    /// its word row in the input matrix, so that the learned embedding and
+    /// the features are concatenated. The feature dimensions of subword
+    /// rows are set to zero, so that the features of a token are scaled
+    /// like the rest of its mean input embedding. The features are not
+    /// changed when rows are reset or set to zero.
+    ///
+    /// `features` must contain a row for every word in the input
+    /// vocabulary, indexed by word index, and it must have fewer columns
+    /// than the model has dimensions.
+    pub fn set_side_features(&mut self, features: ArrayView2<f32>) {
+        let n_words = self.input_vocab().len();
+        assert_eq!(
+            features.nrows(),
+            n_words,
+            "Number of feature rows does not match the vocabulary size"
+        );
+        let dims = self.config().dims as usize;
+        assert!(
+            features.ncols() < dims,
+            "The side features should have fewer dimensions than the model"
+        );
+
+        let n_rows = self.input.view().nrows();
+        let learned_dims = dims - features.ncols();
+        for idx in 0..n_rows {
+            let mut embed = self.input_embedding_mut(idx);
+            let mut embed_features = embed.slice_mut(s![learned_dims..]);
+            if idx < n_words {
+                embed_features.assign(&features.row(idx));
+            } else {
+                embed_features.fill(0.);
+            }
+        }
+
+        // The other senses of a word have the same features.
+        for word_idx in 0..n_words {
+            for sense in 1..self.n_senses() {
+                let idx = self.sense_input_idx(word_idx, sense);
+                self.input_embedding_mut(idx)
+                    .slice_mut(s![learned_dims..])
+                    .assign(&features.row(word_idx));
+            }
+        }
+
+        self.n_frozen_dims = features.ncols();
+    }
+
+    /// Get the input index of the word embedding of a word sense.
+    ///
+    /// The first sense of a word is its word embedding in the input
+    /// matrix. The embeddings of other senses are stored in a separate
+    /// matrix, their indices follow the rows of the input matrix, so that
+    /// they can be updated like input embeddings.
+    pub(crate) fn sense_input_idx(&self, word_idx: usize, sense: usize) -> usize {
+        assert!(sense < self.n_senses(), "Sense out of range: {}", sense);
+        if sense == 0 {
+            word_idx
+        } else {
+            self.input.view().nrows() + word_idx * (self.n_senses() - 1) + sense - 1
+        }
+    }
+
+    /// Get the mean input embedding of the given indices with a word sense.
+    ///
+    /// This is the mean input embedding, where the word embedding is
+    /// replaced by the embedding of the given sense of the word.
+    pub(crate) fn mean_sense_embedding<'a, I>(&self, idx: &'a I, sense: usize) -> Array1<f32>
+    where
+        I: WordIdx,
+        &'a I: IntoIterator<Item = u64>,
+    {
+        let mut embed = self.mean_input_embedding(idx);
+        if sense > 0 {
+            let word_idx = idx.word_idx() as usize;
+            let sense_idx = self.sense_input_idx(word_idx, sense) - self.input.view().nrows();
+            let senses = self.senses.as_ref().unwrap().view();
+            let input = self.input.view();
+            let scale = match self.subword_composition {
+                SubwordComposition::Mean => 1. / idx.len() as f32,
+                SubwordComposition::Sum => 1.,
+            };
+            scaled_add(embed.view_mut(), senses.row(sense_idx), scale);
+            scaled_add(embed.view_mut(), input.row(word_idx), -scale);
+        }
+
+        embed
+    }
+
+    /// Find input embeddings with non-finite values.
+    ///
+    /// Returns the indices of the input matrix rows that contain NaN or
+    /// infinite values. The embeddings of additional word senses are
+    /// checked as well, their indices follow the rows of the input matrix
+    /// (see `sense_input_idx`). If `reset` is `true`, the learned
+    /// dimensions of these rows are initialized anew, using the same
+    /// distribution as `TrainModel::from`. The random number generator for
+    /// resets is seeded from the model's initialization, so that resets of
+    /// models constructed with `TrainModel::from_seed` are reproducible.
+    ///
+    /// With Hogwild training, a non-finite value in one embedding spreads
+    /// to other embeddings through gradients. This check can be used to
+    /// detect such values early.
+    pub fn check_finite_inputs(&mut self, reset: bool) -> Vec<usize> {
+        let input = self.input.view();
+        let senses = self.senses.as_ref().map(HogwildArray2::view);
+        let non_finite = input
+            .outer_iter()
+            .chain(senses.iter().flat_map(|senses| senses.outer_iter()))
+            .enumerate()
+            .filter(|(_, row)| row.iter().any(|v| !v.is_finite()))
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+
+        if reset {
+            let learned_dims = self.learned_dims();
+            let distribution = init_distribution(self.config());
+            let mut rng = self.reset_rng.clone();
+            for &idx in &non_finite {
+                self.input_embedding_mut(idx)
+                    .slice_mut(s![..learned_dims])
+                    .assign(&Array1::random_using(learned_dims, distribution, &mut rng));
+            }
+            self.reset_rng = rng;
+        }
+
+        non_finite
+    }
 }
 
 impl<V, T> TrainModel<T>
@@ -95,15 +396,412 @@ where
     pub fn input_vocab(&self) -> &V {
         self.trainer.input_vocab()
     }
+
+    /// Returns `true` if this model is approximately equal to `other`.
+    ///
+    /// Two models are approximately equal when their input vocabularies
+    /// have the same words in the same order and the elements of their
+    /// input, output and projection matrices differ by at most `eps`.
+    pub fn approx_eq(&self, other: &Self, eps: f32) -> bool {
+        let same_vocab = self
+            .input_vocab()
+            .types()
+            .iter()
+            .map(CountedType::label)
+            .eq(other.input_vocab().types().iter().map(CountedType::label));
+
+        let same_projection = match (self.projection(), other.projection()) {
+            (Some(projection), Some(other_projection)) => {
+                array_all_close(projection.view(), other_projection.view(), eps)
+            }
+            (None, None) => true,
+            _ => false,
+        };
+
+        same_vocab
+            && same_projection
+            && array_all_close(self.input.view(), other.input.view(), eps)
+            && array_all_close(self.output.view(), other.output.view(), eps)
+    }
+}
+
+impl<V, T> PartialEq for TrainModel<T>
+where
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+{
+    /// Models are equal when they are approximately equal with *eps = 0*.
+    fn eq(&self, other: &Self) -> bool {
+        self.approx_eq(other, 0.)
+    }
+}
+
+impl<V, T> TrainModel<T>
+where
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+    V::VocabType: Borrow<str> + ToString,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    /// Answer the analogy *a* is to *b* as *c* is to *?*.
+    ///
+    /// Returns the `k` vocabulary words whose input embeddings are most
+    /// similar to `b - a + c`, together with their cosine similarities.
+    /// Embeddings are l2-normalized before computing the query, the query
+    /// words themselves are excluded from the results.
+    pub fn analogy(
+        &self,
+        a: &str,
+        b: &str,
+        c: &str,
+        k: usize,
+    ) -> Result<Vec<(String, f32)>, Error> {
+        let mut query = self.normalized_embedding(b)?;
+        scaled_add(query.view_mut(), self.normalized_embedding(a)?.view(), -1.0);
+        scaled_add(query.view_mut(), self.normalized_embedding(c)?.view(), 1.0);
+
+        Ok(self.nearest(query, &[a, b, c], k))
+    }
+
+    /// Get the `k` words that are most similar to `word`.
+    ///
+    /// The words are ranked by the cosine similarity of their input
+    /// embeddings, `word` itself is excluded from the results. Words with
+    /// the same similarity are ordered by their vocabulary index.
+    pub fn similarity(&self, word: &str, k: usize) -> Result<Vec<(String, f32)>, Error> {
+        let query = self.normalized_embedding(word)?;
+        Ok(self.nearest(query, &[word], k))
+    }
+
+    /// Get how `token` is resolved when its embedding is looked up.
+    ///
+    /// A token is resolved as a word if it is in the vocabulary. Otherwise,
+    /// the embedding of the token is composed from its subwords, if the
+    /// vocabulary has subwords and the token has at least one known
+    /// subword. Other tokens are unknown.
+    pub fn lookup_kind(&self, token: &str) -> LookupKind {
+        let vocab = self.input_vocab();
+        if vocab.idx(token).is_some() {
+            LookupKind::Word
+        } else if vocab.has_subwords(token) {
+            LookupKind::Subwords
+        } else {
+            LookupKind::Unknown
+        }
+    }
+
+    /// Get the l2-normalized input embedding of a vocabulary word.
+    fn normalized_embedding(&self, word: &str) -> Result<Array1<f32>, Error> {
+        let idx = self
+            .input_vocab()
+            .idx(word)
+            .ok_or_else(|| crate::Error::Vocab(word.to_owned()))?;
+        let mut embed = self.mean_input_embedding(&idx);
+        normalize(embed.view_mut());
+        Ok(embed)
+    }
+
+    /// Get the `k` vocabulary words that are most similar to `query`.
+    ///
+    /// Ties are broken by the vocabulary index, so that the results are
+    /// reproducible for a given model and vocabulary.
+    fn nearest(&self, mut query: Array1<f32>, skip: &[&str], k: usize) -> Vec<(String, f32)> {
+        normalize(query.view_mut());
+
+        let vocab = self.input_vocab();
+        let mut results = Vec::with_capacity(vocab.len());
+        for (word_idx, word) in vocab.types().iter().enumerate() {
+            let label = word.label().borrow();
+            if skip.contains(&label) {
+                continue;
+            }
+
+            let idx = vocab.idx(label).unwrap();
+            let mut embed = self.mean_input_embedding(&idx);
+            normalize(embed.view_mut());
+            results.push((
+                word_idx,
+                word.label().to_string(),
+                dot(query.view(), embed.view()),
+            ));
+        }
+
+        results.sort_by(|(idx1, _, sim1), (idx2, _, sim2)| {
+            sim2.partial_cmp(sim1)
+                .unwrap_or(Ordering::Equal)
+                .then(idx1.cmp(idx2))
+        });
+        results.truncate(k);
+
+        results
+            .into_iter()
+            .map(|(_, word, sim)| (word, sim))
+            .collect()
+    }
+
+    /// Get the embedding of a sense of a word.
+    ///
+    /// For subword vocabularies, the embedding is composed from the word
+    /// embedding of the sense and the word's subwords. Returns `None` if
+    /// the word is not in the vocabulary or `sense` is not smaller than
+    /// the number of senses.
+    pub fn sense_embedding(&self, word: &str, sense: usize) -> Option<Array1<f32>> {
+        if sense >= self.n_senses() {
+            return None;
+        }
+
+        self.input_vocab()
+            .idx(word)
+            .map(|idx| self.mean_sense_embedding(&idx, sense))
+    }
+
+    /// Get the input embeddings of all words.
+    ///
+    /// The rows are indexed by word index. For subword vocabularies, the
+    /// embedding of a word is composed from the word and its subwords.
+    pub fn input_word_embeddings(&self) -> Array2<f32> {
+        let vocab = self.input_vocab();
+        let mut embeds = Array2::zeros((vocab.len(), self.config().dims as usize));
+        for (word, mut row) in vocab.types().iter().zip(embeds.outer_iter_mut()) {
+            let idx = vocab.idx(word.label().borrow()).unwrap();
+            row.assign(&self.mean_input_embedding(&idx));
+        }
+
+        embeds
+    }
+
+    /// Rank words by the displacement of their input embeddings.
+    ///
+    /// `init_embeds` contains the input embeddings of the words before
+    /// training, as returned by `input_word_embeddings`.
+    pub fn displacements(&self, init_embeds: ArrayView2<f32>) -> Displacements {
+        let vocab = self.input_vocab();
+        assert_eq!(
+            init_embeds.nrows(),
+            vocab.len(),
+            "Number of initial embeddings does not match the vocabulary size"
+        );
+
+        let embeds = self.input_word_embeddings();
+        let displacements = vocab
+            .types()
+            .iter()
+            .zip(init_embeds.outer_iter().zip(embeds.outer_iter()))
+            .map(|(word, (init_embed, embed))| {
+                TokenDisplacement::new(word.label().to_string(), init_embed, embed)
+            })
+            .collect();
+
+        Displacements::new(displacements)
+    }
+}
+
+impl<C, I, T> TrainModel<T>
+where
+    C: Copy + Clone,
+    I: Indexer,
+    T: Trainer<InputVocab = SubwordVocab<C, I>>,
+{
+    /// Compose the embedding of a token from its subwords.
+    ///
+    /// The embedding is the mean (or sum, see `set_subword_composition`)
+    /// of the token's n-gram embeddings and, if the token is in the
+    /// vocabulary, its word embedding. This is the composition that
+    /// finalfusion uses for lookups, up to finalfusion's l2 normalization.
+    /// Tokens without known n-grams have a zero vector as their embedding.
+    /// Pruned n-grams (see `prune_subwords`) have zero embeddings in the
+    /// composition of unknown tokens.
+    pub fn compose_subword(&self, token: &str) -> Array1<f32> {
+        let vocab = self.input_vocab();
+        let mut indices = vocab
+            .ngram_indices(token)
+            .into_iter()
+            .map(|(_, idx)| idx as usize + vocab.len())
+            .collect::<Vec<_>>();
+        let in_vocab = vocab.idx(token);
+        if let Some(ref idx) = in_vocab {
+            indices.push(idx.word_idx() as usize);
+        }
+
+        let mut embed = Array1::zeros((self.config().dims as usize,));
+        if indices.is_empty() {
+            return embed;
+        }
+
+        let input = self.input.view();
+        for &idx in &indices {
+            if in_vocab.is_none() && self.pruned_subwords.binary_search(&idx).is_ok() {
+                continue;
+            }
+            scaled_add(embed.view_mut(), input.index_axis(Axis(0), idx), 1.0);
+        }
+        if self.subword_composition == SubwordComposition::Mean {
+            scale(embed.view_mut(), 1.0 / indices.len() as f32);
+        }
+
+        embed
+    }
+
+    /// Get the cosine similarities of token pairs.
+    ///
+    /// The embeddings of both tokens are composed from their subwords (see
+    /// `compose_subword`), so that the tokens do not have to be in the
+    /// vocabulary. The similarity of a pair is `None` if either token is
+    /// unknown, that is, not in the vocabulary and without known subwords,
+    /// or if the embedding of either token is a zero vector, e.g. because
+    /// its subwords were pruned.
+    pub fn similarities(&self, pairs: &[(String, String)]) -> Vec<Option<f32>> {
+        let vocab = self.input_vocab();
+        let embedding = |token: &str| {
+            if vocab.idx(token).is_none() && !vocab.has_subwords(token) {
+                return None;
+            }
+
+            let mut embed = self.compose_subword(token);
+            if dot(embed.view(), embed.view()) == 0. {
+                return None;
+            }
+            l2_normalize(embed.view_mut());
+            Some(embed)
+        };
+
+        pairs
+            .iter()
+            .map(|(token1, token2)| {
+                let embed1 = embedding(token1)?;
+                let embed2 = embedding(token2)?;
+                Some(dot(embed1.view(), embed2.view()))
+            })
+            .collect()
+    }
+
+    /// Prune the subword embeddings that are rarely used.
+    ///
+    /// The subword embeddings with a count below `min_count` are written
+    /// as zeros, see `SubwordVocab::rare_subword_indices` for the counts.
+    /// Such embeddings are only trained with the few occurrences of rare
+    /// words and mostly add noise to the embeddings of unknown tokens.
+    ///
+    /// The embeddings of vocabulary words are computed before pruning and
+    /// do not change. However, pruning changes the embeddings of unknown
+    /// tokens, since they are composed from the remaining subwords.
+    /// Returns the number of pruned subword embeddings.
+    pub fn prune_subwords(&mut self, min_count: usize) -> usize {
+        self.pruned_subwords = self
+            .input_vocab()
+            .rare_subword_indices(min_count)
+            .into_iter()
+            .map(|idx| idx as usize)
+            .collect();
+        self.pruned_subwords.len()
+    }
 }
 
 impl<T> TrainModel<T> {
+    /// Construct a model from a trainer and input/output matrices.
+    #[cfg(test)]
+    pub(crate) fn from_parts(
+        trainer: T,
+        input: HogwildArray2<f32>,
+        output: HogwildArray2<f32>,
+    ) -> TrainModel<T> {
+        TrainModel {
+            trainer,
+            input,
+            output,
+            projection: None,
+            senses: None,
+            n_frozen_dims: 0,
+            pruned_subwords: Vec::new(),
+            reset_rng: StdRng::seed_from_u64(0),
+            subword_composition: SubwordComposition::Mean,
+            surface_forms: None,
+        }
+    }
+
+    /// Set the surface forms of the vocabulary words.
+    ///
+    /// The model is written with all surface forms of a word, words without
+    /// surface forms are written as is. This should be set after training,
+    /// since clones of the model do not share the surface forms.
+    pub fn set_surface_forms(&mut self, surface_forms: SurfaceForms) {
+        self.surface_forms = Some(surface_forms);
+    }
+
+    /// Set the composition of token embeddings from subwords.
+    ///
+    /// The composition is used for the input embeddings in training, for
+    /// the written word embeddings and by `compose_subword`. It is recorded
+    /// in the metadata of models with subwords, so that consumers of a
+    /// model can compose embeddings of unknown tokens in the same manner.
+    /// This should be set before training. The default composition is
+    /// `SubwordComposition::Mean`.
+    pub fn set_subword_composition(&mut self, composition: SubwordComposition) {
+        self.subword_composition = composition;
+    }
+
+    /// Get the composition of token embeddings from subwords.
+    pub fn subword_composition(&self) -> SubwordComposition {
+        self.subword_composition
+    }
+
+    /// Compute per-dimension statistics of the input matrix.
+    ///
+    /// The statistics are computed over all rows of the input matrix,
+    /// including the subword buckets of subword vocabularies.
+    pub fn input_dimension_stats(&self) -> DimensionStats {
+        DimensionStats::from_matrix(self.input.view())
+    }
+
+    /// Get a read-only handle to the input matrix.
+    ///
+    /// The handle shares the matrix with this model, so it can be used
+    /// to evaluate or checkpoint the embeddings from another thread while
+    /// training continues.
+    pub fn input_matrix(&self) -> ReadOnlyHogwildArray<f32, Ix2> {
+        self.input.freeze()
+    }
+
+    /// Set input embeddings that were never updated to zero.
+    ///
+    /// `update_counts` contains the number of updates of every input row,
+    /// see `SGD::track_input_updates`. Rows without updates still have
+    /// their random initialization, writing them as zeros makes the
+    /// output compress better. Only the learned dimensions are set to
+    /// zero, side features (see `TrainModel::set_side_features`) are
+    /// kept. Returns the number of rows that were set to zero.
+    pub fn zero_untrained_inputs(&mut self, update_counts: &[u64]) -> usize {
+        assert_eq!(
+            update_counts.len(),
+            self.input.view().nrows(),
+            "Number of update counts does not match the number of input rows"
+        );
+
+        let learned_dims = self.input.view().ncols() - self.n_frozen_dims;
+        let mut n_zeroed = 0;
+        for (idx, _) in update_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count == 0)
+        {
+            self.input_embedding_mut(idx)
+                .slice_mut(s![..learned_dims])
+                .fill(0.);
+            n_zeroed += 1;
+        }
+
+        n_zeroed
+    }
+
     /// Get this model's trainer mutably.
     pub fn trainer(&mut self) -> &mut T {
         &mut self.trainer
     }
 
     /// Get the mean input embedding of the given indices.
+    ///
+    /// The embedding is the sum of the embeddings instead when the
+    /// subword composition is `SubwordComposition::Sum`.
     pub(crate) fn mean_input_embedding<'a, I>(&self, idx: &'a I) -> Array1<f32>
     where
         I: WordIdx,
@@ -115,12 +813,16 @@ impl<T> TrainModel<T> {
                 .row(idx.into_iter().next().unwrap() as usize)
                 .to_owned()
         } else {
-            Self::mean_embedding(self.input.view(), idx)
+            Self::mean_embedding(self.input.view(), idx, self.subword_composition)
         }
     }
 
-    /// Get the mean input embedding of the given indices.
-    fn mean_embedding<'a, I>(embeds: ArrayView2<f32>, indices: &'a I) -> Array1<f32>
+    /// Get the mean (or sum) input embedding of the given indices.
+    fn mean_embedding<'a, I>(
+        embeds: ArrayView2<f32>,
+        indices: &'a I,
+        composition: SubwordComposition,
+    ) -> Array1<f32>
     where
         I: WordIdx,
         &'a I: IntoIterator<Item = u64>,
@@ -135,7 +837,9 @@ impl<T> TrainModel<T> {
             );
         }
 
-        scale(embed.view_mut(), 1.0 / len as f32);
+        if composition == SubwordComposition::Mean {
+            scale(embed.view_mut(), 1.0 / len as f32);
+        }
 
         embed
     }
@@ -148,18 +852,62 @@ impl<T> TrainModel<T> {
     }
 
     /// Get the input embedding with the given index mutably.
+    ///
+    /// Indices beyond the input matrix are embeddings of word senses, see
+    /// `sense_input_idx`.
     #[inline]
     pub(crate) fn input_embedding_mut(&mut self, idx: usize) -> ArrayViewMut1<f32> {
+        if let Some(ref mut senses) = self.senses {
+            let n_rows = self.input.view().nrows();
+            if idx >= n_rows {
+                return senses.subview_mut(Axis(0), idx - n_rows);
+            }
+        }
+
         self.input.subview_mut(Axis(0), idx)
     }
 
-    pub(crate) fn into_parts(self) -> Result<(T, Array2<f32>), Error> {
-        let input = match Arc::try_unwrap(self.input.into_inner()) {
+    /// Destruct the model into the trainer and the input matrix.
+    ///
+    /// The output matrix is only needed for training and is freed, so that
+    /// it does not add to the memory use while the model is written. The
+    /// input matrix is returned as is, a memory-mapped matrix is not copied
+    /// into memory.
+    pub(crate) fn into_parts(self) -> Result<(T, HogwildStorage<f32, Ix2>), Error> {
+        let TrainModel {
+            trainer,
+            input,
+            output,
+            projection,
+            senses,
+            n_frozen_dims: _,
+            pruned_subwords: _,
+            reset_rng: _,
+            subword_composition: _,
+            surface_forms: _,
+        } = self;
+
+        // Drop the output matrix first, with tied weights it shares the
+        // input matrix.
+        drop(output);
+        drop(projection);
+        drop(senses);
+
+        let input = match Arc::try_unwrap(input.into_inner()) {
             Ok(input) => input.into_inner(),
             Err(_) => return Err(err_msg("Cannot unwrap input matrix.")),
         };
 
-        Ok((self.trainer, input))
+        Ok((trainer, input))
+    }
+
+    /// Get the projection from input to output embeddings.
+    ///
+    /// The projection matrix has the shape *(output_dims, dims)*. Returns
+    /// `None` when the input and output dimensionality are equal.
+    #[inline]
+    pub(crate) fn projection(&self) -> Option<&HogwildArray2<f32>> {
+        self.projection.as_ref()
     }
 
     /// Get the output embedding with the given index.
@@ -175,6 +923,46 @@ impl<T> TrainModel<T> {
     }
 }
 
+impl<V, T> TrainModel<T>
+where
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+    V::VocabType: ToString,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    /// Get the embedding of a sense of the word with the given index.
+    fn word_sense_embedding(&self, word_idx: usize, sense: usize) -> Array1<f32> {
+        let vocab = self.input_vocab();
+        let idx = vocab.idx(vocab.types()[word_idx].label()).unwrap();
+        self.mean_sense_embedding(&idx, sense)
+    }
+
+    /// Get the l2-normalized embeddings of the additional word senses.
+    ///
+    /// Returns the written forms of the senses (see `sense_forms`), their
+    /// normalized embeddings, and the norms of the embeddings.
+    fn normalized_sense_embeddings(&self) -> (Vec<String>, Array2<f32>, Vec<f32>) {
+        let forms = written_forms(self.input_vocab(), self.surface_forms.as_ref());
+        let senses = sense_forms(&forms, self.n_senses());
+
+        let mut matrix = Array2::zeros((senses.len(), self.config().dims as usize));
+        let mut norms = vec![0f32; senses.len()];
+        let mut sense_forms = Vec::with_capacity(senses.len());
+        for ((mut row, norm), (form, word, sense)) in
+            matrix.outer_iter_mut().zip(&mut norms).zip(senses)
+        {
+            let mut embed = self.word_sense_embedding(word, sense);
+            if dot(embed.view(), embed.view()) > 0. {
+                *norm = l2_normalize(embed.view_mut());
+            }
+            row.assign(&embed);
+            sense_forms.push(form);
+        }
+
+        (sense_forms, matrix, norms)
+    }
+}
+
 impl<W, T, V, M> WriteModelBinary<W> for TrainModel<T>
 where
     W: Seek + Write,
@@ -184,8 +972,13 @@ where
     for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
     M: Serialize,
 {
-    fn write_model_binary(self, write: &mut W, mut train_info: TrainInfo) -> Result<(), Error> {
-        let (trainer, mut input_matrix) = self.into_parts()?;
+    fn write_model_binary(mut self, write: &mut W, mut train_info: TrainInfo) -> Result<(), Error> {
+        let (sense_forms, sense_matrix, sense_norms) = self.normalized_sense_embeddings();
+        let surface_forms = self.surface_forms.take();
+        let pruned_subwords = mem::take(&mut self.pruned_subwords);
+        let subword_composition = self.subword_composition;
+        let (trainer, mut input_storage) = self.into_parts()?;
+        let mut input_matrix = input_storage.view_mut();
         let mut metadata = Value::try_from(trainer.to_metadata())?;
         let build_info = Value::try_from(VersionInfo::new())?;
         let metadata_table = metadata
@@ -193,8 +986,26 @@ where
             .ok_or_else(|| err_msg("Metadata has to be 'Table'."))?;
         metadata_table.insert("version_info".to_string(), build_info);
         train_info.set_end();
+        let user_metadata = train_info.metadata().clone();
         let train_info = Value::try_from(train_info)?;
         metadata_table.insert("training_info".to_string(), train_info);
+        if trainer.input_vocab().n_input_types() > trainer.input_vocab().len() {
+            metadata_table.insert(
+                "subword_composition".to_string(),
+                Value::try_from(subword_composition)?,
+            );
+        }
+
+        // Merge user-provided metadata, without replacing training metadata.
+        for (key, value) in user_metadata {
+            if metadata_table.contains_key(&key) {
+                return Err(err_msg(format!(
+                    "Metadata key '{}' is used by the training metadata",
+                    key
+                )));
+            }
+            metadata_table.insert(key, Value::String(value));
+        }
 
         // Compute and write word embeddings.
         let mut norms = vec![0f32; trainer.input_vocab().len()];
@@ -205,37 +1016,244 @@ where
             .enumerate()
         {
             let input = trainer.input_vocab().idx(word.label()).unwrap();
-            let mut embed = Self::mean_embedding(input_matrix.view(), &input);
-            *norm = l2_normalize(embed.view_mut());
+            let mut embed = Self::mean_embedding(input_matrix.view(), &input, subword_composition);
+            // Words without trained rows can have all-zero embeddings.
+            if dot(embed.view(), embed.view()) > 0. {
+                *norm = l2_normalize(embed.view_mut());
+            }
             input_matrix.index_axis_mut(Axis(0), i).assign(&embed);
         }
 
+        // Prune subwords after computing the word embeddings, so that
+        // only the embeddings of unknown words change.
+        for idx in pruned_subwords {
+            input_matrix.index_axis_mut(Axis(0), idx).fill(0.);
+        }
+
+        // Rows with an index past the input matrix are sense rows.
+        let n_rows = input_matrix.nrows();
         let vocab: VocabWrap = trainer.try_into_input_vocab()?.into();
-        let storage = NdArray::new(input_matrix);
-        let norms = NdNorms::new(Array1::from(norms));
+        let (vocab, mut rows, mut norms) = match surface_forms {
+            Some(surface_forms) => expand_surface_forms(vocab, n_rows, &norms, &surface_forms),
+            None => (vocab, (0..n_rows).collect(), norms),
+        };
+        let vocab = if sense_forms.is_empty() {
+            vocab
+        } else {
+            norms.extend(sense_norms);
+            append_words(vocab, &mut rows, sense_forms, n_rows)
+        };
 
-        Embeddings::new(Some(Metadata::new(metadata)), vocab, storage, norms)
-            .write_embeddings(write)
-            .map_err(|err| err.into())
+        let rows = rows.into_iter().map(|idx| {
+            if idx < n_rows {
+                input_matrix.index_axis(Axis(0), idx)
+            } else {
+                sense_matrix.index_axis(Axis(0), idx - n_rows)
+            }
+        });
+        let dims = input_matrix.ncols();
+        write_embeddings_rows(write, Metadata::new(metadata), vocab, dims, rows, norms)
     }
 }
 
-/// Trainer Trait.
-pub trait Trainer {
-    type InputVocab: Vocab;
-    type Metadata;
+impl<W, T, V> WriteModelText<W> for TrainModel<T>
+where
+    W: Write,
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+    V::VocabType: ToString,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    fn write_model_text(&self, write: &mut W, write_dims: bool) -> Result<(), Error> {
+        let vocab = self.input_vocab();
+        let forms = written_forms(vocab, self.surface_forms.as_ref());
 
-    /// Get the trainer's input vocabulary.
-    fn input_vocab(&self) -> &Self::InputVocab;
+        let senses = sense_forms(&forms, self.n_senses());
 
-    /// Destruct the trainer and get the input vocabulary.
-    fn try_into_input_vocab(self) -> Result<Self::InputVocab, Error>;
+        if write_dims {
+            let n_forms = forms.iter().map(Vec::len).sum::<usize>() + senses.len();
+            writeln!(write, "{} {}", n_forms, self.config().dims)?;
+        }
 
-    /// Get the number of possible input types.
-    ///
-    /// In a model with subword units this value is calculated as:
-    /// `2^n_buckets + input_vocab.len()`.
-    fn n_input_types(&self) -> usize;
+        for (word, forms) in vocab.types().iter().zip(forms) {
+            let input = vocab.idx(word.label()).unwrap();
+            let embed = self.mean_input_embedding(&input);
+            let components = embed.iter().map(ToString::to_string).collect::<Vec<_>>();
+            let components = components.join(" ");
+            for form in forms {
+                writeln!(write, "{} {}", form, components)?;
+            }
+        }
+
+        for (form, word, sense) in senses {
+            let embed = self.word_sense_embedding(word, sense);
+            let components = embed.iter().map(ToString::to_string).collect::<Vec<_>>();
+            writeln!(write, "{} {}", form, components.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W, T, V> WriteModelNpz<W> for TrainModel<T>
+where
+    W: Write + Seek,
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+    V::VocabType: ToString,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    fn write_model_npz<VW>(&self, write: &mut W, vocab_write: &mut VW) -> Result<(), Error>
+    where
+        VW: Write,
+    {
+        let vocab = self.input_vocab();
+        let forms = written_forms(vocab, self.surface_forms.as_ref());
+
+        let senses = sense_forms(&forms, self.n_senses());
+
+        let n_forms = forms.iter().map(Vec::len).sum::<usize>() + senses.len();
+        let mut embeddings = Array2::zeros((n_forms, self.config().dims as usize));
+        let mut rows = embeddings.outer_iter_mut();
+        for (word, forms) in vocab.types().iter().zip(forms) {
+            let input = vocab.idx(word.label()).unwrap();
+            let embed = self.mean_input_embedding(&input);
+            for form in forms {
+                rows.next().unwrap().assign(&embed);
+                writeln!(vocab_write, "{}", form)?;
+            }
+        }
+        for (form, word, sense) in senses {
+            rows.next()
+                .unwrap()
+                .assign(&self.word_sense_embedding(word, sense));
+            writeln!(vocab_write, "{}", form)?;
+        }
+
+        // NpzWriter only finalizes the archive when it is dropped, which
+        // ignores errors. Write the archive directly, so that errors in
+        // finalizing it are returned.
+        let mut npz = ZipWriter::new(&mut *write);
+        npz.start_file(
+            "embeddings.npy",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        embeddings.write_npy(&mut npz)?;
+        npz.finish()?.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<W, T, V> WriteModelNorms<W> for TrainModel<T>
+where
+    W: Write,
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+    V::VocabType: ToString,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    fn write_model_norms(&self, write: &mut W) -> Result<(), Error> {
+        let vocab = self.input_vocab();
+        let forms = written_forms(vocab, self.surface_forms.as_ref());
+
+        let senses = sense_forms(&forms, self.n_senses());
+
+        for (word, forms) in vocab.types().iter().zip(forms) {
+            let input = vocab.idx(word.label()).unwrap();
+            let embed = self.mean_input_embedding(&input);
+            let norm = dot(embed.view(), embed.view()).sqrt();
+            for form in forms {
+                writeln!(write, "{}\t{}", form, norm)?;
+            }
+        }
+
+        for (form, word, sense) in senses {
+            let embed = self.word_sense_embedding(word, sense);
+            writeln!(
+                write,
+                "{}\t{}",
+                form,
+                dot(embed.view(), embed.view()).sqrt()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W, T, V> WriteModelWord2Vec<W> for TrainModel<T>
+where
+    W: Write,
+    T: Trainer<InputVocab = V>,
+    V: Vocab,
+    V::VocabType: ToString,
+    for<'a> &'a V::IdxType: IntoIterator<Item = u64>,
+{
+    fn write_model_word2vec(&self, write: &mut W) -> Result<(), Error> {
+        let vocab = self.input_vocab();
+        let forms = written_forms(vocab, self.surface_forms.as_ref());
+
+        let senses = sense_forms(&forms, self.n_senses());
+
+        let n_forms = forms.iter().map(Vec::len).sum::<usize>() + senses.len();
+        writeln!(write, "{} {}", n_forms, self.config().dims)?;
+
+        let mut write_embedding = |form: &str, embed: ArrayView1<f32>| -> Result<(), Error> {
+            write!(write, "{} ", form)?;
+            for &v in embed.iter() {
+                write.write_all(&v.to_le_bytes())?;
+            }
+
+            // word2vec separates embeddings by a newline.
+            write.write_all(b"\n")?;
+
+            Ok(())
+        };
+
+        for (word, forms) in vocab.types().iter().zip(forms) {
+            let input = vocab.idx(word.label()).unwrap();
+            let embed = self.mean_input_embedding(&input);
+            for form in forms {
+                write_embedding(&form, embed.view())?;
+            }
+        }
+
+        for (form, word, sense) in senses {
+            write_embedding(&form, self.word_sense_embedding(word, sense).view())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the distribution of the initial weights.
+///
+/// The weights are drawn uniformly from *[-1/dims, 1/dims]*, or from
+/// *[-init_scale, init_scale]* if `init_scale` is set.
+fn init_distribution(config: &CommonConfig) -> Uniform<f32> {
+    let init_bound = config
+        .init_scale
+        .unwrap_or_else(|| 1.0 / config.dims as f32);
+    Uniform::new_inclusive(-init_bound, init_bound)
+}
+
+/// Trainer Trait.
+pub trait Trainer {
+    type InputVocab: Vocab;
+    type Metadata;
+
+    /// Get the trainer's input vocabulary.
+    fn input_vocab(&self) -> &Self::InputVocab;
+
+    /// Destruct the trainer and get the input vocabulary.
+    fn try_into_input_vocab(self) -> Result<Self::InputVocab, Error>;
+
+    /// Get the number of possible input types.
+    ///
+    /// In a model with subword units this value is calculated as:
+    /// `2^n_buckets + input_vocab.len()`.
+    fn n_input_types(&self) -> usize;
 
     /// Get the number of possible outputs.
     ///
@@ -246,6 +1264,15 @@ pub trait Trainer {
     /// Get this Trainer's common hyperparameters.
     fn config(&self) -> &CommonConfig;
 
+    /// Use the input matrix as the output matrix.
+    ///
+    /// If this method returns `true`, output `i` uses row `i` of the input
+    /// matrix. This requires that the number of outputs is not larger than
+    /// the number of inputs.
+    fn tied_weights(&self) -> bool {
+        false
+    }
+
     /// Get this Trainer's configuration.
     fn to_metadata(&self) -> Self::Metadata;
 }
@@ -262,6 +1289,29 @@ where
     type Contexts: IntoIterator<Item = usize>;
 
     fn train_iter_from(&mut self, sequence: &S) -> Self::Iter;
+
+    /// Recycle an iterator that was returned by `train_iter_from`.
+    ///
+    /// Implementations can use this to reuse the iterator's buffers for
+    /// the next sequence. The default implementation drops the iterator.
+    fn recycle(&mut self, _iter: Self::Iter) {}
+
+    /// Get the tokens of the last sequence that were skipped.
+    ///
+    /// The default implementation does not skip tokens.
+    fn skipped_tokens(&self) -> SkippedTokens {
+        SkippedTokens::default()
+    }
+}
+
+/// Tokens of a sequence that are never focus tokens.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SkippedTokens {
+    /// Number of tokens that were discarded by subsampling.
+    pub n_discarded: usize,
+
+    /// Number of unknown tokens.
+    pub n_oov: usize,
 }
 
 /// Negative Samples
@@ -270,36 +1320,314 @@ where
 /// should follow the distribution of the underlying output vocabulary.
 pub trait NegativeSamples {
     fn negative_sample(&mut self, output: usize) -> usize;
+
+    /// Sample a negative for the given focus word and output.
+    ///
+    /// The default implementation ignores the focus word.
+    fn focus_negative_sample(&mut self, _focus: usize, output: usize) -> usize {
+        self.negative_sample(output)
+    }
+
+    /// Sample a dropout mask.
+    ///
+    /// Every element of `mask` is set to `true` with probability
+    /// `keep_prob`, marking the dimensions that are kept in an update.
+    fn dropout_mask(&mut self, keep_prob: f32, mask: &mut [bool]);
+}
+
+/// Get the forms in which the words of a vocabulary are written.
+///
+/// Without surface forms, every word is written as is.
+fn written_forms<V>(vocab: &V, surface_forms: Option<&SurfaceForms>) -> Vec<Vec<String>>
+where
+    V: Vocab,
+    V::VocabType: ToString,
+{
+    vocab
+        .types()
+        .iter()
+        .map(|word| {
+            let word = word.label().to_string();
+            match surface_forms.map(|surface_forms| surface_forms.forms(&word)) {
+                Some(forms) if !forms.is_empty() => forms.to_vec(),
+                _ => vec![word],
+            }
+        })
+        .collect()
+}
+
+/// Get the forms in which the additional senses of words are written.
+///
+/// Sense *k > 0* of a word is written as *form#k* for every written form
+/// of the word, after all words. Returns tuples of the form, the word
+/// index, and the sense.
+fn sense_forms(forms: &[Vec<String>], n_senses: usize) -> Vec<(String, usize, usize)> {
+    let mut senses = Vec::new();
+    for sense in 1..n_senses {
+        for (word_idx, word_forms) in forms.iter().enumerate() {
+            senses.extend(
+                word_forms
+                    .iter()
+                    .map(|form| (format!("{}#{}", form, sense), word_idx, sense)),
+            );
+        }
+    }
+
+    senses
+}
+
+/// Add words to a finalfusion vocabulary and their rows to its rows.
+///
+/// The words get consecutive rows, starting at `first_row`. Their rows
+/// are inserted after the rows of the known words, before the rows of
+/// subwords.
+fn append_words(
+    vocab: VocabWrap,
+    rows: &mut Vec<usize>,
+    words: Vec<String>,
+    first_row: usize,
+) -> VocabWrap {
+    let n_words = vocab.words_len();
+    rows.splice(n_words..n_words, first_row..first_row + words.len());
+
+    let mut expanded_words = vocab.words().to_vec();
+    expanded_words.extend(words);
+
+    with_words(vocab, expanded_words)
+}
+
+/// Replace the words of a finalfusion vocabulary, keeping its subwords.
+fn with_words(vocab: VocabWrap, words: Vec<String>) -> VocabWrap {
+    match vocab {
+        VocabWrap::SimpleVocab(_) => FiFuSimpleVocab::new(words).into(),
+        VocabWrap::BucketSubwordVocab(vocab) => {
+            FiFuSubwordVocab::new(words, vocab.min_n(), vocab.max_n(), *vocab.indexer()).into()
+        }
+        VocabWrap::FastTextSubwordVocab(vocab) => {
+            FiFuSubwordVocab::new(words, vocab.min_n(), vocab.max_n(), *vocab.indexer()).into()
+        }
+        VocabWrap::ExplicitSubwordVocab(vocab) => {
+            FiFuSubwordVocab::new(words, vocab.min_n(), vocab.max_n(), vocab.indexer().clone())
+                .into()
+        }
+    }
+}
+
+/// Replace the words of a finalfusion vocabulary by their surface forms.
+///
+/// The row and norm of a word are repeated for each of its surface forms.
+/// The subword rows of the matrix with `n_rows` rows follow the word rows
+/// unchanged.
+fn expand_surface_forms(
+    vocab: VocabWrap,
+    n_rows: usize,
+    norms: &[f32],
+    surface_forms: &SurfaceForms,
+) -> (VocabWrap, Vec<usize>, Vec<f32>) {
+    let mut forms = Vec::new();
+    let mut rows = Vec::new();
+    for (idx, word) in vocab.words().iter().enumerate() {
+        match surface_forms.forms(word) {
+            [] => {
+                forms.push(word.clone());
+                rows.push(idx);
+            }
+            word_forms => {
+                forms.extend(word_forms.iter().cloned());
+                rows.extend(word_forms.iter().map(|_| idx));
+            }
+        }
+    }
+
+    let expanded_norms = rows.iter().map(|&idx| norms[idx]).collect();
+    rows.extend(vocab.words_len()..n_rows);
+
+    (with_words(vocab, forms), rows, expanded_norms)
+}
+
+/// Write embeddings in finalfusion format.
+///
+/// finalfusion only writes embedding matrices that are in memory. So that
+/// a memory-mapped matrix does not have to be copied, the metadata and
+/// vocabulary chunks are written by finalfusion and the matrix and norms
+/// chunks are written from the given rows of length `dims`.
+fn write_embeddings_rows<'a, W>(
+    write: &mut W,
+    metadata: Metadata,
+    vocab: VocabWrap,
+    dims: usize,
+    rows: impl ExactSizeIterator<Item = ArrayView1<'a, f32>>,
+    norms: Vec<f32>,
+) -> Result<(), Error>
+where
+    W: Seek + Write,
+{
+    // Write the embeddings with an empty matrix, up to the matrix chunk.
+    let n_words = vocab.words_len();
+    let storage = NdArray::new(Array2::zeros((rows.len(), 0)));
+    let mut chunks = Cursor::new(Vec::new());
+    Embeddings::new(
+        Some(metadata),
+        vocab,
+        storage,
+        NdNorms::new(Array1::zeros(n_words)),
+    )
+    .write_embeddings(&mut chunks)?;
+    let chunks = chunks.into_inner();
+    let mut chunk_start = 12 + 4 * read_u32(&chunks, 8) as usize;
+    while read_u32(&chunks, chunk_start) != FIFU_NDARRAY_CHUNK {
+        chunk_start += 12 + read_u64(&chunks, chunk_start + 4) as usize;
+    }
+    write.write_all(&chunks[..chunk_start])?;
+
+    let n_rows = rows.len() as u64;
+    let n_bytes = n_rows * dims as u64 * 4;
+    write_chunk_header(write, FIFU_NDARRAY_CHUNK, 16, n_bytes)?;
+    write.write_all(&n_rows.to_le_bytes())?;
+    write.write_all(&(dims as u32).to_le_bytes())?;
+    write.write_all(&FIFU_F32_TYPE.to_le_bytes())?;
+    write_padding(write)?;
+    for row in rows {
+        for &v in row {
+            write.write_all(&v.to_le_bytes())?;
+        }
+    }
+
+    write_chunk_header(write, FIFU_NDNORMS_CHUNK, 12, norms.len() as u64 * 4)?;
+    write.write_all(&(norms.len() as u64).to_le_bytes())?;
+    write.write_all(&FIFU_F32_TYPE.to_le_bytes())?;
+    write_padding(write)?;
+    for norm in norms {
+        write.write_all(&norm.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// finalfusion chunk identifier of embedding matrices.
+const FIFU_NDARRAY_CHUNK: u32 = 2;
+
+/// finalfusion chunk identifier of embedding norms.
+const FIFU_NDNORMS_CHUNK: u32 = 6;
+
+/// finalfusion type identifier of `f32`.
+const FIFU_F32_TYPE: u32 = 10;
+
+/// Write the identifier and length of a finalfusion chunk.
+///
+/// The chunk consists of `n_header_bytes` bytes, padding to align the
+/// data to `f32`, and `n_data_bytes` bytes of data.
+fn write_chunk_header<W>(
+    write: &mut W,
+    identifier: u32,
+    n_header_bytes: u64,
+    n_data_bytes: u64,
+) -> Result<(), Error>
+where
+    W: Seek + Write,
+{
+    write.write_all(&identifier.to_le_bytes())?;
+    let chunk_len = n_header_bytes + fifu_padding(write)? + n_data_bytes;
+    write.write_all(&chunk_len.to_le_bytes())?;
+    Ok(())
+}
+
+/// Write the padding that aligns the following data to `f32`.
+fn write_padding<W>(write: &mut W) -> Result<(), Error>
+where
+    W: Seek + Write,
+{
+    let padding = vec![0; fifu_padding(write)? as usize];
+    write.write_all(&padding)?;
+    Ok(())
+}
+
+/// Get the number of padding bytes that align data at the current position.
+///
+/// Like finalfusion, data that is already aligned is padded by 4 bytes.
+fn fifu_padding<W>(write: &mut W) -> Result<u64, Error>
+where
+    W: Seek,
+{
+    Ok(4 - write.stream_position()? % 4)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// l2-normalize a vector, zero vectors are left unchanged.
+fn normalize(v: ArrayViewMut1<f32>) {
+    if dot(v.view(), v.view()) > 0. {
+        l2_normalize(v);
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{BufReader, Cursor};
+    use std::sync::Arc;
+
+    use finalfusion::prelude::{
+        Embeddings, MmapEmbeddings, ReadEmbeddings, ReadWord2Vec, StorageWrap, VocabWrap,
+    };
+    use finalfusion::storage::{NdArray, StorageView};
     use finalfusion::subword::FinalfusionHashIndexer;
-    use ndarray::Array2;
-    use rand::FromEntropy;
+    use finalfusion::vocab::Vocab as FiFuVocab;
+    use ndarray::{s, Array2, Axis};
+    use ndarray_npy::NpzReader;
+    use rand::{FromEntropy, SeedableRng};
     use rand_xorshift::XorShiftRng;
 
-    use super::TrainModel;
+    use super::{TrainModel, Trainer};
+    use crate::config;
     use crate::config::SubwordVocabConfig;
-    use crate::idx::WordWithSubwordsIdx;
+    use crate::idx::{WordIdx, WordWithSubwordsIdx};
+    use crate::io::{
+        TrainInfo, WriteModelBinary, WriteModelNorms, WriteModelNpz, WriteModelText,
+        WriteModelWord2Vec,
+    };
+    use crate::sgd::SGD;
     use crate::skipgram_trainer::SkipgramTrainer;
-    use crate::util::all_close;
+    use crate::source::build_vocab;
+    use crate::util::numeric::{all_close, close};
+    use crate::util::ReseedOnCloneRng;
+    use crate::vec_simd::l2_normalize;
     use crate::{
-        BucketConfig, CommonConfig, LossType, ModelType, SkipGramConfig, SubwordVocab, VocabBuilder,
+        BucketConfig, CommonConfig, LookupKind, ModelType, SimpleVocab, SimpleVocabConfig,
+        SkipGramConfig, SubwordComposition, SubwordHash, SubwordVocab, SurfaceForms, Vocab,
+        VocabBuilder,
     };
 
+    type TestVocab = SubwordVocab<BucketConfig, FinalfusionHashIndexer>;
+
+    type SimpleWords = SimpleVocab<String>;
+
     const TEST_COMMON_CONFIG: CommonConfig = CommonConfig {
-        dims: 3,
         epochs: 5,
-        loss: LossType::LogisticNegativeSampling,
-        lr: 0.05,
-        negative_samples: 5,
-        zipf_exponent: 0.5,
+        ..config::TEST_COMMON_CONFIG
     };
 
     const TEST_SKIP_CONFIG: SkipGramConfig = SkipGramConfig {
         context_size: 5,
+        context_subsampling_threshold: 0.,
+        discard_boundary_tokens: false,
+        discard_oov_contexts: false,
+        exclude_focus_negatives: false,
+        min_context: 0,
         model: ModelType::SkipGram,
+        tied_weights: false,
     };
 
     const VOCAB_CONF: SubwordVocabConfig<BucketConfig> = SubwordVocabConfig {
@@ -307,9 +1635,970 @@ mod tests {
         min_count: 2,
         max_n: 6,
         min_n: 3,
-        indexer: BucketConfig { buckets_exp: 21 },
+        indexer: BucketConfig {
+            buckets_exp: 21,
+            hash: SubwordHash::FinalFusion,
+        },
     };
 
+    #[test]
+    pub fn estimated_bytes_matches_allocation() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.min_count = 1;
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for token in &["to", "be", "or", "not", "to", "be"] {
+            builder.count(token.to_string());
+        }
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let mut skipgram_config = TEST_SKIP_CONFIG;
+        skipgram_config.model = ModelType::StructuredSkipGram;
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            skipgram_config,
+        );
+        let n_words = trainer.input_vocab().len();
+        let estimate = TEST_COMMON_CONFIG.estimated_bytes(
+            n_words,
+            trainer.n_input_types() - n_words,
+            trainer.n_output_types(),
+        );
+
+        let model = TrainModel::from(trainer);
+        let allocated =
+            (model.input.view().len() + model.output.view().len()) * std::mem::size_of::<f32>();
+        assert_eq!(estimate, allocated);
+        // 4 words + 16 buckets, 4 words * 10 context positions.
+        assert_eq!(estimate, (20 + 40) * 3 * 4);
+    }
+
+    #[test]
+    pub fn tied_weights_allocate_input_matrix_only() {
+        let mut skipgram_config = TEST_SKIP_CONFIG;
+        skipgram_config.tied_weights = true;
+        let trainer = SkipgramTrainer::new(
+            test_vocab(),
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            skipgram_config,
+        );
+        // 1 word + 16 buckets, the outputs use the input matrix.
+        assert_eq!(trainer.n_input_types(), 17);
+        assert_eq!(TEST_COMMON_CONFIG.estimated_bytes(1, 16, 0), 17 * 3 * 4);
+
+        let model = TrainModel::from(trainer);
+        assert_eq!(model.output.view().as_ptr(), model.input.view().as_ptr());
+    }
+
+    #[test]
+    pub fn into_parts_frees_output_matrix() {
+        let model = test_model();
+        let output = Arc::downgrade(&model.output.clone().into_inner());
+        assert!(output.upgrade().is_some());
+
+        let (_, input) = model.into_parts().unwrap();
+        assert!(
+            output.upgrade().is_none(),
+            "Output matrix is still allocated"
+        );
+        assert_eq!(input.view().nrows(), 16 + 1);
+    }
+
+    fn test_vocab() -> TestVocab {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.min_count = 1;
+        vocab_config.indexer.buckets_exp = 4;
+
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        builder.count("bla".to_string());
+        builder.into()
+    }
+
+    fn test_model() -> TrainModel<SkipgramTrainer<ReseedOnCloneRng<XorShiftRng>, TestVocab>> {
+        TrainModel::from(SkipgramTrainer::new(
+            test_vocab(),
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        ))
+    }
+
+    #[test]
+    pub fn init_scale_bounds_initial_weights() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.min_count = 1;
+        vocab_config.indexer.buckets_exp = 6;
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        builder.count("bla".to_string());
+        let vocab: TestVocab = builder.into();
+
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.dims = 50;
+        common_config.output_dims = 50;
+        common_config.init_scale = Some(0.5);
+        let model = TrainModel::from_seed(
+            SkipgramTrainer::new(
+                vocab,
+                XorShiftRng::from_entropy(),
+                common_config,
+                TEST_SKIP_CONFIG,
+            ),
+            42,
+        );
+
+        for matrix in &[model.input.view(), model.output.view()] {
+            let max_abs = matrix.iter().fold(0f32, |max, v| max.max(v.abs()));
+            let mean_abs = matrix.iter().map(|v| v.abs()).sum::<f32>() / matrix.len() as f32;
+            assert!(max_abs <= 0.5);
+            assert!(max_abs > 0.45);
+            // The mean absolute value of U(-s, s) is s / 2.
+            assert!(close(mean_abs, 0.25, 0.02));
+        }
+    }
+
+    #[test]
+    pub fn seeded_models_start_identical() {
+        let seeded = |seed| {
+            TrainModel::from_seed(
+                SkipgramTrainer::new(
+                    test_vocab(),
+                    XorShiftRng::from_entropy(),
+                    TEST_COMMON_CONFIG,
+                    TEST_SKIP_CONFIG,
+                ),
+                seed,
+            )
+        };
+
+        // Shards that are initialized with the same seed.
+        let shard0 = seeded(42);
+        let shard1 = seeded(42);
+        assert_eq!(shard0.input.view(), shard1.input.view());
+        assert_eq!(shard0.output.view(), shard1.output.view());
+
+        let other = seeded(43);
+        assert_ne!(shard0.input.view(), other.input.view());
+        assert_ne!(shard0.output.view(), other.output.view());
+    }
+
+    #[test]
+    pub fn mmap_input_trains_like_in_memory_input() {
+        let source = vec![
+            "the cat sat on the mat"
+                .split_whitespace()
+                .map(ToOwned::to_owned)
+                .collect::<Vec<_>>();
+            10
+        ];
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.discard_threshold = 1.;
+        vocab_config.indexer.buckets_exp = 8;
+        let vocab: TestVocab = build_vocab(&source, vocab_config).unwrap();
+
+        // Clones of the trainer's RNG are seeded from entropy. Without
+        // negatives and with a context size of 1, training does not
+        // depend on random numbers.
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.negative_samples = 0;
+        let mut skip_config = TEST_SKIP_CONFIG;
+        skip_config.context_size = 1;
+        let trainer = || {
+            SkipgramTrainer::new(
+                vocab.clone(),
+                XorShiftRng::seed_from_u64(42),
+                common_config,
+                skip_config,
+            )
+        };
+
+        let path = std::env::temp_dir().join(format!("ff-hogwild-{}.bin", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mmap_model = TrainModel::from_seed_mmap(trainer(), 42, &file).unwrap();
+        let n_bytes = mmap_model.input.view().len() * 4;
+        assert_eq!(fs::metadata(&path).unwrap().len(), n_bytes as u64);
+        let model = TrainModel::from_seed(trainer(), 42);
+        assert_eq!(mmap_model.input.view(), model.input.view());
+
+        let mut mmap_sgd = SGD::new(mmap_model);
+        mmap_sgd.train_source(&source).unwrap();
+        let mut sgd = SGD::new(model);
+        sgd.train_source(&source).unwrap();
+
+        let (_, mmap_input) = mmap_sgd.into_model().into_parts().unwrap();
+        let (_, input) = sgd.into_model().into_parts().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_ne!(
+            input.view(),
+            TrainModel::from_seed(trainer(), 42).input.view()
+        );
+        assert_eq!(mmap_input.view(), input.view());
+    }
+
+    #[test]
+    pub fn mmap_input_is_written_like_in_memory_input() {
+        let trainer = || {
+            SkipgramTrainer::new(
+                test_vocab(),
+                XorShiftRng::seed_from_u64(42),
+                TEST_COMMON_CONFIG,
+                TEST_SKIP_CONFIG,
+            )
+        };
+
+        let path = std::env::temp_dir().join(format!("ff-write-{}.bin", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        let mmap_model = TrainModel::from_seed_mmap(trainer(), 42, &file).unwrap();
+        let model = TrainModel::from_seed(trainer(), 42);
+
+        let read_back = |model: TrainModel<_>| {
+            let mut data = Cursor::new(Vec::new());
+            model
+                .write_model_binary(&mut data, TrainInfo::new("a".into(), "b".into(), 1))
+                .unwrap();
+            data.set_position(0);
+            let embeds: Embeddings<VocabWrap, NdArray> =
+                Embeddings::read_embeddings(&mut data).unwrap();
+            (
+                embeds.storage().view().to_owned(),
+                embeds.norms().unwrap().to_vec(),
+            )
+        };
+        let (mmap_matrix, mmap_norms) = read_back(mmap_model);
+        let (matrix, norms) = read_back(model);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(mmap_matrix.nrows(), test_vocab().n_input_types());
+        assert_eq!(mmap_matrix, matrix);
+        assert_eq!(mmap_norms, norms);
+    }
+
+    #[test]
+    pub fn models_are_approx_eq_within_eps() {
+        let seeded = |seed| {
+            TrainModel::from_seed(
+                SkipgramTrainer::new(
+                    test_vocab(),
+                    XorShiftRng::from_entropy(),
+                    TEST_COMMON_CONFIG,
+                    TEST_SKIP_CONFIG,
+                ),
+                seed,
+            )
+        };
+
+        let model = seeded(42);
+        assert!(model.approx_eq(&model, 0.));
+        assert!(model == model);
+
+        let mut perturbed = seeded(42);
+        assert!(model.approx_eq(&perturbed, 0.));
+        perturbed.input_embedding_mut(0)[0] += 1e-3;
+        assert!(!model.approx_eq(&perturbed, 1e-4));
+        assert!(model.approx_eq(&perturbed, 1e-2));
+        assert!(model != perturbed);
+    }
+
+    #[test]
+    pub fn input_matrix_shares_embeddings() {
+        let mut model = test_model();
+        let input = model.input_matrix();
+        model.input_embedding_mut(0).fill(1.);
+        assert_eq!(input.view(), model.input.view());
+        assert!(input.subview(Axis(0), 0).iter().all(|&v| v == 1.));
+    }
+
+    #[test]
+    pub fn check_finite_inputs_detects_and_resets() {
+        let mut model = test_model();
+        assert!(model.check_finite_inputs(true).is_empty());
+
+        model.input_embedding_mut(3)[1] = f32::NAN;
+        model.input_embedding_mut(5)[0] = f32::INFINITY;
+
+        // Without reset, the rows are only reported.
+        assert_eq!(model.check_finite_inputs(false), vec![3, 5]);
+        assert!(model.input_embedding(3)[1].is_nan());
+
+        assert_eq!(model.check_finite_inputs(true), vec![3, 5]);
+        let init_bound = 1. / TEST_COMMON_CONFIG.dims as f32;
+        for &idx in &[3, 5] {
+            assert!(model
+                .input_embedding(idx)
+                .iter()
+                .all(|v| v.abs() <= init_bound));
+        }
+        assert!(model.check_finite_inputs(false).is_empty());
+    }
+
+    #[test]
+    pub fn check_finite_inputs_resets_learned_dims_reproducibly() {
+        let seeded_model = || {
+            let trainer = SkipgramTrainer::new(
+                test_vocab(),
+                XorShiftRng::seed_from_u64(42),
+                TEST_COMMON_CONFIG,
+                TEST_SKIP_CONFIG,
+            );
+            let mut model = TrainModel::from_seed(trainer, 42);
+            model.set_side_features(Array2::from_elem((1, 1), 0.5).view());
+            model.input_embedding_mut(0)[0] = f32::NAN;
+            model.input_embedding_mut(0)[2] = f32::NAN;
+            model
+        };
+
+        let mut model = seeded_model();
+        let mut other = seeded_model();
+        assert_eq!(model.check_finite_inputs(true), vec![0]);
+        assert_eq!(other.check_finite_inputs(true), vec![0]);
+        let embed = model.input_embedding(0);
+        assert_eq!(
+            embed.slice(s![..2]),
+            other.input_embedding(0).slice(s![..2])
+        );
+        assert!(embed[0].is_finite() && embed[1].is_finite());
+
+        // The side feature is kept, even though it is not finite.
+        assert!(embed[2].is_nan());
+    }
+
+    #[test]
+    pub fn write_model_text_round_trip() {
+        let model = test_model();
+        let mut data = Vec::new();
+        model.write_model_text(&mut data, true).unwrap();
+
+        let text = String::from_utf8(data).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("1 3"));
+
+        // The word embedding is the mean of the word and subword embeddings.
+        let mut parts = lines.next().unwrap().split(' ');
+        assert_eq!(parts.next(), Some("bla"));
+        let embed = parts.map(|v| v.parse().unwrap()).collect::<Vec<f32>>();
+        let idx = model.input_vocab().idx("bla").unwrap();
+        assert_eq!(embed, model.mean_input_embedding(&idx).to_vec());
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    pub fn sense_rows_have_side_features_and_are_checked() {
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.senses = 2;
+        let mut model = TrainModel::from_seed(
+            SkipgramTrainer::new(
+                test_vocab(),
+                XorShiftRng::seed_from_u64(42),
+                common_config,
+                TEST_SKIP_CONFIG,
+            ),
+            42,
+        );
+        model.set_side_features(Array2::from_elem((1, 1), 0.5).view());
+
+        let sense_idx = model.sense_input_idx(0, 1);
+        assert_eq!(model.input_embedding_mut(sense_idx)[2], 0.5);
+
+        model.input_embedding_mut(sense_idx)[0] = f32::NAN;
+        assert_eq!(model.check_finite_inputs(true), vec![sense_idx]);
+        assert!(model.input_embedding_mut(sense_idx)[0].is_finite());
+        assert!(model.check_finite_inputs(false).is_empty());
+    }
+
+    #[test]
+    pub fn senses_are_written_after_words() {
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.senses = 3;
+        let model = TrainModel::from_seed(
+            SkipgramTrainer::new(
+                test_vocab(),
+                XorShiftRng::seed_from_u64(42),
+                common_config,
+                TEST_SKIP_CONFIG,
+            ),
+            42,
+        );
+
+        let mut data = Vec::new();
+        model.write_model_text(&mut data, true).unwrap();
+        let text = String::from_utf8(data).unwrap();
+        let words = text
+            .lines()
+            .skip(1)
+            .map(|line| line.split(' ').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(text.lines().next(), Some("3 3"));
+        assert_eq!(words, ["bla", "bla#1", "bla#2"]);
+
+        let mut data = Cursor::new(Vec::new());
+        let sense = model.sense_embedding("bla", 2).unwrap();
+        model
+            .write_model_binary(&mut data, TrainInfo::new("a".into(), "b".into(), 1))
+            .unwrap();
+        data.set_position(0);
+        let embeds: Embeddings<VocabWrap, StorageWrap> =
+            Embeddings::read_embeddings(&mut data).unwrap();
+        assert_eq!(embeds.vocab().words(), ["bla", "bla#1", "bla#2"]);
+        let embed = embeds.embedding_with_norm("bla#2").unwrap();
+        let norm = sense.dot(&sense).sqrt();
+        assert!(close(embed.norm, norm, 1e-6));
+        assert!(all_close(
+            (&embed.embedding * norm).as_slice().unwrap(),
+            sense.as_slice().unwrap(),
+            1e-6
+        ));
+    }
+
+    #[test]
+    pub fn write_model_word2vec_round_trip() {
+        let model = test_model();
+        let mut data = Vec::new();
+        model.write_model_word2vec(&mut data).unwrap();
+
+        let header = b"1 3\nbla ";
+        assert_eq!(&data[..header.len()], header);
+        let embed = data[header.len()..header.len() + 12]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>();
+        let idx = model.input_vocab().idx("bla").unwrap();
+        assert_eq!(embed, model.mean_input_embedding(&idx).to_vec());
+        assert_eq!(&data[header.len() + 12..], b"\n");
+
+        // The output can be read by finalfusion.
+        let embeds: Embeddings<finalfusion::vocab::SimpleVocab, finalfusion::storage::NdArray> =
+            Embeddings::read_word2vec_binary(&mut Cursor::new(data)).unwrap();
+        assert_eq!(embeds.dims(), 3);
+        assert!(embeds.embedding("bla").is_some());
+    }
+
+    #[test]
+    pub fn write_model_npz_round_trip() {
+        let model = test_model();
+        let mut data = Cursor::new(Vec::new());
+        let mut vocab_data = Vec::new();
+        model.write_model_npz(&mut data, &mut vocab_data).unwrap();
+        assert_eq!(vocab_data, b"bla\n");
+
+        let mut npz = NpzReader::new(Cursor::new(data.into_inner())).unwrap();
+        let embeddings: Array2<f32> = npz.by_name("embeddings.npy").unwrap();
+        assert_eq!(embeddings.shape(), &[1, 3]);
+        let idx = model.input_vocab().idx("bla").unwrap();
+        assert_eq!(embeddings.row(0), model.mean_input_embedding(&idx));
+    }
+
+    #[test]
+    pub fn mean_composition_is_sum_divided_by_ngram_count() {
+        let mut model = test_model();
+        let mean = model.compose_subword("blub");
+        model.set_subword_composition(SubwordComposition::Sum);
+        let sum = model.compose_subword("blub");
+
+        // "blub" is unknown, its embedding is composed of its n-grams only.
+        let n_ngrams = model.input_vocab().ngram_indices("blub").len();
+        assert!(n_ngrams > 1);
+        assert!(all_close(
+            mean.as_slice().unwrap(),
+            (sum / n_ngrams as f32).as_slice().unwrap(),
+            1e-6
+        ));
+
+        // The composition is recorded in the metadata.
+        let mut data = Cursor::new(Vec::new());
+        model
+            .write_model_binary(&mut data, TrainInfo::new("a".into(), "b".into(), 1))
+            .unwrap();
+        data.set_position(0);
+        let embeds: Embeddings<VocabWrap, StorageWrap> =
+            Embeddings::read_embeddings(&mut data).unwrap();
+        assert_eq!(
+            embeds.metadata().unwrap()["subword_composition"].as_str(),
+            Some("Sum")
+        );
+    }
+
+    #[test]
+    pub fn sum_composition_is_used_for_input_embeddings() {
+        let mut model = test_model();
+        let idx = model.input_vocab().idx("bla").unwrap();
+        let mean = model.mean_input_embedding(&idx);
+        model.set_subword_composition(SubwordComposition::Sum);
+        let sum = model.mean_input_embedding(&idx);
+
+        assert!(idx.len() > 1);
+        assert!(all_close(
+            (mean * idx.len() as f32).as_slice().unwrap(),
+            sum.as_slice().unwrap(),
+            1e-6
+        ));
+    }
+
+    #[test]
+    pub fn embedding_matrix_is_aligned_for_mmap() {
+        let model = test_model();
+        let mut data = Cursor::new(Vec::new());
+        model
+            .write_model_binary(&mut data, TrainInfo::new("a".into(), "b".into(), 1))
+            .unwrap();
+        let data = data.into_inner();
+
+        let read_u32 = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+        let read_u64 = |pos: usize| u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap());
+
+        // Header: magic, version, number of chunks, chunk identifiers.
+        assert_eq!(&data[..4], b"FiFu");
+        let mut pos = 12 + read_u32(8) as usize * 4;
+
+        // Chunks: identifier, length, data. The matrix data is at the end
+        // of the embedding matrix chunk (identifier 2).
+        let mut matrix_offset = None;
+        while pos < data.len() {
+            let end = pos + 12 + read_u64(pos + 4) as usize;
+            if read_u32(pos) == 2 {
+                let n_rows = read_u64(pos + 12) as usize;
+                let n_cols = read_u32(pos + 20) as usize;
+                matrix_offset = Some(end - n_rows * n_cols * 4);
+            }
+            pos = end;
+        }
+        assert_eq!(matrix_offset.unwrap() % 4, 0);
+
+        // The matrix can be memory mapped without copying.
+        let path = std::env::temp_dir().join(format!("ff-mmap-{}.fifu", std::process::id()));
+        fs::write(&path, &data).unwrap();
+        let mut read = BufReader::new(File::open(&path).unwrap());
+        let mmapped: Result<Embeddings<VocabWrap, StorageWrap>, _> =
+            Embeddings::mmap_embeddings(&mut read);
+        fs::remove_file(&path).unwrap();
+        let embeds: Embeddings<VocabWrap, StorageWrap> =
+            Embeddings::read_embeddings(&mut Cursor::new(data)).unwrap();
+        assert_eq!(mmapped.unwrap().embedding("bla"), embeds.embedding("bla"));
+    }
+
+    #[test]
+    pub fn compose_subword_matches_finalfusion_lookup() {
+        let model = test_model();
+        let idx = model.input_vocab().idx("bla").unwrap();
+        let in_vocab = model.compose_subword("bla");
+        assert_eq!(in_vocab, model.mean_input_embedding(&idx));
+        let oov = model.compose_subword("blub");
+
+        let mut data = Cursor::new(Vec::new());
+        model
+            .write_model_binary(&mut data, TrainInfo::new("a".into(), "b".into(), 1))
+            .unwrap();
+        data.set_position(0);
+        let embeds: Embeddings<VocabWrap, StorageWrap> =
+            Embeddings::read_embeddings(&mut data).unwrap();
+
+        for (token, composed) in [("bla", in_vocab), ("blub", oov)].iter_mut() {
+            l2_normalize(composed.view_mut());
+            let lookup = embeds.embedding(token).unwrap();
+            assert!(all_close(
+                composed.as_slice().unwrap(),
+                lookup.view().as_slice().unwrap(),
+                1e-5
+            ));
+        }
+    }
+
+    #[test]
+    pub fn pruned_subwords_only_change_unknown_words() {
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.min_count = 1;
+        vocab_config.indexer.buckets_exp = 10;
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(vocab_config);
+        for _ in 0..10 {
+            builder.count("tea".to_string());
+        }
+        builder.count("tequila".to_string());
+        let mut model = TrainModel::from(SkipgramTrainer::new(
+            builder.into(),
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        ));
+        let vocab: &TestVocab = model.input_vocab();
+        let tequila = vocab.idx("tequila").unwrap();
+        let tequila_embed = model.mean_input_embedding(&tequila);
+        let unpruned_oov = model.compose_subword("quilt");
+
+        let n_pruned = model.prune_subwords(2);
+        assert!(n_pruned > 0);
+        let mut pruned_oov = model.compose_subword("quilt");
+        assert_ne!(pruned_oov, unpruned_oov);
+
+        let mut data = Cursor::new(Vec::new());
+        model
+            .write_model_binary(&mut data, TrainInfo::new("a".into(), "b".into(), 1))
+            .unwrap();
+        data.set_position(0);
+        let embeds: Embeddings<VocabWrap, NdArray> =
+            Embeddings::read_embeddings(&mut data).unwrap();
+
+        let n_zero = embeds
+            .storage()
+            .view()
+            .outer_iter()
+            .filter(|row| row.iter().all(|&v| v == 0.))
+            .count();
+        assert_eq!(n_zero, n_pruned);
+
+        // The embedding of the rare word itself does not change.
+        let norm = embeds.embedding_with_norm("tequila").unwrap().norm;
+        let mut written = embeds.embedding("tequila").unwrap().into_owned();
+        written *= norm;
+        assert!(all_close(
+            written.as_slice().unwrap(),
+            tequila_embed.as_slice().unwrap(),
+            1e-5
+        ));
+
+        l2_normalize(pruned_oov.view_mut());
+        assert!(all_close(
+            pruned_oov.as_slice().unwrap(),
+            embeds.embedding("quilt").unwrap().as_slice().unwrap(),
+            1e-5
+        ));
+    }
+
+    #[test]
+    pub fn user_metadata_is_merged() {
+        let mut metadata = HashMap::new();
+        metadata.insert("host".to_string(), "localhost".to_string());
+        metadata.insert("git_commit".to_string(), "4a3bc1f".to_string());
+        let mut train_info = TrainInfo::new("corpus.txt".into(), "model.fifu".into(), 1);
+        train_info.set_metadata(metadata).unwrap();
+
+        let mut data = Cursor::new(Vec::new());
+        test_model()
+            .write_model_binary(&mut data, train_info)
+            .unwrap();
+        data.set_position(0);
+
+        let embeds: Embeddings<VocabWrap, StorageWrap> =
+            Embeddings::read_embeddings(&mut data).unwrap();
+        let metadata = embeds.metadata().unwrap();
+        assert_eq!(metadata["host"].as_str(), Some("localhost"));
+        assert_eq!(metadata["git_commit"].as_str(), Some("4a3bc1f"));
+        assert_eq!(metadata["common_config"]["dims"].as_integer(), Some(3));
+        assert_eq!(
+            metadata["model_config"]["context_size"].as_integer(),
+            Some(5)
+        );
+        assert_eq!(
+            metadata["training_info"]["corpus"].as_str(),
+            Some("corpus.txt")
+        );
+        assert!(metadata.get("version_info").is_some());
+        assert!(metadata.get("vocab_config").is_some());
+    }
+
+    #[test]
+    pub fn user_metadata_does_not_replace_training_metadata() {
+        let mut metadata = HashMap::new();
+        metadata.insert("common_config".to_string(), "custom".to_string());
+        let mut train_info = TrainInfo::new("corpus.txt".into(), "model.fifu".into(), 1);
+        assert!(train_info.set_metadata(metadata).is_err());
+        assert!(train_info.metadata().is_empty());
+    }
+
+    /// Get a model with hand-picked embeddings.
+    fn analogy_model() -> TrainModel<SkipgramTrainer<ReseedOnCloneRng<XorShiftRng>, SimpleWords>> {
+        let mut builder: VocabBuilder<_, String> = VocabBuilder::new(SimpleVocabConfig {
+            min_count: 1,
+            discard_threshold: 1.,
+        });
+        let embeds = [
+            ("man", [1., 0., 0.]),
+            ("woman", [1., 1., 0.]),
+            ("king", [1., 0., 1.]),
+            ("queen", [1., 1., 1.]),
+            ("apple", [0., 0., 1.]),
+        ];
+        for &(word, _) in &embeds {
+            builder.count(word.to_string());
+        }
+        let vocab: SimpleVocab<String> = builder.into();
+
+        let mut model = TrainModel::from(SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::from_entropy(),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        ));
+        for &(word, embed) in &embeds {
+            let idx = model.input_vocab().idx(word).unwrap().word_idx() as usize;
+            model
+                .input_embedding_mut(idx)
+                .assign(&ndarray::arr1(&embed));
+        }
+
+        model
+    }
+
+    #[test]
+    pub fn analogy_ranks_answer_first() {
+        let model = analogy_model();
+
+        let results = model.analogy("man", "woman", "king", 2).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "queen");
+        assert!(results[0].1 > results[1].1);
+        assert!(results
+            .iter()
+            .all(|(word, _)| !["man", "woman", "king"].contains(&word.as_str())));
+
+        let results = model.similarity("king", 1).unwrap();
+        assert_eq!(results[0].0, "queen");
+        assert!(close(results[0].1, (2f32 / 3.).sqrt(), 1e-5));
+
+        assert!(model.analogy("man", "woman", "prince", 1).is_err());
+    }
+
+    #[test]
+    pub fn lookup_kinds_are_reported() {
+        let model = test_model();
+        assert_eq!(model.lookup_kind("bla"), LookupKind::Word);
+        assert_eq!(model.lookup_kind("blub"), LookupKind::Subwords);
+        assert!(model.input_vocab().idx("blub").is_none());
+
+        // Without subwords, unknown tokens are unknown.
+        let model = analogy_model();
+        assert_eq!(model.lookup_kind("king"), LookupKind::Word);
+        assert_eq!(model.lookup_kind("kings"), LookupKind::Unknown);
+    }
+
+    #[test]
+    pub fn cooccurring_pairs_are_more_similar() {
+        let mut source = Vec::new();
+        for _ in 0..50 {
+            source.push(vec!["abc".to_string(), "def".to_string()]);
+            source.push(vec!["uvw".to_string(), "xyz".to_string()]);
+        }
+
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.discard_threshold = 1.;
+        vocab_config.indexer.buckets_exp = 10;
+        let vocab: TestVocab = build_vocab(&source, vocab_config).unwrap();
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.dims = 10;
+        common_config.output_dims = 10;
+        common_config.negative_samples = 2;
+        let mut skip_config = TEST_SKIP_CONFIG;
+        skip_config.tied_weights = true;
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            skip_config,
+        );
+        let mut sgd = SGD::new(TrainModel::from_seed(trainer, 42));
+        sgd.train_source(&source).unwrap();
+        let model = sgd.into_model();
+
+        let pairs = [("abc", "def"), ("abc", "xyz"), ("abcd", "def")]
+            .iter()
+            .map(|&(token1, token2)| (token1.to_string(), token2.to_string()))
+            .collect::<Vec<_>>();
+        let sims = model.similarities(&pairs);
+        assert_eq!(sims.len(), 3);
+        assert!(sims[0].unwrap() > sims[1].unwrap());
+        // Unknown words are composed from their subwords.
+        assert!(sims[2].is_some());
+    }
+
+    #[test]
+    pub fn similarities_of_zero_embeddings_are_none() {
+        let mut model = test_model();
+        model.input.view_mut().fill(0.);
+        let sims = model.similarities(&[("bla".to_string(), "bla".to_string())]);
+        assert_eq!(sims, [None]);
+    }
+
+    #[test]
+    pub fn input_dimension_stats_match_brute_force() {
+        let source = vec![vec!["the".to_string(), "cat".to_string(), "sat".to_string()]; 20];
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.discard_threshold = 1.;
+        vocab_config.indexer.buckets_exp = 6;
+        let vocab: TestVocab = build_vocab(&source, vocab_config).unwrap();
+        let mut common_config = TEST_COMMON_CONFIG;
+        common_config.dims = 4;
+        common_config.output_dims = 4;
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            common_config,
+            TEST_SKIP_CONFIG,
+        );
+        let mut sgd = SGD::new(TrainModel::from_seed(trainer, 42));
+        sgd.train_source(&source).unwrap();
+        let model = sgd.into_model();
+
+        let stats = model.input_dimension_stats();
+        let input = model.input.view();
+        assert_eq!(stats.n_rows(), input.nrows());
+        assert_eq!(stats.means().len(), 4);
+        for (dim, column) in input.axis_iter(Axis(1)).enumerate() {
+            let n = column.len() as f32;
+            let mean = column.sum() / n;
+            let variance = column.iter().map(|&v| (v - mean).powi(2)).sum::<f32>() / n;
+            assert!(close(stats.means()[dim], mean, 1e-5));
+            assert!(close(stats.variances()[dim], variance, 1e-5));
+        }
+    }
+
+    #[test]
+    pub fn trained_tokens_are_displaced_more() {
+        let mut source = vec![vec!["aaa".to_string(), "bbb".to_string()]; 20];
+        source.push(vec!["zzz".to_string()]);
+        let mut vocab_config = VOCAB_CONF;
+        vocab_config.discard_threshold = 1.;
+        vocab_config.min_count = 1;
+        vocab_config.indexer.buckets_exp = 10;
+        let vocab: TestVocab = build_vocab(&source, vocab_config).unwrap();
+        let trainer = SkipgramTrainer::new(
+            vocab,
+            XorShiftRng::seed_from_u64(42),
+            TEST_COMMON_CONFIG,
+            TEST_SKIP_CONFIG,
+        );
+        let mut sgd = SGD::new(TrainModel::from_seed(trainer, 42));
+        let init_embeds = sgd.model().input_word_embeddings();
+
+        // "zzz" is never trained.
+        source.pop();
+        sgd.train_source(&source).unwrap();
+        let displacements = sgd.model().displacements(init_embeds.view());
+
+        let rank = |token: &str| {
+            displacements
+                .ranked()
+                .iter()
+                .position(|displacement| displacement.token() == token)
+                .unwrap()
+        };
+        assert!(rank("aaa") < rank("zzz"));
+        assert!(rank("bbb") < rank("zzz"));
+        assert_eq!(displacements.least_moved(1).next().unwrap().token(), "zzz");
+        assert_eq!(displacements.ranked()[rank("zzz")].displacement(), 0.);
+    }
+
+    #[test]
+    pub fn similarity_ties_are_ordered_by_vocab_index() {
+        let model = analogy_model();
+        let word_idx = |word: &str| model.input_vocab().idx(word).unwrap().word_idx();
+
+        // "woman" and "king" have the same similarity to "man".
+        let results = model.similarity("man", 4).unwrap();
+        assert_eq!(results[0].1, results[1].1);
+        let mut tied = ["woman", "king"];
+        tied.sort_by_key(|word| word_idx(word));
+        assert_eq!(results[0].0, tied[0]);
+        assert_eq!(results[1].0, tied[1]);
+        assert_eq!(results[2].0, "queen");
+        assert_eq!(results[3].0, "apple");
+    }
+
+    #[test]
+    pub fn written_norms_are_l2_norms_of_embeddings() {
+        let model = analogy_model();
+        let mut data = Vec::new();
+        model.write_model_norms(&mut data).unwrap();
+        let text = String::from_utf8(data).unwrap();
+
+        let mut n_words = 0;
+        for line in text.lines() {
+            let mut parts = line.split('\t');
+            let word = parts.next().unwrap();
+            let norm: f32 = parts.next().unwrap().parse().unwrap();
+            assert_eq!(parts.next(), None);
+
+            let idx = model.input_vocab().idx(word).unwrap().word_idx() as usize;
+            let embed = model.input_embedding(idx);
+            assert!(close(norm, embed.dot(&embed).sqrt(), 1e-6));
+            n_words += 1;
+        }
+        assert_eq!(n_words, model.input_vocab().len());
+        assert!(text.contains("queen\t1.73205"));
+    }
+
+    #[test]
+    pub fn surface_forms_share_embedding_in_text_output() {
+        let mut model = analogy_model();
+        let mut surface_forms = SurfaceForms::new();
+        for form in &["King", "king", "queen"] {
+            surface_forms.count(form);
+        }
+        model.set_surface_forms(surface_forms);
+
+        let mut data = Vec::new();
+        model.write_model_text(&mut data, true).unwrap();
+        let text = String::from_utf8(data).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("6 3"));
+
+        let embeds = lines
+            .map(|line| {
+                let (word, embed) = line.split_at(line.find(' ').unwrap());
+                (word.to_string(), embed.to_string())
+            })
+            .collect::<HashMap<_, _>>();
+        assert_eq!(embeds.len(), 6);
+        assert_eq!(embeds["King"], embeds["king"]);
+        assert_ne!(embeds["king"], embeds["queen"]);
+        assert!(embeds.contains_key("apple"));
+    }
+
+    #[test]
+    pub fn surface_forms_share_embedding_in_binary_output() {
+        let mut model = test_model();
+        let mut surface_forms = SurfaceForms::new();
+        for form in &["Bla", "bla", "BLA"] {
+            surface_forms.count(form);
+        }
+        model.set_surface_forms(surface_forms);
+        let oov = model.compose_subword("blub");
+
+        let mut data = Cursor::new(Vec::new());
+        model
+            .write_model_binary(&mut data, TrainInfo::new("a".into(), "b".into(), 1))
+            .unwrap();
+        data.set_position(0);
+        let embeds: Embeddings<VocabWrap, StorageWrap> =
+            Embeddings::read_embeddings(&mut data).unwrap();
+
+        assert_eq!(embeds.vocab().words(), &["Bla", "bla", "BLA"]);
+        let bla = embeds.embedding("bla").unwrap();
+        assert_eq!(embeds.embedding("Bla").unwrap(), bla);
+        assert_eq!(embeds.embedding("BLA").unwrap(), bla);
+
+        // Subword embeddings are preserved.
+        let mut oov = oov;
+        l2_normalize(oov.view_mut());
+        assert!(all_close(
+            oov.as_slice().unwrap(),
+            embeds.embedding("blub").unwrap().view().as_slice().unwrap(),
+            1e-5
+        ));
+    }
+
     #[test]
     pub fn model_embed_methods() {
         let mut vocab_config = VOCAB_CONF.clone();
@@ -329,8 +2618,8 @@ mod tests {
             .unwrap()
             .into();
 
-        let mut model = TrainModel {
-            trainer: SkipgramTrainer::new(
+        let mut model = TrainModel::from_parts(
+            SkipgramTrainer::new(
                 vocab,
                 XorShiftRng::from_entropy(),
                 common_config,
@@ -338,7 +2627,7 @@ mod tests {
             ),
             input,
             output,
-        };
+        );
 
         // Input embeddings
         assert!(all_close(
@@ -1,16 +1,43 @@
-use rand::{FromEntropy, SeedableRng};
+use rand::{FromEntropy, Rng, SeedableRng};
 use rand_core::{self, RngCore};
 use serde::Serialize;
 
+pub mod numeric;
+
 pub static EOS: &str = "</s>";
 
-/// Tolerance for small negative values.
-const NEGATIVE_TOLERANCE: f32 = 1e-5;
+/// Default tolerance for small negative values.
+pub const NEGATIVE_TOLERANCE: f32 = 1e-5;
 
 /// Add a small value, to prevent returning Inf on underflow.
+///
+/// This uses the default tolerance `NEGATIVE_TOLERANCE`, see
+/// `safe_ln_with_tolerance`.
 #[inline]
 pub fn safe_ln(v: f32) -> f32 {
-    (v + NEGATIVE_TOLERANCE).ln()
+    safe_ln_with_tolerance(v, NEGATIVE_TOLERANCE)
+}
+
+/// Add `tolerance`, to prevent returning Inf on underflow.
+///
+/// The tolerance bounds the result from below by *ln(tolerance)*. A
+/// smaller tolerance distorts the logarithm of small values less, but
+/// results in larger losses when `v` underflows.
+#[inline]
+pub fn safe_ln_with_tolerance(v: f32, tolerance: f32) -> f32 {
+    (v + tolerance).ln()
+}
+
+/// Sample a dropout mask with `rng`.
+///
+/// Every element of `mask` is set to `true` with probability `keep_prob`.
+pub fn dropout_mask<R>(rng: &mut R, keep_prob: f32, mask: &mut [bool])
+where
+    R: Rng,
+{
+    for keep in mask {
+        *keep = rng.gen_range(0f32, 1f32) < keep_prob;
+    }
 }
 
 /// RNG that reseeds on clone.
@@ -77,16 +104,12 @@ impl VersionInfo {
     }
 }
 
-#[cfg(test)]
-pub use self::test::*;
-
 #[cfg(test)]
 mod test {
-    use ndarray::{ArrayView, Dimension};
     use rand::{FromEntropy, SeedableRng};
     use rand_core::{self, impls, le, RngCore};
 
-    use super::ReseedOnCloneRng;
+    use super::{safe_ln, safe_ln_with_tolerance, ReseedOnCloneRng, NEGATIVE_TOLERANCE};
 
     #[derive(Clone)]
     struct BogusRng(pub u64);
@@ -120,38 +143,6 @@ mod test {
         }
     }
 
-    pub fn close(a: f32, b: f32, eps: f32) -> bool {
-        let diff = (a - b).abs();
-        if diff > eps {
-            return false;
-        }
-
-        true
-    }
-
-    pub fn all_close(a: &[f32], b: &[f32], eps: f32) -> bool {
-        for (&av, &bv) in a.iter().zip(b) {
-            if !close(av, bv, eps) {
-                return false;
-            }
-        }
-
-        true
-    }
-
-    pub fn array_all_close<Ix>(a: ArrayView<f32, Ix>, b: ArrayView<f32, Ix>, eps: f32) -> bool
-    where
-        Ix: Dimension,
-    {
-        for (&av, &bv) in a.iter().zip(b) {
-            if !close(av, bv, eps) {
-                return false;
-            }
-        }
-
-        true
-    }
-
     #[test]
     fn reseed_on_clone_rng() {
         let bogus_rng = BogusRng::from_entropy();
@@ -163,4 +154,16 @@ mod test {
         // One in 2^64 probability of collision given good entropy source.
         assert_ne!((reseed.0).0, (reseed_clone.0).0);
     }
+
+    #[test]
+    fn safe_ln_is_bounded_by_tolerance() {
+        for &tolerance in &[1e-5f32, 1e-7, 1e-3] {
+            assert_eq!(safe_ln_with_tolerance(0.0, tolerance), tolerance.ln());
+        }
+
+        for &v in &[0.0f32, 1e-6, 0.5, 1.0] {
+            assert_eq!(safe_ln(v), (v + 1e-5).ln());
+            assert_eq!(safe_ln(v), safe_ln_with_tolerance(v, NEGATIVE_TOLERANCE));
+        }
+    }
 }
@@ -0,0 +1,81 @@
+//! Comparison of floating point numbers.
+//!
+//! The functions in this module compare numbers with an absolute
+//! tolerance `eps`: two numbers are close when their absolute difference
+//! is at most `eps`. Consequently, comparisons with `eps = 0` are exact
+//! and NaN is not close to any number, including itself.
+
+use ndarray::{ArrayView, Dimension};
+
+/// Returns `true` if `a` and `b` differ by at most `eps`.
+pub fn close(a: f32, b: f32, eps: f32) -> bool {
+    (a - b).abs() <= eps
+}
+
+/// Returns `true` if all elements of `a` and `b` differ by at most `eps`.
+///
+/// Slices of different lengths are never close.
+pub fn all_close(a: &[f32], b: &[f32], eps: f32) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(&av, &bv)| close(av, bv, eps))
+}
+
+/// Returns `true` if all elements of `a` and `b` differ by at most `eps`.
+///
+/// Arrays of different shapes are never close.
+pub fn array_all_close<Ix>(a: ArrayView<f32, Ix>, b: ArrayView<f32, Ix>, eps: f32) -> bool
+where
+    Ix: Dimension,
+{
+    a.shape() == b.shape() && a.iter().zip(b).all(|(&av, &bv)| close(av, bv, eps))
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::{arr1, arr2};
+
+    use super::{all_close, array_all_close, close};
+
+    #[test]
+    fn close_within_eps() {
+        assert!(close(1., 1.05, 0.1));
+        assert!(close(1., 1., 0.));
+        assert!(!close(1., 1.2, 0.1));
+        assert!(!close(f32::NAN, f32::NAN, 1.));
+    }
+
+    #[test]
+    fn equal_length_slices_are_close() {
+        assert!(all_close(&[1., 2., 3.], &[1.01, 1.99, 3.], 0.02));
+        assert!(array_all_close(
+            arr2(&[[1., 2.], [3., 4.]]).view(),
+            arr2(&[[1.01, 2.], [3., 3.99]]).view(),
+            0.02
+        ));
+    }
+
+    #[test]
+    fn slices_that_differ_are_not_close() {
+        assert!(!all_close(&[1., 2., 3.], &[1., 2.5, 3.], 0.1));
+        assert!(!array_all_close(
+            arr1(&[1., 2.]).view(),
+            arr1(&[1., 2.5]).view(),
+            0.1
+        ));
+    }
+
+    #[test]
+    fn different_lengths_are_not_close() {
+        assert!(!all_close(&[1., 2.], &[1., 2., 3.], 0.1));
+        assert!(!all_close(&[1., 2., 3.], &[1., 2.], 0.1));
+        assert!(!array_all_close(
+            arr1(&[1., 2.]).view(),
+            arr1(&[1., 2., 3.]).view(),
+            0.1
+        ));
+        assert!(!array_all_close(
+            arr2(&[[1., 2.], [3., 4.]]).view(),
+            arr2(&[[1., 2., 3., 4.]]).view(),
+            0.1
+        ));
+    }
+}
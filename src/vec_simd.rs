@@ -296,7 +296,7 @@ mod tests {
     use ndarray_rand::rand_distr::Uniform;
     use ndarray_rand::RandomExt;
 
-    use crate::util::{all_close, array_all_close, close};
+    use crate::util::numeric::{all_close, array_all_close, close};
 
     use super::{
         dot_f32x4, dot_unvectorized, l2_normalize, scale_f32x4, scale_unvectorized,
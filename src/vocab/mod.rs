@@ -1,5 +1,6 @@
 pub(crate) mod simple;
 pub(crate) mod subword;
+pub(crate) mod surface_forms;
 
 use std::borrow::Borrow;
 use std::collections::HashMap;
@@ -37,6 +38,21 @@ impl CountedType<String> {
     }
 }
 
+/// How a token is resolved by a vocabulary.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LookupKind {
+    /// The token is in the vocabulary.
+    Word,
+
+    /// The token is not in the vocabulary, but has known subwords.
+    ///
+    /// The embedding of the token is composed from its subwords.
+    Subwords,
+
+    /// The token is neither in the vocabulary nor has known subwords.
+    Unknown,
+}
+
 /// Trait for lookup of indices.
 pub trait Vocab {
     type VocabType: Hash + Eq;
@@ -67,6 +83,14 @@ pub trait Vocab {
     /// Get the number of possible input types.
     fn n_input_types(&self) -> usize;
 
+    /// Returns `true` if `token` has at least one known subword.
+    ///
+    /// This does not check whether `token` itself is in the vocabulary.
+    /// Vocabularies without subwords always return `false`.
+    fn has_subwords(&self, _token: &str) -> bool {
+        false
+    }
+
     /// Get all types in the vocabulary.
     fn types(&self) -> &[CountedType<Self::VocabType>];
 
@@ -76,6 +100,20 @@ pub trait Vocab {
     /// was constructed from, **before** removing types that are below the
     /// minimum count.
     fn n_types(&self) -> usize;
+
+    /// Get the rank-frequency data of the vocabulary.
+    ///
+    /// Returns `(rank, count)` pairs in order of descending count, with
+    /// ranks starting at 1. This data can be used to plot the Zipf curve of
+    /// the corpus or to fit its Zipf exponent.
+    fn rank_frequencies(&self) -> Vec<(usize, usize)> {
+        // Types are sorted by descending frequency.
+        self.types()
+            .iter()
+            .enumerate()
+            .map(|(idx, t)| (idx + 1, t.count()))
+            .collect()
+    }
 }
 
 /// Generic builder struct to count types.
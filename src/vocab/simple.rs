@@ -161,6 +161,26 @@ mod tests {
         }
     }
 
+    #[test]
+    pub fn rank_frequencies_simple_vocab() {
+        let mut builder: VocabBuilder<SimpleVocabConfig, &str> =
+            VocabBuilder::new(TEST_SIMPLECONFIG);
+        for token in "d a d b a d c d a b d a".split_whitespace() {
+            builder.count(token);
+        }
+
+        let vocab: SimpleVocab<&str> = builder.into();
+        let rank_frequencies = vocab.rank_frequencies();
+        assert_eq!(rank_frequencies, vec![(1, 5), (2, 4), (3, 2)]);
+
+        for (idx, &(rank, _)) in rank_frequencies.iter().enumerate() {
+            assert_eq!(rank, idx + 1);
+        }
+        for idx in 1..rank_frequencies.len() {
+            assert!(rank_frequencies[idx - 1].1 >= rank_frequencies[idx].1);
+        }
+    }
+
     #[test]
     pub fn test_simple_vocab_builder() {
         let mut builder: VocabBuilder<SimpleVocabConfig, &str> =
@@ -186,7 +206,7 @@ mod tests {
         assert_eq!("a", a.label);
         assert_eq!(5, a.count());
         // 0.0001 / 5/18 + (0.0001 / 5/18).sqrt() = 0.019334
-        assert!(util::close(
+        assert!(util::numeric::close(
             0.019334,
             vocab.discard(vocab.idx("a").unwrap().word_idx() as usize),
             1e-5
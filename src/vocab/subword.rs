@@ -1,7 +1,9 @@
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::hash::Hash;
 
+use finalfusion::compat::fasttext::FastTextIndexer;
 use finalfusion::subword::{
     BucketIndexer, ExplicitIndexer, FinalfusionHashIndexer, Indexer, NGrams, SubwordIndices,
 };
@@ -12,6 +14,12 @@ use crate::vocab::{bracket, create_discards, create_indices};
 use crate::{util, BucketConfig, NGramConfig, SubwordVocabConfig, Vocab, VocabBuilder, Word};
 
 /// A corpus vocabulary with subword lookup.
+///
+/// The subword indices of the words in the vocabulary are computed once,
+/// when the vocabulary is constructed. Looking up a word with `idx` does
+/// not enumerate or hash its n-grams again. The memory use of this cache
+/// is proportional to the vocabulary size times the average number of
+/// n-grams per word.
 #[derive(Clone)]
 pub struct SubwordVocab<C, I> {
     config: SubwordVocabConfig<C>,
@@ -87,9 +95,84 @@ where
         self.idx(word)
             .map(|idx| &self.words[idx.word_idx() as usize])
     }
+
+    /// Get the subword n-grams of a token with their indices.
+    ///
+    /// This returns the n-grams of the bracketed token together with the
+    /// index that the indexer assigns to each n-gram. The token does not
+    /// need to be in the vocabulary. N-grams that are unknown to the
+    /// indexer are omitted.
+    ///
+    /// The returned indices are indexer indices (e.g. buckets). In the
+    /// input matrix, subword rows are stored after the word rows, so the
+    /// corresponding row is the returned index plus the vocabulary size.
+    pub fn ngram_indices(&self, token: &str) -> Vec<(String, u64)> {
+        if token == util::EOS {
+            return Vec::new();
+        }
+
+        bracket(token)
+            .as_str()
+            .subword_indices_with_ngrams(
+                self.config.min_n as usize,
+                self.config.max_n as usize,
+                &self.indexer,
+            )
+            .filter_map(|(ngram, idx)| idx.map(|idx| (ngram.to_owned(), idx)))
+            .collect()
+    }
+
+    /// Get the indices with the most colliding n-grams.
+    ///
+    /// The n-grams of all words in the vocabulary are grouped by their
+    /// indexer index (e.g. bucket). Indices to which at least two
+    /// distinct n-grams are mapped are returned with their sorted n-grams.
+    /// At most `n` indices are returned, in descending order of the number
+    /// of n-grams; ties are ordered by index.
+    pub fn ngram_collisions(&self, n: usize) -> Vec<(u64, Vec<String>)> {
+        let mut ngrams: BTreeMap<u64, BTreeSet<String>> = BTreeMap::new();
+        for word in &self.words {
+            for (ngram, idx) in self.ngram_indices(word.word()) {
+                ngrams.entry(idx).or_default().insert(ngram);
+            }
+        }
+
+        let mut collisions = ngrams
+            .into_iter()
+            .filter(|(_, ngrams)| ngrams.len() > 1)
+            .map(|(idx, ngrams)| (idx, ngrams.into_iter().collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        // Stable sort, indices with the same number of n-grams stay sorted.
+        collisions.sort_by_key(|(_, ngrams)| cmp::Reverse(ngrams.len()));
+        collisions.truncate(n);
+
+        collisions
+    }
 }
 
 impl<C, I> SubwordVocab<C, I> {
+    /// Get the subword indices that are rarely used by the vocabulary.
+    ///
+    /// The count of a subword index is the sum of the counts of the words
+    /// that have at least one n-gram with that index. The indices with a
+    /// count below `min_count` are returned in ascending order. As the
+    /// indices returned by `idx`, they are offset by the number of words.
+    /// Indices that no word uses are not returned.
+    pub fn rare_subword_indices(&self, min_count: usize) -> Vec<u64> {
+        let mut counts: BTreeMap<u64, usize> = BTreeMap::new();
+        for (word, subwords) in self.words.iter().zip(&self.subwords) {
+            for &idx in subwords.iter().collect::<BTreeSet<_>>() {
+                *counts.entry(idx).or_insert(0) += word.count();
+            }
+        }
+
+        counts
+            .into_iter()
+            .filter(|&(_, count)| count < min_count)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
     pub(crate) fn subword_indices_idx(&self, idx: usize) -> Option<&[u64]> {
         self.subwords.get(idx).map(|v| v.as_slice())
     }
@@ -123,6 +206,10 @@ where
         self.discards[idx]
     }
 
+    fn has_subwords(&self, token: &str) -> bool {
+        !self.ngram_indices(token).is_empty()
+    }
+
     fn n_input_types(&self) -> usize {
         self.len() + self.indexer.upper_bound() as usize
     }
@@ -137,31 +224,64 @@ where
 }
 
 /// Constructs a `SubwordVocab` from a `VocabBuilder<T>` where `T: Into<String>`.
-impl<I, T> From<VocabBuilder<SubwordVocabConfig<BucketConfig>, T>> for SubwordVocab<BucketConfig, I>
+///
+/// The vocabulary uses 2^`buckets_exp` buckets with the finalfusion hash.
+impl<T> From<VocabBuilder<SubwordVocabConfig<BucketConfig>, T>>
+    for SubwordVocab<BucketConfig, FinalfusionHashIndexer>
 where
     T: Hash + Eq + Into<String>,
-    I: BucketIndexer,
 {
     fn from(builder: VocabBuilder<SubwordVocabConfig<BucketConfig>, T>) -> Self {
         let config = builder.config;
+        let n_items = builder.n_items;
+        let words = bucket_vocab_words(builder);
+        SubwordVocab::new(
+            config,
+            words,
+            n_items,
+            FinalfusionHashIndexer::new(config.indexer.buckets_exp as usize),
+        )
+    }
+}
 
-        let mut words: Vec<_> = builder
-            .items
-            .into_iter()
-            .map(|(word, count)| (word.into(), count))
-            .filter(|(word, count)| word == util::EOS || *count >= config.min_count as usize)
-            .map(|(word, count)| Word::new(word, count))
-            .collect();
-        words.sort_unstable_by(|w1, w2| w2.cmp(&w1));
+/// Constructs a `SubwordVocab` from a `VocabBuilder<T>` where `T: Into<String>`.
+///
+/// The vocabulary uses 2^`buckets_exp` buckets with the fastText hash.
+impl<T> From<VocabBuilder<SubwordVocabConfig<BucketConfig>, T>>
+    for SubwordVocab<BucketConfig, FastTextIndexer>
+where
+    T: Hash + Eq + Into<String>,
+{
+    fn from(builder: VocabBuilder<SubwordVocabConfig<BucketConfig>, T>) -> Self {
+        let config = builder.config;
+        let n_items = builder.n_items;
+        let words = bucket_vocab_words(builder);
         SubwordVocab::new(
             config,
             words,
-            builder.n_items,
-            I::new(config.indexer.buckets_exp as usize),
+            n_items,
+            FastTextIndexer::new(2usize.pow(config.indexer.buckets_exp)),
         )
     }
 }
 
+/// Get the words of a bucket vocabulary, sorted by descending frequency.
+fn bucket_vocab_words<T>(builder: VocabBuilder<SubwordVocabConfig<BucketConfig>, T>) -> Vec<Word>
+where
+    T: Hash + Eq + Into<String>,
+{
+    let config = builder.config;
+    let mut words: Vec<_> = builder
+        .items
+        .into_iter()
+        .map(|(word, count)| (word.into(), count))
+        .filter(|(word, count)| word == util::EOS || *count >= config.min_count as usize)
+        .map(|(word, count)| Word::new(word, count))
+        .collect();
+    words.sort_unstable_by(|w1, w2| w2.cmp(&w1));
+    words
+}
+
 /// Constructs a `SubwordVocab` from a `VocabBuilder<T>` where `T: Into<String>`.
 impl<T> From<VocabBuilder<SubwordVocabConfig<NGramConfig>, T>>
     for SubwordVocab<NGramConfig, ExplicitIndexer>
@@ -228,15 +348,19 @@ macro_rules! impl_into_vocabwrap (
 );
 
 impl_into_vocabwrap!(SubwordVocab<BucketConfig, FinalfusionHashIndexer>);
+impl_into_vocabwrap!(SubwordVocab<BucketConfig, FastTextIndexer>);
 impl_into_vocabwrap!(SubwordVocab<NGramConfig, ExplicitIndexer>);
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeSet;
+
     use super::{SubwordVocab, Vocab, VocabBuilder};
     use crate::config::SubwordVocabConfig;
     use crate::idx::WordIdx;
-    use crate::{util, BucketConfig, NGramConfig};
+    use crate::{util, BucketConfig, NGramConfig, SubwordHash};
 
+    use finalfusion::compat::fasttext::FastTextIndexer;
     use finalfusion::subword::{ExplicitIndexer, FinalfusionHashIndexer, Indexer};
 
     const TEST_SUBWORDCONFIG: SubwordVocabConfig<BucketConfig> = SubwordVocabConfig {
@@ -244,7 +368,10 @@ mod tests {
         min_count: 2,
         max_n: 6,
         min_n: 3,
-        indexer: BucketConfig { buckets_exp: 21 },
+        indexer: BucketConfig {
+            buckets_exp: 21,
+            hash: SubwordHash::FinalFusion,
+        },
     };
 
     const TEST_NGRAMCONFIG: SubwordVocabConfig<NGramConfig> = SubwordVocabConfig {
@@ -306,7 +433,7 @@ mod tests {
             vec![1141947, 215572, 1324230, 0],
             vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>()
         );
-        assert!(util::close(
+        assert!(util::numeric::close(
             0.019058,
             vocab.discard(vocab.idx("to").unwrap().word_idx() as usize),
             1e-5,
@@ -320,7 +447,7 @@ mod tests {
             vec![277351, 1105488, 1482882, 1],
             vocab.idx("be").unwrap().into_iter().collect::<Vec<_>>()
         );
-        assert!(util::close(
+        assert!(util::numeric::close(
             0.019058,
             vocab.discard(vocab.idx("be").unwrap().word_idx() as usize),
             1e-5,
@@ -338,7 +465,7 @@ mod tests {
                 .collect::<Vec<_>>(),
             vec![2]
         );
-        assert!(util::close(
+        assert!(util::numeric::close(
             0.027158,
             vocab.discard(vocab.idx(util::EOS).unwrap().word_idx() as usize),
             1e-5,
@@ -348,6 +475,225 @@ mod tests {
         assert!(vocab.idx("too").is_none());
     }
 
+    fn assert_cached_subword_indices<C, I>(vocab: &SubwordVocab<C, I>)
+    where
+        C: Copy + Clone,
+        I: Indexer,
+    {
+        for (idx, word) in vocab.types().iter().enumerate() {
+            let fresh = vocab
+                .ngram_indices(word.word())
+                .into_iter()
+                .map(|(_, ngram_idx)| ngram_idx + vocab.len() as u64)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                vocab.subword_indices_idx(idx).unwrap(),
+                fresh.as_slice(),
+                "Cached subword indices of '{}' are stale",
+                word.word()
+            );
+        }
+    }
+
+    #[test]
+    pub fn cached_subword_indices_match_fresh_indices() {
+        let tokens = &[
+            "to", "be", "or", "not", "to", "be", "or", "Daniël", "Daniël", "</s>",
+        ];
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_SUBWORDCONFIG);
+        tokens.iter().for_each(|&token| builder.count(token));
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        assert_eq!(vocab.len(), 5);
+        assert_cached_subword_indices(&vocab);
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_SUBWORDCONFIG);
+        tokens.iter().for_each(|&token| builder.count(token));
+        let vocab: SubwordVocab<_, FastTextIndexer> = builder.into();
+        assert_cached_subword_indices(&vocab);
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_NGRAMCONFIG);
+        tokens.iter().for_each(|&token| builder.count(token));
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+        assert_cached_subword_indices(&vocab);
+    }
+
+    #[test]
+    pub fn bucket_vocab_ngram_indices() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_SUBWORDCONFIG);
+        builder.count("to");
+        builder.count("to");
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        let mut ngram_indices = vocab.ngram_indices("to");
+        ngram_indices.sort();
+        assert_eq!(
+            ngram_indices,
+            vec![
+                ("<to".to_string(), 215569),
+                ("<to>".to_string(), 1141944),
+                ("to>".to_string(), 1324227)
+            ]
+        );
+
+        // Subword rows are offset by the vocabulary size.
+        let mut rows = vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>();
+        rows.pop();
+        rows.sort();
+        let mut expected_rows = ngram_indices
+            .iter()
+            .map(|(_, idx)| idx + vocab.len() as u64)
+            .collect::<Vec<_>>();
+        expected_rows.sort();
+        assert_eq!(rows, expected_rows);
+
+        // Unknown tokens also have n-grams.
+        assert_eq!(vocab.ngram_indices("too").len(), 6);
+        assert!(vocab.ngram_indices(util::EOS).is_empty());
+    }
+
+    #[test]
+    pub fn finalfusion_bucket_vocab_ngram_indices() {
+        let mut config = TEST_SUBWORDCONFIG;
+        config.min_n = 5;
+        config.max_n = 5;
+        config.indexer.hash = SubwordHash::FinalFusion;
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        builder.count("Daniël");
+        builder.count("Daniël");
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        assert_eq!(vocab.indexer.upper_bound(), 2u64.pow(21));
+
+        // Bucket indices computed with 64-bit FNV-1a over the number of
+        // characters (u64) and the characters (u32), both little-endian.
+        // These indices must not depend on the target platform.
+        let mut ngram_indices = vocab.ngram_indices("Daniël");
+        ngram_indices.sort();
+        assert_eq!(
+            ngram_indices,
+            vec![
+                ("<Dani".to_string(), 2065822),
+                ("Danië".to_string(), 311961),
+                ("aniël".to_string(), 1679745),
+                ("niël>".to_string(), 620206),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn fasttext_bucket_vocab_ngram_indices() {
+        let mut config = TEST_SUBWORDCONFIG;
+        config.min_n = 5;
+        config.max_n = 5;
+        config.indexer.hash = SubwordHash::FastText;
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        builder.count("Daniël");
+        builder.count("Daniël");
+        let vocab: SubwordVocab<_, FastTextIndexer> = builder.into();
+        assert_eq!(vocab.indexer.upper_bound(), 2u64.pow(21));
+
+        // Bucket indices computed with fastText's hash function. The
+        // non-ASCII character exercises fastText's sign extension.
+        let mut ngram_indices = vocab.ngram_indices("Daniël");
+        ngram_indices.sort();
+        assert_eq!(
+            ngram_indices,
+            vec![
+                ("<Dani".to_string(), 1694517),
+                ("Danië".to_string(), 1688161),
+                ("aniël".to_string(), 1397693),
+                ("niël>".to_string(), 1016616),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn colliding_ngrams_are_reported() {
+        let mut config = TEST_SUBWORDCONFIG;
+        config.indexer.buckets_exp = 1;
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        builder.count("to");
+        builder.count("to");
+        builder.count(util::EOS);
+        builder.count(util::EOS);
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+
+        // With two buckets, "<to" and "to>" share bucket 1, "<to>" is
+        // alone in bucket 0.
+        assert_eq!(
+            vocab.ngram_collisions(10),
+            vec![(1, vec!["<to".to_string(), "to>".to_string()])]
+        );
+        assert!(vocab.ngram_collisions(0).is_empty());
+
+        // Explicit n-gram indices never collide.
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_NGRAMCONFIG);
+        builder.count("to");
+        builder.count("to");
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+        assert!(vocab.ngram_collisions(10).is_empty());
+    }
+
+    #[test]
+    pub fn subwords_of_only_rare_words_are_rare() {
+        let mut config = TEST_SUBWORDCONFIG;
+        config.min_count = 1;
+
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(config);
+        for _ in 0..10 {
+            builder.count("tea");
+        }
+        builder.count("tequila");
+        let vocab: SubwordVocab<_, FinalfusionHashIndexer> = builder.into();
+        let rows = |word: &str| {
+            vocab
+                .ngram_indices(word)
+                .into_iter()
+                .map(|(_, idx)| idx + vocab.len() as u64)
+                .collect::<BTreeSet<_>>()
+        };
+
+        // "<te" is shared with "tea", "qui" only occurs in "tequila".
+        let rare = vocab.rare_subword_indices(2);
+        let tequila_only = rows("tequila")
+            .difference(&rows("tea"))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(rare, tequila_only);
+        assert!(!rare.is_empty());
+        assert!(vocab.rare_subword_indices(1).is_empty());
+        assert_eq!(
+            vocab.rare_subword_indices(12).len(),
+            rows("tequila").union(&rows("tea")).count()
+        );
+    }
+
+    #[test]
+    pub fn ngram_vocab_ngram_indices() {
+        let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_NGRAMCONFIG);
+        builder.count("to");
+        builder.count("to");
+        let vocab: SubwordVocab<_, ExplicitIndexer> = builder.into();
+
+        // sorted n-grams: ["to>", "<to>", "<to"]
+        let mut ngram_indices = vocab.ngram_indices("to");
+        ngram_indices.sort();
+        assert_eq!(
+            ngram_indices,
+            vec![
+                ("<to".to_string(), 2),
+                ("<to>".to_string(), 1),
+                ("to>".to_string(), 0)
+            ]
+        );
+
+        // Only n-grams that are known to the indexer are returned.
+        assert_eq!(vocab.ngram_indices("tor"), vec![("<to".to_string(), 2)]);
+    }
+
     #[test]
     pub fn test_ngram_vocab_builder() {
         let mut builder: VocabBuilder<_, &str> = VocabBuilder::new(TEST_NGRAMCONFIG.clone());
@@ -383,7 +729,7 @@ mod tests {
             vec![5, 6, 3, 0],
             vocab.idx("to").unwrap().into_iter().collect::<Vec<_>>()
         );
-        assert!(util::close(
+        assert!(util::numeric::close(
             0.019058,
             vocab.discard(vocab.idx("to").unwrap().word_idx() as usize),
             1e-5,
@@ -398,7 +744,7 @@ mod tests {
             vec![7, 8, 4, 1],
             vocab.idx("be").unwrap().into_iter().collect::<Vec<_>>()
         );
-        assert!(util::close(
+        assert!(util::numeric::close(
             0.019058,
             vocab.discard(vocab.idx("be").unwrap().word_idx() as usize),
             1e-5,
@@ -416,7 +762,7 @@ mod tests {
                 .into_iter()
                 .collect::<Vec<_>>()
         );
-        assert!(util::close(
+        assert!(util::numeric::close(
             0.027158,
             vocab.discard(vocab.idx(util::EOS).unwrap().word_idx() as usize),
             1e-5,
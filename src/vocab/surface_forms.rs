@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+/// Surface forms of lowercased words.
+///
+/// When a model is trained on a lowercased corpus, the vocabulary only
+/// contains lowercased words. This type records the forms in which each
+/// lowercased word was observed in the corpus, so that every surface form
+/// can be written to the output with the embedding of its lowercased word.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SurfaceForms {
+    forms: HashMap<String, Vec<String>>,
+}
+
+impl SurfaceForms {
+    /// Construct an empty set of surface forms.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an observed surface form.
+    ///
+    /// The form is recorded for its lowercased word, the lowercased word
+    /// is returned.
+    pub fn count(&mut self, form: &str) -> String {
+        let word = form.to_lowercase();
+        let forms = self.forms.entry(word.clone()).or_default();
+        if !forms.iter().any(|f| f == form) {
+            forms.push(form.to_owned());
+        }
+
+        word
+    }
+
+    /// Get the observed surface forms of a lowercased word.
+    ///
+    /// The forms are in the order in which they were first observed. An
+    /// empty slice is returned for words that were not observed.
+    pub fn forms(&self, word: &str) -> &[String] {
+        self.forms.get(word).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SurfaceForms;
+
+    #[test]
+    fn surface_forms_are_recorded_once() {
+        let mut forms = SurfaceForms::new();
+        for form in &["The", "the", "THE", "The", "cat"] {
+            forms.count(form);
+        }
+
+        assert_eq!(forms.forms("the"), &["The", "the", "THE"]);
+        assert_eq!(forms.forms("cat"), &["cat"]);
+        assert!(forms.forms("The").is_empty());
+        assert_eq!(forms.count("Dog"), "dog");
+    }
+}